@@ -0,0 +1,465 @@
+//! Static analysis of queries, e.g. to allow only queries that do not build or change nodes.
+use crate::value::Position;
+use mq_lang::{AstExpr, AstNode, AstPattern, Shared};
+use pyo3::prelude::*;
+use std::{cell::RefCell, collections::HashSet, rc::Rc};
+
+/// Builtins that build new nodes or change existing ones, including those of the builtin module.
+const MUTATING_FUNCTIONS: [&str; 38] = [
+    "decrease_header_depth",
+    "decrease_header_level",
+    "demote_heading",
+    "from_html",
+    "increase_header_depth",
+    "increase_header_level",
+    "load_markdown",
+    "promote_heading",
+    "set_attr",
+    "set_check",
+    "set_children",
+    "set_code_block_lang",
+    "set_list_ordered",
+    "set_ref",
+    "shift_left",
+    "shift_right",
+    "to_blockquote",
+    "to_callout",
+    "to_code",
+    "to_code_inline",
+    "to_delete",
+    "to_em",
+    "to_h",
+    "to_hr",
+    "to_image",
+    "to_link",
+    "to_markdown",
+    "to_math",
+    "to_math_inline",
+    "to_md_fragment",
+    "to_md_list",
+    "to_md_table_align",
+    "to_md_table_cell",
+    "to_md_table_row",
+    "to_md_text",
+    "to_mdx",
+    "to_strong",
+    "update",
+];
+
+/// What a query does, as found by `analyze` without running it.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct QueryAnalysis {
+    #[pyo3(get)]
+    read_only: bool,
+    #[pyo3(get)]
+    selectors: Vec<String>,
+    #[pyo3(get)]
+    functions: Vec<String>,
+    #[pyo3(get)]
+    mutations: Vec<String>,
+}
+
+#[pymethods]
+impl QueryAnalysis {
+    fn __repr__(&self) -> String {
+        format!(
+            "QueryAnalysis(read_only={}, selectors={:?}, functions={:?}, mutations={:?})",
+            if self.read_only { "True" } else { "False" },
+            self.selectors,
+            self.functions,
+            self.mutations
+        )
+    }
+}
+
+/// A problem found in a query by `check_syntax`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct Diagnostic {
+    #[pyo3(get)]
+    message: String,
+    /// Where the problem is in the query, None when mq-lang does not know.
+    #[pyo3(get)]
+    span: Option<Position>,
+    #[pyo3(get)]
+    severity: &'static str,
+}
+
+#[pymethods]
+impl Diagnostic {
+    fn __repr__(&self) -> String {
+        match &self.span {
+            Some(span) => format!(
+                "Diagnostic({:?}, {:?}, {})",
+                self.severity,
+                self.message,
+                span.__repr__()
+            ),
+            None => format!("Diagnostic({:?}, {:?})", self.severity, self.message),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Analyzer {
+    /// Names bound by the query itself: functions, macros, variables and parameters.
+    defined: HashSet<String>,
+    selectors: Vec<String>,
+    /// Every function called or referenced, defined by the query or not.
+    calls: Vec<String>,
+    /// The name of every call, in order.
+    call_sites: Vec<mq_lang::IdentWithToken>,
+    /// Constructs whose effect cannot be known without running the query.
+    opaque: Vec<String>,
+    /// Every name used as a value, such as a builtin passed by name or a variable.
+    references: Vec<String>,
+}
+
+impl Analyzer {
+    fn visit(&mut self, node: &Shared<AstNode>) {
+        match &*node.expr {
+            AstExpr::Selector(selector) => push_unique(&mut self.selectors, selector.to_string()),
+            AstExpr::SelectorChain(selectors) => selectors
+                .iter()
+                .for_each(|selector| push_unique(&mut self.selectors, selector.to_string())),
+            AstExpr::SelectorCall(selector, args) => {
+                push_unique(&mut self.selectors, selector.to_string());
+                self.visit_all(args);
+            }
+            AstExpr::Call(ident, args) => {
+                push_unique(&mut self.calls, ident.name.to_string());
+                self.call_sites.push(ident.clone());
+                self.visit_all(args);
+            }
+            AstExpr::Ident(ident) => {
+                // A builtin passed by name can be called later, e.g. `map(to_strong)`.
+                if MUTATING_FUNCTIONS.contains(&ident.name.to_string().as_str()) {
+                    push_unique(&mut self.calls, ident.name.to_string());
+                }
+
+                push_unique(&mut self.references, ident.name.to_string());
+            }
+            AstExpr::CallDynamic(callable, args) => {
+                push_unique(&mut self.opaque, "dynamic call".to_string());
+                self.visit(callable);
+                self.visit_all(args);
+            }
+            AstExpr::Def(ident, params, program) => {
+                self.defined.insert(ident.name.to_string());
+                self.define_params(params);
+                self.visit_all(program);
+            }
+            AstExpr::Macro(ident, params, body) => {
+                self.defined.insert(ident.name.to_string());
+                self.define_params(params);
+                self.visit(body);
+            }
+            AstExpr::Fn(params, program) => {
+                self.define_params(params);
+                self.visit_all(program);
+            }
+            AstExpr::Let(pattern, value) | AstExpr::Var(pattern, value) => {
+                self.define_pattern(pattern);
+                self.visit(value);
+            }
+            AstExpr::Foreach(ident, values, program) => {
+                self.defined.insert(ident.name.to_string());
+                self.visit(values);
+                self.visit_all(program);
+            }
+            AstExpr::As(ident, value) | AstExpr::Assign(ident, value) => {
+                self.defined.insert(ident.name.to_string());
+                self.visit(value);
+            }
+            AstExpr::Block(program) | AstExpr::Loop(program) => self.visit_all(program),
+            AstExpr::Module(ident, program) => {
+                self.defined.insert(ident.name.to_string());
+                self.visit_all(program);
+            }
+            AstExpr::While(condition, program) => {
+                self.visit(condition);
+                self.visit_all(program);
+            }
+            AstExpr::And(nodes) | AstExpr::Or(nodes) => self.visit_all(nodes),
+            AstExpr::If(branches) => branches.iter().for_each(|(condition, body)| {
+                if let Some(condition) = condition {
+                    self.visit(condition);
+                }
+
+                self.visit(body);
+            }),
+            AstExpr::Match(value, arms) => {
+                self.visit(value);
+
+                for arm in arms {
+                    self.define_pattern(&arm.pattern);
+
+                    if let Some(guard) = &arm.guard {
+                        self.visit(guard);
+                    }
+
+                    self.visit(&arm.body);
+                }
+            }
+            // mq-lang does not export the segment type, so the expressions inside cannot be visited.
+            AstExpr::InterpolatedString(_) => push_unique(&mut self.opaque, "string interpolation".to_string()),
+            AstExpr::QualifiedAccess(path, _) => {
+                let name = path.iter().map(|ident| ident.name.to_string()).collect::<Vec<_>>();
+                push_unique(&mut self.opaque, name.join("::"));
+            }
+            AstExpr::Include(_) => push_unique(&mut self.opaque, "include".to_string()),
+            AstExpr::Import(_) => push_unique(&mut self.opaque, "import".to_string()),
+            AstExpr::Paren(node) | AstExpr::Quote(node) | AstExpr::Unquote(node) | AstExpr::Break(Some(node)) => {
+                self.visit(node)
+            }
+            AstExpr::Try(body, fallback) => {
+                self.visit(body);
+                self.visit(fallback);
+            }
+            AstExpr::Literal(_) | AstExpr::Self_ | AstExpr::Nodes | AstExpr::Break(None) | AstExpr::Continue => {}
+        }
+    }
+
+    fn visit_all<'a>(&mut self, nodes: impl IntoIterator<Item = &'a Shared<AstNode>>) {
+        nodes.into_iter().for_each(|node| self.visit(node));
+    }
+
+    fn define_params(&mut self, params: &mq_lang::AstParams) {
+        for param in params {
+            self.defined.insert(param.ident.name.to_string());
+
+            if let Some(default) = &param.default {
+                self.visit(default);
+            }
+        }
+    }
+
+    fn define_pattern(&mut self, pattern: &AstPattern) {
+        match pattern {
+            AstPattern::Ident(ident) => {
+                self.defined.insert(ident.name.to_string());
+            }
+            AstPattern::Array(patterns) | AstPattern::Or(patterns) => {
+                patterns.iter().for_each(|pattern| self.define_pattern(pattern))
+            }
+            AstPattern::ArrayRest(patterns, rest) => {
+                patterns.iter().for_each(|pattern| self.define_pattern(pattern));
+                self.defined.insert(rest.name.to_string());
+            }
+            AstPattern::Dict(entries) => entries.iter().for_each(|(_, pattern)| self.define_pattern(pattern)),
+            AstPattern::Literal(_) | AstPattern::Wildcard | AstPattern::Type(_) => {}
+        }
+    }
+}
+
+fn push_unique(values: &mut Vec<String>, value: String) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+/// Returns the names of the functions, macros, variables and modules `code` defines at the top
+/// level, in order, or none if it cannot be parsed.
+pub(crate) fn defined_names(code: &str) -> Vec<String> {
+    let Ok(program) = mq_lang::parse(code, Rc::new(RefCell::new(mq_lang::Arena::new(256)))) else {
+        return Vec::new();
+    };
+    let mut names = Vec::new();
+
+    for node in &program {
+        match &*node.expr {
+            AstExpr::Def(ident, ..) | AstExpr::Macro(ident, ..) | AstExpr::Module(ident, _) => {
+                push_unique(&mut names, ident.name.to_string())
+            }
+            AstExpr::Let(pattern, _) | AstExpr::Var(pattern, _) => {
+                let mut analyzer = Analyzer::default();
+                analyzer.define_pattern(pattern);
+                let mut defined = analyzer.defined.into_iter().collect::<Vec<_>>();
+                defined.sort();
+                defined.into_iter().for_each(|name| push_unique(&mut names, name));
+            }
+            _ => {}
+        }
+    }
+
+    names
+}
+
+/// Returns the names `code` calls or uses as values, whether or not it defines them itself, and the
+/// constructs whose effect cannot be known without running it.
+pub(crate) fn used_names(code: &str) -> PyResult<(Vec<String>, Vec<String>)> {
+    let program = mq_lang::parse(code, Rc::new(RefCell::new(mq_lang::Arena::new(256))))
+        .map_err(|e| crate::error::query_error("Error parsing query", &e))?;
+    let mut analyzer = Analyzer::default();
+    analyzer.visit_all(&program);
+
+    let mut names = analyzer.calls;
+    analyzer
+        .references
+        .into_iter()
+        .for_each(|name| push_unique(&mut names, name));
+    Ok((names, analyzer.opaque))
+}
+
+/// Returns the calls in `code` to functions that are neither builtins nor defined by the query, the
+/// first call of each name, or none if the query cannot be parsed.
+///
+/// Queries that include or import modules are not checked, as the names those define are only
+/// known once they are loaded.
+fn unknown_calls(code: &str) -> Vec<mq_lang::IdentWithToken> {
+    let Ok(program) = mq_lang::parse(code, Rc::new(RefCell::new(mq_lang::Arena::new(256)))) else {
+        return Vec::new();
+    };
+    let mut analyzer = Analyzer::default();
+    analyzer.visit_all(&program);
+
+    if analyzer.opaque.iter().any(|kind| matches!(kind.as_str(), "include" | "import")) {
+        return Vec::new();
+    }
+
+    let builtins = crate::builtins::function_names();
+    let mut seen = HashSet::new();
+
+    analyzer
+        .call_sites
+        .into_iter()
+        .filter(|ident| {
+            let name = ident.name.to_string();
+            !builtins.contains(&name) && !analyzer.defined.contains(&name) && seen.insert(name)
+        })
+        .collect()
+}
+
+/// Raises `ParseError` for the first call in `code` to a function that is neither a builtin nor
+/// defined by the query.
+pub(crate) fn check_calls(code: &str) -> PyResult<()> {
+    match unknown_calls(code).first() {
+        Some(ident) => Err(crate::error::parse_error_at(
+            format!("Error compiling query: Unknown function `{}`", ident.name),
+            code,
+            ident.token.as_deref(),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns where `token` is in `code`, with the byte offsets of its start and end.
+fn token_span(code: &str, token: &mq_lang::Token) -> Position {
+    let point = |position: mq_lang::Position| mq_markdown::Point {
+        line: position.line as usize,
+        column: position.column,
+    };
+    // Lines and columns count from 1, columns in characters.
+    let offset = |position: mq_lang::Position| {
+        let line_start = code
+            .split_inclusive('\n')
+            .take((position.line as usize).saturating_sub(1))
+            .map(str::len)
+            .sum::<usize>();
+        let column = code[line_start..]
+            .char_indices()
+            .nth(position.column.saturating_sub(1))
+            .map_or(code.len() - line_start, |(i, _)| i);
+        line_start + column
+    };
+
+    Position::new(
+        mq_markdown::Position {
+            start: point(token.range.start),
+            end: point(token.range.end),
+        },
+        Some((offset(token.range.start), offset(token.range.end))),
+    )
+}
+
+/// Compiles `code` without running it and returns the problems found, none if the query is valid.
+///
+/// mq-lang stops at the first error, so at most one diagnostic is returned for a query that does not
+/// compile. One that does gets a diagnostic for each function it calls that is neither a builtin nor
+/// defined by the query.
+#[pyfunction]
+pub(crate) fn check_syntax(code: &str) -> Vec<Diagnostic> {
+    let Err(e) = crate::new_engine(&crate::Options::default()).compile(code) else {
+        return unknown_calls(code)
+            .into_iter()
+            .map(|ident| Diagnostic {
+                message: format!("Unknown function `{}`", ident.name),
+                span: ident.token.map(|token| token_span(code, &token)),
+                severity: "error",
+            })
+            .collect();
+    };
+    let span = e.cause.token().map(|token| {
+        let point = |position: mq_lang::Position| mq_markdown::Point {
+            line: position.line as usize,
+            column: position.column,
+        };
+        // Errors at the end of the query point just past it.
+        let offsets = (
+            e.location.offset().min(code.len()),
+            (e.location.offset() + e.location.len()).min(code.len()),
+        );
+
+        Position::new(
+            mq_markdown::Position {
+                start: point(token.range.start),
+                end: point(token.range.end),
+            },
+            Some(offsets),
+        )
+    });
+    let span = span.or_else(|| {
+        crate::error::is_end_of_input(&e).then(|| {
+            let (line, column) = crate::error::end_of(code);
+            let end = mq_markdown::Point { line, column };
+            let offset = code.trim_end().len();
+            Position::new(
+                mq_markdown::Position {
+                    start: end.clone(),
+                    end,
+                },
+                Some((offset, offset)),
+            )
+        })
+    });
+
+    vec![Diagnostic {
+        message: e.to_string(),
+        span,
+        severity: "error",
+    }]
+}
+
+/// Parses `code` and reports the selectors and functions it uses, without running it.
+///
+/// A query is read-only when it calls none of the builtins that build or change nodes. Imports,
+/// includes, module functions, string interpolation and calls through values cannot be checked,
+/// so queries using them are never read-only.
+#[pyfunction]
+pub(crate) fn analyze(code: &str) -> PyResult<QueryAnalysis> {
+    let program = mq_lang::parse(code, Rc::new(RefCell::new(mq_lang::Arena::new(256))))
+        .map_err(|e| crate::error::query_error("Error parsing query", &e))?;
+    let mut analyzer = Analyzer::default();
+    analyzer.visit_all(&program);
+
+    let functions = analyzer
+        .calls
+        .iter()
+        .filter(|name| !analyzer.defined.contains(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut mutations = functions
+        .iter()
+        .filter(|name| MUTATING_FUNCTIONS.contains(&name.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    mutations.extend(analyzer.opaque);
+
+    Ok(QueryAnalysis {
+        read_only: mutations.is_empty(),
+        selectors: analyzer.selectors,
+        functions,
+        mutations,
+    })
+}
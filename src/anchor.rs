@@ -0,0 +1,615 @@
+//! Heading anchors as generated by GitHub, GitLab or a custom function, for checking and rewriting
+//! `#fragment` links.
+use crate::{Options, SlugStyle, content::Content, input, lint::LintFinding, render, section, transform};
+use indexmap::IndexMap;
+use mq_markdown::Node;
+use pyo3::{
+    IntoPyObjectExt,
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// A heading that can be linked to with `#slug`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct Anchor {
+    #[pyo3(get)]
+    slug: String,
+    #[pyo3(get)]
+    text: String,
+    #[pyo3(get)]
+    depth: u8,
+    #[pyo3(get)]
+    line: usize,
+    #[pyo3(get)]
+    column: usize,
+}
+
+#[pymethods]
+impl Anchor {
+    fn __repr__(&self) -> String {
+        format!(
+            "Anchor(slug={:?}, text={:?}, depth={}, line={}, column={})",
+            self.slug, self.text, self.depth, self.line, self.column
+        )
+    }
+}
+
+/// A Python function from heading text to slug, for `SlugStyle.CUSTOM`.
+#[derive(Clone)]
+pub(crate) struct SlugFunction(Arc<Py<PyAny>>);
+
+impl fmt::Debug for SlugFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SlugFunction")
+    }
+}
+
+impl PartialEq for SlugFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<'py> IntoPyObject<'py> for SlugFunction {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = std::convert::Infallible;
+
+    fn into_pyobject(self, py: Python<'py>) -> Result<Self::Output, Self::Error> {
+        Ok(self.0.clone_ref(py).into_bound(py))
+    }
+}
+
+impl<'a, 'py> FromPyObject<'a, 'py> for SlugFunction {
+    type Error = PyErr;
+
+    fn extract(obj: Borrowed<'a, 'py, PyAny>) -> PyResult<Self> {
+        if !obj.is_callable() {
+            return Err(PyTypeError::new_err(format!(
+                "slug function must be callable, not {}",
+                obj.get_type().name()?
+            )));
+        }
+
+        Ok(Self(Arc::new(obj.to_owned().unbind())))
+    }
+}
+
+/// Generates unique slugs in a `SlugStyle`, numbering repeated slugs as `slug-1`, `slug-2`...
+#[derive(Debug, Default)]
+pub(crate) struct Slugger {
+    style: SlugStyle,
+    function: Option<SlugFunction>,
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    /// Returns a slugger for the `slug_style` and `slug_function` of `options`.
+    pub(crate) fn new(options: &Options) -> PyResult<Self> {
+        let style = options.slug_style.unwrap_or_default();
+        base_slug(style, options.slug_function.as_ref(), "")?;
+
+        Ok(Self {
+            style,
+            function: options.slug_function.clone(),
+            occurrences: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn slug(&mut self, text: &str) -> PyResult<String> {
+        let base = base_slug(self.style, self.function.as_ref(), text)?;
+        let mut slug = base.clone();
+
+        while self.occurrences.contains_key(&slug) {
+            let count = self.occurrences.entry(base.clone()).or_default();
+            *count += 1;
+            slug = format!("{}-{}", base, count);
+        }
+
+        self.occurrences.insert(slug.clone(), 0);
+        Ok(slug)
+    }
+}
+
+/// Returns the slug of `text` in `style`, before repeated slugs are numbered.
+fn base_slug(style: SlugStyle, function: Option<&SlugFunction>, text: &str) -> PyResult<String> {
+    match (style, function) {
+        (SlugStyle::Github, _) => Ok(github_slug(text)),
+        (SlugStyle::Gitlab, _) => Ok(gitlab_slug(text)),
+        (SlugStyle::Custom, Some(function)) => Python::attach(|py| {
+            function
+                .0
+                .call1(py, (text,))?
+                .extract::<String>(py)
+                .map_err(|_| PyTypeError::new_err("slug function must return a str"))
+        }),
+        (SlugStyle::Custom, None) => Err(PyValueError::new_err("SlugStyle.CUSTOM requires a slug function")),
+    }
+}
+
+/// Lowercases `text`, drops punctuation and turns each space into a hyphen.
+fn github_slug(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Like `github_slug` for the trimmed text, with runs of hyphens collapsed into one, and prefixed
+/// with `anchor-` when only digits are left.
+fn gitlab_slug(text: &str) -> String {
+    let slug = github_slug(text.trim()).chars().fold(String::new(), |mut slug, c| {
+        if c != '-' || !slug.ends_with('-') {
+            slug.push(c);
+        }
+
+        slug
+    });
+
+    if !slug.is_empty() && slug.chars().all(|c| c.is_ascii_digit()) {
+        format!("anchor-{}", slug)
+    } else {
+        slug
+    }
+}
+
+/// Returns the anchor slug of `text` in `style`, as a heading with that text would get as the
+/// first heading of a document.
+///
+/// With `SlugStyle.CUSTOM`, `function` is called with the text and returns the slug.
+#[pyfunction]
+#[pyo3(signature = (text, style=SlugStyle::Github, function=None))]
+pub(crate) fn slugify(text: &str, style: SlugStyle, function: Option<SlugFunction>) -> PyResult<String> {
+    base_slug(style, function.as_ref(), text)
+}
+
+/// Returns the anchor of every heading of a document, in document order.
+pub(crate) fn headings(content: &str, options: &Options) -> PyResult<Vec<Anchor>> {
+    let mut slugger = Slugger::new(options)?;
+
+    input::parse(content, options)?
+        .into_iter()
+        .filter_map(|value| match value {
+            mq_lang::RuntimeValue::Markdown(node, _) => match node.as_ref() {
+                Node::Heading(heading) => {
+                    let text = section::plain_text(&node);
+                    Some(slugger.slug(&text).map(|slug| Anchor {
+                        slug,
+                        text,
+                        depth: heading.depth,
+                        line: heading.position.as_ref().map_or(0, |p| p.start.line),
+                        column: heading.position.as_ref().map_or(0, |p| p.start.column),
+                    }))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the slug of every heading of `input` with a position, keyed by the heading's position.
+pub(crate) fn heading_slugs(
+    input: &[mq_lang::RuntimeValue],
+    options: &Options,
+) -> PyResult<HashMap<(usize, usize), String>> {
+    let mut slugger = Slugger::new(options)?;
+    let mut slugs = HashMap::new();
+
+    for value in input {
+        if let mq_lang::RuntimeValue::Markdown(node, _) = value
+            && let Node::Heading(heading) = node.as_ref()
+        {
+            let slug = slugger.slug(&section::plain_text(node))?;
+
+            if let Some(position) = &heading.position {
+                slugs.insert((position.start.line, position.start.column), slug);
+            }
+        }
+    }
+
+    Ok(slugs)
+}
+
+/// Whether `heading` starts with an anchor inserted by `insert_anchors`.
+fn has_anchor(heading: &mq_markdown::Heading) -> bool {
+    matches!(heading.values.first(), Some(Node::Html(html)) if html.value.starts_with("<a id="))
+}
+
+/// Inserts an `<a id="slug"></a>` anchor at the start of every heading when `options.heading_anchors`
+/// is set, for renderers that do not generate heading ids.
+pub(crate) fn insert_anchors(nodes: Vec<Node>, options: &Options) -> PyResult<Vec<Node>> {
+    if !options.heading_anchors {
+        return Ok(nodes);
+    }
+
+    let mut slugger = Slugger::new(options)?;
+    let mut error = None;
+    let nodes = transform::rewrite(nodes, &mut |node| match node {
+        Node::Heading(mut heading) if error.is_none() && !has_anchor(&heading) => {
+            match slugger.slug(&section::plain_text(&Node::Heading(heading.clone()))) {
+                Ok(slug) => heading.values.insert(
+                    0,
+                    Node::Html(mq_markdown::Html {
+                        value: format!("<a id=\"{}\"></a>", slug),
+                        position: None,
+                    }),
+                ),
+                Err(e) => error = Some(e),
+            }
+
+            vec![Node::Heading(heading)]
+        }
+        node => vec![node],
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(nodes),
+    }
+}
+
+/// Returns the anchors of a document's headings by slug, in document order.
+///
+/// Slugs follow `options.slug_style`, GitHub's by default: lowercased, without punctuation and with
+/// spaces as hyphens. Repeated headings get `-1`, `-2`... suffixes.
+#[pyfunction]
+#[pyo3(signature = (content, options=None))]
+pub(crate) fn anchors(content: Content<'_>, options: Option<Options>) -> PyResult<IndexMap<String, Anchor>> {
+    Ok(headings(content.as_str()?, &options.unwrap_or_default())?
+        .into_iter()
+        .map(|anchor| (anchor.slug.clone(), anchor))
+        .collect())
+}
+
+fn collect_headings(node: &Node, texts: &mut Vec<String>) {
+    match node {
+        Node::Heading(_) => texts.push(section::plain_text(node)),
+        node => node.children().iter().for_each(|child| collect_headings(child, texts)),
+    }
+}
+
+/// Renames headings and updates the `#fragment` links that point to them, given a mapping of old
+/// heading text to new heading text.
+///
+/// Headings that were already renamed are recognized by their new text, so links still pointing
+/// to the old anchors are updated too.
+#[pyfunction]
+#[pyo3(signature = (content, renames, options=None))]
+pub(crate) fn rename_headings(
+    content: Content<'_>,
+    renames: IndexMap<String, String>,
+    options: Option<Options>,
+) -> PyResult<String> {
+    let nodes = transform::parse(&content)?;
+    let mut texts = Vec::new();
+    nodes.iter().for_each(|node| collect_headings(node, &mut texts));
+
+    let originals = renames
+        .iter()
+        .map(|(old, new)| (new.as_str(), old.as_str()))
+        .collect::<HashMap<_, _>>();
+    let slug_options = options.clone().unwrap_or_default();
+    let mut old_slugs = Slugger::new(&slug_options)?;
+    let mut new_slugs = Slugger::new(&slug_options)?;
+    let mut fragments = HashMap::new();
+
+    for text in &texts {
+        let old = old_slugs.slug(originals.get(text.as_str()).copied().unwrap_or(text))?;
+        let new = new_slugs.slug(renames.get(text).unwrap_or(text))?;
+
+        if old != new {
+            fragments.insert(old, new);
+        }
+    }
+
+    let fragment = |url: &str| {
+        url.strip_prefix('#')
+            .and_then(|slug| fragments.get(slug))
+            .map(|slug| mq_markdown::Url::new(format!("#{}", slug)))
+    };
+
+    let nodes = transform::rewrite(nodes, &mut |node| match node {
+        Node::Heading(mut heading) => {
+            if let Some(new) = renames.get(&section::plain_text(&Node::Heading(heading.clone()))) {
+                heading.values = vec![Node::Text(mq_markdown::Text {
+                    value: new.clone(),
+                    position: None,
+                })];
+            }
+
+            vec![Node::Heading(heading)]
+        }
+        Node::Link(mut link) => {
+            if let Some(url) = fragment(link.url.as_str()) {
+                link.url = url;
+            }
+
+            vec![Node::Link(link)]
+        }
+        Node::Definition(mut definition) => {
+            if let Some(url) = fragment(definition.url.as_str()) {
+                definition.url = url;
+            }
+
+            vec![Node::Definition(definition)]
+        }
+        node => vec![node],
+    });
+
+    render::to_markdown(nodes, options)
+}
+
+/// A heading of a table of contents built by `toc`, with the headings nested under it.
+struct TocNode {
+    anchor: Anchor,
+    children: Vec<TocNode>,
+}
+
+impl TocNode {
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("title", &self.anchor.text)?;
+        dict.set_item("level", self.anchor.depth)?;
+        dict.set_item("slug", &self.anchor.slug)?;
+        dict.set_item("line", self.anchor.line)?;
+        dict.set_item("column", self.anchor.column)?;
+        dict.set_item(
+            "children",
+            self.children
+                .iter()
+                .map(|child| child.to_dict(py))
+                .collect::<PyResult<Vec<_>>>()?,
+        )?;
+        Ok(dict)
+    }
+
+    fn write_markdown(&self, indent: usize, out: &mut String) {
+        let title = self
+            .anchor
+            .text
+            .chars()
+            .flat_map(|c| match c {
+                '[' | ']' | '\\' => vec!['\\', c],
+                c => vec![c],
+            })
+            .collect::<String>();
+        out.push_str(&format!(
+            "{}- [{}](#{})\n",
+            "  ".repeat(indent),
+            title,
+            self.anchor.slug
+        ));
+        self.children
+            .iter()
+            .for_each(|child| child.write_markdown(indent + 1, out));
+    }
+}
+
+/// Nests each heading under the closest heading before it with a smaller depth.
+fn nest(anchors: Vec<Anchor>) -> Vec<TocNode> {
+    let mut roots = Vec::new();
+    // The open headings, each deeper than the one before it.
+    let mut stack: Vec<TocNode> = Vec::new();
+
+    for anchor in anchors {
+        while stack.last().is_some_and(|node| node.anchor.depth >= anchor.depth) {
+            close(&mut stack, &mut roots);
+        }
+
+        stack.push(TocNode {
+            anchor,
+            children: Vec::new(),
+        });
+    }
+
+    while !stack.is_empty() {
+        close(&mut stack, &mut roots);
+    }
+
+    roots
+}
+
+fn close(stack: &mut Vec<TocNode>, roots: &mut Vec<TocNode>) {
+    if let Some(node) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => roots.push(node),
+        }
+    }
+}
+
+/// Builds a table of contents of the headings up to `max_level`, linking to their anchors in
+/// `options.slug_style`.
+///
+/// With `format="markdown"` it is returned as a nested markdown list, which `verify_toc` accepts;
+/// with `format="list"` as a list of dicts with the `title`, `level`, `slug`, `line`, `column` and
+/// nested `children` of each heading.
+#[pyfunction]
+#[pyo3(signature = (content, max_level=3, format="markdown", options=None))]
+pub(crate) fn toc<'py>(
+    py: Python<'py>,
+    content: Content<'_>,
+    max_level: u8,
+    format: &str,
+    options: Option<Options>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let anchors = headings(content.as_str()?, &options.unwrap_or_default())?
+        .into_iter()
+        .filter(|anchor| anchor.depth <= max_level)
+        .collect();
+    let nodes = nest(anchors);
+
+    match format {
+        "markdown" => {
+            let mut out = String::new();
+            nodes.iter().for_each(|node| node.write_markdown(0, &mut out));
+            out.into_bound_py_any(py)
+        }
+        "list" => nodes
+            .iter()
+            .map(|node| node.to_dict(py))
+            .collect::<PyResult<Vec<_>>>()?
+            .into_bound_py_any(py),
+        _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "Invalid toc format: {:?}, expected \"markdown\" or \"list\"",
+            format
+        ))),
+    }
+}
+
+/// An entry of a table of contents: a list item linking to `#slug`.
+struct TocEntry {
+    slug: String,
+    text: String,
+    level: usize,
+    line: usize,
+    column: usize,
+}
+
+fn first_link(node: &Node) -> Option<Node> {
+    match node {
+        Node::Link(_) => Some(node.clone()),
+        node => node.children().iter().find_map(first_link),
+    }
+}
+
+/// Checks that the table of contents after `marker` lists the headings that follow it, in order
+/// and at their levels, and returns the discrepancies in document order.
+///
+/// The table of contents is the list right after the marker; its links must point to the anchors of
+/// the headings in `options.slug_style`.
+#[pyfunction]
+#[pyo3(signature = (content, marker="<!-- toc -->", options=None))]
+pub(crate) fn verify_toc(content: Content<'_>, marker: &str, options: Option<Options>) -> PyResult<Vec<LintFinding>> {
+    let options = options.unwrap_or_default();
+    let content = content.as_str()?;
+    let nodes = input::parse(content, &options)?
+        .into_iter()
+        .filter_map(|value| match value {
+            mq_lang::RuntimeValue::Markdown(node, _) => Some(*node),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let Some(start) = nodes
+        .iter()
+        .position(|node| matches!(node, Node::Html(html) if html.value.trim() == marker.trim()))
+    else {
+        return Ok(vec![LintFinding::new(
+            "missing-toc",
+            format!("No table of contents marker {:?}", marker),
+            1,
+            1,
+        )]);
+    };
+
+    let entries = nodes[start + 1..]
+        .iter()
+        .map_while(|node| match node {
+            Node::List(list) => Some(list),
+            _ => None,
+        })
+        .filter_map(|list| match first_link(&Node::List(list.clone()))? {
+            Node::Link(link) => Some(TocEntry {
+                slug: link.url.as_str().strip_prefix('#')?.to_string(),
+                text: section::plain_text(&Node::Link(link.clone())),
+                level: list.level as usize,
+                line: link.position.as_ref().map_or(0, |p| p.start.line),
+                column: link.position.as_ref().map_or(0, |p| p.start.column),
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let toc_end = nodes[start + 1..]
+        .iter()
+        .take_while(|node| matches!(node, Node::List(_)))
+        .chain(std::iter::once(&nodes[start]))
+        .filter_map(|node| node.position().map(|p| p.end.line))
+        .max()
+        .unwrap_or(0);
+    let headings = headings(content, &options)?
+        .into_iter()
+        .filter(|heading| heading.line > toc_end)
+        .collect::<Vec<_>>();
+    let min_depth = headings.iter().map(|heading| heading.depth).min().unwrap_or(1);
+
+    let mut findings = Vec::new();
+
+    for heading in &headings {
+        if !entries.iter().any(|entry| entry.slug == heading.slug) {
+            findings.push(LintFinding::new(
+                "missing-entry",
+                format!("Heading \"{}\" is missing from the table of contents", heading.text),
+                heading.line,
+                heading.column,
+            ));
+        }
+    }
+
+    let mut last = None;
+
+    for entry in &entries {
+        let Some((index, heading)) = headings
+            .iter()
+            .enumerate()
+            .find(|(_, heading)| heading.slug == entry.slug)
+        else {
+            findings.push(LintFinding::new(
+                "stale-entry",
+                format!(
+                    "Entry \"{}\" links to #{}, which is not a heading",
+                    entry.text, entry.slug
+                ),
+                entry.line,
+                entry.column,
+            ));
+            continue;
+        };
+
+        if entry.text != heading.text {
+            findings.push(LintFinding::new(
+                "text-mismatch",
+                format!("Entry \"{}\" does not match heading \"{}\"", entry.text, heading.text),
+                entry.line,
+                entry.column,
+            ));
+        }
+
+        let level = (heading.depth - min_depth) as usize;
+
+        if entry.level != level {
+            findings.push(LintFinding::new(
+                "level-mismatch",
+                format!(
+                    "Entry \"{}\" is at level {}, but its heading is at level {}",
+                    entry.text, entry.level, level
+                ),
+                entry.line,
+                entry.column,
+            ));
+        }
+
+        if last.is_some_and(|last| index < last) {
+            findings.push(LintFinding::new(
+                "wrong-order",
+                format!("Entry \"{}\" is out of document order", entry.text),
+                entry.line,
+                entry.column,
+            ));
+        }
+
+        last = last.max(Some(index));
+    }
+
+    findings.sort_by_key(|finding| (finding.line, finding.column));
+    Ok(findings)
+}
@@ -0,0 +1,326 @@
+//! Incremental construction of markdown documents.
+//!
+//! Plain strings passed to the builder are treated as literal text and escaped, while
+//! `mq.nodes` instances are inserted unchanged.
+use crate::{Options, anchor, nodes, render, value::MQValue};
+use mq_markdown::{Node, TableAlignKind};
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyList, PyString, PyTuple},
+};
+
+/// A top-level block of the document.
+#[derive(Debug, Clone)]
+enum Block {
+    /// Inline nodes rendered side by side, e.g. a paragraph.
+    Inline(Vec<Node>),
+    /// Block-level nodes rendered line by line, e.g. list items or table cells.
+    Nodes(Vec<Node>),
+}
+
+#[pyclass(skip_from_py_object)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DocumentBuilder {
+    blocks: Vec<Block>,
+}
+
+#[pymethods]
+impl DocumentBuilder {
+    #[new]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn heading<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        depth: u8,
+        text: &Bound<'py, PyAny>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        if !(1..=6).contains(&depth) {
+            return Err(PyValueError::new_err(format!(
+                "Heading depth must be between 1 and 6, got {}",
+                depth
+            )));
+        }
+
+        let values = extract_inline(text, false)?;
+        slf.blocks.push(Block::Nodes(vec![Node::Heading(mq_markdown::Heading {
+            depth,
+            values,
+            position: None,
+        })]));
+        Ok(slf)
+    }
+
+    pub fn paragraph<'py>(mut slf: PyRefMut<'py, Self>, text: &Bound<'py, PyAny>) -> PyResult<PyRefMut<'py, Self>> {
+        let values = extract_inline(text, false)?;
+        slf.blocks.push(Block::Inline(values));
+        Ok(slf)
+    }
+
+    /// Appends a list. A nested Python list inside `items` becomes a sub-list of the preceding item.
+    #[pyo3(signature = (items, ordered=false))]
+    pub fn list<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        items: &Bound<'py, PyAny>,
+        ordered: bool,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let mut nodes = Vec::new();
+        push_list_items(items, ordered, 0, &mut nodes)?;
+        slf.blocks.push(Block::Nodes(nodes));
+        Ok(slf)
+    }
+
+    /// Appends a table. When `header` is omitted the first row is used as the header.
+    #[pyo3(signature = (rows, header=None, align=None))]
+    pub fn table<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        rows: Vec<Bound<'py, PyAny>>,
+        header: Option<Bound<'py, PyAny>>,
+        align: Option<Vec<Option<String>>>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let mut rows = rows
+            .iter()
+            .map(|row| row.try_iter()?.collect::<PyResult<Vec<_>>>())
+            .collect::<PyResult<Vec<_>>>()?;
+
+        let header = match header {
+            Some(header) => header.try_iter()?.collect::<PyResult<Vec<_>>>()?,
+            None if rows.is_empty() => {
+                return Err(PyValueError::new_err("table requires a header or at least one row"));
+            }
+            None => rows.remove(0),
+        };
+
+        let columns = header.len();
+        let align = align.unwrap_or_default();
+
+        if align.len() > columns {
+            return Err(PyValueError::new_err(format!(
+                "table has {} columns but {} alignments were given",
+                columns,
+                align.len()
+            )));
+        }
+
+        let mut nodes = Vec::with_capacity((rows.len() + 1) * columns + 1);
+
+        for (row, cells) in std::iter::once(&header).chain(rows.iter()).enumerate() {
+            if cells.len() > columns {
+                return Err(PyValueError::new_err(format!(
+                    "table row {} has {} cells but the header has {} columns",
+                    row,
+                    cells.len(),
+                    columns
+                )));
+            }
+
+            for column in 0..columns {
+                let values = match cells.get(column) {
+                    Some(cell) => extract_inline(cell, true)?,
+                    None => Vec::new(),
+                };
+
+                nodes.push(Node::TableCell(mq_markdown::TableCell {
+                    values,
+                    column,
+                    row,
+                    position: None,
+                }));
+            }
+
+            if row == 0 {
+                nodes.push(Node::TableAlign(mq_markdown::TableAlign {
+                    align: (0..columns)
+                        .map(|column| match align.get(column).cloned().flatten() {
+                            Some(kind) => parse_align(&kind),
+                            None => Ok(TableAlignKind::None),
+                        })
+                        .collect::<PyResult<Vec<_>>>()?,
+                    position: None,
+                }));
+            }
+        }
+
+        slf.blocks.push(Block::Nodes(nodes));
+        Ok(slf)
+    }
+
+    #[pyo3(signature = (value, lang=None, meta=None))]
+    pub fn code(
+        mut slf: PyRefMut<'_, Self>,
+        value: String,
+        lang: Option<String>,
+        meta: Option<String>,
+    ) -> PyRefMut<'_, Self> {
+        slf.blocks.push(Block::Nodes(vec![Node::Code(mq_markdown::Code {
+            value: value.trim_end_matches('\n').to_string(),
+            lang,
+            meta,
+            fence: true,
+            position: None,
+        })]));
+        slf
+    }
+
+    pub fn blockquote<'py>(mut slf: PyRefMut<'py, Self>, text: &Bound<'py, PyAny>) -> PyResult<PyRefMut<'py, Self>> {
+        let values = extract_inline(text, false)?;
+        slf.blocks
+            .push(Block::Nodes(vec![Node::Blockquote(mq_markdown::Blockquote {
+                values,
+                position: None,
+            })]));
+        Ok(slf)
+    }
+
+    pub fn horizontal_rule(mut slf: PyRefMut<'_, Self>) -> PyRefMut<'_, Self> {
+        slf.blocks
+            .push(Block::Nodes(vec![Node::HorizontalRule(mq_markdown::HorizontalRule {
+                position: None,
+            })]));
+        slf
+    }
+
+    /// Appends an `mq.nodes.Node` or the nodes of an `mq.MQValue` as its own block.
+    pub fn node<'py>(mut slf: PyRefMut<'py, Self>, node: &Bound<'py, PyAny>) -> PyResult<PyRefMut<'py, Self>> {
+        let nodes = if let Ok(node) = node.cast::<nodes::Node>() {
+            vec![node.borrow().node.clone()]
+        } else if let Ok(value) = node.cast::<MQValue>() {
+            value.get().markdown_nodes()?
+        } else {
+            return Err(PyTypeError::new_err(format!(
+                "expected mq.nodes.Node or mq.MQValue, not {}",
+                node.get_type().name()?
+            )));
+        };
+
+        slf.blocks.push(Block::Nodes(nodes));
+        Ok(slf)
+    }
+
+    #[pyo3(signature = (options=None))]
+    pub fn build(&self, options: Option<Options>) -> PyResult<String> {
+        let settings = options.unwrap_or_default();
+        let options = settings.render_options();
+        let mut lengths = Vec::with_capacity(self.blocks.len());
+        let mut nodes = Vec::new();
+
+        for block in &self.blocks {
+            let (Block::Inline(values) | Block::Nodes(values)) = block;
+            lengths.push(values.len());
+            nodes.extend(render::apply_all(values.clone())?);
+        }
+
+        // Anchors are inserted across the whole document so that repeated headings get distinct
+        // slugs. Each top-level node stays a single node, so the blocks can be split out again.
+        let mut nodes = anchor::insert_anchors(nodes, &settings)?.into_iter();
+        let mut document = self
+            .blocks
+            .iter()
+            .zip(lengths)
+            .map(|(block, len)| {
+                let values = render::apply_options(nodes.by_ref().take(len).collect(), &settings);
+
+                match block {
+                    Block::Inline(_) => values.iter().map(|node| node.to_string_with(&options)).collect(),
+                    Block::Nodes(_) => render::write_blocks(values, &settings).trim_end_matches('\n').to_string(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n\n");
+
+        if !document.is_empty() {
+            document.push('\n');
+        }
+
+        Ok(render::tidy(document, &settings))
+    }
+
+    pub fn __len__(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("DocumentBuilder({} blocks)", self.blocks.len())
+    }
+}
+
+fn push_list_items(items: &Bound<'_, PyAny>, ordered: bool, level: u8, nodes: &mut Vec<Node>) -> PyResult<()> {
+    let mut index = 0;
+
+    for item in items.try_iter()? {
+        let item = item?;
+
+        if item.is_instance_of::<PyList>() {
+            if index == 0 {
+                return Err(PyValueError::new_err("a nested list must follow a list item"));
+            }
+
+            push_list_items(&item, ordered, level + 1, nodes)?;
+            continue;
+        }
+
+        nodes.push(Node::List(mq_markdown::List {
+            values: extract_inline(&item, false)?,
+            index,
+            level,
+            ordered,
+            checked: None,
+            position: None,
+        }));
+        index += 1;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn extract_inline(obj: &Bound<'_, PyAny>, in_table: bool) -> PyResult<Vec<Node>> {
+    if let Ok(s) = obj.cast::<PyString>() {
+        // A table cell ends at the end of its line.
+        let text = if in_table { s.to_string().replace('\n', " ") } else { s.to_string() };
+
+        return Ok(vec![Node::Text(mq_markdown::Text {
+            value: render::escape_text(&text),
+            position: None,
+        })]);
+    }
+
+    if obj.is_instance_of::<nodes::Node>() {
+        return nodes::extract_children(obj);
+    }
+
+    if let Ok(value) = obj.cast::<MQValue>() {
+        return value.get().markdown_nodes();
+    }
+
+    if obj.is_instance_of::<PyList>() || obj.is_instance_of::<PyTuple>() {
+        return obj
+            .try_iter()?
+            .map(|item| extract_inline(&item?, in_table))
+            .collect::<PyResult<Vec<_>>>()
+            .map(|values| values.into_iter().flatten().collect());
+    }
+
+    if in_table {
+        return extract_inline(obj.str()?.as_any(), in_table);
+    }
+
+    Err(PyTypeError::new_err(format!(
+        "expected str, mq.nodes.Node, mq.MQValue or a list of them, not {}",
+        obj.get_type().name()?
+    )))
+}
+
+fn parse_align(kind: &str) -> PyResult<TableAlignKind> {
+    match kind {
+        "left" => Ok(TableAlignKind::Left),
+        "right" => Ok(TableAlignKind::Right),
+        "center" => Ok(TableAlignKind::Center),
+        "none" => Ok(TableAlignKind::None),
+        _ => Err(PyValueError::new_err(format!(
+            "invalid alignment {:?}, expected 'left', 'right', 'center' or 'none'",
+            kind
+        ))),
+    }
+}
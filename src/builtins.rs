@@ -0,0 +1,110 @@
+//! Metadata on the functions and selectors every query can use, e.g. for editor completion.
+use pyo3::prelude::*;
+use std::collections::HashSet;
+
+/// A builtin function, macro or selector, as listed by `builtins`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct Builtin {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    signature: String,
+    #[pyo3(get)]
+    doc: String,
+    /// `"function"` for functions implemented by the engine, `"module"` for functions and macros
+    /// defined by the builtin module and `"selector"` for selectors.
+    #[pyo3(get)]
+    category: &'static str,
+}
+
+#[pymethods]
+impl Builtin {
+    fn __repr__(&self) -> String {
+        format!("Builtin({:?}, {:?})", self.signature, self.category)
+    }
+}
+
+impl Builtin {
+    fn new(name: &str, params: &[&str], doc: &str, category: &'static str) -> Self {
+        Self {
+            name: name.to_string(),
+            signature: match params {
+                [] if category == "selector" => name.to_string(),
+                params => format!("{}({})", name, params.join(", ")),
+            },
+            doc: doc.to_string(),
+            category,
+        }
+    }
+}
+
+/// Returns the functions and macros the builtin module defines, documented by the `#` comment
+/// lines above them. Names starting with `_` are internal and left out.
+fn module_functions() -> Vec<Builtin> {
+    let mut doc = Vec::new();
+    let mut builtins = Vec::new();
+
+    for line in mq_lang::BUILTIN_MODULE_FILE.lines() {
+        if let Some(comment) = line.strip_prefix('#') {
+            doc.push(comment.trim());
+            continue;
+        }
+
+        // Every definition in the module has its parameters on the first line.
+        let signature = line
+            .strip_prefix("def ")
+            .or_else(|| line.strip_prefix("macro "))
+            .and_then(|rest| rest.find(')').map(|end| &rest[..=end]));
+
+        if let Some((name, params)) = signature.and_then(|signature| signature.split_once('('))
+            && !name.starts_with('_')
+        {
+            let params = params
+                .trim_end_matches(')')
+                .split(',')
+                .map(str::trim)
+                .filter(|param| !param.is_empty())
+                .collect::<Vec<_>>();
+            builtins.push(Builtin::new(name.trim(), &params, &doc.join("\n"), "module"));
+        }
+
+        doc.clear();
+    }
+
+    builtins
+}
+
+/// Returns the names of the engine's functions, internal ones included, and of the builtin module's.
+pub(crate) fn function_names() -> HashSet<String> {
+    mq_lang::BUILTIN_FUNCTION_DOC
+        .keys()
+        .chain(mq_lang::INTERNAL_FUNCTION_DOC.keys())
+        .map(|name| name.to_string())
+        .chain(module_functions().into_iter().map(|builtin| builtin.name))
+        .collect()
+}
+
+/// Returns every builtin function and selector, sorted by name within the engine's functions, the
+/// builtin module's functions and the selectors.
+///
+/// The documentation comes from mq-lang, so it matches the engine this module was built with.
+#[pyfunction]
+pub(crate) fn builtins() -> Vec<Builtin> {
+    let mut functions = mq_lang::BUILTIN_FUNCTION_DOC
+        .iter()
+        .filter(|(name, _)| !name.starts_with('_'))
+        .map(|(name, doc)| Builtin::new(name, doc.params, doc.description, "function"))
+        .collect::<Vec<_>>();
+    let mut module = module_functions();
+    let mut selectors = mq_lang::BUILTIN_SELECTOR_DOC
+        .iter()
+        .map(|(name, doc)| Builtin::new(name, doc.params, doc.description, "selector"))
+        .collect::<Vec<_>>();
+
+    for builtins in [&mut functions, &mut module, &mut selectors] {
+        builtins.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    functions.into_iter().chain(module).chain(selectors).collect()
+}
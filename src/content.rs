@@ -0,0 +1,210 @@
+//! Document content accepted from Python.
+//!
+//! `str`, `bytes`, `bytearray` and `memoryview`s spanning a whole one of them are read in place
+//! without copying.
+//! A `bytearray` is copied when the GIL is released while it is read, as it could be resized meanwhile.
+//! Other buffer-protocol objects (e.g. `mmap`, `multiprocessing.shared_memory` buffers) are copied
+//! once, since the stable ABI targeted by this crate does not expose the buffer protocol.
+//! Bytes in an encoding other than UTF-8 are decoded into a new string.
+use crate::Encoding;
+use pyo3::{
+    exceptions::{PyTypeError, PyUnicodeDecodeError, PyValueError},
+    prelude::*,
+    pybacked::PyBackedStr,
+    types::{PyByteArray, PyBytes, PyMemoryView, PyString},
+};
+use std::borrow::Cow;
+
+pub(crate) enum Content<'py> {
+    Str(PyBackedStr),
+    Bytes(Bound<'py, PyBytes>),
+    ByteArray(Bound<'py, PyByteArray>),
+}
+
+impl<'a, 'py> FromPyObject<'a, 'py> for Content<'py> {
+    type Error = PyErr;
+
+    fn extract(obj: Borrowed<'a, 'py, PyAny>) -> PyResult<Self> {
+        if let Ok(s) = obj.cast::<PyString>() {
+            return Ok(Content::Str(s.to_owned().try_into()?));
+        }
+
+        Self::from_buffer(&obj.to_owned())
+    }
+}
+
+impl<'py> Content<'py> {
+    fn from_buffer(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(bytes) = obj.cast::<PyBytes>() {
+            return Ok(Content::Bytes(bytes.clone()));
+        }
+
+        if let Ok(bytes) = obj.cast::<PyByteArray>() {
+            return Ok(Content::ByteArray(bytes.clone()));
+        }
+
+        let view = PyMemoryView::from(obj).map_err(|_| {
+            PyTypeError::new_err(format!(
+                "content must be str, bytes or an object supporting the buffer protocol, not {}",
+                obj.get_type().name().map(|n| n.to_string()).unwrap_or_default()
+            ))
+        })?;
+
+        // A view spanning a whole bytes/bytearray object can borrow the object itself.
+        let base = view.getattr("obj")?;
+        let nbytes: usize = view.getattr("nbytes")?.extract()?;
+        let contiguous: bool = view.getattr("c_contiguous")?.extract()?;
+
+        if contiguous
+            && (base.is_instance_of::<PyBytes>() || base.is_instance_of::<PyByteArray>())
+            && base.len()? == nbytes
+        {
+            return Self::from_buffer(&base);
+        }
+
+        // Any other buffer, e.g. an `mmap`, is copied: the stable ABI has no access to its memory.
+        Ok(Content::Bytes(view.call_method0("tobytes")?.cast_into()?))
+    }
+
+    pub(crate) fn as_str(&self) -> PyResult<&str> {
+        let (py, bytes) = match self {
+            Content::Str(s) => return Ok(s),
+            Content::Bytes(b) => (b.py(), b.as_bytes()),
+            // SAFETY: the GIL is held for as long as the returned borrow lives and no Python code
+            // runs while the document is parsed and evaluated, so the bytearray cannot be resized.
+            // Callers that release the GIL use `decode_detached`, which copies it.
+            Content::ByteArray(b) => (b.py(), unsafe { b.as_bytes() }),
+        };
+
+        std::str::from_utf8(bytes).map_err(|e| {
+            PyUnicodeDecodeError::new_utf8(py, bytes, e)
+                .map(|err| PyErr::from_value(err.into_any()))
+                .unwrap_or_else(|err| err)
+        })
+    }
+
+    /// Decodes the content as `encoding`, UTF-8 by default, following the `errors` policy of
+    /// `bytes.decode`: `"strict"` raises on invalid sequences and `"replace"` replaces them with
+    /// U+FFFD.
+    pub(crate) fn decode(&self, errors: &str, encoding: Option<Encoding>) -> PyResult<Cow<'_, str>> {
+        let (py, bytes, encoding) = match (self, encoding) {
+            (Content::Str(_), _) | (_, None | Some(Encoding::Utf8)) => return self.decode_utf8(errors),
+            (Content::Bytes(b), Some(encoding)) => (b.py(), b.as_bytes(), encoding),
+            // SAFETY: as in `as_str`.
+            (Content::ByteArray(b), Some(encoding)) => (b.py(), unsafe { b.as_bytes() }, encoding),
+        };
+
+        check_errors(errors)?;
+        decode_bytes(py, bytes, errors, encoding).map(Cow::Owned)
+    }
+
+    fn decode_utf8(&self, errors: &str) -> PyResult<Cow<'_, str>> {
+        match errors {
+            "strict" => self.as_str().map(Cow::Borrowed),
+            "replace" => Ok(match self {
+                Content::Str(s) => Cow::Borrowed(s),
+                Content::Bytes(b) => String::from_utf8_lossy(b.as_bytes()),
+                // SAFETY: as in `as_str`.
+                Content::ByteArray(b) => String::from_utf8_lossy(unsafe { b.as_bytes() }),
+            }),
+            _ => Err(invalid_errors(errors)),
+        }
+    }
+
+    /// Like `decode`, but copies a `bytearray`, which other threads could resize once the GIL is
+    /// released. `str` and `bytes` are immutable and still read in place.
+    pub(crate) fn decode_detached(&self, errors: &str, encoding: Option<Encoding>) -> PyResult<Cow<'_, str>> {
+        match self {
+            Content::ByteArray(_) => self.decode(errors, encoding).map(|s| Cow::Owned(s.into_owned())),
+            _ => self.decode(errors, encoding),
+        }
+    }
+}
+
+fn invalid_errors(errors: &str) -> PyErr {
+    PyValueError::new_err(format!("invalid errors {:?}, expected 'strict' or 'replace'", errors))
+}
+
+pub(crate) fn check_errors(errors: &str) -> PyResult<()> {
+    match errors {
+        "strict" | "replace" => Ok(()),
+        _ => Err(invalid_errors(errors)),
+    }
+}
+
+/// Decodes the bytes of a file. Without an `encoding`, a byte order mark selects UTF-8 or UTF-16
+/// and is dropped, and files without one are read as UTF-8. `errors` is applied as by
+/// `Content::decode`.
+pub(crate) fn decode_file(py: Python<'_>, bytes: &[u8], errors: &str, encoding: Option<Encoding>) -> PyResult<String> {
+    check_errors(errors)?;
+
+    if let Some(encoding) = encoding {
+        return decode_bytes(py, bytes, errors, encoding);
+    }
+
+    match bytes {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => decode_utf8(py, rest, errors),
+        [0xFF, 0xFE, rest @ ..] => decode_utf16(py, rest, errors, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => decode_utf16(py, rest, errors, u16::from_be_bytes),
+        bytes => decode_utf8(py, bytes, errors),
+    }
+}
+
+/// Decodes `bytes` as `encoding`. `Encoding::Utf16` drops a byte order mark, which selects the
+/// byte order.
+fn decode_bytes(py: Python<'_>, bytes: &[u8], errors: &str, encoding: Encoding) -> PyResult<String> {
+    match (encoding, bytes) {
+        (Encoding::Utf8, bytes) => decode_utf8(py, bytes, errors),
+        (Encoding::Utf16, [0xFE, 0xFF, rest @ ..]) => decode_utf16(py, rest, errors, u16::from_be_bytes),
+        (Encoding::Utf16, [0xFF, 0xFE, rest @ ..]) | (Encoding::Utf16 | Encoding::Utf16Le, rest) => {
+            decode_utf16(py, rest, errors, u16::from_le_bytes)
+        }
+        (Encoding::Utf16Be, bytes) => decode_utf16(py, bytes, errors, u16::from_be_bytes),
+        (Encoding::Latin1, bytes) => Ok(bytes.iter().copied().map(char::from).collect()),
+    }
+}
+
+fn decode_utf8(py: Python<'_>, bytes: &[u8], errors: &str) -> PyResult<String> {
+    if errors == "replace" {
+        return Ok(String::from_utf8_lossy(bytes).into_owned());
+    }
+
+    std::str::from_utf8(bytes).map(str::to_string).map_err(|e| {
+        PyUnicodeDecodeError::new_utf8(py, bytes, e)
+            .map(|err| PyErr::from_value(err.into_any()))
+            .unwrap_or_else(|err| err)
+    })
+}
+
+fn decode_utf16(py: Python<'_>, bytes: &[u8], errors: &str, unit: fn([u8; 2]) -> u16) -> PyResult<String> {
+    let units = bytes.chunks(2).map(|pair| match pair {
+        [a, b] => unit([*a, *b]),
+        // A trailing odd byte cannot be a whole code unit, so it decodes as a lone surrogate would.
+        _ => 0xDC00,
+    });
+    let mut text = String::with_capacity(bytes.len() / 2);
+    let mut offset = 0;
+
+    for c in char::decode_utf16(units) {
+        match c {
+            Ok(c) => {
+                text.push(c);
+                offset += c.len_utf16() * 2;
+            }
+            Err(_) if errors == "replace" => {
+                text.push(char::REPLACEMENT_CHARACTER);
+                offset += 2;
+            }
+            Err(_) => {
+                let end = (offset + 2).min(bytes.len());
+                return Err(
+                    PyUnicodeDecodeError::new(py, c"utf-16", bytes, offset..end, c"invalid UTF-16 code unit")
+                        .map(|err| PyErr::from_value(err.into_any()))
+                        .unwrap_or_else(|err| err),
+                );
+            }
+        }
+    }
+
+    Ok(text)
+}
@@ -0,0 +1,97 @@
+//! Converting HTML to markdown with Python callbacks for some elements, e.g. a site's own widgets.
+use crate::{ConversionOptions, content::Content, convert_html, input};
+use pyo3::{exceptions::PyTypeError, prelude::*, types::PyDict};
+
+/// An HTML to markdown converter with handlers for the elements the fixed conversion does not
+/// express, e.g. galleries or admonitions.
+#[pyclass(module = "mq")]
+pub(crate) struct HtmlConverter {
+    options: Option<ConversionOptions>,
+    /// The CSS selector and callback of each handler, in the order they were registered.
+    handlers: Vec<(String, Py<PyAny>)>,
+}
+
+#[pymethods]
+impl HtmlConverter {
+    #[new]
+    #[pyo3(signature = (options=None))]
+    fn new(options: Option<ConversionOptions>) -> Self {
+        Self {
+            options,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Registers `handler` for the elements matching `tag`, a tag name or CSS selector, replacing
+    /// the handler registered for it before. When several handlers match an element, the one
+    /// registered last is used.
+    ///
+    /// The handler is called with the attributes of the element and the markdown of its content, and
+    /// returns the markdown to replace the element with, or `None` to convert it as usual.
+    fn on_element(&mut self, tag: &str, handler: Bound<'_, PyAny>) -> PyResult<()> {
+        if !handler.is_callable() {
+            return Err(PyTypeError::new_err("handler must be callable"));
+        }
+
+        input::css_selector(tag)?;
+        self.handlers.retain(|(selector, _)| selector != tag);
+        self.handlers.push((tag.to_string(), handler.unbind()));
+        Ok(())
+    }
+
+    /// Converts `content` to markdown, as `html_to_markdown` does but with the handlers applied.
+    #[pyo3(signature = (content, errors="strict"))]
+    fn convert(&self, py: Python<'_>, content: Content<'_>, errors: &str) -> PyResult<String> {
+        let content = content.decode(errors, None)?;
+
+        self.convert_html(py, &content, self.options.as_ref())
+    }
+
+    fn __repr__(&self) -> String {
+        let tags = self.handlers.iter().map(|(tag, _)| tag.as_str()).collect::<Vec<_>>();
+        format!("HtmlConverter(handlers={:?})", tags)
+    }
+}
+
+impl HtmlConverter {
+    fn convert_html(&self, py: Python<'_>, html: &str, options: Option<&ConversionOptions>) -> PyResult<String> {
+        if self.handlers.is_empty() {
+            return convert_html(html, options);
+        }
+
+        // The content of an element is converted as a fragment, without the document's title or front matter.
+        let inner_options = ConversionOptions {
+            root_selector: None,
+            generate_front_matter: false,
+            use_title_as_h1: false,
+            ..options.cloned().unwrap_or_default()
+        };
+        let selectors = self.handlers.iter().map(|(tag, _)| tag.clone()).collect::<Vec<_>>();
+        let (html, replacements) = input::handle_elements(html, &selectors, |index, element| {
+            let attrs = PyDict::new(py);
+
+            for (name, value) in element.value().attrs() {
+                attrs.set_item(name, value)?;
+            }
+
+            let inner = self.convert_html(py, &element.inner_html(), Some(&inner_options))?;
+            let markdown = self.handlers[index]
+                .1
+                .bind(py)
+                .call1((attrs, inner.trim_matches('\n')))?;
+
+            markdown
+                .extract::<Option<String>>()
+                .map(|markdown| markdown.map(|markdown| markdown.trim_matches('\n').to_string()))
+                .map_err(|_| PyTypeError::new_err("element handlers must return a str or None"))
+        })?;
+        let markdown = convert_html(&html, options)?;
+
+        Ok(replacements
+            .iter()
+            .enumerate()
+            .fold(markdown, |markdown, (i, replacement)| {
+                markdown.replace(&input::handled_element_placeholder(i), replacement)
+            }))
+    }
+}
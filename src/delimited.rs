@@ -0,0 +1,151 @@
+//! CSV and TSV input, parsed into table cells the same way as markdown tables.
+//!
+//! The first row is the header. Fields may be quoted with `"`, with `""` standing for a quote, so
+//! they can hold delimiters and line breaks.
+use crate::MQParseInputError;
+use mq_lang::RuntimeValue;
+use mq_markdown::{Node, Point, Position, TableAlign, TableAlignKind, TableCell, Text};
+use pyo3::prelude::*;
+
+/// Parses `content` with fields separated by `delimiter` into a header row, the alignment row of
+/// a markdown table and the data rows, one value per cell. Blank lines are skipped.
+pub(crate) fn parse(content: &str, delimiter: char) -> PyResult<Vec<RuntimeValue>> {
+    let mut values = Vec::new();
+    let mut lexer = Lexer {
+        chars: content.char_indices().peekable(),
+        len: content.len(),
+        line: 1,
+        line_start: 0,
+    };
+    let mut row = 0;
+
+    while lexer.chars.peek().is_some() {
+        if lexer.eat_newline() {
+            continue;
+        }
+
+        let mut column = 0;
+
+        loop {
+            let (value, position) = lexer.field(delimiter)?;
+            values.push(RuntimeValue::Markdown(
+                Box::new(Node::TableCell(TableCell {
+                    values: vec![Node::Text(Text {
+                        value,
+                        position: Some(position.clone()),
+                    })],
+                    column,
+                    row,
+                    position: Some(position),
+                })),
+                None,
+            ));
+            column += 1;
+
+            match lexer.chars.peek() {
+                Some(&(_, c)) if c == delimiter => {
+                    lexer.chars.next();
+                }
+                _ => break,
+            }
+        }
+
+        if row == 0 {
+            values.push(RuntimeValue::Markdown(
+                Box::new(Node::TableAlign(TableAlign {
+                    align: vec![TableAlignKind::None; column],
+                    position: None,
+                })),
+                None,
+            ));
+        }
+
+        lexer.eat_newline();
+        row += 1;
+    }
+
+    Ok(values)
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    len: usize,
+    line: usize,
+    /// The byte offset the current line starts at.
+    line_start: usize,
+}
+
+impl Lexer<'_> {
+    /// The 1-based line and byte column of `offset`.
+    fn point(&self, offset: usize) -> Point {
+        Point {
+            line: self.line,
+            column: offset - self.line_start + 1,
+        }
+    }
+
+    /// The byte offset of the next character.
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.len, |&(i, _)| i)
+    }
+
+    /// Skips a line break, returning whether there was one.
+    fn eat_newline(&mut self) -> bool {
+        match self.chars.peek() {
+            Some(&(_, '\r')) => {
+                self.chars.next();
+                self.chars.next_if(|&(_, c)| c == '\n');
+            }
+            Some(&(_, '\n')) => {
+                self.chars.next();
+            }
+            _ => return false,
+        }
+
+        self.line += 1;
+        self.line_start = self.offset();
+        true
+    }
+
+    /// Reads a field up to the next delimiter or line break, returning its text and position.
+    fn field(&mut self, delimiter: char) -> PyResult<(String, Position)> {
+        let offset = self.offset();
+        let start = self.point(offset);
+        let mut value = String::new();
+
+        if self.chars.next_if(|&(_, c)| c == '"').is_some() {
+            loop {
+                match self.chars.next() {
+                    Some((_, '"')) if self.chars.next_if(|&(_, c)| c == '"').is_some() => value.push('"'),
+                    Some((_, '"')) => break,
+                    Some((i, '\n')) => {
+                        value.push('\n');
+                        self.line += 1;
+                        self.line_start = i + 1;
+                    }
+                    Some((_, c)) => value.push(c),
+                    None => {
+                        return Err(MQParseInputError::new_err(format!(
+                            "Error parsing delimited input: unterminated quoted field starting at line {}, column {}",
+                            start.line, start.column
+                        )));
+                    }
+                }
+            }
+        }
+
+        // Text after a closing quote, or an unquoted field, runs to the delimiter or line break.
+        while let Some(&(_, c)) = self.chars.peek() {
+            if c == delimiter || c == '\n' || c == '\r' {
+                break;
+            }
+
+            value.push(c);
+            self.chars.next();
+        }
+
+        let offset = self.offset();
+        let end = self.point(offset);
+        Ok((value, Position { start, end }))
+    }
+}
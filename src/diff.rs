@@ -0,0 +1,208 @@
+//! Comparing two documents block by block, e.g. to report that a heading was renamed.
+use crate::{Options, content::Content, input, render, value::Position};
+use mq_lang::RuntimeValue;
+use mq_markdown::Node;
+use pyo3::prelude::*;
+use similar::{Algorithm, DiffOp};
+
+/// A block added, removed or modified between two documents, as returned by `diff`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct Change {
+    /// `"added"`, `"removed"` or `"modified"`.
+    #[pyo3(get)]
+    kind: &'static str,
+    /// The kind of block, e.g. `"heading"`, `"paragraph"`, `"list_item"` or `"table"`.
+    #[pyo3(get, name = "type")]
+    block_type: &'static str,
+    /// The rendered block in the new document, or in the old one if it was removed.
+    #[pyo3(get)]
+    text: String,
+    #[pyo3(get)]
+    position: Option<Position>,
+    /// The rendered block in the old document, for modified blocks.
+    #[pyo3(get)]
+    old_text: Option<String>,
+    #[pyo3(get)]
+    old_position: Option<Position>,
+}
+
+#[pymethods]
+impl Change {
+    fn __repr__(&self) -> String {
+        format!("Change({:?}, {:?}, {:?})", self.kind, self.block_type, self.text)
+    }
+
+    /// A one-line description, e.g. `heading modified: "# Setup" -> "# Installation"`.
+    fn __str__(&self) -> String {
+        match &self.old_text {
+            Some(old_text) => format!("{} {}: {:?} -> {:?}", self.block_type, self.kind, old_text, self.text),
+            None => format!("{} {}: {:?}", self.block_type, self.kind, self.text),
+        }
+    }
+}
+
+/// The top-level nodes that make up one block, e.g. the inline nodes of a paragraph.
+struct Block {
+    block_type: &'static str,
+    nodes: Vec<Node>,
+    text: String,
+}
+
+impl Block {
+    fn position(&self) -> Option<Position> {
+        let start = self.nodes.first()?.position()?;
+        let end = self.nodes.last()?.position()?;
+
+        Some(Position::new(
+            mq_markdown::Position {
+                start: start.start,
+                end: end.end,
+            },
+            None,
+        ))
+    }
+}
+
+fn block_type(node: &Node) -> &'static str {
+    match node {
+        Node::Heading(_) => "heading",
+        Node::List(_) => "list_item",
+        Node::Code(_) => "code",
+        Node::Blockquote(_) => "blockquote",
+        Node::Html(_) => "html",
+        Node::Math(_) => "math",
+        Node::Definition(_) => "definition",
+        Node::Footnote(_) => "footnote",
+        Node::HorizontalRule(_) => "thematic_break",
+        Node::Yaml(_) | Node::Toml(_) => "front_matter",
+        Node::TableCell(_) | Node::TableRow(_) | Node::TableAlign(_) => "table",
+        Node::MdxJsxFlowElement(_) | Node::MdxFlowExpression(_) | Node::MdxJsEsm(_) => "mdx",
+        _ => "paragraph",
+    }
+}
+
+/// Groups the top-level nodes into blocks. Inline nodes and table cells on adjacent lines belong to
+/// the same paragraph or table, as the parser does not keep those containers.
+fn blocks(input: Vec<RuntimeValue>, options: &Options) -> PyResult<Vec<Block>> {
+    let mut groups: Vec<(&'static str, Vec<Node>)> = Vec::new();
+
+    for value in input {
+        let RuntimeValue::Markdown(node, _) = value else {
+            continue;
+        };
+        let node = *node;
+
+        if matches!(node, Node::Empty) {
+            continue;
+        }
+
+        let block_type = block_type(&node);
+
+        match groups.last_mut() {
+            Some((last_type, nodes))
+                if *last_type == block_type
+                    && matches!(block_type, "paragraph" | "table")
+                    && adjacent(nodes.last(), &node) =>
+            {
+                nodes.push(node)
+            }
+            _ => groups.push((block_type, vec![node])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(block_type, nodes)| {
+            let text = render::to_markdown(nodes.clone(), Some(options.clone()))?;
+
+            Ok(Block {
+                block_type,
+                text: text.trim_matches('\n').to_string(),
+                nodes,
+            })
+        })
+        .collect()
+}
+
+/// Whether `node` continues the block ending with `last`, which is assumed without positions.
+fn adjacent(last: Option<&Node>, node: &Node) -> bool {
+    match (last.and_then(Node::position), node.position()) {
+        (Some(last), Some(position)) => position.start.line <= last.end.line + 1,
+        _ => true,
+    }
+}
+
+fn change(kind: &'static str, block: &Block, old: Option<&Block>) -> Change {
+    Change {
+        kind,
+        block_type: block.block_type,
+        text: block.text.clone(),
+        position: block.position(),
+        old_text: old.map(|old| old.text.clone()),
+        old_position: old.and_then(Block::position),
+    }
+}
+
+/// Adds the changes between runs of old and new blocks that differ, pairing blocks of the same type
+/// in order as modified and reporting the rest as removed or added.
+fn replace(old: &[Block], new: &[Block], changes: &mut Vec<Change>) {
+    let old_types = old.iter().map(|block| block.block_type).collect::<Vec<_>>();
+    let new_types = new.iter().map(|block| block.block_type).collect::<Vec<_>>();
+
+    for op in similar::capture_diff_slices(Algorithm::Myers, &old_types, &new_types) {
+        let (_, old_range, new_range) = op.as_tag_tuple();
+        let (old, new) = (&old[old_range], &new[new_range]);
+
+        match op {
+            DiffOp::Equal { .. } => old
+                .iter()
+                .zip(new)
+                .for_each(|(old, new)| changes.push(change("modified", new, Some(old)))),
+            _ => {
+                changes.extend(old.iter().map(|block| change("removed", block, None)));
+                changes.extend(new.iter().map(|block| change("added", block, None)));
+            }
+        }
+    }
+}
+
+/// Compares two documents block by block and returns the blocks added, removed or modified, in
+/// document order.
+///
+/// Blocks are headings, paragraphs, list items, tables, code blocks and the like, compared by their
+/// rendered markdown, so changes in formatting alone, such as `*` for `_`, are not reported.
+#[pyfunction]
+#[pyo3(signature = (old_content, new_content, options=None))]
+pub(crate) fn diff(
+    old_content: Content<'_>,
+    new_content: Content<'_>,
+    options: Option<Options>,
+) -> PyResult<Vec<Change>> {
+    let options = options.unwrap_or_default();
+    let old = blocks(input::parse(old_content.as_str()?, &options)?, &options)?;
+    let new = blocks(input::parse(new_content.as_str()?, &options)?, &options)?;
+
+    let old_text = old
+        .iter()
+        .map(|block| (block.block_type, block.text.as_str()))
+        .collect::<Vec<_>>();
+    let new_text = new
+        .iter()
+        .map(|block| (block.block_type, block.text.as_str()))
+        .collect::<Vec<_>>();
+    let mut changes = Vec::new();
+
+    for op in similar::capture_diff_slices(Algorithm::Myers, &old_text, &new_text) {
+        let (_, old_range, new_range) = op.as_tag_tuple();
+
+        match op {
+            DiffOp::Equal { .. } => {}
+            DiffOp::Delete { .. } => changes.extend(old[old_range].iter().map(|block| change("removed", block, None))),
+            DiffOp::Insert { .. } => changes.extend(new[new_range].iter().map(|block| change("added", block, None))),
+            DiffOp::Replace { .. } => replace(&old[old_range], &new[new_range], &mut changes),
+        }
+    }
+
+    Ok(changes)
+}
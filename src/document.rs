@@ -0,0 +1,205 @@
+//! Documents kept parsed between queries, so that an edit re-parses only the blocks it touches.
+use crate::{
+    Detached, InputFormat, MQParseInputError, Options, content::Content, engine, input, query_args, result::MQResult,
+};
+use indexmap::IndexMap;
+use mq_lang::RuntimeValue;
+use mq_markdown::Node;
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::{collections::HashMap, ops::Range, time::Instant};
+
+/// The top-level nodes parsed from `content[start..end]`, whose first line is line `line` of the
+/// document, counting from zero.
+struct Block {
+    start: usize,
+    end: usize,
+    line: usize,
+    nodes: Vec<Node>,
+}
+
+/// A parsed document, from `load`.
+#[pyclass(skip_from_py_object)]
+pub(crate) struct Document {
+    #[pyo3(get)]
+    content: String,
+    options: Options,
+    blocks: Vec<Block>,
+}
+
+/// Whether the blocks of `content` can be parsed on their own. Reference definitions and footnotes
+/// are resolved document-wide, and normalized input no longer matches the offsets of edits.
+fn incremental(content: &str, options: &Options) -> bool {
+    options.input_format.unwrap_or(InputFormat::Markdown) == InputFormat::Markdown
+        && options.unicode_normalization.is_none()
+        && !input::has_reference_definitions(content)
+}
+
+/// Splits `content` into blocks, parsing those `reuse` does not return the nodes of when called with
+/// their start, end and line. Returns the blocks and the range of `content` that was parsed.
+fn parse_blocks(
+    content: &str,
+    options: &Options,
+    mut reuse: impl FnMut(usize, usize, usize) -> Option<Vec<Node>>,
+) -> PyResult<(Vec<Block>, Option<Range<usize>>)> {
+    if !incremental(content, options) {
+        let nodes = input::parse_values(content, options)?
+            .into_iter()
+            .filter_map(|value| match value {
+                RuntimeValue::Markdown(node, _) => Some(*node),
+                _ => None,
+            })
+            .collect();
+        let block = Block {
+            start: 0,
+            end: content.len(),
+            line: 0,
+            nodes,
+        };
+
+        return Ok((vec![block], Some(0..content.len())));
+    }
+
+    let mut blocks = Vec::new();
+    let mut parsed: Option<Range<usize>> = None;
+    let mut start = 0;
+
+    for chunk in input::split_chunks(content, 0) {
+        let end = start + chunk.text.len();
+        let nodes = match reuse(start, end, chunk.line_offset) {
+            Some(nodes) => nodes,
+            None => {
+                parsed = Some(parsed.map_or(start, |parsed| parsed.start)..end);
+                input::parse_chunk(&chunk)
+                    .map_err(|e| MQParseInputError::new_err(format!("Error evaluating query: {}", e)))?
+            }
+        };
+
+        blocks.push(Block {
+            start,
+            end,
+            line: chunk.line_offset,
+            nodes,
+        });
+        start = end;
+    }
+
+    Ok((blocks, parsed))
+}
+
+#[pymethods]
+impl Document {
+    fn __repr__(&self) -> String {
+        format!("Document({} bytes, {} blocks)", self.content.len(), self.blocks.len())
+    }
+
+    /// Replaces the bytes in `range` with `new_text` and re-parses the blocks the edit touched,
+    /// returning the range of the new content that was re-parsed.
+    fn apply_edit(&mut self, py: Python<'_>, range: (usize, usize), new_text: &str) -> PyResult<(usize, usize)> {
+        let (start, end) = range;
+
+        if start > end || end > self.content.len() {
+            return Err(PyValueError::new_err(format!(
+                "Edit range {}..{} is out of bounds for a document of {} bytes",
+                start,
+                end,
+                self.content.len()
+            )));
+        }
+
+        if !self.content.is_char_boundary(start) || !self.content.is_char_boundary(end) {
+            return Err(PyValueError::new_err(format!(
+                "Edit range {}..{} does not fall on character boundaries",
+                start, end
+            )));
+        }
+
+        let mut content = self.content.clone();
+        content.replace_range(start..end, new_text);
+
+        let edit_end = start + new_text.len();
+        let options = &self.options;
+        let mut old = std::mem::take(&mut self.blocks)
+            .into_iter()
+            .map(|block| ((block.start, block.end), block))
+            .collect::<HashMap<_, _>>();
+
+        // Blocks before the edit are where they were, and blocks after it moved by its change in length.
+        let (blocks, parsed) = py.detach(|| {
+            parse_blocks(&content, options, |block_start, block_end, line| {
+                if block_end <= start {
+                    return old.remove(&(block_start, block_end)).map(|block| block.nodes);
+                }
+
+                if block_start < edit_end {
+                    return None;
+                }
+
+                let moved = |offset: usize| offset - edit_end + end;
+                let block = old.remove(&(moved(block_start), moved(block_end)))?;
+                let shift = line as isize - block.line as isize;
+                let mut nodes = block.nodes;
+
+                if shift != 0 {
+                    nodes.iter_mut().for_each(|node| input::shift_lines(node, shift));
+                }
+
+                Some(nodes)
+            })
+        })?;
+
+        self.content = content;
+        self.blocks = blocks;
+        let parsed = parsed.unwrap_or(start..start);
+        Ok((parsed.start, parsed.end))
+    }
+
+    /// Runs `code` against the document without parsing it again.
+    #[pyo3(signature = (code, args=None))]
+    fn run(&self, py: Python<'_>, code: &str, args: Option<IndexMap<String, Bound<'_, PyAny>>>) -> PyResult<MQResult> {
+        let args = query_args(args.unwrap_or_default())?;
+        let run = Detached(|prefix: Option<&str>, input| {
+            engine::eval_timed(prefix.unwrap_or(code), input, &self.options, args.clone())
+        });
+
+        py.detach(move || {
+            let run = run;
+            let start = Instant::now();
+            let input = self
+                .blocks
+                .iter()
+                .flat_map(|block| block.nodes.iter().cloned())
+                .map(RuntimeValue::from)
+                .collect();
+            let input = input::finish(input, &self.options);
+
+            crate::eval_parsed(code, &self.content, input, Vec::new(), start, &self.options, run.0)
+        })
+    }
+}
+
+/// Parses `content` into a `Document` that can be queried repeatedly and edited in place.
+///
+/// Markdown documents are split into top-level blocks, so `Document.apply_edit` re-parses only
+/// the blocks an edit touches. Documents with reference definitions or footnotes, MDX and
+/// normalized input are parsed whole after every edit.
+#[pyfunction]
+#[pyo3(signature = (content, options=None, errors="strict"))]
+pub(crate) fn load(py: Python<'_>, content: Content<'_>, options: Option<Options>, errors: &str) -> PyResult<Document> {
+    let options = options.unwrap_or_default();
+
+    if !matches!(
+        options.input_format.unwrap_or(InputFormat::Markdown),
+        InputFormat::Markdown | InputFormat::Mdx
+    ) {
+        return Err(PyValueError::new_err("load supports markdown and MDX input only"));
+    }
+
+    let content = content.decode_detached(errors, options.encoding)?.into_owned();
+    let (blocks, _) = py.detach(|| parse_blocks(&content, &options, |_, _, _| None))?;
+
+    Ok(Document {
+        content,
+        options,
+        blocks,
+    })
+}
@@ -0,0 +1,723 @@
+//! Evaluating queries on a warmed engine, so calls do not pay for loading the builtin module.
+use crate::{
+    InputFormat, Options, analyze, content::Content, front_matter, new_engine, result::MQResult, with_source,
+};
+use indexmap::IndexMap;
+use mq_lang::{CompiledProgram, DefaultEngine, MqResult, RuntimeValue};
+use pyo3::{exceptions::PyTypeError, prelude::*, types::PyTuple};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, LazyLock, Mutex, MutexGuard, Weak,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Keywords of statements that add names to the engine's environment.
+const DEFINING_KEYWORDS: [&str; 7] = ["def", "let", "var", "macro", "module", "import", "include"];
+
+/// The bytes of queries the cached engine may evaluate before it is replaced, since the engine
+/// keeps the tokens of every query it parsed.
+const MAX_QUERY_BYTES: usize = 1 << 20;
+
+thread_local! {
+    /// The cached engine and the bytes of queries it evaluated.
+    static ENGINE: RefCell<Option<(DefaultEngine, usize)>> = const { RefCell::new(None) };
+}
+
+/// Evaluates `code` on `input` with the builtin module loaded, within the limits of `options`.
+///
+/// Queries reuse one engine per thread. Queries that define names or are given arguments get a
+/// fresh engine instead, so definitions never leak from one call into the next.
+pub(crate) fn eval(
+    code: &str,
+    input: impl IntoIterator<Item = RuntimeValue>,
+    options: &Options,
+) -> PyResult<Vec<RuntimeValue>> {
+    eval_args(code, input, options, Vec::new())
+}
+
+/// Like `eval`, with `args` bound as variables before the query runs.
+pub(crate) fn eval_args(
+    code: &str,
+    input: impl IntoIterator<Item = RuntimeValue>,
+    options: &Options,
+    args: Vec<(String, RuntimeValue)>,
+) -> PyResult<Vec<RuntimeValue>> {
+    crate::security::check(code, options)?;
+    let values = with_engine(code, options, !args.is_empty(), |engine| {
+        for (name, value) in args {
+            engine.define_value(&name, value);
+        }
+
+        engine.eval(code, input.into_iter())
+    })
+    .map_err(|e| crate::error::query_error("Error evaluating query", &e))?
+        .into_iter()
+        .collect::<Vec<_>>();
+    check_limits(&values, options)?;
+
+    Ok(values)
+}
+
+/// The input values of a query.
+pub(crate) type Input = std::vec::IntoIter<RuntimeValue>;
+
+/// Raises `LimitExceededError` if `values` hold more than `options.max_output_nodes` values,
+/// counting the items of arrays.
+pub(crate) fn check_limits(values: &[RuntimeValue], options: &Options) -> PyResult<()> {
+    if let Some(max) = options.max_output_nodes {
+        let count = values.iter().map(count_values).sum::<usize>();
+
+        if count > max {
+            return Err(crate::error::limit_exceeded(format!(
+                "Query returned {} values, more than max_output_nodes={}",
+                count, max
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the values in `value`, with empty ones standing in for nodes a query did not match.
+fn count_values(value: &RuntimeValue) -> usize {
+    match value {
+        RuntimeValue::Array(values) => values.iter().map(count_values).sum(),
+        RuntimeValue::None => 0,
+        RuntimeValue::Markdown(node, _) if node.is_empty() || node.is_empty_fragment() => 0,
+        _ => 1,
+    }
+}
+
+/// Like `eval`, with `args` bound as variables, also returning how long compiling and evaluating
+/// the query took.
+pub(crate) fn eval_timed(
+    code: &str,
+    input: impl Iterator<Item = RuntimeValue>,
+    options: &Options,
+    args: Vec<(String, RuntimeValue)>,
+) -> (MqResult, Duration, Duration) {
+    let mut compile = Duration::ZERO;
+    let mut eval = Duration::ZERO;
+    let values = with_engine(code, options, !args.is_empty(), |engine| {
+        for (name, value) in args {
+            engine.define_value(&name, value);
+        }
+
+        let start = Instant::now();
+        let program = engine.compile(code)?;
+        compile = start.elapsed();
+
+        let start = Instant::now();
+        let values = engine.eval_compiled(&program, input);
+        eval = start.elapsed();
+        values
+    });
+
+    (values, compile, eval)
+}
+
+/// Runs `f` on the cached engine, or on a fresh one for `options` when `fresh` is set, `code`
+/// defines names or the recursion depth is limited.
+///
+/// Modules are only loaded by queries that import or include them, so the cached engine never
+/// needs the module paths of `options`.
+fn with_engine(code: &str, options: &Options, fresh: bool, f: impl FnOnce(&mut DefaultEngine) -> MqResult) -> MqResult {
+    if fresh || defines_names(code) || options.max_recursion_depth.is_some() {
+        return f(&mut new_engine(options));
+    }
+
+    let (mut engine, bytes) = ENGINE
+        .with_borrow_mut(Option::take)
+        .unwrap_or_else(|| (new_engine(&Options::default()), 0));
+    let values = f(&mut engine);
+    let bytes = bytes + code.len();
+
+    // A failed query may leave the engine mid-call, so it is not reused.
+    if values.is_ok() && bytes < MAX_QUERY_BYTES {
+        ENGINE.with_borrow_mut(|cached| *cached = Some((engine, bytes)));
+    }
+
+    values
+}
+
+/// Returns whether `code` uses one of `DEFINING_KEYWORDS` outside of string literals and comments.
+fn defines_names(code: &str) -> bool {
+    let bytes = code.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                let start = i;
+
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+
+                if DEFINING_KEYWORDS.contains(&&code[start..i]) && (start == 0 || bytes[start - 1] != b'.') {
+                    return true;
+                }
+
+                continue;
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    false
+}
+
+/// The hits and misses of an engine's result cache, as returned by `Engine.cache_info`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct CacheInfo {
+    #[pyo3(get)]
+    hits: usize,
+    #[pyo3(get)]
+    misses: usize,
+    #[pyo3(get)]
+    maxsize: usize,
+    #[pyo3(get)]
+    currsize: usize,
+}
+
+#[pymethods]
+impl CacheInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "CacheInfo(hits={}, misses={}, maxsize={}, currsize={})",
+            self.hits, self.misses, self.maxsize, self.currsize
+        )
+    }
+}
+
+/// The results of the compiled query by the content it ran against, least recently used first.
+#[derive(Default)]
+struct ResultCache {
+    size: usize,
+    results: IndexMap<String, MQResult>,
+    hits: usize,
+    misses: usize,
+}
+
+impl ResultCache {
+    fn get(&mut self, key: &str) -> Option<MQResult> {
+        match self.results.shift_remove_entry(key) {
+            Some((key, result)) => {
+                self.hits += 1;
+                self.results.insert(key, result.clone());
+                Some(result)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, key: String, result: MQResult) {
+        if self.results.len() >= self.size {
+            self.results.shift_remove_index(0);
+        }
+
+        self.results.insert(key, result);
+    }
+}
+
+/// A query compiled once, to be run against many documents.
+///
+/// The names defined by `eval` and `load_module` remain available to later calls. Engines can be
+/// shared between threads: each thread evaluates on an environment of its own, built from the query
+/// and the definitions made so far, so calls from several threads never share interpreter state.
+#[pyclass(frozen)]
+pub(crate) struct Engine {
+    /// Identifies the environments of the engine in `ENVIRONMENTS`.
+    id: usize,
+    /// Dropped with the engine, so threads can tell which of their environments are no longer used.
+    alive: Arc<()>,
+    /// The query the engine runs.
+    #[pyo3(get)]
+    code: String,
+    options: Options,
+    state: Mutex<State>,
+}
+
+/// What calls on any thread may change, behind the engine's lock.
+struct State {
+    /// The source of the modules loaded with `load_module` and of the queries that defined names,
+    /// loaded again when an environment is built.
+    modules: Vec<String>,
+    /// The names defined by `load_module` and `eval`, in order.
+    names: Vec<String>,
+    /// Counts the changes to `modules`, so environments built before one are built again.
+    generation: usize,
+    /// The results of the compiled query, if the engine was created with a `cache_size`.
+    cache: Option<ResultCache>,
+}
+
+/// An engine with the query compiled and the modules of a `State` loaded, as of `generation`.
+struct Environment {
+    engine: DefaultEngine,
+    program: CompiledProgram,
+    generation: usize,
+    /// The names the query and the loaded modules define.
+    names: Vec<String>,
+}
+
+/// The names the builtin module defines in every engine.
+static BUILTIN_NAMES: LazyLock<Vec<String>> = LazyLock::new(|| analyze::defined_names(mq_lang::BUILTIN_MODULE_FILE));
+
+impl Drop for Environment {
+    // mq-lang functions hold the environment they were defined in, which holds them in turn, so the
+    // names are cleared to free the engine's values.
+    fn drop(&mut self) {
+        for name in BUILTIN_NAMES.iter().chain(&self.names) {
+            self.engine.define_value(name, RuntimeValue::NONE);
+        }
+    }
+}
+
+static NEXT_ENGINE_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The environments of the engines used on this thread, by engine id, with a handle that tells
+    /// whether the engine still exists. mq-lang engines cannot move between threads, so each thread
+    /// builds its own.
+    static ENVIRONMENTS: RefCell<HashMap<usize, (Weak<()>, Environment)>> = RefCell::new(HashMap::new());
+}
+
+/// Drops the environments of engines that were dropped, which only the thread that built them can do.
+fn prune(environments: &mut HashMap<usize, (Weak<()>, Environment)>) {
+    environments.retain(|_, (alive, _)| alive.strong_count() > 0);
+}
+
+#[pymethods]
+impl Engine {
+    #[new]
+    #[pyo3(signature = (code="self", options=None, cache_size=None))]
+    pub(crate) fn new(code: &str, options: Option<Options>, cache_size: Option<usize>) -> PyResult<Self> {
+        let options = options.unwrap_or_default();
+        let (environment, program) = compile(code, &options)?;
+        let engine = Self {
+            id: NEXT_ENGINE_ID.fetch_add(1, Ordering::Relaxed),
+            alive: Arc::new(()),
+            code: code.to_string(),
+            options,
+            state: Mutex::new(State {
+                modules: Vec::new(),
+                names: Vec::new(),
+                generation: 0,
+                cache: cache_size.filter(|size| *size > 0).map(|size| ResultCache {
+                    size,
+                    ..ResultCache::default()
+                }),
+            }),
+        };
+
+        engine.keep(Environment {
+            engine: environment,
+            program,
+            generation: 0,
+            names: analyze::defined_names(code),
+        });
+        Ok(engine)
+    }
+
+    /// Loads the definitions of an mq module into the engine, so the query can call them.
+    ///
+    /// `module` is the path of a `.mq` file, or the source of a module when it names no file.
+    pub(crate) fn load_module(&self, module: &Bound<'_, PyAny>) -> PyResult<()> {
+        let path = match module.extract::<String>() {
+            Ok(module) if !Path::new(&module).is_file() => None,
+            _ => Some(module.extract::<PathBuf>()?),
+        };
+        let source = match &path {
+            Some(path) => {
+                std::fs::read_to_string(path).map_err(|e| with_source(e.into(), Some(&path.to_string_lossy())))?
+            }
+            None => module.extract::<String>()?,
+        };
+
+        let name = path.as_ref().map(|path| path.to_string_lossy());
+        crate::security::check(&source, &self.options).map_err(|e| with_source(e, name.as_deref()))?;
+        let mut environment = self.environment()?;
+        load(&mut environment.engine, &source).map_err(|e| with_source(e, name.as_deref()))?;
+        self.define(source, &mut environment);
+        self.keep(environment);
+        Ok(())
+    }
+
+    /// Runs the compiled query against `content`, as `mq.run` does.
+    ///
+    /// Called as `eval(code, content)`, runs `code` instead, against no input if `content` is
+    /// `None`. The functions and variables it defines stay available to later calls.
+    #[pyo3(signature = (*args, source=None, errors="strict"))]
+    pub(crate) fn eval(
+        &self,
+        py: Python<'_>,
+        args: &Bound<'_, PyTuple>,
+        source: Option<&str>,
+        errors: &str,
+    ) -> PyResult<MQResult> {
+        let (code, content) = match args.len() {
+            1 => (None, Some(args.get_item(0)?.extract::<Content<'_>>()?)),
+            2 => (
+                Some(args.get_item(0)?.extract::<String>()?),
+                args.get_item(1)?.extract::<Option<Content<'_>>>()?,
+            ),
+            n => {
+                return Err(PyTypeError::new_err(format!(
+                    "eval() takes content, or code and content, but {} positional arguments were given",
+                    n
+                )));
+            }
+        };
+        let options = match content {
+            Some(_) => self.options.clone(),
+            None => Options {
+                input_format: Some(InputFormat::Null),
+                ..self.options.clone()
+            },
+        };
+        let content = match &content {
+            Some(content) => content.decode_detached(errors, options.encoding)?,
+            None => "".into(),
+        };
+        // Only results of the compiled query are cached, as other code may change what it returns.
+        let key = code.is_none().then_some(&*content);
+
+        if let (Some(key), Some(cache)) = (key, &mut self.state().cache)
+            && let Some(mut result) = cache.get(key)
+        {
+            if let Some(source) = source {
+                result.set_source(source);
+            }
+
+            return Ok(result);
+        }
+
+        let mut environment = self.environment()?;
+        let query = code.as_deref().unwrap_or(&self.code);
+        let mut failed = false;
+        let mut traced = false;
+        let result = crate::eval_with(py, query, &content, &options, |prefix, input| {
+            let start = Instant::now();
+            let values = match (prefix, &code) {
+                (Some(prefix), _) => {
+                    traced = true;
+                    environment.engine.eval(prefix, input)
+                }
+                (None, Some(code)) => environment.engine.eval(code, input),
+                (None, None) => environment.engine.eval_compiled(&environment.program, input),
+            };
+            failed |= prefix.is_none() && values.is_err();
+            (values, Duration::ZERO, start.elapsed())
+        });
+
+        // A failed query may leave the engine mid-call, and the prefixes run to trace a pipeline may
+        // define names the query does not, so the environment is built again by the next call.
+        if !failed {
+            if let Some(code) = code
+                && defines_names(&code)
+            {
+                self.define(code, &mut environment);
+            }
+
+            if !traced {
+                self.keep(environment);
+            }
+        }
+
+        let mut result = result.map_err(|e| with_source(e, source))?;
+
+        if let (Some(key), Some(cache)) = (key, &mut self.state().cache) {
+            cache.insert(key.to_string(), result.clone());
+        }
+
+        if let Some(source) = source {
+            result.set_source(source);
+        }
+
+        Ok(result)
+    }
+
+    /// Returns the hits and misses of the result cache, like `functools.lru_cache`.
+    pub(crate) fn cache_info(&self) -> CacheInfo {
+        let state = self.state();
+        let cache = state.cache.as_ref();
+
+        CacheInfo {
+            hits: cache.map_or(0, |cache| cache.hits),
+            misses: cache.map_or(0, |cache| cache.misses),
+            maxsize: cache.map_or(0, |cache| cache.size),
+            currsize: cache.map_or(0, |cache| cache.results.len()),
+        }
+    }
+
+    /// Discards the cached results and resets the hits and misses.
+    pub(crate) fn cache_clear(&self) {
+        if let Some(cache) = &mut self.state().cache {
+            *cache = ResultCache {
+                size: cache.size,
+                ..ResultCache::default()
+            };
+        }
+    }
+
+    /// Discards the names defined by `eval` and `load_module`, starting over with a fresh engine.
+    pub(crate) fn reset(&self) -> PyResult<()> {
+        let mut state = self.state();
+        state.modules.clear();
+        state.names.clear();
+        state.generation += 1;
+        state.clear_results();
+        Ok(())
+    }
+
+    /// Returns the names defined by `eval` and `load_module`, in the order they were first defined.
+    pub(crate) fn defined_names(&self) -> Vec<String> {
+        self.state().names.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Engine({:?})", self.code)
+    }
+}
+
+impl Engine {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes this thread's environment of the engine, building it if there is none or definitions
+    /// were made or discarded since it was built.
+    ///
+    /// The environment is put back by `keep`, so a call that fails leaves none behind.
+    fn environment(&self) -> PyResult<Environment> {
+        let environment = ENVIRONMENTS.with_borrow_mut(|environments| {
+            prune(environments);
+            environments.remove(&self.id).map(|(_, environment)| environment)
+        });
+        let (generation, modules, mut names) = {
+            let state = self.state();
+
+            match environment {
+                Some(environment) if environment.generation == state.generation => return Ok(environment),
+                _ => (state.generation, state.modules.clone(), state.names.clone()),
+            }
+        };
+        names.extend(analyze::defined_names(&self.code));
+
+        let (mut engine, program) = compile(&self.code, &self.options)?;
+
+        for module in &modules {
+            load(&mut engine, module)?;
+        }
+
+        Ok(Environment {
+            engine,
+            program,
+            generation,
+            names,
+        })
+    }
+
+    fn keep(&self, environment: Environment) {
+        ENVIRONMENTS.with_borrow_mut(|environments| {
+            prune(environments);
+            environments.insert(self.id, (Arc::downgrade(&self.alive), environment));
+        });
+    }
+
+    /// Records the source of a module or query that defined names in `environment`, to load it
+    /// again when other environments are built.
+    fn define(&self, source: String, environment: &mut Environment) {
+        let mut state = self.state();
+
+        for name in analyze::defined_names(&source) {
+            if !state.names.contains(&name) {
+                state.names.push(name.clone());
+            }
+
+            environment.names.push(name);
+        }
+
+        // An environment that missed definitions made on another thread is built again instead.
+        if environment.generation == state.generation {
+            environment.generation += 1;
+        }
+
+        state.modules.push(source);
+        state.generation += 1;
+        state.clear_results();
+    }
+}
+
+impl Drop for Engine {
+    // Environments on other threads are dropped by the next engine call on each of them, or with the
+    // thread.
+    fn drop(&mut self) {
+        let _ = ENVIRONMENTS.try_with(|environments| {
+            environments
+                .try_borrow_mut()
+                .map(|mut environments| environments.remove(&self.id))
+        });
+    }
+}
+
+impl State {
+    /// Discards the cached results, which the names defined since may change, keeping the hits and
+    /// misses.
+    fn clear_results(&mut self) {
+        if let Some(cache) = &mut self.cache {
+            cache.results.clear();
+        }
+    }
+}
+
+/// Compiles `code` into an `Engine` without running it, raising `ParseError` if it is invalid or
+/// calls a function that is neither a builtin nor defined by the query.
+#[pyfunction(name = "compile")]
+#[pyo3(signature = (code, options=None))]
+pub(crate) fn compile_query(code: &str, options: Option<Options>) -> PyResult<Engine> {
+    let engine = Engine::new(code, options, None)?;
+    analyze::check_calls(code)?;
+    Ok(engine)
+}
+
+/// Runs `code` against each of `contents` on a pool of threads, with the GIL released, returning
+/// the results in the order of `contents`.
+///
+/// With `front_matter`, only the documents whose front matter has those values, or for which that
+/// callable returns true, are queried, and the others get `None` in place of a result.
+///
+/// Each thread compiles the query once on its own engine, as engines cannot move between threads.
+#[pyfunction]
+#[pyo3(signature = (code, contents, options=None, errors="strict", front_matter=None))]
+pub(crate) fn run_many(
+    py: Python<'_>,
+    code: &str,
+    contents: Vec<Content<'_>>,
+    options: Option<Options>,
+    errors: &str,
+    front_matter: Option<Bound<'_, PyAny>>,
+) -> PyResult<Vec<Option<MQResult>>> {
+    let options = options.unwrap_or_default();
+    let filter = front_matter.map(front_matter::Filter::new).transpose()?;
+    let contents = contents
+        .iter()
+        .enumerate()
+        .map(|(i, content)| {
+            let content = content.decode_detached(errors, options.encoding)?;
+
+            let selected = match &filter {
+                Some(filter) => filter
+                    .matches(&content)
+                    .map_err(|e| with_source(e, Some(&format!("contents[{}]", i))))?,
+                None => true,
+            };
+
+            Ok(selected.then_some(content))
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    // Checked up front, so compile errors are raised once rather than by every thread.
+    compile(code, &options)?;
+
+    let threads = std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(contents.iter().flatten().count());
+    let next = AtomicUsize::new(0);
+    let results = py.detach(|| {
+        std::thread::scope(|s| {
+            (0..threads)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut results = Vec::new();
+                        let (mut engine, mut program) = compile(code, &options)?;
+
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(content) = contents.get(i) else {
+                                return Ok::<_, PyErr>(results);
+                            };
+                            let Some(content) = content else {
+                                continue;
+                            };
+                            let mut failed = false;
+                            let result = crate::eval_detached(code, content, &options, |prefix, input| {
+                                let start = Instant::now();
+                                // Prefixes run on an engine of their own, so their definitions do not
+                                // reach the next document.
+                                let values = match prefix {
+                                    Some(prefix) => new_engine(&options).eval(prefix, input),
+                                    None => engine.eval_compiled(&program, input),
+                                };
+                                failed |= prefix.is_none() && values.is_err();
+                                (values, Duration::ZERO, start.elapsed())
+                            });
+
+                            if failed {
+                                (engine, program) = compile(code, &options)?;
+                            }
+
+                            results.push((i, result));
+                        }
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().expect("query thread panicked"))
+                .collect::<PyResult<Vec<_>>>()
+        })
+    })?;
+
+    let mut results = results.into_iter().flatten().collect::<Vec<_>>();
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut output = std::iter::repeat_with(|| None).take(contents.len()).collect::<Vec<_>>();
+
+    for (i, result) in results {
+        output[i] = Some(result.map_err(|e| with_source(e, Some(&format!("contents[{}]", i))))?);
+    }
+
+    Ok(output)
+}
+
+fn compile(code: &str, options: &Options) -> PyResult<(DefaultEngine, CompiledProgram)> {
+    crate::security::check(code, options)?;
+    let mut engine = new_engine(options);
+    let program = engine
+        .compile(code)
+        .map_err(|e| crate::error::query_error("Error compiling query", &e))?;
+
+    Ok((engine, program))
+}
+
+/// Runs the module `source` on `engine`, which keeps the functions and variables it defines.
+fn load(engine: &mut DefaultEngine, source: &str) -> PyResult<()> {
+    engine
+        .eval(source, mq_lang::null_input().into_iter())
+        .map_err(|e| crate::error::query_error("Error loading module", &e))?;
+
+    Ok(())
+}
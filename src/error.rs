@@ -0,0 +1,125 @@
+//! Exceptions raised for queries that cannot be parsed or run, with where in the query they failed.
+use pyo3::prelude::*;
+
+pyo3::create_exception!(
+    mq,
+    ParseError,
+    pyo3::exceptions::PyRuntimeError,
+    "Raised when a query, or a module it imports, cannot be parsed."
+);
+
+pyo3::create_exception!(
+    mq,
+    EvalError,
+    pyo3::exceptions::PyRuntimeError,
+    "Raised when a query fails while it runs."
+);
+
+pyo3::create_exception!(
+    mq,
+    LimitExceededError,
+    EvalError,
+    "Raised when a query exceeds one of the limits set in its options."
+);
+
+pyo3::create_exception!(
+    mq,
+    SecurityError,
+    pyo3::exceptions::PyRuntimeError,
+    "Raised when a query uses something the `SecurityPolicy` of its options does not allow."
+);
+
+/// Converts an mq-lang error into a `ParseError` or `EvalError`, with the message after `prefix`.
+///
+/// The exception gets `line` and `column` (1-based), the offending `token` and the `snippet`, the
+/// line of the query it is on, all None when mq-lang does not know where the error is.
+pub(crate) fn query_error(prefix: &str, e: &mq_lang::Error) -> PyErr {
+    let token = e.cause.token();
+    let code = e.source_code.inner();
+    let (line, column, snippet) = match (token, is_end_of_input(e)) {
+        (Some(token), _) => (
+            Some(token.range.start.line as usize),
+            Some(token.range.start.column),
+            code.lines().nth((token.range.start.line as usize).saturating_sub(1)),
+        ),
+        (None, true) => {
+            let (line, column) = end_of(code);
+            (Some(line), Some(column), code.lines().nth(line - 1))
+        }
+        (None, false) => (None, None, None),
+    };
+
+    Python::attach(|py| {
+        let message = format!("{}: {}", prefix, e);
+        // mq-lang does not export the error kinds, so they are told apart by name.
+        let cause = format!("{:?}", e.cause);
+        let err = if cause.starts_with("Runtime(RecursionError(") {
+            LimitExceededError::new_err(message)
+        } else if cause.starts_with("Runtime(") {
+            EvalError::new_err(message)
+        } else {
+            ParseError::new_err(message)
+        };
+        let value = err.value(py);
+
+        // Setting attributes on a fresh exception only fails when out of memory.
+        let _ = value.setattr("line", line);
+        let _ = value.setattr("column", column);
+        // The end of the query is shown as an empty token.
+        let _ = value.setattr(
+            "token",
+            token
+                .map(|token| token.kind.to_string())
+                .filter(|kind| !kind.is_empty()),
+        );
+        let _ = value.setattr("snippet", snippet);
+
+        err
+    })
+}
+
+/// Whether `e` is for a query that ended before an expression did, which mq-lang reports without
+/// a token.
+pub(crate) fn is_end_of_input(e: &mq_lang::Error) -> bool {
+    // mq-lang does not export the error kinds, so they are told apart by name.
+    format!("{:?}", e.cause).starts_with("Syntax(UnexpectedEOFDetected(")
+}
+
+/// Returns the line and column, counting from 1, just past the end of `code` without its trailing
+/// whitespace.
+pub(crate) fn end_of(code: &str) -> (usize, usize) {
+    let code = code.trim_end();
+    let last = code.split('\n').next_back().unwrap_or_default();
+    (code.matches('\n').count() + 1, last.chars().count() + 1)
+}
+
+/// A `ParseError` for a problem found outside of mq-lang at `token` of `code`, with the same
+/// attributes as those `query_error` raises.
+pub(crate) fn parse_error_at(message: String, code: &str, token: Option<&mq_lang::Token>) -> PyErr {
+    Python::attach(|py| {
+        let err = ParseError::new_err(message);
+        let value = err.value(py);
+        let snippet = token.and_then(|token| code.lines().nth((token.range.start.line as usize).saturating_sub(1)));
+
+        let _ = value.setattr("line", token.map(|token| token.range.start.line));
+        let _ = value.setattr("column", token.map(|token| token.range.start.column));
+        let _ = value.setattr("token", token.map(|token| token.kind.to_string()));
+        let _ = value.setattr("snippet", snippet);
+
+        err
+    })
+}
+
+/// A `LimitExceededError` for a limit checked outside of mq-lang, with no position in the query.
+pub(crate) fn limit_exceeded(message: String) -> PyErr {
+    Python::attach(|py| {
+        let err = LimitExceededError::new_err(message);
+        let value = err.value(py);
+
+        for name in ["line", "column", "token", "snippet"] {
+            let _ = value.setattr(name, py.None());
+        }
+
+        err
+    })
+}
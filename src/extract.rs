@@ -0,0 +1,548 @@
+//! Helpers that pull specific kinds of content out of a document without writing a query.
+use crate::{
+    InputFormat, Options,
+    content::Content,
+    engine, input, nodes, render, section, table, transform,
+    value::{MQValue, Position},
+};
+use indexmap::IndexMap;
+use mq_markdown::Node;
+use pyo3::{
+    IntoPyObjectExt,
+    exceptions::PyTypeError,
+    prelude::*,
+    types::{PyDict, PyIterator, PyTuple},
+};
+use std::collections::HashMap;
+
+/// A code block, as returned by `code_blocks` and passed to `update_code_blocks`.
+///
+/// It also unpacks and compares as the `(lang, value, meta, (line, column))` tuple `code_blocks`
+/// used to return.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct CodeBlock {
+    #[pyo3(get)]
+    lang: Option<String>,
+    #[pyo3(get)]
+    meta: Option<String>,
+    #[pyo3(get)]
+    value: String,
+    position: Option<mq_markdown::Position>,
+}
+
+impl From<&mq_markdown::Code> for CodeBlock {
+    fn from(code: &mq_markdown::Code) -> Self {
+        Self {
+            lang: code.lang.clone(),
+            meta: code.meta.clone(),
+            value: code.value.clone(),
+            position: code.position.clone(),
+        }
+    }
+}
+
+impl CodeBlock {
+    fn to_tuple<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyTuple>> {
+        let point = self.position.as_ref().map(|p| (p.start.line, p.start.column));
+        (self.lang.clone(), self.value.clone(), self.meta.clone(), point).into_pyobject(py)
+    }
+}
+
+#[pymethods]
+impl CodeBlock {
+    #[getter]
+    fn position(&self) -> Option<Position> {
+        Some(Position::new(self.position.clone()?, None))
+    }
+
+    fn __len__(&self) -> usize {
+        4
+    }
+
+    fn __getitem__<'py>(&self, py: Python<'py>, index: isize) -> PyResult<Bound<'py, PyAny>> {
+        self.to_tuple(py)?.as_any().get_item(index)
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        self.to_tuple(py)?.try_iter()
+    }
+
+    fn __eq__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<bool> {
+        match other.cast::<CodeBlock>() {
+            Ok(other) => self.to_tuple(py)?.eq(other.get().to_tuple(py)?),
+            Err(_) => self.to_tuple(py)?.eq(other),
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        let lang = self.lang.as_ref().map_or("None".to_string(), |lang| format!("{:?}", lang));
+        format!("CodeBlock(lang={}, value={:?})", lang, self.value)
+    }
+}
+
+/// A link as `(text, url, (line, column))`.
+type SectionLink = (String, String, Option<(usize, usize)>);
+
+/// Returns the code blocks of a document, optionally only those in `lang`.
+///
+/// With `concat=True` the code of all matching blocks is joined into a single string instead.
+#[pyfunction]
+#[pyo3(signature = (content, lang=None, concat=false, options=None))]
+pub(crate) fn code_blocks(
+    py: Python<'_>,
+    content: Content<'_>,
+    lang: Option<&str>,
+    concat: bool,
+    options: Option<Options>,
+) -> PyResult<Py<PyAny>> {
+    let mut blocks = Vec::new();
+
+    for value in input::parse(content.as_str()?, &options.unwrap_or_default())? {
+        if let mq_lang::RuntimeValue::Markdown(node, _) = value {
+            collect_code_blocks(&node, lang, &mut blocks);
+        }
+    }
+
+    if concat {
+        blocks
+            .into_iter()
+            .map(|block| block.value + "\n")
+            .collect::<String>()
+            .into_py_any(py)
+    } else {
+        blocks.into_py_any(py)
+    }
+}
+
+/// Calls `f` with each code block, optionally only those in `lang`, and returns the document with
+/// the blocks updated.
+///
+/// `f` returns the new code as a string, a node from `mq.nodes` to replace the block with, or `None`
+/// to leave the block as it is.
+#[pyfunction]
+#[pyo3(signature = (content, f, lang=None, options=None))]
+pub(crate) fn update_code_blocks(
+    content: Content<'_>,
+    f: &Bound<'_, PyAny>,
+    lang: Option<&str>,
+    options: Option<Options>,
+) -> PyResult<String> {
+    let mut error = None;
+
+    let nodes = transform::rewrite(transform::parse(&content)?, &mut |node| {
+        let Node::Code(mut code) = node else {
+            return vec![node];
+        };
+
+        if error.is_some() || lang.is_some_and(|lang| code.lang.as_deref() != Some(lang)) {
+            return vec![Node::Code(code)];
+        }
+
+        let updated = f.call1((CodeBlock::from(&code),)).and_then(|updated| {
+            if updated.is_none() {
+                Ok(None)
+            } else if let Ok(node) = updated.cast::<nodes::Node>() {
+                // The block's position keeps the blank lines around it when rendering.
+                let mut node = node.borrow().node.clone();
+                node.set_position(code.position.clone());
+                Ok(Some(node))
+            } else if let Ok(value) = updated.extract::<String>() {
+                code.value = value.trim_end_matches('\n').to_string();
+                Ok(Some(Node::Code(code.clone())))
+            } else {
+                Err(PyTypeError::new_err(
+                    "code block function must return a str, a node or None",
+                ))
+            }
+        });
+
+        match updated {
+            Ok(updated) => vec![updated.unwrap_or(Node::Code(code))],
+            Err(e) => {
+                error = Some(e);
+                vec![Node::Code(code)]
+            }
+        }
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => render::to_markdown(nodes, options),
+    }
+}
+
+/// Returns the tables of a document, each as a list of rows keyed by the header cells' text.
+///
+/// Cells are read as plain text, so inline markup such as emphasis, code and links is dropped.
+#[pyfunction]
+#[pyo3(signature = (content, options=None))]
+pub(crate) fn extract_tables(content: Content<'_>, options: Option<Options>) -> PyResult<Vec<Vec<table::Record>>> {
+    let mut tables: Vec<Vec<mq_markdown::TableCell>> = Vec::new();
+
+    for value in input::parse(content.as_str()?, &options.unwrap_or_default())? {
+        if let mq_lang::RuntimeValue::Markdown(node, _) = value
+            && let Node::TableCell(cell) = *node
+        {
+            // Table cells are top-level values, so a table starts at each first header cell.
+            match tables.last_mut() {
+                Some(table) if (cell.row, cell.column) != (0, 0) => table.push(cell),
+                _ => tables.push(vec![cell]),
+            }
+        }
+    }
+
+    Ok(tables.into_iter().map(table::records).collect())
+}
+
+fn collect_code_blocks(node: &Node, lang: Option<&str>, blocks: &mut Vec<CodeBlock>) {
+    if let Node::Code(code) = node {
+        if lang.is_none() || code.lang.as_deref() == lang {
+            blocks.push(CodeBlock::from(code));
+        }
+
+        return;
+    }
+
+    for child in node.children() {
+        collect_code_blocks(&child, lang, blocks);
+    }
+}
+
+/// Returns the MDX JSX elements of a document in order, including nested ones, optionally only
+/// those of the component `name`.
+///
+/// The content is parsed as MDX whatever the input format of `options`.
+#[pyfunction]
+#[pyo3(signature = (content, name=None, options=None))]
+pub(crate) fn mdx_components(
+    content: Content<'_>,
+    name: Option<&str>,
+    options: Option<Options>,
+) -> PyResult<Vec<MQValue>> {
+    let options = Options {
+        input_format: Some(InputFormat::Mdx),
+        ..options.unwrap_or_default()
+    };
+    let content = content.as_str()?;
+    let input = input::parse(content, &options)?;
+    let annotations = input::Annotations::new(&input, content, &options)?;
+
+    let mut elements = Vec::new();
+    input
+        .iter()
+        .filter_map(|value| match value {
+            mq_lang::RuntimeValue::Markdown(node, _) => Some(node),
+            _ => None,
+        })
+        .for_each(|node| collect_mdx_elements(node, &mut elements));
+
+    elements
+        .into_iter()
+        .map(|node| {
+            let mut value = MQValue::try_from(mq_lang::RuntimeValue::Markdown(Box::new(node), None))?;
+            annotations.apply(&mut value);
+            Ok(value)
+        })
+        .filter(|value| name.is_none() || value.as_ref().is_ok_and(|value| value.component().as_deref() == name))
+        .collect()
+}
+
+fn collect_mdx_elements(node: &Node, elements: &mut Vec<Node>) {
+    if matches!(node, Node::MdxJsxFlowElement(_) | Node::MdxJsxTextElement(_)) {
+        elements.push(node.clone());
+    }
+
+    for child in node.children() {
+        collect_mdx_elements(&child, elements);
+    }
+}
+
+/// Returns the outgoing links of each section by heading path, in document order.
+///
+/// Every section is included, with an empty list when it has no links; links before the first
+/// heading are under the empty path `()`. Reference links are resolved through their definitions,
+/// while `#fragment` links to the same document and unresolved references are left out.
+#[pyfunction]
+pub(crate) fn links_by_section<'py>(py: Python<'py>, content: Content<'_>) -> PyResult<Bound<'py, PyDict>> {
+    let nodes = transform::parse(&content)?;
+    let mut definitions = HashMap::new();
+    nodes.iter().for_each(|node| collect_definitions(node, &mut definitions));
+
+    let mut sections = IndexMap::<Vec<String>, Vec<SectionLink>>::new();
+    let mut path: Vec<(u8, String)> = Vec::new();
+
+    for node in &nodes {
+        if let Node::Heading(heading) = node {
+            while path.last().is_some_and(|(depth, _)| *depth >= heading.depth) {
+                path.pop();
+            }
+
+            path.push((heading.depth, section::plain_text(node)));
+            sections
+                .entry(path.iter().map(|(_, text)| text.clone()).collect())
+                .or_default();
+        }
+
+        let key = path.iter().map(|(_, text)| text.clone()).collect::<Vec<_>>();
+        let mut links = Vec::new();
+        collect_links(node, &definitions, &mut links);
+
+        if !links.is_empty() {
+            sections.entry(key).or_default().extend(links);
+        }
+    }
+
+    let dict = PyDict::new(py);
+
+    for (path, links) in sections {
+        dict.set_item(PyTuple::new(py, path)?, links)?;
+    }
+
+    Ok(dict)
+}
+
+pub(crate) fn collect_definitions(node: &Node, definitions: &mut HashMap<String, String>) {
+    if let Node::Definition(definition) = node {
+        definitions
+            .entry(definition.ident.to_lowercase())
+            .or_insert_with(|| definition.url.as_str().to_string());
+    }
+
+    for child in node.children() {
+        collect_definitions(&child, definitions);
+    }
+}
+
+fn collect_links(node: &Node, definitions: &HashMap<String, String>, links: &mut Vec<SectionLink>) {
+    let (text, url, position) = match node {
+        Node::Link(link) => (
+            section::plain_text(node),
+            Some(link.url.as_str().to_string()),
+            link.position.as_ref(),
+        ),
+        Node::LinkRef(link) => (
+            link.values.iter().map(section::plain_text).collect(),
+            definitions.get(&link.ident.to_lowercase()).cloned(),
+            link.position.as_ref(),
+        ),
+        node => {
+            for child in node.children() {
+                collect_links(&child, definitions, links);
+            }
+
+            return;
+        }
+    };
+
+    if let Some(url) = url.filter(|url| !url.starts_with('#')) {
+        links.push((text, url, position.map(|p| (p.start.line, p.start.column))));
+    }
+}
+
+/// Parses the meta part of a code block info string into `key=value` attributes, in order.
+///
+/// Values may be quoted with `"` or `'`; bare words such as `linenums` and brace groups such as
+/// `{1,3-5}` are flags without a value.
+pub(crate) fn parse_meta(meta: &str) -> Vec<(String, Option<String>)> {
+    let mut attributes = Vec::new();
+    let mut chars = meta.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut key = String::new();
+
+        if c == '{' {
+            for c in chars.by_ref() {
+                key.push(c);
+
+                if c == '}' {
+                    break;
+                }
+            }
+
+            attributes.push((key, None));
+            continue;
+        }
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '=' {
+                break;
+            }
+
+            key.push(c);
+            chars.next();
+        }
+
+        let mut value = None;
+
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            let mut text = String::new();
+
+            match chars.peek() {
+                Some(&quote @ ('"' | '\'')) => {
+                    chars.next();
+
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' if chars.peek() == Some(&quote) => text.extend(chars.next()),
+                            c if c == quote => break,
+                            c => text.push(c),
+                        }
+                    }
+                }
+                _ => {
+                    while let Some(&c) = chars.peek() {
+                        if c.is_whitespace() {
+                            break;
+                        }
+
+                        text.push(c);
+                        chars.next();
+                    }
+                }
+            }
+
+            value = Some(text);
+        }
+
+        if !key.is_empty() {
+            attributes.push((key, value));
+        }
+    }
+
+    attributes
+}
+
+/// A query match with the blocks around it, as markdown.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct Snippet {
+    #[pyo3(get)]
+    value: MQValue,
+    /// The block containing the match, `None` for matches without a position.
+    #[pyo3(get)]
+    block: Option<String>,
+    #[pyo3(get)]
+    before: Vec<String>,
+    #[pyo3(get)]
+    after: Vec<String>,
+}
+
+#[pymethods]
+impl Snippet {
+    /// Returns the context blocks and the block containing the match as one markdown document.
+    fn to_markdown(&self) -> String {
+        let block = self.block.clone().unwrap_or_else(|| self.value.text());
+        let mut markdown = self
+            .before
+            .iter()
+            .chain(std::iter::once(&block))
+            .chain(self.after.iter())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        markdown.push('\n');
+        markdown
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Snippet(value={:?}, before={}, after={})",
+            self.value.text(),
+            self.before.len(),
+            self.after.len()
+        )
+    }
+}
+
+/// Runs `query` and returns each non-empty match with up to `before` and `after` blocks around the
+/// block that contains it, e.g. for search result previews.
+///
+/// Blocks are the runs of the document separated by blank lines, such as paragraphs, whole lists
+/// and tables.
+#[pyfunction]
+#[pyo3(signature = (content, query, before=1, after=1, options=None))]
+pub(crate) fn extract_with_context(
+    content: Content<'_>,
+    query: &str,
+    before: usize,
+    after: usize,
+    options: Option<Options>,
+) -> PyResult<Vec<Snippet>> {
+    let options = options.unwrap_or_default();
+    let content = content.as_str()?;
+    let input = input::parse(content, &options)?;
+    let annotations = input::Annotations::new(&input, content, &options)?;
+    let values = engine::eval(query, input.clone(), &options)?;
+
+    // Each block as its first line and the nodes it consists of.
+    let mut blocks: Vec<(usize, Vec<Node>)> = Vec::new();
+    let mut end = 0;
+
+    for value in input {
+        let mq_lang::RuntimeValue::Markdown(node, _) = value else {
+            continue;
+        };
+
+        match node.position() {
+            Some(position) if blocks.is_empty() || position.start.line > end + 1 => {
+                end = position.end.line;
+                blocks.push((position.start.line, vec![*node]));
+            }
+            position => {
+                end = end.max(position.map_or(end, |position| position.end.line));
+
+                if let Some((_, nodes)) = blocks.last_mut() {
+                    nodes.push(*node);
+                }
+            }
+        }
+    }
+
+    let blocks = blocks
+        .into_iter()
+        .map(|(line, nodes)| {
+            let markdown = render::to_markdown(nodes, Some(options.clone()))?;
+            Ok((line, markdown.trim_end().to_string()))
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    let texts = |range: std::ops::Range<usize>| blocks[range].iter().map(|(_, text)| text.clone()).collect();
+
+    let mut snippets = Vec::new();
+
+    for value in values {
+        let mut value = MQValue::try_from(value)?;
+
+        if value.__len__() == 0 {
+            continue;
+        }
+
+        annotations.apply(&mut value);
+
+        let line = value.as_node().and_then(|node| node.position()).map(|p| p.start.line);
+        let index = line.and_then(|line| blocks.iter().rposition(|(start, _)| *start <= line));
+
+        snippets.push(match index {
+            Some(i) => Snippet {
+                block: Some(blocks[i].1.clone()),
+                before: texts(i.saturating_sub(before)..i),
+                after: texts(i + 1..(i + 1 + after).min(blocks.len())),
+                value,
+            },
+            None => Snippet {
+                value,
+                block: None,
+                before: Vec::new(),
+                after: Vec::new(),
+            },
+        });
+    }
+
+    Ok(snippets)
+}
@@ -0,0 +1,255 @@
+//! A fluent selector API that builds mq queries from Python method calls.
+//!
+//! `mq.doc(content).headings(level=2).text()` compiles to `.h2 | to_text()` and runs it against
+//! the document, so users can start without knowing the query language and read the generated
+//! query from `Selection.query` when they outgrow it.
+use crate::{Options, content::Content, eval, result::MQResult, value::MQValue};
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyBool, PyFloat, PyInt, PyIterator, PyList, PyString, PyTuple},
+};
+
+#[pyclass(skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct Selection {
+    content: String,
+    options: Options,
+    #[pyo3(get)]
+    query: String,
+}
+
+impl Selection {
+    fn then(&self, step: &str) -> Self {
+        Self {
+            content: self.content.clone(),
+            options: self.options.clone(),
+            query: if self.query == "self" {
+                step.to_string()
+            } else {
+                format!("{} | {}", self.query, step)
+            },
+        }
+    }
+
+    fn eval(&self, step: Option<&str>) -> PyResult<MQResult> {
+        let query = match step {
+            Some(step) => self.then(step).query,
+            None => self.query.clone(),
+        };
+
+        Python::attach(|py| eval(py, &query, &self.content, &self.options))
+    }
+}
+
+#[pymethods]
+impl Selection {
+    #[pyo3(signature = (level=None))]
+    pub fn headings(&self, level: Option<u8>) -> PyResult<Self> {
+        match level {
+            Some(level @ 1..=6) => Ok(self.then(&format!(".h{}", level))),
+            Some(level) => Err(PyValueError::new_err(format!(
+                "Heading level must be between 1 and 6, got {}",
+                level
+            ))),
+            None => Ok(self.then(".h")),
+        }
+    }
+
+    pub fn links(&self) -> Self {
+        self.then(".link")
+    }
+
+    pub fn images(&self) -> Self {
+        self.then(".image")
+    }
+
+    #[pyo3(signature = (lang=None))]
+    pub fn code(&self, lang: Option<&str>) -> Self {
+        match lang {
+            Some(lang) => self.then(&format!(".code({})", string_literal(lang))),
+            None => self.then(".code"),
+        }
+    }
+
+    pub fn lists(&self) -> Self {
+        self.then(".list")
+    }
+
+    pub fn blockquotes(&self) -> Self {
+        self.then(".blockquote")
+    }
+
+    pub fn table_cells(&self) -> Self {
+        self.then(".[][]")
+    }
+
+    /// Keeps only values whose text contains `text`.
+    pub fn contains(&self, text: &str) -> Self {
+        self.then(&format!("select(contains(to_text(self), {}))", string_literal(text)))
+    }
+
+    /// Appends an arbitrary mq query step.
+    pub fn select(&self, query: &str) -> Self {
+        self.then(query)
+    }
+
+    pub fn run(&self) -> PyResult<MQResult> {
+        self.eval(None)
+    }
+
+    pub fn values(&self) -> PyResult<Vec<MQValue>> {
+        Ok(self
+            .eval(None)?
+            .values
+            .into_iter()
+            .filter(|value| value.__len__() > 0)
+            .collect())
+    }
+
+    pub fn first(&self) -> PyResult<Option<MQValue>> {
+        Ok(self.values()?.into_iter().next())
+    }
+
+    pub fn markdown(&self) -> PyResult<Vec<String>> {
+        Ok(self.eval(None)?.values())
+    }
+
+    pub fn text(&self) -> PyResult<Vec<String>> {
+        Ok(self.eval(Some("to_text()"))?.values())
+    }
+
+    pub fn urls(&self) -> PyResult<Vec<String>> {
+        Ok(self.eval(Some("attr(self, \"url\")"))?.values())
+    }
+
+    pub fn __len__(&self) -> PyResult<usize> {
+        Ok(self.values()?.len())
+    }
+
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        PyList::new(py, self.values()?)?.try_iter()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Selection({:?})", self.query)
+    }
+}
+
+/// Starts a fluent selection over a document.
+#[pyfunction]
+#[pyo3(signature = (content, options=None))]
+pub(crate) fn doc(content: Content<'_>, options: Option<Options>) -> PyResult<Selection> {
+    Ok(Selection {
+        content: content.as_str()?.to_string(),
+        options: options.unwrap_or_default(),
+        query: "self".to_string(),
+    })
+}
+
+/// Builds a query by substituting each `?` outside string literals with the next argument.
+///
+/// Arguments are rendered as mq literals, so user-provided text can never change the
+/// structure of the query.
+#[pyfunction]
+#[pyo3(name = "Q", signature = (template, *args))]
+pub(crate) fn query(template: &str, args: &Bound<'_, PyTuple>) -> PyResult<String> {
+    let mut args = args.iter();
+    let mut query = String::with_capacity(template.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut placeholders = 0;
+
+    for ch in template.chars() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '?' if !in_string => {
+                placeholders += 1;
+
+                match args.next() {
+                    Some(arg) => query.push_str(&literal(&arg)?),
+                    None => {
+                        return Err(PyValueError::new_err(format!(
+                            "Not enough arguments for query template: {:?}",
+                            template
+                        )));
+                    }
+                }
+
+                continue;
+            }
+            _ => {}
+        }
+
+        query.push(ch);
+    }
+
+    let extra = args.count();
+
+    if extra > 0 {
+        return Err(PyValueError::new_err(format!(
+            "Query template has {} placeholders but {} arguments were given",
+            placeholders,
+            placeholders + extra
+        )));
+    }
+
+    Ok(query)
+}
+
+/// Renders a Python value as an mq literal.
+fn literal(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    if value.is_none() {
+        Ok("None".to_string())
+    } else if let Ok(b) = value.cast::<PyBool>() {
+        Ok(b.is_true().to_string())
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(value.extract::<i64>()?.to_string())
+    } else if value.is_instance_of::<PyFloat>() {
+        let n = value.extract::<f64>()?;
+
+        if n.is_finite() {
+            Ok(n.to_string())
+        } else {
+            Err(PyValueError::new_err(format!("Cannot use {} in a query", n)))
+        }
+    } else if value.is_instance_of::<PyString>() {
+        Ok(string_literal(&value.extract::<String>()?))
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        Ok(format!(
+            "[{}]",
+            value
+                .try_iter()?
+                .map(|item| literal(&item?))
+                .collect::<PyResult<Vec<_>>>()?
+                .join(", ")
+        ))
+    } else {
+        Err(PyTypeError::new_err(format!(
+            "Cannot use a value of type {} in a query",
+            value.get_type().name()?
+        )))
+    }
+}
+
+/// Quotes `s` as an mq string literal.
+pub(crate) fn string_literal(s: &str) -> String {
+    let mut literal = String::with_capacity(s.len() + 2);
+    literal.push('"');
+
+    for ch in s.chars() {
+        match ch {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            ch => literal.push(ch),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
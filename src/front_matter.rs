@@ -0,0 +1,317 @@
+//! Reading and rewriting the YAML (`---`) or TOML (`+++`) front matter at the start of a document.
+use crate::{MQParseInputError, content::Content};
+use pyo3::{
+    IntoPyObjectExt,
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple},
+};
+use yaml_rust2::{Yaml, YamlEmitter, YamlLoader};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    fn fence(self) -> &'static str {
+        match self {
+            Format::Yaml => "---",
+            Format::Toml => "+++",
+        }
+    }
+}
+
+struct FrontMatter<'a> {
+    format: Format,
+    /// The lines between the fences.
+    source: &'a str,
+    /// Everything after the closing fence.
+    body: &'a str,
+}
+
+/// Splits off the front matter, which must start on the first line and be closed by the same fence.
+fn split(content: &str) -> Option<FrontMatter<'_>> {
+    let mut lines = content.split_inclusive('\n');
+    let first = lines.next()?;
+    let format = match first.trim_end() {
+        "---" => Format::Yaml,
+        "+++" => Format::Toml,
+        _ => return None,
+    };
+    let start = first.len();
+    let mut offset = start;
+
+    for line in lines {
+        if line.trim_end() == format.fence() {
+            return Some(FrontMatter {
+                format,
+                source: &content[start..offset],
+                body: &content[offset + line.len()..],
+            });
+        }
+
+        offset += line.len();
+    }
+
+    None
+}
+
+/// Returns the front matter of `content` as a dict, empty when the document has none.
+///
+/// TOML dates and times are returned as strings.
+#[pyfunction]
+pub(crate) fn get_front_matter<'py>(py: Python<'py>, content: Content<'_>) -> PyResult<Bound<'py, PyAny>> {
+    parse(py, content.as_str()?)
+}
+
+fn parse<'py>(py: Python<'py>, content: &str) -> PyResult<Bound<'py, PyAny>> {
+    let Some(front_matter) = split(content) else {
+        return Ok(PyDict::new(py).into_any());
+    };
+
+    match front_matter.format {
+        Format::Yaml => {
+            let docs = YamlLoader::load_from_str(front_matter.source)
+                .map_err(|e| MQParseInputError::new_err(format!("Error parsing YAML front matter: {}", e)))?;
+
+            match docs.into_iter().next() {
+                Some(hash @ Yaml::Hash(_)) => yaml_to_py(py, hash),
+                None | Some(Yaml::Null) => Ok(PyDict::new(py).into_any()),
+                Some(_) => Err(MQParseInputError::new_err(
+                    "Error parsing YAML front matter: expected a mapping",
+                )),
+            }
+        }
+        Format::Toml => {
+            let table = front_matter
+                .source
+                .parse::<toml::Table>()
+                .map_err(|e| MQParseInputError::new_err(format!("Error parsing TOML front matter: {}", e)))?;
+            toml_to_py(py, toml::Value::Table(table))
+        }
+    }
+}
+
+/// Which documents a batch run queries, by their front matter.
+pub(crate) enum Filter<'py> {
+    /// The values the front matter must have, compared with `==`.
+    Values(Bound<'py, PyDict>),
+    /// A callable taking the front matter dict and returning whether to query the document.
+    Predicate(Bound<'py, PyAny>),
+}
+
+impl<'py> Filter<'py> {
+    pub(crate) fn new(filter: Bound<'py, PyAny>) -> PyResult<Self> {
+        if filter.is_callable() {
+            return Ok(Filter::Predicate(filter));
+        }
+
+        filter
+            .cast_into::<PyDict>()
+            .map(Filter::Values)
+            .map_err(|_| PyTypeError::new_err("front_matter must be a dict of required values or a callable"))
+    }
+
+    /// Whether the front matter of `content` matches, raising `MQParseInputError` if it is invalid.
+    pub(crate) fn matches(&self, content: &str) -> PyResult<bool> {
+        match self {
+            Filter::Values(values) => {
+                let front_matter = parse(values.py(), content)?;
+
+                for (key, value) in values {
+                    match front_matter.get_item(&key) {
+                        Ok(actual) if actual.eq(&value)? => {}
+                        _ => return Ok(false),
+                    }
+                }
+
+                Ok(true)
+            }
+            Filter::Predicate(predicate) => predicate.call1((parse(predicate.py(), content)?,))?.is_truthy(),
+        }
+    }
+}
+
+/// Replaces the front matter of `content` with `front_matter`, leaving the body as it is.
+///
+/// `format` is `"yaml"` or `"toml"`, by default that of the existing front matter or YAML if there
+/// is none. An empty dict removes the front matter.
+#[pyfunction]
+#[pyo3(signature = (content, front_matter, format=None))]
+pub(crate) fn set_front_matter(
+    content: Content<'_>,
+    front_matter: &Bound<'_, PyDict>,
+    format: Option<&str>,
+) -> PyResult<String> {
+    let content = content.as_str()?;
+    let existing = split(content);
+    let body = existing.as_ref().map_or(content, |existing| existing.body);
+    let format = match format {
+        Some("yaml") => Format::Yaml,
+        Some("toml") => Format::Toml,
+        Some(format) => {
+            return Err(PyValueError::new_err(format!(
+                "Invalid front matter format: {:?}, expected \"yaml\" or \"toml\"",
+                format
+            )));
+        }
+        None => existing.map_or(Format::Yaml, |existing| existing.format),
+    };
+
+    if front_matter.is_empty() {
+        return Ok(body.to_string());
+    }
+
+    let source = match format {
+        Format::Yaml => {
+            let mut out = String::new();
+            YamlEmitter::new(&mut out)
+                .dump(&py_to_yaml(front_matter.as_any(), "front_matter")?)
+                .map_err(|e| PyValueError::new_err(format!("Error writing YAML front matter: {}", e)))?;
+            // The emitter starts the document with its own `---` line.
+            format!("{}\n", out.trim_start_matches("---").trim_start_matches('\n'))
+        }
+        Format::Toml => match py_to_toml(front_matter.as_any(), "front_matter")? {
+            toml::Value::Table(table) => table.to_string(),
+            _ => unreachable!("a dict is converted to a table"),
+        },
+    };
+
+    Ok(format!("{}\n{}{}\n{}", format.fence(), source, format.fence(), body))
+}
+
+fn yaml_to_py<'py>(py: Python<'py>, yaml: Yaml) -> PyResult<Bound<'py, PyAny>> {
+    match yaml {
+        Yaml::Real(_) => yaml.as_f64().into_bound_py_any(py),
+        Yaml::Integer(i) => i.into_bound_py_any(py),
+        Yaml::String(s) => s.into_bound_py_any(py),
+        Yaml::Boolean(b) => b.into_bound_py_any(py),
+        Yaml::Array(array) => PyList::new(
+            py,
+            array
+                .into_iter()
+                .map(|item| yaml_to_py(py, item))
+                .collect::<PyResult<Vec<_>>>()?,
+        )
+        .map(Bound::into_any),
+        Yaml::Hash(hash) => {
+            let dict = PyDict::new(py);
+
+            for (key, value) in hash {
+                dict.set_item(yaml_to_py(py, key)?, yaml_to_py(py, value)?)?;
+            }
+
+            Ok(dict.into_any())
+        }
+        Yaml::Null | Yaml::Alias(_) | Yaml::BadValue => Ok(py.None().into_bound(py)),
+    }
+}
+
+fn toml_to_py<'py>(py: Python<'py>, value: toml::Value) -> PyResult<Bound<'py, PyAny>> {
+    match value {
+        toml::Value::String(s) => s.into_bound_py_any(py),
+        toml::Value::Integer(i) => i.into_bound_py_any(py),
+        toml::Value::Float(f) => f.into_bound_py_any(py),
+        toml::Value::Boolean(b) => b.into_bound_py_any(py),
+        toml::Value::Datetime(datetime) => datetime.to_string().into_bound_py_any(py),
+        toml::Value::Array(array) => PyList::new(
+            py,
+            array
+                .into_iter()
+                .map(|item| toml_to_py(py, item))
+                .collect::<PyResult<Vec<_>>>()?,
+        )
+        .map(Bound::into_any),
+        toml::Value::Table(table) => {
+            let dict = PyDict::new(py);
+
+            for (key, value) in table {
+                dict.set_item(key, toml_to_py(py, value)?)?;
+            }
+
+            Ok(dict.into_any())
+        }
+    }
+}
+
+fn unsupported(value: &Bound<'_, PyAny>, path: &str, format: &str) -> PyErr {
+    match value.get_type().name() {
+        Ok(name) => PyTypeError::new_err(format!(
+            "{}: cannot write a value of type {} to {} front matter",
+            path, name, format
+        )),
+        Err(e) => e,
+    }
+}
+
+fn py_to_yaml(value: &Bound<'_, PyAny>, path: &str) -> PyResult<Yaml> {
+    if value.is_none() {
+        Ok(Yaml::Null)
+    } else if let Ok(b) = value.cast::<PyBool>() {
+        Ok(Yaml::Boolean(b.is_true()))
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(Yaml::Integer(value.extract::<i64>()?))
+    } else if value.is_instance_of::<PyFloat>() {
+        let f = value.extract::<f64>()?;
+        Ok(Yaml::Real(match f {
+            f if f.is_nan() => ".nan".to_string(),
+            f if f.is_infinite() => (if f > 0.0 { ".inf" } else { "-.inf" }).to_string(),
+            // Debug formatting always includes a decimal point or exponent, so it reads back as a float.
+            f => format!("{:?}", f),
+        }))
+    } else if value.is_instance_of::<PyString>() {
+        Ok(Yaml::String(value.extract::<String>()?))
+    } else if let Ok(dict) = value.cast::<PyDict>() {
+        dict.iter()
+            .map(|(k, v)| {
+                let value = py_to_yaml(&v, &format!("{}[{}]", path, k.repr()?))?;
+                Ok((py_to_yaml(&k, path)?, value))
+            })
+            .collect::<PyResult<_>>()
+            .map(Yaml::Hash)
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        value
+            .try_iter()?
+            .enumerate()
+            .map(|(i, item)| py_to_yaml(&item?, &format!("{}[{}]", path, i)))
+            .collect::<PyResult<_>>()
+            .map(Yaml::Array)
+    } else {
+        Err(unsupported(value, path, "YAML"))
+    }
+}
+
+fn py_to_toml(value: &Bound<'_, PyAny>, path: &str) -> PyResult<toml::Value> {
+    if let Ok(b) = value.cast::<PyBool>() {
+        Ok(toml::Value::Boolean(b.is_true()))
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(toml::Value::Integer(value.extract::<i64>()?))
+    } else if value.is_instance_of::<PyFloat>() {
+        Ok(toml::Value::Float(value.extract::<f64>()?))
+    } else if value.is_instance_of::<PyString>() {
+        Ok(toml::Value::String(value.extract::<String>()?))
+    } else if let Ok(dict) = value.cast::<PyDict>() {
+        dict.iter()
+            .map(|(k, v)| {
+                let key = k
+                    .extract::<String>()
+                    .map_err(|_| PyTypeError::new_err(format!("{}: TOML keys must be strings", path)))?;
+                let value = py_to_toml(&v, &format!("{}[{:?}]", path, key))?;
+                Ok((key, value))
+            })
+            .collect::<PyResult<_>>()
+            .map(toml::Value::Table)
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        value
+            .try_iter()?
+            .enumerate()
+            .map(|(i, item)| py_to_toml(&item?, &format!("{}[{}]", path, i)))
+            .collect::<PyResult<_>>()
+            .map(toml::Value::Array)
+    } else {
+        // TOML has no null, so None is rejected here too.
+        Err(unsupported(value, path, "TOML"))
+    }
+}
@@ -0,0 +1,988 @@
+//! Parsing of input documents into runtime values.
+//!
+//! Markdown input can optionally be parsed in parallel: the document is split
+//! at top-level block boundaries (never inside fenced code, math blocks, raw
+//! HTML blocks or front matter) and each chunk is parsed on its own thread.
+use crate::{
+    HtmlPolicy, InputFormat, MQParseInputError, Options, UnicodeNormalization, anchor, delimited, sanitize,
+    section, transform,
+    value::MQValue,
+};
+use mq_lang::RuntimeValue;
+use mq_markdown::{Markdown, Node, TableAlignKind};
+use pyo3::prelude::*;
+use std::{
+    collections::{HashMap, VecDeque},
+    num::NonZeroUsize,
+};
+
+/// Documents smaller than this are always parsed on the calling thread.
+const MIN_PARALLEL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How deeply HTML elements may nest when no limit is set. Converting HTML recurses once per
+/// level, so deeper documents could exhaust the stack.
+const DEFAULT_MAX_HTML_DEPTH: usize = 512;
+
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+];
+
+/// Elements whose content is text up to their closing tag.
+const RAW_TEXT_ELEMENTS: [&str; 4] = ["script", "style", "textarea", "title"];
+
+const OPTIONAL_END_ELEMENTS: [&str; 8] = ["p", "li", "dt", "dd", "tr", "td", "th", "option"];
+
+pub(crate) fn parse(content: &str, options: &Options) -> PyResult<Vec<RuntimeValue>> {
+    Ok(finish(parse_values(content, options)?, options))
+}
+
+/// Parses `content` like `parse`, without the steps that depend on the whole document.
+pub(crate) fn parse_values(content: &str, options: &Options) -> PyResult<Vec<RuntimeValue>> {
+    let normalized;
+    let content = match options.unicode_normalization {
+        Some(form) => {
+            normalized = normalize_unicode(content, form)?;
+            normalized.as_str()
+        }
+        None => content,
+    };
+    let format = options.input_format.unwrap_or(InputFormat::Markdown);
+    match format {
+        InputFormat::Markdown if options.parallel_parse => parse_markdown_parallel(content),
+        InputFormat::Markdown => mq_lang::parse_markdown_input(content),
+        InputFormat::Mdx => mq_lang::parse_mdx_input(content),
+        InputFormat::Text => mq_lang::parse_text_input(content),
+        InputFormat::Html => {
+            check_html_depth(content, options.max_html_depth)?;
+
+            match &options.html_selector {
+                Some(selector) => mq_lang::parse_html_input(&select_html(content, selector)?),
+                None => mq_lang::parse_html_input(content),
+            }
+        }
+        InputFormat::Raw => Ok(mq_lang::raw_input(content)),
+        InputFormat::Null => Ok(mq_lang::null_input()),
+        InputFormat::Csv => Ok(delimited::parse(content, ',')?),
+        InputFormat::Tsv => Ok(delimited::parse(content, '\t')?),
+    }
+    .map_err(|e| MQParseInputError::new_err(format!("Error evaluating query: {}", e)))
+}
+
+/// Numbers the list items of parsed markdown and MDX values and applies `Options.html_policy`.
+pub(crate) fn finish(mut values: Vec<RuntimeValue>, options: &Options) -> Vec<RuntimeValue> {
+    let format = options.input_format.unwrap_or(InputFormat::Markdown);
+
+    if matches!(format, InputFormat::Markdown | InputFormat::Mdx) {
+        number_list_items(&mut values);
+    }
+
+    match options.html_policy.unwrap_or_default() {
+        HtmlPolicy::Keep => values,
+        _ if !matches!(format, InputFormat::Markdown | InputFormat::Mdx) => values,
+        // Inline tags are values of their own, so an element can span several values.
+        policy => apply_html_policy(
+            values
+                .into_iter()
+                .filter_map(|value| match value {
+                    RuntimeValue::Markdown(node, _) => Some(*node),
+                    _ => None,
+                })
+                .collect(),
+            policy,
+        )
+        .into_iter()
+        .map(RuntimeValue::from)
+        .collect(),
+    }
+}
+
+/// Sets the index of every list item to its position within its own list.
+///
+/// The parser numbers the items of nested lists together with those of the enclosing list.
+fn number_list_items(values: &mut [RuntimeValue]) {
+    // The open lists as `(level, ordered, item count)`, outermost first.
+    let mut lists: Vec<(u8, bool, usize)> = Vec::new();
+
+    for value in values.iter_mut() {
+        let RuntimeValue::Markdown(node, _) = value else {
+            lists.clear();
+            continue;
+        };
+        let Node::List(list) = node.as_mut() else {
+            lists.clear();
+            continue;
+        };
+
+        while lists.last().is_some_and(|(l, _, _)| *l > list.level) {
+            lists.pop();
+        }
+
+        match lists.last_mut() {
+            Some((l, ordered, count)) if *l == list.level && *ordered == list.ordered => {
+                list.index = *count;
+                *count += 1;
+            }
+            _ => {
+                if lists.last().is_some_and(|(l, _, _)| *l == list.level) {
+                    lists.pop();
+                }
+
+                list.index = 0;
+                lists.push((list.level, list.ordered, 1));
+            }
+        }
+    }
+}
+
+/// The details of the input document that values are annotated with: their sections, the start
+/// numbers of ordered lists, the tables of cells and the anchors of headings.
+pub(crate) struct Annotations {
+    outline: section::Outline,
+    list_starts: HashMap<(usize, usize), usize>,
+    table_cells: HashMap<(usize, usize), (usize, Option<String>)>,
+    anchors: HashMap<(usize, usize), String>,
+    line_starts: Vec<usize>,
+}
+
+impl Annotations {
+    pub(crate) fn new(input: &[RuntimeValue], content: &str, options: &Options) -> PyResult<Self> {
+        Ok(Self {
+            outline: section::Outline::new(input),
+            list_starts: list_starts(input, content, options),
+            table_cells: table_cells(input),
+            anchors: anchor::heading_slugs(input, options)?,
+            line_starts: line_starts(content, options),
+        })
+    }
+
+    pub(crate) fn apply(&self, value: &mut MQValue) {
+        self.outline.assign(value);
+        value.set_list_start(&self.list_starts);
+        value.set_table_cell(&self.table_cells);
+        value.set_anchor(&self.anchors);
+        value.set_offsets(&self.line_starts);
+    }
+}
+
+/// Returns the byte offset of every line of `content`, or nothing when positions do not refer to
+/// the input lines.
+pub(crate) fn line_starts(content: &str, options: &Options) -> Vec<usize> {
+    if !matches!(
+        options.input_format.unwrap_or(InputFormat::Markdown),
+        InputFormat::Markdown | InputFormat::Mdx | InputFormat::Csv | InputFormat::Tsv
+    ) || options.unicode_normalization.is_some()
+    {
+        return Vec::new();
+    }
+
+    std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect()
+}
+
+/// Returns the start number of the list of every ordered list item, keyed by item position.
+///
+/// The parser does not keep start numbers, so they are read from the marker of each list's first item.
+pub(crate) fn list_starts(
+    input: &[RuntimeValue],
+    content: &str,
+    options: &Options,
+) -> HashMap<(usize, usize), usize> {
+    let mut starts = HashMap::new();
+
+    // Positions refer to the input lines only for markdown input.
+    if !matches!(
+        options.input_format.unwrap_or(InputFormat::Markdown),
+        InputFormat::Markdown | InputFormat::Mdx
+    ) {
+        return starts;
+    }
+
+    let lines = content.lines().collect::<Vec<_>>();
+    // The start numbers of the open ordered lists as `(level, start)`, outermost first.
+    let mut lists: Vec<(u8, usize)> = Vec::new();
+
+    for value in input {
+        let RuntimeValue::Markdown(node, _) = value else {
+            continue;
+        };
+        let Node::List(list) = node.as_ref() else {
+            continue;
+        };
+        let Some(position) = &list.position else {
+            continue;
+        };
+
+        if !list.ordered {
+            continue;
+        }
+
+        if list.index == 0 {
+            // The item position is that of its content, so the marker is what precedes it.
+            let number = lines.get(position.start.line.saturating_sub(1)).and_then(|line| {
+                let marker = line.chars().take(position.start.column.saturating_sub(1)).collect::<String>();
+                let marker = marker.trim_end().strip_suffix(['.', ')'])?;
+                let digits = &marker[marker.trim_end_matches(|c: char| c.is_ascii_digit()).len()..];
+
+                digits.parse::<usize>().ok()
+            });
+
+            lists.retain(|(level, _)| *level < list.level);
+
+            match number {
+                Some(number) => lists.push((list.level, number)),
+                None => continue,
+            }
+        }
+
+        if let Some((_, start)) = lists.iter().rev().find(|(level, _)| *level == list.level) {
+            starts.insert((position.start.line, position.start.column), *start);
+        }
+    }
+
+    starts
+}
+
+/// Returns the table index and column alignment of every table cell, keyed by cell position.
+///
+/// Table cells are top-level values, so a table starts at each first header cell and its
+/// alignment row follows the header cells.
+pub(crate) fn table_cells(input: &[RuntimeValue]) -> HashMap<(usize, usize), (usize, Option<String>)> {
+    // The alignments of each table, and each cell's position with its table and column index.
+    let mut aligns: Vec<Vec<TableAlignKind>> = Vec::new();
+    let mut cells = Vec::new();
+
+    for value in input {
+        let RuntimeValue::Markdown(node, _) = value else {
+            continue;
+        };
+
+        match node.as_ref() {
+            Node::TableCell(cell) => {
+                if (cell.row, cell.column) == (0, 0) || aligns.is_empty() {
+                    aligns.push(Vec::new());
+                }
+
+                if let Some(position) = &cell.position {
+                    cells.push((position.start.line, position.start.column, aligns.len() - 1, cell.column));
+                }
+            }
+            Node::TableAlign(align) => {
+                if let Some(kinds) = aligns.last_mut() {
+                    kinds.clone_from(&align.align);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|(line, column, table, index)| {
+            let alignment = match aligns[table].get(index) {
+                Some(TableAlignKind::Left) => Some("left".to_string()),
+                Some(TableAlignKind::Right) => Some("right".to_string()),
+                Some(TableAlignKind::Center) => Some("center".to_string()),
+                _ => None,
+            };
+
+            ((line, column), (table, alignment))
+        })
+        .collect()
+}
+
+/// Normalizes `content` with Python's `unicodedata`, which already ships the Unicode tables.
+pub(crate) fn normalize_unicode(content: &str, form: UnicodeNormalization) -> PyResult<String> {
+    // ASCII text is unchanged by every normalization form.
+    if content.is_ascii() {
+        return Ok(content.to_string());
+    }
+
+    Python::attach(|py| {
+        py.import("unicodedata")?
+            .call_method1("normalize", (form.form(), content))?
+            .extract()
+    })
+}
+
+/// Strips the raw HTML among `nodes` and their children, keeping its text, or converts it to
+/// markdown nodes, keeping it when nothing converts.
+///
+/// An inline element is an opening and a closing tag node with the nodes between them, which is
+/// converted as a whole.
+fn apply_html_policy(nodes: Vec<Node>, policy: HtmlPolicy) -> Vec<Node> {
+    let mut rest = nodes
+        .into_iter()
+        .map(|mut node| {
+            let children = node.children();
+
+            if !children.is_empty() {
+                node.set_children(apply_html_policy(children, policy));
+            }
+
+            node
+        })
+        .collect::<VecDeque<_>>();
+    let mut nodes = Vec::with_capacity(rest.len());
+
+    while let Some(node) = rest.pop_front() {
+        let Node::Html(html) = &node else {
+            nodes.push(node);
+            continue;
+        };
+
+        if policy == HtmlPolicy::Strip {
+            let text = html_text(&html.value);
+
+            if !text.is_empty() {
+                nodes.push(Node::Text(mq_markdown::Text {
+                    value: text,
+                    position: html.position.clone(),
+                }));
+            }
+
+            continue;
+        }
+
+        match closing_tag(&html.value, &rest) {
+            Some(end) => {
+                let inner = rest.drain(..end).collect();
+                let close = rest.pop_front().expect("the closing tag follows the inner nodes");
+                nodes.extend(convert_inline_html(node, inner, close));
+            }
+            None => nodes.extend(convert_html(node)),
+        }
+    }
+
+    nodes
+}
+
+/// Converts a raw HTML node to markdown nodes, keeping it when nothing converts.
+fn convert_html(node: Node) -> Vec<Node> {
+    if check_html_depth(&node.value(), None).is_err() {
+        return vec![node];
+    }
+
+    let nodes = match Markdown::from_html_str(&node.value()) {
+        Ok(markdown) if !markdown.nodes.is_empty() => markdown.nodes,
+        _ => return vec![node],
+    };
+
+    // Converted positions are relative to the HTML fragment, so move them to where it starts.
+    let offset = node.position().map_or(0, |position| position.start.line.saturating_sub(1));
+
+    nodes
+        .into_iter()
+        .map(|mut converted| {
+            shift_lines(&mut converted, offset as isize);
+            converted
+        })
+        .collect()
+}
+
+/// The text that stands in for the content of an inline element while converting it.
+const INLINE_CONTENT_PLACEHOLDER: &str = "MQINLINECONTENTX";
+
+/// Converts the inline element made of the `open` and `close` tag nodes and the `inner` nodes
+/// between them, keeping all three when nothing converts.
+fn convert_inline_html(open: Node, inner: Vec<Node>, close: Node) -> Vec<Node> {
+    let position = open.position().zip(close.position()).map(|(start, end)| mq_markdown::Position {
+        start: start.start,
+        end: end.end,
+    });
+    let convert = |content: &str| {
+        Markdown::from_html_str(&format!("{}{}{}", open.value(), content, close.value()))
+            .map(|markdown| markdown.nodes)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut node| {
+                set_positions(&mut node, &position);
+                node
+            })
+            .collect::<Vec<_>>()
+    };
+    let converts = |nodes: &[Node]| !nodes.is_empty() && !nodes.iter().any(|node| matches!(node, Node::Html(_)));
+
+    // The inner nodes replace the placeholder, keeping their own markdown and positions.
+    let mut content = Some(inner.clone());
+    let converted = transform::rewrite(convert(INLINE_CONTENT_PLACEHOLDER), &mut |node| match node {
+        Node::Text(text) if text.value == INLINE_CONTENT_PLACEHOLDER && content.is_some() => {
+            content.take().unwrap_or_default()
+        }
+        node => vec![node],
+    });
+
+    if content.is_none() && converts(&converted) {
+        return converted;
+    }
+
+    // The placeholder ends up inside a value, e.g. of inline code, so convert the text itself.
+    if let Some(text) = inner
+        .iter()
+        .map(|node| match node {
+            Node::Text(text) => Some(text.value.as_str()),
+            _ => None,
+        })
+        .collect::<Option<String>>()
+    {
+        let converted = convert(&sanitize::escape(&text));
+
+        if converts(&converted) {
+            return converted;
+        }
+    }
+
+    [vec![open], inner, vec![close]].concat()
+}
+
+/// Returns the index in `rest` of the tag closing the inline element `html` opens, if it is a
+/// lone opening tag.
+fn closing_tag(html: &str, rest: &VecDeque<Node>) -> Option<usize> {
+    let opening = sanitize::parse_tag(html).filter(|tag| {
+        tag.len == html.len() && !tag.closing && !tag.self_closing && !VOID_ELEMENTS.contains(&tag.name.as_str())
+    })?;
+    let mut depth = 0;
+
+    for (i, node) in rest.iter().enumerate() {
+        let Node::Html(html) = node else {
+            continue;
+        };
+        let Some(tag) = sanitize::parse_tag(&html.value).filter(|tag| tag.len == html.value.len()) else {
+            continue;
+        };
+
+        if tag.name != opening.name || tag.self_closing {
+            continue;
+        }
+
+        match (tag.closing, depth) {
+            (true, 0) => return Some(i),
+            (true, _) => depth -= 1,
+            (false, _) => depth += 1,
+        }
+    }
+
+    None
+}
+
+fn set_positions(node: &mut Node, position: &Option<mq_markdown::Position>) {
+    node.set_position(position.clone());
+
+    let children = node.children();
+
+    if !children.is_empty() {
+        node.set_children(
+            children
+                .into_iter()
+                .map(|mut child| {
+                    set_positions(&mut child, position);
+                    child
+                })
+                .collect(),
+        );
+    }
+}
+
+/// The text of `html` without its tags, comments and the content of elements such as `<script>`,
+/// with each line trimmed and blank lines removed.
+fn html_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let Some(tag) = sanitize::parse_tag(rest) else {
+            text.push('<');
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.len..];
+
+        if !tag.closing && RAW_TEXT_ELEMENTS.contains(&tag.name.as_str()) {
+            rest = skip_raw_text(rest, &tag.name);
+        }
+    }
+
+    text.push_str(rest);
+    text.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join("\n")
+}
+
+/// Skips the content of the raw text element `name` up to its closing tag.
+fn skip_raw_text<'a>(html: &'a str, name: &str) -> &'a str {
+    let close = format!("</{}", name);
+
+    html.as_bytes()
+        .windows(close.len())
+        .position(|window| window.eq_ignore_ascii_case(close.as_bytes()))
+        .map_or("", |end| &html[end..])
+}
+
+/// Fails with `MQParseInputError` when elements in `html` nest deeper than `max`.
+///
+/// The depth is estimated from the tags alone, without building a tree, so documents that are too
+/// deep are rejected in linear time.
+pub(crate) fn check_html_depth(html: &str, max: Option<usize>) -> PyResult<()> {
+    let max = max.unwrap_or(DEFAULT_MAX_HTML_DEPTH);
+
+    // Every level opens a tag, so documents with fewer tags cannot nest too deeply.
+    if html.matches('<').count() <= max {
+        return Ok(());
+    }
+
+    let mut open = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let Some(tag) = sanitize::parse_tag(rest) else {
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.len..];
+
+        if tag.closing {
+            if let Some(i) = open.iter().rposition(|name| *name == tag.name) {
+                open.truncate(i);
+            }
+        } else if !tag.self_closing && !VOID_ELEMENTS.contains(&tag.name.as_str()) {
+            if RAW_TEXT_ELEMENTS.contains(&tag.name.as_str()) {
+                rest = skip_raw_text(rest, &tag.name);
+                continue;
+            }
+
+            // A repeated `<p>` or `<li>` closes the previous one.
+            if open.last() == Some(&tag.name) && OPTIONAL_END_ELEMENTS.contains(&tag.name.as_str()) {
+                open.pop();
+            }
+
+            open.push(tag.name);
+
+            if open.len() > max {
+                return Err(MQParseInputError::new_err(format!(
+                    "HTML input nests elements deeper than the maximum depth of {}",
+                    max
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn css_selector(selector: &str) -> PyResult<scraper::Selector> {
+    scraper::Selector::parse(selector).map_err(|e| {
+        pyo3::exceptions::PyValueError::new_err(format!("Invalid CSS selector '{}': {:?}", selector, e))
+    })
+}
+
+/// Elements that start a block of their own, rather than continuing the text around them.
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "audio", "blockquote", "details", "dialog", "div", "dl", "fieldset", "figure",
+    "footer", "form", "header", "iframe", "main", "nav", "ol", "pre", "section", "table", "ul", "video",
+];
+
+/// The text that stands in for the `index`th element kept as HTML while converting.
+pub(crate) fn kept_html_placeholder(index: usize) -> String {
+    format!("MQKEEPHTML{}X", index)
+}
+
+/// Prepares HTML for conversion to markdown: removes the elements matching any of `exclude`,
+/// replaces the elements with a tag in `keep` by placeholders and narrows the body to the elements
+/// matching `root`, keeping the head for the title and front matter.
+///
+/// Returns the HTML and the outer HTML of the kept elements, in placeholder order.
+pub(crate) fn scope_html(
+    content: &str,
+    root: Option<&str>,
+    exclude: &[String],
+    keep: &[String],
+) -> PyResult<(String, Vec<String>)> {
+    let mut html = scraper::Html::parse_document(content);
+
+    for selector in exclude {
+        let selector = css_selector(selector)?;
+        let ids = html.select(&selector).map(|element| element.id()).collect::<Vec<_>>();
+
+        for id in ids {
+            if let Some(mut node) = html.tree.get_mut(id) {
+                node.detach();
+            }
+        }
+    }
+
+    let kept = match keep {
+        [] => Vec::new(),
+        keep => replace_elements(
+            &mut html,
+            &css_selector(&keep.join(", "))?,
+            kept_html_placeholder,
+            |element| Ok(Some((element.html(), is_block(&element)))),
+        )?,
+    };
+
+    let html = match root {
+        Some(root) => {
+            let head = css_selector("head")?;
+            let head = html.select(&head).next().map(|head| head.html()).unwrap_or_default();
+            format!("<html>{}<body>{}</body></html>", head, select_html(&html.html(), root)?)
+        }
+        None => html.html(),
+    };
+
+    Ok((html, kept))
+}
+
+fn is_block(element: &scraper::ElementRef<'_>) -> bool {
+    BLOCK_TAGS.contains(&element.value().name())
+}
+
+/// Replaces the outermost elements matching `selector` by the texts `placeholder` gives for their
+/// index, for which `replace` returns a value and whether the element is a block, and returns the
+/// values in placeholder order. Elements for which `replace` returns `None` are left as they are.
+fn replace_elements<T>(
+    html: &mut scraper::Html,
+    selector: &scraper::Selector,
+    placeholder: fn(usize) -> String,
+    mut replace: impl FnMut(scraper::ElementRef<'_>) -> PyResult<Option<(T, bool)>>,
+) -> PyResult<Vec<T>> {
+    let elements = html.select(selector).map(|element| element.id()).collect::<Vec<_>>();
+    let paragraph = scraper::Html::parse_fragment("<p></p>")
+        .select(&css_selector("p")?)
+        .next()
+        .map(|p| scraper::Node::Element(p.value().clone()));
+    let mut values = Vec::new();
+
+    for id in &elements {
+        let Some(element) = html.tree.get(*id).and_then(scraper::ElementRef::wrap) else {
+            continue;
+        };
+
+        // Elements inside a replaced element go with it.
+        if element.ancestors().any(|ancestor| elements.contains(&ancestor.id())) {
+            continue;
+        }
+
+        let Some((value, block)) = replace(element)? else {
+            continue;
+        };
+        let text = scraper::Node::Text(scraper::node::Text {
+            text: placeholder(values.len()).as_str().into(),
+        });
+        values.push(value);
+
+        if let Some(mut node) = html.tree.get_mut(*id) {
+            // Block elements get a paragraph of their own, so they are not joined to the text around them.
+            match &paragraph {
+                Some(paragraph) if block => node.insert_before(paragraph.clone()).append(text),
+                _ => node.insert_before(text),
+            };
+            node.detach();
+        }
+    }
+
+    Ok(values)
+}
+
+/// The text that stands in for the `index`th element converted by an element handler.
+pub(crate) fn handled_element_placeholder(index: usize) -> String {
+    format!("MQELEMENT{}X", index)
+}
+
+/// Replaces the elements matching any of the `handlers` selectors by placeholders, for which
+/// `handle` returns the markdown given the index of the last matching handler and the element, and
+/// returns the HTML and the markdown in placeholder order.
+pub(crate) fn handle_elements(
+    content: &str,
+    handlers: &[String],
+    mut handle: impl FnMut(usize, scraper::ElementRef<'_>) -> PyResult<Option<String>>,
+) -> PyResult<(String, Vec<String>)> {
+    let mut html = scraper::Html::parse_document(content);
+    let selectors = handlers
+        .iter()
+        .map(|selector| css_selector(selector))
+        .collect::<PyResult<Vec<_>>>()?;
+    let markdown = replace_elements(
+        &mut html,
+        &css_selector(&handlers.join(", "))?,
+        handled_element_placeholder,
+        |element| {
+            let Some(index) = selectors.iter().rposition(|selector| selector.matches(&element)) else {
+                return Ok(None);
+            };
+            let block = is_block(&element);
+
+            // Markdown of several lines cannot be part of a paragraph.
+            Ok(handle(index, element)?.map(|markdown| {
+                let block = block || markdown.contains('\n');
+                (markdown, block)
+            }))
+        },
+    )?;
+
+    Ok((html.html(), markdown))
+}
+
+/// Returns the outer HTML of the elements matching the CSS `selector`, in document order.
+fn select_html(content: &str, selector: &str) -> PyResult<String> {
+    let selector = css_selector(selector)?;
+
+    Ok(scraper::Html::parse_document(content)
+        .select(&selector)
+        .map(|element| match element.value().name() {
+            // The converter keeps only the first main/article and skips navigation, so landmark
+            // elements the selector picked explicitly are unwrapped to keep their content.
+            "main" | "article" | "nav" | "aside" | "noscript" => format!("<div>{}</div>", element.inner_html()),
+            _ => element.html(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// Returns the non-fatal issues parsing `content` with `options` runs into.
+pub(crate) fn parse_warnings(content: &str, options: &Options) -> Vec<String> {
+    let markdown = options.input_format.unwrap_or(InputFormat::Markdown) == InputFormat::Markdown;
+
+    if markdown && options.parallel_parse && has_reference_definitions(content) {
+        vec!["parallel_parse was ignored because the document contains reference definitions".to_string()]
+    } else {
+        Vec::new()
+    }
+}
+
+/// A contiguous slice of the document together with the zero-based line it starts on.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Chunk<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) line_offset: usize,
+}
+
+fn parse_markdown_parallel(content: &str) -> miette::Result<Vec<RuntimeValue>> {
+    let threads = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = (content.len() / threads).max(MIN_PARALLEL_CHUNK_SIZE);
+
+    // Reference definitions and footnotes are resolved document-wide, so a
+    // chunk would not see definitions living in another chunk.
+    if threads == 1 || content.len() < chunk_size * 2 || has_reference_definitions(content) {
+        return mq_lang::parse_markdown_input(content);
+    }
+
+    let chunks = split_chunks(content, chunk_size);
+
+    if chunks.len() < 2 {
+        return mq_lang::parse_markdown_input(content);
+    }
+
+    let parsed = std::thread::scope(|s| {
+        chunks
+            .iter()
+            .map(|chunk| s.spawn(move || parse_chunk(chunk)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().expect("markdown parser thread panicked"))
+            .collect::<miette::Result<Vec<_>>>()
+    })?;
+
+    Ok(parsed.into_iter().flatten().map(RuntimeValue::from).collect())
+}
+
+pub(crate) fn parse_chunk(chunk: &Chunk<'_>) -> miette::Result<Vec<Node>> {
+    let mut nodes = Markdown::from_markdown_str(chunk.text)?.nodes;
+
+    if chunk.line_offset > 0 {
+        nodes
+            .iter_mut()
+            .for_each(|node| shift_lines(node, chunk.line_offset as isize));
+    }
+
+    Ok(nodes)
+}
+
+/// Moves `node` and its children down by `offset` lines, or up when it is negative.
+pub(crate) fn shift_lines(node: &mut Node, offset: isize) {
+    if let Some(mut position) = node.position() {
+        position.start.line = position.start.line.saturating_add_signed(offset);
+        position.end.line = position.end.line.saturating_add_signed(offset);
+        node.set_position(Some(position));
+    }
+
+    let children = node.children();
+
+    if !children.is_empty() {
+        node.set_children(
+            children
+                .into_iter()
+                .map(|mut child| {
+                    shift_lines(&mut child, offset);
+                    child
+                })
+                .collect(),
+        );
+    }
+}
+
+pub(crate) fn has_reference_definitions(content: &str) -> bool {
+    content.lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with('[') && line.contains("]:")
+    })
+}
+
+/// The multi-line construct the scanner is currently inside of.
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    FrontMatter(&'static str),
+    Fence(char, usize),
+    Math,
+    Html(&'static str),
+}
+
+/// Splits `content` into chunks of at least `chunk_size` bytes at top-level block boundaries, or at
+/// every boundary when it is 0.
+pub(crate) fn split_chunks(content: &str, chunk_size: usize) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut block: Option<Block> = None;
+    let mut chunk_start = 0;
+    let mut chunk_line = 0;
+    let mut previous_blank = false;
+    let mut offset = 0;
+
+    for (line_no, line) in content.split_inclusive('\n').enumerate() {
+        let text = line.trim_end_matches(['\n', '\r']);
+
+        if block.is_none() && previous_blank && offset - chunk_start >= chunk_size && is_block_start(text) {
+            chunks.push(Chunk {
+                text: &content[chunk_start..offset],
+                line_offset: chunk_line,
+            });
+            chunk_start = offset;
+            chunk_line = line_no;
+        }
+
+        block = match block {
+            None if line_no == 0 && text == "---" => Some(Block::FrontMatter("---")),
+            None if line_no == 0 && text == "+++" => Some(Block::FrontMatter("+++")),
+            None => open_block(text),
+            Some(Block::FrontMatter(marker)) if text == marker => None,
+            Some(Block::Fence(ch, len)) if closes_fence(text, ch, len) => None,
+            Some(Block::Math) if text.trim() == "$$" => None,
+            Some(Block::Html(end)) if text.to_ascii_lowercase().contains(end) => None,
+            current => current,
+        };
+
+        previous_blank = text.trim().is_empty();
+        offset += line.len();
+    }
+
+    if chunk_start < content.len() {
+        chunks.push(Chunk {
+            text: &content[chunk_start..],
+            line_offset: chunk_line,
+        });
+    }
+
+    chunks
+}
+
+/// Returns for each line of `content` whether it belongs to fenced code, a math block, a raw HTML
+/// block or front matter, whose whitespace is content.
+pub(crate) fn literal_lines(content: &str) -> Vec<bool> {
+    let mut block: Option<Block> = None;
+
+    content
+        .lines()
+        .enumerate()
+        .map(|(line_no, text)| {
+            let inside = block.is_some();
+
+            block = match block.take() {
+                None if line_no == 0 && text == "---" => Some(Block::FrontMatter("---")),
+                None if line_no == 0 && text == "+++" => Some(Block::FrontMatter("+++")),
+                None => open_block(text),
+                Some(Block::FrontMatter(marker)) if text == marker => None,
+                Some(Block::Fence(ch, len)) if closes_fence(text, ch, len) => None,
+                Some(Block::Math) if text.trim() == "$$" => None,
+                Some(Block::Html(end)) if text.to_ascii_lowercase().contains(end) => None,
+                current => current,
+            };
+
+            inside || block.is_some()
+        })
+        .collect()
+}
+
+/// Returns true when `line` can only start a new top-level block, i.e. it is not
+/// indented (continuation/indented code) and is not a list item that could merge
+/// with a preceding loose list.
+fn is_block_start(line: &str) -> bool {
+    let Some(first) = line.chars().next() else {
+        return false;
+    };
+
+    if first.is_whitespace() {
+        return false;
+    }
+
+    if matches!(first, '-' | '*' | '+') {
+        return !line[1..].starts_with([' ', '\t']) && line.len() > 1;
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    !(digits > 0 && line[digits..].starts_with(['.', ')']))
+}
+
+fn open_block(line: &str) -> Option<Block> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+
+    if indent > 3 {
+        return None;
+    }
+
+    let line = &line[indent..];
+
+    for ch in ['`', '~'] {
+        let len = line.chars().take_while(|c| *c == ch).count();
+
+        if len >= 3 && !(ch == '`' && line[len..].contains('`')) {
+            return Some(Block::Fence(ch, len));
+        }
+    }
+
+    if line.trim_end() == "$$" {
+        return Some(Block::Math);
+    }
+
+    let lower = line.to_ascii_lowercase();
+
+    if let Some(rest) = lower.strip_prefix("<!--") {
+        return (!rest.contains("-->")).then_some(Block::Html("-->"));
+    }
+
+    [
+        ("<script", "</script>"),
+        ("<pre", "</pre>"),
+        ("<style", "</style>"),
+        ("<textarea", "</textarea>"),
+    ]
+    .into_iter()
+    .find(|(open, close)| lower.starts_with(open) && !lower.contains(close))
+    .map(|(_, close)| Block::Html(close))
+}
+
+fn closes_fence(line: &str, ch: char, len: usize) -> bool {
+    let line = line.trim_start_matches(' ');
+    let count = line.chars().take_while(|c| *c == ch).count();
+    count >= len && line[count..].trim().is_empty()
+}
@@ -76,14 +76,57 @@
 //!
 //! result = mq.run('.', markdown, options)
 //! ```
+mod analyze;
+mod anchor;
+mod builder;
+mod builtins;
+mod content;
+mod converter;
+mod delimited;
+mod diff;
+mod document;
+mod engine;
+mod error;
+mod extract;
+mod fluent;
+mod front_matter;
+mod input;
+mod links;
+mod lint;
+mod provenance;
+mod render;
+mod sanitize;
+mod section;
+mod security;
+mod serialize;
+mod stats;
+mod stream;
+mod table;
+mod text;
+mod transform;
+mod update;
+pub mod nodes;
 pub mod result;
 pub mod value;
 
-use pyo3::prelude::*;
+use indexmap::IndexMap;
+use pyo3::{IntoPyObjectExt, prelude::*};
+use content::Content;
 use result::MQResult;
-use value::MQValue;
+use value::{MQValue, MarkdownType};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-#[pyclass(eq, eq_int, from_py_object)]
+pyo3::create_exception!(
+    mq,
+    MQParseInputError,
+    pyo3::exceptions::PyRuntimeError,
+    "Raised when an input document cannot be parsed."
+);
+
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 enum InputFormat {
     #[pyo3(name = "MARKDOWN")]
@@ -99,9 +142,28 @@ enum InputFormat {
     Raw,
     #[pyo3(name = "NULL")]
     Null,
+    #[pyo3(name = "CSV")]
+    Csv,
+    #[pyo3(name = "TSV")]
+    Tsv,
 }
 
-#[pyclass(eq, eq_int, from_py_object)]
+/// The format `MQResult.render` writes results in.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    #[pyo3(name = "MARKDOWN")]
+    #[default]
+    Markdown,
+    #[pyo3(name = "HTML")]
+    Html,
+    #[pyo3(name = "JSON")]
+    Json,
+    #[pyo3(name = "TEXT")]
+    Text,
+}
+
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum ListStyle {
     #[pyo3(name = "DASH")]
@@ -113,7 +175,17 @@ pub enum ListStyle {
     Star,
 }
 
-#[pyclass(eq, eq_int, from_py_object)]
+impl From<ListStyle> for mq_markdown::ListStyle {
+    fn from(style: ListStyle) -> Self {
+        match style {
+            ListStyle::Dash => mq_markdown::ListStyle::Dash,
+            ListStyle::Plus => mq_markdown::ListStyle::Plus,
+            ListStyle::Star => mq_markdown::ListStyle::Star,
+        }
+    }
+}
+
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum TitleSurroundStyle {
     #[pyo3(name = "DOUBLE")]
@@ -125,7 +197,17 @@ pub enum TitleSurroundStyle {
     PAREN,
 }
 
-#[pyclass(eq, eq_int, from_py_object)]
+impl From<TitleSurroundStyle> for mq_markdown::TitleSurroundStyle {
+    fn from(style: TitleSurroundStyle) -> Self {
+        match style {
+            TitleSurroundStyle::Double => mq_markdown::TitleSurroundStyle::Double,
+            TitleSurroundStyle::Single => mq_markdown::TitleSurroundStyle::Single,
+            TitleSurroundStyle::PAREN => mq_markdown::TitleSurroundStyle::Paren,
+        }
+    }
+}
+
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum UrlSurroundStyle {
     #[pyo3(name = "ANGLE")]
@@ -135,98 +217,1200 @@ pub enum UrlSurroundStyle {
     None,
 }
 
-#[pyclass(eq, from_py_object)]
+impl From<UrlSurroundStyle> for mq_markdown::UrlSurroundStyle {
+    fn from(style: UrlSurroundStyle) -> Self {
+        match style {
+            UrlSurroundStyle::Angle => mq_markdown::UrlSurroundStyle::Angle,
+            UrlSurroundStyle::None => mq_markdown::UrlSurroundStyle::None,
+        }
+    }
+}
+
+/// How math blocks are written when rendering: as `$$` blocks or as fenced `math` code blocks.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathStyle {
+    #[pyo3(name = "DOLLAR")]
+    Dollar,
+    #[pyo3(name = "FENCED")]
+    Fenced,
+}
+
+/// How level 1 and 2 headings are written when rendering: with `#` or underlined with `=` and `-`.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeadingStyle {
+    #[pyo3(name = "ATX")]
+    Atx,
+    #[pyo3(name = "SETEXT")]
+    Setext,
+}
+
+/// The delimiter emphasis or strong emphasis is written with: `*`/`**` or `_`/`__`.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmphasisStyle {
+    #[pyo3(name = "STAR")]
+    Star,
+    #[pyo3(name = "UNDERSCORE")]
+    Underscore,
+}
+
+/// The character fenced code blocks are written with.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeFenceStyle {
+    #[pyo3(name = "BACKTICK")]
+    Backtick,
+    #[pyo3(name = "TILDE")]
+    Tilde,
+}
+
+/// How hard line breaks are written: a backslash or two spaces at the end of the line.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HardBreakStyle {
+    #[pyo3(name = "BACKSLASH")]
+    Backslash,
+    #[pyo3(name = "SPACES")]
+    Spaces,
+}
+
+/// How link and image titles are written when rendering.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkTitlePolicy {
+    /// Collapse whitespace, escape quotes that would end the title and drop empty titles.
+    #[pyo3(name = "NORMALIZE")]
+    Normalize,
+    /// Drop titles.
+    #[pyo3(name = "STRIP")]
+    Strip,
+}
+
+/// How raw HTML inside markdown is represented after parsing.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HtmlPolicy {
+    #[pyo3(name = "KEEP")]
+    #[default]
+    Keep,
+    #[pyo3(name = "CONVERT")]
+    Convert,
+    #[pyo3(name = "STRIP")]
+    Strip,
+}
+
+/// How heading text is turned into the slug of its anchor.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SlugStyle {
+    /// Lowercase, without punctuation and with spaces as hyphens.
+    #[pyo3(name = "GITHUB")]
+    #[default]
+    Github,
+    /// As GitHub, with runs of hyphens collapsed and `anchor-` before slugs of only digits.
+    #[pyo3(name = "GITLAB")]
+    Gitlab,
+    /// Whatever `Options.slug_function` returns for the text.
+    #[pyo3(name = "CUSTOM")]
+    Custom,
+}
+
+/// The Unicode normalization form applied to input before parsing.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnicodeNormalization {
+    #[pyo3(name = "NFC")]
+    Nfc,
+    #[pyo3(name = "NFKC")]
+    Nfkc,
+}
+
+/// The encoding of content passed as bytes or a buffer; `str` content is used as it is.
+#[pyclass(eq, eq_int, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    #[pyo3(name = "UTF8")]
+    Utf8,
+    /// UTF-16 with the byte order given by a byte order mark, little-endian without one.
+    #[pyo3(name = "UTF16")]
+    Utf16,
+    #[pyo3(name = "UTF16_LE")]
+    Utf16Le,
+    #[pyo3(name = "UTF16_BE")]
+    Utf16Be,
+    #[pyo3(name = "LATIN1")]
+    Latin1,
+}
+
+/// Pickles the variants of each enum by name, e.g. as `getattr(mq.InputFormat, "HTML")`.
+macro_rules! reduce_by_name {
+    ($($enum:ty),*) => {$(
+        #[pymethods]
+        impl $enum {
+            fn __reduce__<'py>(
+                slf: &Bound<'py, Self>,
+            ) -> PyResult<(Bound<'py, PyAny>, (Bound<'py, pyo3::types::PyType>, String))> {
+                let repr = slf.as_any().repr()?.extract::<String>()?;
+                let name = repr.rsplit('.').next().unwrap_or_default().to_string();
+                Ok((slf.py().import("builtins")?.getattr("getattr")?, (slf.as_any().get_type(), name)))
+            }
+        }
+    )*};
+}
+
+reduce_by_name!(
+    InputFormat,
+    OutputFormat,
+    ListStyle,
+    TitleSurroundStyle,
+    UrlSurroundStyle,
+    MathStyle,
+    HeadingStyle,
+    EmphasisStyle,
+    CodeFenceStyle,
+    HardBreakStyle,
+    LinkTitlePolicy,
+    HtmlPolicy,
+    SlugStyle,
+    UnicodeNormalization,
+    Encoding,
+    MarkdownType
+);
+
+impl UnicodeNormalization {
+    pub(crate) fn form(&self) -> &'static str {
+        match self {
+            UnicodeNormalization::Nfc => "NFC",
+            UnicodeNormalization::Nfkc => "NFKC",
+        }
+    }
+}
+
+#[pyclass(eq, from_py_object, module = "mq")]
+#[derive(Debug, Clone, PartialEq, Default)]
 struct Options {
     #[pyo3(get, set)]
     input_format: Option<InputFormat>,
     #[pyo3(get, set)]
+    output_format: Option<OutputFormat>,
+    #[pyo3(get, set)]
     list_style: Option<ListStyle>,
     #[pyo3(get, set)]
     link_title_style: Option<TitleSurroundStyle>,
     #[pyo3(get, set)]
     link_url_style: Option<UrlSurroundStyle>,
+    #[pyo3(get, set)]
+    percent_encode_urls: bool,
+    #[pyo3(get, set)]
+    link_titles: Option<LinkTitlePolicy>,
+    #[pyo3(get, set)]
+    math_style: Option<MathStyle>,
+    #[pyo3(get, set)]
+    heading_style: Option<HeadingStyle>,
+    #[pyo3(get, set)]
+    emphasis_style: Option<EmphasisStyle>,
+    #[pyo3(get, set)]
+    strong_style: Option<EmphasisStyle>,
+    #[pyo3(get, set)]
+    code_fence_style: Option<CodeFenceStyle>,
+    #[pyo3(get, set)]
+    hard_break_style: Option<HardBreakStyle>,
+    #[pyo3(get, set)]
+    list_marker_spacing: Option<usize>,
+    #[pyo3(get, set)]
+    table_padding: bool,
+    #[pyo3(get, set)]
+    trim_trailing_whitespace: bool,
+    #[pyo3(get, set)]
+    max_blank_lines: Option<usize>,
+    #[pyo3(get, set)]
+    final_newline: bool,
+    #[pyo3(get, set)]
+    heading_anchors: bool,
+    #[pyo3(get, set)]
+    slug_style: Option<SlugStyle>,
+    #[pyo3(get, set)]
+    slug_function: Option<anchor::SlugFunction>,
+    #[pyo3(get, set)]
+    html_attributes: Option<render::HtmlAttributes>,
+    #[pyo3(get, set)]
+    parallel_parse: bool,
+    #[pyo3(get, set)]
+    record_provenance: bool,
+    #[pyo3(get, set)]
+    collect_timings: bool,
+    #[pyo3(get, set)]
+    debug: bool,
+    #[pyo3(get, set)]
+    max_results: Option<usize>,
+    #[pyo3(get, set)]
+    max_output_bytes: Option<usize>,
+    #[pyo3(get, set)]
+    display_limit: Option<usize>,
+    #[pyo3(get, set)]
+    html_selector: Option<String>,
+    #[pyo3(get, set)]
+    html_policy: Option<HtmlPolicy>,
+    #[pyo3(get, set)]
+    max_html_depth: Option<usize>,
+    #[pyo3(get, set)]
+    unicode_normalization: Option<UnicodeNormalization>,
+    #[pyo3(get, set)]
+    encoding: Option<Encoding>,
+    #[pyo3(get, set)]
+    module_paths: Option<Vec<PathBuf>>,
+    #[pyo3(get)]
+    max_recursion_depth: Option<u32>,
+    #[pyo3(get, set)]
+    max_output_nodes: Option<usize>,
+    #[pyo3(get, set)]
+    security: Option<security::SecurityPolicy>,
 }
 
 #[pymethods]
 impl Options {
     #[new]
-    pub fn new() -> Self {
-        Self::default()
+    #[pyo3(signature = (**kwargs))]
+    pub fn new(py: Python<'_>, kwargs: Option<&Bound<'_, pyo3::types::PyDict>>) -> PyResult<Self> {
+        let options = Bound::new(py, Self::default())?;
+        set_fields(options.as_any(), "Options", kwargs)?;
+        Ok(options.borrow().clone())
+    }
+
+    fn __reduce__<'py>(
+        slf: &Bound<'py, Self>,
+    ) -> PyResult<(Bound<'py, pyo3::types::PyType>, (), Bound<'py, pyo3::types::PyDict>)> {
+        Ok((slf.get_type(), (), fields(slf.as_any())?))
+    }
+
+    fn __setstate__(slf: &Bound<'_, Self>, state: &Bound<'_, pyo3::types::PyDict>) -> PyResult<()> {
+        set_fields(slf.as_any(), "Options", Some(state))
+    }
+
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone()
+    }
+
+    #[setter]
+    fn set_max_recursion_depth(&mut self, depth: Option<u32>) -> PyResult<()> {
+        if let Some(depth) = depth.filter(|depth| *depth > MAX_RECURSION_DEPTH) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "max_recursion_depth={} is above the limit of {} calls, as deeper calls would overflow the stack",
+                depth, MAX_RECURSION_DEPTH
+            )));
+        }
+
+        self.max_recursion_depth = depth;
+        Ok(())
     }
 }
 
-#[pyclass(eq, from_py_object)]
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
-struct ConversionOptions {
+/// The fields of an options object by name, as its attributes.
+fn fields<'py>(obj: &Bound<'py, PyAny>) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+    let fields = pyo3::types::PyDict::new(obj.py());
+
+    // Every field is a getter and setter, while the methods are not descriptors of that kind.
+    for item in obj.get_type().getattr("__dict__")?.call_method0("items")?.try_iter()? {
+        let (name, attr) = item?.extract::<(String, Bound<'_, PyAny>)>()?;
+
+        if attr.get_type().name()? == "getset_descriptor" {
+            fields.set_item(&name, obj.getattr(&name)?)?;
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Sets the fields of a new options object from the keyword arguments of its constructor, so
+/// values are checked by the same setters as attribute assignment.
+fn set_fields(obj: &Bound<'_, PyAny>, class: &str, kwargs: Option<&Bound<'_, pyo3::types::PyDict>>) -> PyResult<()> {
+    for (key, value) in kwargs.into_iter().flatten() {
+        let name = key.extract::<String>()?;
+
+        obj.setattr(&name, value).map_err(|e| {
+            if e.is_instance_of::<pyo3::exceptions::PyAttributeError>(obj.py()) {
+                pyo3::exceptions::PyTypeError::new_err(format!(
+                    "{}() got an unexpected keyword argument '{}'",
+                    class, name
+                ))
+            } else {
+                e
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+impl Options {
+    pub(crate) fn render_options(&self) -> mq_markdown::RenderOptions {
+        mq_markdown::RenderOptions {
+            list_style: self.list_style.unwrap_or_default().into(),
+            link_url_style: self.link_url_style.unwrap_or_default().into(),
+            link_title_style: self.link_title_style.unwrap_or_default().into(),
+        }
+    }
+}
+
+#[pyclass(eq, from_py_object, module = "mq")]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct ConversionOptions {
     #[pyo3(get, set)]
     extract_scripts_as_code_blocks: bool,
     #[pyo3(get, set)]
     generate_front_matter: bool,
     #[pyo3(get, set)]
     use_title_as_h1: bool,
+    #[pyo3(get, set)]
+    max_depth: Option<usize>,
+    /// A CSS selector for the elements to convert, e.g. `"article.main"`, instead of the whole body.
+    #[pyo3(get, set)]
+    root_selector: Option<String>,
+    /// CSS selectors for elements to leave out, e.g. `["nav", "footer", ".ad"]`.
+    #[pyo3(get, set)]
+    exclude_selectors: Vec<String>,
+    /// Tag names of elements to keep as HTML in the markdown, e.g. `["table", "details"]`.
+    #[pyo3(get, set)]
+    keep_html_for: Vec<String>,
 }
 
 #[pymethods]
 impl ConversionOptions {
     #[new]
-    pub fn new() -> Self {
-        Self::default()
+    #[pyo3(signature = (**kwargs))]
+    pub fn new(py: Python<'_>, kwargs: Option<&Bound<'_, pyo3::types::PyDict>>) -> PyResult<Self> {
+        let options = Bound::new(py, Self::default())?;
+        set_fields(options.as_any(), "ConversionOptions", kwargs)?;
+        Ok(options.borrow().clone())
+    }
+
+    fn __reduce__<'py>(
+        slf: &Bound<'py, Self>,
+    ) -> PyResult<(Bound<'py, pyo3::types::PyType>, (), Bound<'py, pyo3::types::PyDict>)> {
+        Ok((slf.get_type(), (), fields(slf.as_any())?))
+    }
+
+    fn __setstate__(slf: &Bound<'_, Self>, state: &Bound<'_, pyo3::types::PyDict>) -> PyResult<()> {
+        set_fields(slf.as_any(), "ConversionOptions", Some(state))
+    }
+
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone()
+    }
+
+    /// Builds options from a mapping of option names to values, e.g. a crawler's configuration file.
+    ///
+    /// Unknown names and invalid values raise, so a configuration can be checked before a batch starts.
+    #[staticmethod]
+    pub fn from_dict(config: &Bound<'_, pyo3::types::PyDict>) -> PyResult<Self> {
+        let mut options = Self::default();
+
+        for (key, value) in config.iter() {
+            let key = key.extract::<String>()?;
+            let invalid = |expected: &str| {
+                pyo3::exceptions::PyTypeError::new_err(format!(
+                    "Conversion option '{}' must be {}, got {}",
+                    key,
+                    expected,
+                    value.get_type().name().map_or_else(|_| "?".to_string(), |name| name.to_string())
+                ))
+            };
+            let flag = || value.cast::<pyo3::types::PyBool>().map(|b| b.is_true()).map_err(|_| invalid("a bool"));
+            let strings = || {
+                if value.is_instance_of::<pyo3::types::PyString>() {
+                    return Err(invalid("a list of str"));
+                }
+
+                value.extract::<Vec<String>>().map_err(|_| invalid("a list of str"))
+            };
+
+            match key.as_str() {
+                "extract_scripts_as_code_blocks" => options.extract_scripts_as_code_blocks = flag()?,
+                "generate_front_matter" => options.generate_front_matter = flag()?,
+                "use_title_as_h1" => options.use_title_as_h1 = flag()?,
+                "max_depth" if value.is_none() => options.max_depth = None,
+                "max_depth" => {
+                    if value.is_instance_of::<pyo3::types::PyBool>() {
+                        return Err(invalid("an int or None"));
+                    }
+
+                    let depth = value.extract::<i64>().map_err(|_| invalid("an int or None"))?;
+
+                    if depth < 1 {
+                        return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                            "Conversion option 'max_depth' must be at least 1, got {}",
+                            depth
+                        )));
+                    }
+
+                    options.max_depth = Some(depth as usize);
+                }
+                "root_selector" => {
+                    options.root_selector = value.extract().map_err(|_| invalid("a str or None"))?;
+                }
+                "exclude_selectors" => options.exclude_selectors = strings()?,
+                "keep_html_for" => options.keep_html_for = strings()?,
+                key => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "Unknown conversion option '{}', expected one of 'extract_scripts_as_code_blocks', \
+                         'generate_front_matter', 'use_title_as_h1', 'max_depth', 'root_selector', \
+                         'exclude_selectors' or 'keep_html_for'",
+                        key
+                    )));
+                }
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Builds options from a JSON object, validated as by `from_dict`.
+    #[staticmethod]
+    pub fn from_json(py: Python<'_>, data: &str) -> PyResult<Self> {
+        let config = py.import("json")?.call_method1("loads", (data,))?;
+        let config = config.cast::<pyo3::types::PyDict>().map_err(|_| {
+            pyo3::exceptions::PyValueError::new_err("Conversion options JSON must be an object")
+        })?;
+
+        Self::from_dict(config)
     }
 }
 
 #[pyfunction]
-#[pyo3(signature = (code, content, options=None))]
-fn run(code: &str, content: &str, options: Option<Options>) -> PyResult<MQResult> {
-    let mut engine = mq_lang::DefaultEngine::default();
-    engine.load_builtin_module();
+#[pyo3(signature = (code, content, options=None, source=None, errors="strict", args=None))]
+fn run(
+    py: Python<'_>,
+    code: &str,
+    content: Content<'_>,
+    options: Option<Options>,
+    source: Option<&str>,
+    errors: &str,
+    args: Option<IndexMap<String, Bound<'_, PyAny>>>,
+) -> PyResult<MQResult> {
+    let args = query_args(args.unwrap_or_default())?;
     let options = options.unwrap_or_default();
-    let input = match options.input_format.unwrap_or(InputFormat::Markdown) {
-        InputFormat::Markdown => mq_lang::parse_markdown_input(content),
-        InputFormat::Mdx => mq_lang::parse_mdx_input(content),
-        InputFormat::Text => mq_lang::parse_text_input(content),
-        InputFormat::Html => mq_lang::parse_html_input(content),
-        InputFormat::Raw => Ok(mq_lang::raw_input(content)),
-        InputFormat::Null => Ok(mq_lang::null_input()),
+    let content = content.decode_detached(errors, options.encoding)?;
+    let mut result = eval_with(py, code, &content, &options, |prefix, input| {
+        engine::eval_timed(prefix.unwrap_or(code), input, &options, args.clone())
+    })
+    .map_err(|e| with_source(e, source))?;
+
+    if let Some(source) = source {
+        result.set_source(source);
     }
-    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Error evaluating query: {}", e)))?;
 
-    engine
-        .eval(code, input.into_iter())
-        .map(|values| MQResult {
-            values: values.into_iter().map(Into::into).collect::<Vec<_>>(),
+    Ok(result)
+}
+
+/// Runs `code` against `content` in the running event loop's default executor, returning an
+/// asyncio future for the result.
+///
+/// `run` releases the GIL while the query runs, so the event loop keeps serving other tasks.
+#[pyfunction]
+#[pyo3(signature = (code, content, options=None, source=None, errors="strict", args=None))]
+fn run_async<'py>(
+    py: Python<'py>,
+    code: &str,
+    content: Content<'_>,
+    options: Option<Options>,
+    source: Option<&str>,
+    errors: &str,
+    args: Option<Bound<'py, PyAny>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+    let run = wrap_pyfunction!(run, py)?;
+    // Decoded now, so a bytearray changed after the call does not affect the query.
+    let content = content.decode(errors, options.as_ref().and_then(|options| options.encoding))?;
+    let kwargs = pyo3::types::PyDict::new(py);
+    kwargs.set_item("options", options)?;
+    kwargs.set_item("source", source)?;
+    kwargs.set_item("errors", errors)?;
+    kwargs.set_item("args", args)?;
+    let call = py
+        .import("functools")?
+        .getattr("partial")?
+        .call((run, code, content.as_ref()), Some(&kwargs))?;
+
+    event_loop.call_method1("run_in_executor", (py.None(), call))
+}
+
+/// Converts the `args` of `run` into values to bind as variables, checking that every name is an
+/// mq identifier.
+pub(crate) fn query_args(args: IndexMap<String, Bound<'_, PyAny>>) -> PyResult<Vec<(String, mq_lang::RuntimeValue)>> {
+    args.into_iter()
+        .map(|(name, value)| {
+            let mut chars = name.chars();
+            let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            if !valid {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Invalid argument name {:?}, expected an identifier",
+                    name
+                )));
+            }
+
+            let value = value::runtime_value(&value, &format!("args[{:?}]", name))?;
+            Ok((name, value))
         })
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Error evaluating query: {}", e)))
+        .collect()
+}
+
+/// Reads the file at `path` and runs `code` against it, with the path as the result's source.
+///
+/// Files starting with a UTF-16 byte order mark are decoded as UTF-16, others as UTF-8, unless
+/// `options.encoding` is set.
+#[pyfunction]
+#[pyo3(signature = (code, path, options=None, errors="strict"))]
+fn run_file(py: Python<'_>, code: &str, path: PathBuf, options: Option<Options>, errors: &str) -> PyResult<MQResult> {
+    eval_file(py, code, &path, &options.unwrap_or_default(), errors)
+}
+
+/// Runs `code` against every file matching the glob `pattern`, where `**` matches any number of
+/// directories, returning the results by path.
+///
+/// With `front_matter`, only the files whose front matter has those values, or for which that
+/// callable returns true, are queried and returned.
+///
+/// With `lazy=True`, returns an iterator that finds and queries each file as it is reached instead.
+#[pyfunction]
+#[pyo3(signature = (code, pattern, options=None, errors="strict", front_matter=None, lazy=false))]
+fn run_glob<'py>(
+    py: Python<'py>,
+    code: &str,
+    pattern: &str,
+    options: Option<Options>,
+    errors: &str,
+    front_matter: Option<Bound<'py, PyAny>>,
+    lazy: bool,
+) -> PyResult<Bound<'py, PyAny>> {
+    let options = options.unwrap_or_default();
+    content::check_errors(errors)?;
+
+    if lazy {
+        // Checked now, so an invalid filter is not first reported by the iterator.
+        if let Some(front_matter) = &front_matter {
+            front_matter::Filter::new(front_matter.clone())?;
+        }
+
+        let kwargs = pyo3::types::PyDict::new(py);
+        kwargs.set_item("recursive", true)?;
+        let paths = py.import("glob")?.call_method("iglob", (pattern,), Some(&kwargs))?;
+        let results = stream::GlobResults::new(paths.try_iter()?, code, options, errors, front_matter);
+
+        return results.into_bound_py_any(py);
+    }
+
+    let filter = front_matter.map(front_matter::Filter::new).transpose()?;
+    let mut results = IndexMap::new();
+
+    for path in glob(py, pattern)? {
+        if let Some((source, result)) = eval_glob_file(py, code, &path, &options, errors, filter.as_ref())? {
+            results.insert(source, result);
+        }
+    }
+
+    results.into_bound_py_any(py)
+}
+
+/// Runs `code` against the file at `path` found by a glob, returning its path and result, or
+/// `None` if `filter` leaves it out.
+pub(crate) fn eval_glob_file(
+    py: Python<'_>,
+    code: &str,
+    path: &Path,
+    options: &Options,
+    errors: &str,
+    filter: Option<&front_matter::Filter<'_>>,
+) -> PyResult<Option<(String, MQResult)>> {
+    let source = path.to_string_lossy().into_owned();
+    let content = read_file(py, path, options, errors)?;
+
+    if let Some(filter) = filter
+        && !filter.matches(&content).map_err(|e| with_source(e, Some(&source)))?
+    {
+        return Ok(None);
+    }
+
+    let result = eval_source(py, code, &content, &source, options)?;
+    Ok(Some((source, result)))
+}
+
+/// Runs `code` against each of the named `documents`, returning the results by name with the names
+/// as their sources.
+///
+/// With `name_var`, the name of each document is bound to a variable of that name, along with `args`.
+#[pyfunction]
+#[pyo3(signature = (code, documents, options=None, errors="strict", name_var=None, args=None))]
+fn run_multi(
+    py: Python<'_>,
+    code: &str,
+    documents: IndexMap<String, Content<'_>>,
+    options: Option<Options>,
+    errors: &str,
+    name_var: Option<String>,
+    args: Option<IndexMap<String, Bound<'_, PyAny>>>,
+) -> PyResult<IndexMap<String, MQResult>> {
+    let options = options.unwrap_or_default();
+    let args = query_args(args.unwrap_or_default())?;
+
+    documents
+        .into_iter()
+        .map(|(name, content)| {
+            let mut args = args.clone();
+
+            if let Some(name_var) = &name_var {
+                let name = IndexMap::from([(name_var.clone(), name.clone().into_bound_py_any(py)?)]);
+                args.extend(query_args(name)?);
+            }
+
+            let content = content.decode_detached(errors, options.encoding)?;
+            let mut result = eval_with(py, code, &content, &options, |prefix, input| {
+                engine::eval_timed(prefix.unwrap_or(code), input, &options, args.clone())
+            })
+            .map_err(|e| with_source(e, Some(&name)))?;
+            result.set_source(&name);
+
+            Ok((name, result))
+        })
+        .collect()
+}
+
+/// Like `run` with the path as the source. Decoding errors are not prefixed with the path, as
+/// `UnicodeDecodeError` cannot be raised with a different message.
+fn eval_file(py: Python<'_>, code: &str, path: &Path, options: &Options, errors: &str) -> PyResult<MQResult> {
+    let content = read_file(py, path, options, errors)?;
+    eval_source(py, code, &content, &path.to_string_lossy(), options)
+}
+
+fn read_file(py: Python<'_>, path: &Path, options: &Options, errors: &str) -> PyResult<String> {
+    let bytes = std::fs::read(path).map_err(|e| with_source(e.into(), Some(&path.to_string_lossy())))?;
+    content::decode_file(py, &bytes, errors, options.encoding)
+}
+
+/// Like `run` with `source` as the source.
+fn eval_source(py: Python<'_>, code: &str, content: &str, source: &str, options: &Options) -> PyResult<MQResult> {
+    let mut result = eval(py, code, content, options).map_err(|e| with_source(e, Some(source)))?;
+    result.set_source(source);
+    Ok(result)
+}
+
+/// Returns the files matching the glob `pattern` in sorted order, where `**` matches any number
+/// of directories.
+pub(crate) fn glob(py: Python<'_>, pattern: &str) -> PyResult<Vec<PathBuf>> {
+    let kwargs = pyo3::types::PyDict::new(py);
+    kwargs.set_item("recursive", true)?;
+
+    let mut paths = py
+        .import("glob")?
+        .call_method("glob", (pattern,), Some(&kwargs))?
+        .extract::<Vec<PathBuf>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+    Ok(paths)
+}
+
+/// Runs a query and returns the text of its non-empty values, joined by newlines as in
+/// `MQResult.text`, or as a list with `join=False`.
+///
+/// No `MQValue` objects are created, which makes this faster for simple extraction.
+#[pyfunction]
+#[pyo3(signature = (code, content, options=None, join=true, source=None, errors="strict", args=None))]
+#[allow(clippy::too_many_arguments)]
+fn run_text<'py>(
+    py: Python<'py>,
+    code: &str,
+    content: Content<'_>,
+    options: Option<Options>,
+    join: bool,
+    source: Option<&str>,
+    errors: &str,
+    args: Option<IndexMap<String, Bound<'_, PyAny>>>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let args = Detached(query_args(args.unwrap_or_default())?);
+    let options = options.unwrap_or_default();
+    let content = content.decode_detached(errors, options.encoding)?;
+    let Detached(values) = py
+        .detach(|| {
+            let args = args;
+            let values = engine::eval_args(code, input::parse(&content, &options)?, &options, args.0)?;
+
+            Ok::<_, PyErr>(Detached(values))
+        })
+        .map_err(|e| with_source(e, source))?;
+    let mut texts = Vec::new();
+    let mut bytes = 0;
+
+    for value in values {
+        let value = MQValue::try_from(value)?;
+
+        if value.__len__() == 0 {
+            continue;
+        }
+
+        let text = value.text();
+        bytes += text.len();
+
+        if options.max_results.is_some_and(|max| texts.len() >= max)
+            || options.max_output_bytes.is_some_and(|max| bytes > max)
+        {
+            break;
+        }
+
+        texts.push(text);
+    }
+
+    if join {
+        texts.join("\n").into_bound_py_any(py)
+    } else {
+        texts.into_bound_py_any(py)
+    }
+}
+
+/// Prefixes the message of `err` with the name of the document it was raised for.
+pub(crate) fn with_source(err: PyErr, source: Option<&str>) -> PyErr {
+    match source {
+        Some(source) => Python::attach(|py| {
+            let prefixed = PyErr::from_type(err.get_type(py), format!("{}: {}", source, err.value(py)));
+
+            // Keep the position attributes of query errors.
+            if let Ok(attributes) = err.value(py).getattr("__dict__") {
+                let _ = prefixed.value(py).setattr("__dict__", attributes);
+            }
+
+            prefixed
+        }),
+        None => err,
+    }
+}
+
+/// The deepest `Options.max_recursion_depth` accepted, mq-lang's own default, which fits in the stack
+/// of any thread. Deeper calls would overflow the stack and crash the process instead of raising.
+const MAX_RECURSION_DEPTH: u32 = if cfg!(debug_assertions) { 40 } else { 192 };
+
+/// Returns an engine with the builtin module loaded, importing modules from `options.module_paths`
+/// and limiting calls to `options.max_recursion_depth` when set.
+pub(crate) fn new_engine(options: &Options) -> mq_lang::DefaultEngine {
+    let mut engine = mq_lang::DefaultEngine::default();
+
+    if let Some(paths) = &options.module_paths {
+        engine.set_search_paths(paths.clone());
+    }
+
+    if let Some(depth) = options.max_recursion_depth {
+        engine.set_max_call_stack_depth(depth);
+    }
+
+    engine.load_builtin_module();
+    engine
+}
+
+pub(crate) fn eval(py: Python<'_>, code: &str, content: &str, options: &Options) -> PyResult<MQResult> {
+    eval_with(py, code, content, options, |prefix, input| {
+        engine::eval_timed(prefix.unwrap_or(code), input, options, Vec::new())
+    })
 }
 
+/// Values that stay on the thread that created them while the GIL is released.
+pub(crate) struct Detached<T>(pub(crate) T);
+
+// SAFETY: `Python::detach` runs its closure on the calling thread, so the engine and the `Rc`-based
+// values inside never reach another thread.
+unsafe impl<T> Send for Detached<T> {}
+
+/// Like `eval`, running the query with `run`, which returns the values and how long compiling and
+/// evaluating took. `run` is given None for the whole query, or the prefix of it to run when
+/// tracing a pipeline.
+///
+/// The GIL is released while the input is parsed, the query runs and the values are converted, so
+/// other Python threads can run meanwhile.
+pub(crate) fn eval_with(
+    py: Python<'_>,
+    code: &str,
+    content: &str,
+    options: &Options,
+    run: impl FnMut(Option<&str>, engine::Input) -> (mq_lang::MqResult, Duration, Duration),
+) -> PyResult<MQResult> {
+    let run = Detached(run);
+
+    py.detach(move || {
+        let run = run;
+        eval_detached(code, content, options, run.0)
+    })
+}
+
+/// Like `eval_with`, for callers that do not hold the GIL. Custom renderers take it as needed.
+pub(crate) fn eval_detached(
+    code: &str,
+    content: &str,
+    options: &Options,
+    run: impl FnMut(Option<&str>, engine::Input) -> (mq_lang::MqResult, Duration, Duration),
+) -> PyResult<MQResult> {
+    let start = Instant::now();
+    let input = input::parse(content, options)?;
+    let warnings = input::parse_warnings(content, options);
+    eval_parsed(code, content, input, warnings, start, options, run)
+}
+
+/// Like `eval_detached`, on the values already parsed from `content` since `start`.
+pub(crate) fn eval_parsed(
+    code: &str,
+    content: &str,
+    input: Vec<mq_lang::RuntimeValue>,
+    mut warnings: Vec<String>,
+    start: Instant,
+    options: &Options,
+    mut run: impl FnMut(Option<&str>, engine::Input) -> (mq_lang::MqResult, Duration, Duration),
+) -> PyResult<MQResult> {
+    security::check(code, options)?;
+    let annotations = input::Annotations::new(&input, content, options)?;
+    let parse = start.elapsed();
+    let traced = options.record_provenance || options.debug;
+    let stages = if traced { provenance::stages(code) } else { Vec::new() };
+    let traced_input = if traced { input.clone() } else { Vec::new() };
+
+    let (values, compile, evaluate) = run(None, input.into_iter());
+    let values = values
+        .map_err(|e| crate::error::query_error("Error evaluating query", &e))?
+        .into_iter()
+        .collect::<Vec<_>>();
+    engine::check_limits(&values, options)?;
+    let prefixes = if traced {
+        provenance::prefixes(code, &stages, &traced_input, &mut run)
+    } else {
+        Vec::new()
+    };
+    let produced_by = if options.record_provenance {
+        warnings.extend(provenance::prefix_warnings(code, &stages, &prefixes));
+        provenance::trace(&stages, &prefixes, &traced_input, &values)
+    } else {
+        Vec::new()
+    };
+    let trace = options
+        .debug
+        .then(|| provenance::steps(code, &stages, &prefixes, &traced_input, &values, compile + evaluate));
+
+    let start = Instant::now();
+    let mut results = Vec::new();
+    let mut count = 0;
+    let mut bytes = 0;
+
+    for (i, value) in values.into_iter().enumerate() {
+        let mut value_warnings = Vec::new();
+        value::conversion_warnings(&value, &format!("values[{}]", i), &mut value_warnings);
+        let mut value = MQValue::try_from(value)?;
+
+        // Empty values stand in for nodes the query did not match, so only non-empty ones count.
+        if value.__len__() > 0 {
+            count += 1;
+            bytes += value.text().len();
+
+            if let Some(max) = options.max_results
+                && count > max
+            {
+                warnings.push(format!("Results were truncated at max_results={}", max));
+                break;
+            }
+
+            if let Some(max) = options.max_output_bytes
+                && bytes > max
+            {
+                warnings.push(format!("Results were truncated at max_output_bytes={}", max));
+                break;
+            }
+        }
+
+        annotations.apply(&mut value);
+
+        if let Some(stage) = produced_by.get(i).copied().flatten() {
+            value.set_stage(stage, provenance::stage_name(&code[stages[stage].clone()]));
+        }
+
+        results.push(value);
+        warnings.extend(value_warnings);
+    }
+
+    let timings = options.collect_timings.then(|| {
+        IndexMap::from([
+            ("parse".to_string(), parse.as_secs_f64()),
+            ("compile".to_string(), compile.as_secs_f64()),
+            ("eval".to_string(), evaluate.as_secs_f64()),
+            ("convert".to_string(), start.elapsed().as_secs_f64()),
+        ])
+    });
+
+    Ok(MQResult {
+        values: results,
+        warnings,
+        source: None,
+        timings,
+        trace,
+        display_limit: options.display_limit,
+        options: Some(options.clone()),
+    })
+}
+
+/// Parses a document into the values a query runs on, without running one.
 #[pyfunction]
 #[pyo3(signature = (content, options=None))]
-fn html_to_markdown(content: &str, options: Option<ConversionOptions>) -> PyResult<String> {
-    mq_markdown::convert_html_to_markdown(
-        content,
-        match options {
-            Some(opts) => mq_markdown::ConversionOptions {
-                extract_scripts_as_code_blocks: opts.extract_scripts_as_code_blocks,
-                generate_front_matter: opts.generate_front_matter,
-                use_title_as_h1: opts.use_title_as_h1,
-            },
-            None => mq_markdown::ConversionOptions::default(),
+fn parse_input(content: Content<'_>, options: Option<Options>) -> PyResult<MQResult> {
+    let options = options.unwrap_or_default();
+    let content = content.as_str()?;
+    let input = input::parse(content, &options)?;
+    let annotations = input::Annotations::new(&input, content, &options)?;
+    let values = input
+        .into_iter()
+        .map(|value| {
+            let mut value = MQValue::try_from(value)?;
+            annotations.apply(&mut value);
+            Ok(value)
+        })
+        .collect::<PyResult<_>>()?;
+
+    Ok(MQResult {
+        values,
+        warnings: input::parse_warnings(content, &options),
+        source: None,
+        timings: None,
+        trace: None,
+        display_limit: options.display_limit,
+        options: Some(options),
+    })
+}
+
+/// Parses a document into a tree of `mq.nodes` objects, one per top-level node, without running a
+/// query. Values other than nodes, e.g. the lines of raw input, become `Text` nodes.
+#[pyfunction]
+#[pyo3(signature = (content, input_format=None))]
+fn parse<'py>(
+    py: Python<'py>,
+    content: Content<'_>,
+    input_format: Option<InputFormat>,
+) -> PyResult<Vec<Bound<'py, PyAny>>> {
+    let options = Options {
+        input_format,
+        ..Options::default()
+    };
+
+    input::parse(content.as_str()?, &options)?
+        .into_iter()
+        .filter(|value| !value.is_none())
+        .map(|value| match value {
+            mq_lang::RuntimeValue::Markdown(node, _) => nodes::to_py(py, *node),
+            value => {
+                let text = mq_markdown::Text {
+                    value: value.to_string(),
+                    position: None,
+                };
+                nodes::to_py(py, mq_markdown::Node::Text(text))
+            }
+        })
+        .collect()
+}
+
+/// Renders a tree of `mq.nodes` objects, e.g. from `parse`, in the output format of `options`.
+#[pyfunction(name = "render")]
+#[pyo3(signature = (tree, options=None))]
+fn render_tree(tree: &Bound<'_, PyAny>, options: Option<Options>) -> PyResult<String> {
+    render::to_output(nodes::extract_children(tree)?, options)
+}
+
+#[pyfunction]
+#[pyo3(signature = (content, options=None, errors="strict"))]
+fn html_to_markdown(content: Content<'_>, options: Option<ConversionOptions>, errors: &str) -> PyResult<String> {
+    let content = content.decode(errors, None)?;
+
+    convert_html(&content, options.as_ref())
+}
+
+/// Converts HTML to markdown, applying the selectors and kept tags of `options`.
+pub(crate) fn convert_html(html: &str, options: Option<&ConversionOptions>) -> PyResult<String> {
+    input::check_html_depth(html, options.and_then(|opts| opts.max_depth))?;
+
+    let Some(opts) = options.filter(|opts| {
+        opts.root_selector.is_some() || !opts.exclude_selectors.is_empty() || !opts.keep_html_for.is_empty()
+    }) else {
+        return mq_markdown::convert_html_to_markdown(html, conversion_options(options)).map_err(conversion_error);
+    };
+
+    let (html, kept) = input::scope_html(
+        html,
+        opts.root_selector.as_deref(),
+        &opts.exclude_selectors,
+        &opts.keep_html_for,
+    )?;
+    let markdown =
+        mq_markdown::convert_html_to_markdown(&html, conversion_options(options)).map_err(conversion_error)?;
+
+    Ok(kept.iter().enumerate().fold(markdown, |markdown, (i, kept)| {
+        markdown.replace(&input::kept_html_placeholder(i), kept)
+    }))
+}
+
+fn conversion_error(e: miette::Error) -> PyErr {
+    MQParseInputError::new_err(format!("Error converting HTML to Markdown: {}", e))
+}
+
+/// Converts an HTML fragment into a markdown value that can be passed to `DocumentBuilder`.
+///
+/// A fragment with a single top-level node, e.g. one `<h2>`, becomes that node; otherwise the
+/// result is an array of the nodes in order.
+#[pyfunction]
+#[pyo3(signature = (html, options=None))]
+fn html_fragment_to_value(html: &str, options: Option<ConversionOptions>) -> PyResult<MQValue> {
+    let markdown = convert_html(html, options.as_ref())?;
+    let mut nodes = mq_markdown::Markdown::from_markdown_str(&markdown)
+        .map_err(conversion_error)?
+        .nodes
+        .into_iter()
+        .map(|node| mq_lang::RuntimeValue::from(node).try_into())
+        .collect::<PyResult<Vec<MQValue>>>()?;
+
+    Ok(match nodes.len() {
+        1 => nodes.remove(0),
+        _ => MQValue::Array { value: nodes },
+    })
+}
+
+fn conversion_options(options: Option<&ConversionOptions>) -> mq_markdown::ConversionOptions {
+    match options {
+        Some(opts) => mq_markdown::ConversionOptions {
+            extract_scripts_as_code_blocks: opts.extract_scripts_as_code_blocks,
+            generate_front_matter: opts.generate_front_matter,
+            use_title_as_h1: opts.use_title_as_h1,
         },
-    )
-    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Error converting HTML to Markdown: {}", e)))
+        None => mq_markdown::ConversionOptions::default(),
+    }
 }
 
 #[pymodule]
 fn mq(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<InputFormat>()?;
+    m.add_class::<OutputFormat>()?;
     m.add_class::<ListStyle>()?;
     m.add_class::<UrlSurroundStyle>()?;
     m.add_class::<TitleSurroundStyle>()?;
+    m.add_class::<LinkTitlePolicy>()?;
+    m.add_class::<MathStyle>()?;
+    m.add_class::<HeadingStyle>()?;
+    m.add_class::<EmphasisStyle>()?;
+    m.add_class::<CodeFenceStyle>()?;
+    m.add_class::<HardBreakStyle>()?;
+    m.add_class::<HtmlPolicy>()?;
+    m.add_class::<SlugStyle>()?;
+    m.add_class::<UnicodeNormalization>()?;
+    m.add_class::<Encoding>()?;
     m.add_class::<Options>()?;
     m.add_class::<MQResult>()?;
+    m.add_class::<engine::CacheInfo>()?;
+    m.add_class::<converter::HtmlConverter>()?;
+    m.add_class::<engine::Engine>()?;
     m.add_class::<MQValue>()?;
+    m.add_class::<MarkdownType>()?;
+    m.add_class::<value::Position>()?;
     m.add_class::<ConversionOptions>()?;
+    m.add_class::<builder::DocumentBuilder>()?;
+    m.add_class::<fluent::Selection>()?;
+    m.add_class::<lint::LintFinding>()?;
+    m.add_class::<lint::QueryRule>()?;
+    m.add_class::<anchor::Anchor>()?;
+    m.add_class::<section::Section>()?;
+    m.add_class::<stats::DocumentStats>()?;
+    m.add_class::<extract::CodeBlock>()?;
+    m.add_class::<extract::Snippet>()?;
+    m.add_class::<links::ExtractedLink>()?;
+    m.add_class::<analyze::QueryAnalysis>()?;
+    m.add_class::<analyze::Diagnostic>()?;
+    m.add_class::<builtins::Builtin>()?;
+    m.add_class::<provenance::TraceStep>()?;
+    m.add_class::<update::UpdateResult>()?;
+    m.add_class::<stream::ValueIterator>()?;
+    m.add_class::<stream::MarkdownChunks>()?;
+    m.add_class::<stream::GlobResults>()?;
+    m.add_class::<diff::Change>()?;
+    m.add_class::<document::Document>()?;
+    m.add_class::<security::SecurityPolicy>()?;
+    m.add("MQParseInputError", m.py().get_type::<MQParseInputError>())?;
+    m.add("InputError", m.py().get_type::<MQParseInputError>())?;
+    m.add("ParseError", m.py().get_type::<error::ParseError>())?;
+    m.add("EvalError", m.py().get_type::<error::EvalError>())?;
+    m.add("LimitExceededError", m.py().get_type::<error::LimitExceededError>())?;
+    m.add("SecurityError", m.py().get_type::<error::SecurityError>())?;
     m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add_function(wrap_pyfunction!(run_async, m)?)?;
+    m.add_function(wrap_pyfunction!(run_file, m)?)?;
+    m.add_function(wrap_pyfunction!(run_glob, m)?)?;
+    m.add_function(wrap_pyfunction!(run_multi, m)?)?;
+    m.add_function(wrap_pyfunction!(engine::run_many, m)?)?;
+    m.add_function(wrap_pyfunction!(run_text, m)?)?;
+    m.add_function(wrap_pyfunction!(stream::run_iter, m)?)?;
+    m.add_function(wrap_pyfunction!(stream::html_to_markdown_stream, m)?)?;
+    m.add_function(wrap_pyfunction!(diff::diff, m)?)?;
+    m.add_function(wrap_pyfunction!(document::load, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_input, m)?)?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(render_tree, m)?)?;
+    m.add_function(wrap_pyfunction!(serialize::from_json, m)?)?;
+    m.add_function(wrap_pyfunction!(analyze::analyze, m)?)?;
+    m.add_function(wrap_pyfunction!(analyze::check_syntax, m)?)?;
+    m.add_function(wrap_pyfunction!(builtins::builtins, m)?)?;
+    m.add_function(wrap_pyfunction!(engine::compile_query, m)?)?;
     m.add_function(wrap_pyfunction!(html_to_markdown, m)?)?;
+    m.add_function(wrap_pyfunction!(html_fragment_to_value, m)?)?;
+    m.add_function(wrap_pyfunction!(update::update, m)?)?;
+    m.add_function(wrap_pyfunction!(update::replace, m)?)?;
+    m.add_function(wrap_pyfunction!(update::update_file, m)?)?;
+    m.add_function(wrap_pyfunction!(update::update_glob, m)?)?;
+    m.add_function(wrap_pyfunction!(fluent::doc, m)?)?;
+    m.add_function(wrap_pyfunction!(fluent::query, m)?)?;
+    m.add_function(wrap_pyfunction!(front_matter::get_front_matter, m)?)?;
+    m.add_function(wrap_pyfunction!(front_matter::set_front_matter, m)?)?;
+    m.add_function(wrap_pyfunction!(extract::code_blocks, m)?)?;
+    m.add_function(wrap_pyfunction!(extract::update_code_blocks, m)?)?;
+    m.add_function(wrap_pyfunction!(extract::extract_tables, m)?)?;
+    m.add_function(wrap_pyfunction!(extract::mdx_components, m)?)?;
+    m.add_function(wrap_pyfunction!(extract::links_by_section, m)?)?;
+    m.add_function(wrap_pyfunction!(extract::extract_with_context, m)?)?;
+    m.add_function(wrap_pyfunction!(links::extract_links, m)?)?;
+    m.add_function(wrap_pyfunction!(links::extract_images, m)?)?;
+    m.add_function(wrap_pyfunction!(links::rewrite_links, m)?)?;
+    m.add_function(wrap_pyfunction!(links::resolve_references, m)?)?;
+    m.add_function(wrap_pyfunction!(links::to_reference_style, m)?)?;
+    m.add_function(wrap_pyfunction!(lint::lint_structure, m)?)?;
+    m.add_function(wrap_pyfunction!(lint::lint, m)?)?;
+    m.add_function(wrap_pyfunction!(lint::audit_images, m)?)?;
+    m.add_function(wrap_pyfunction!(anchor::anchors, m)?)?;
+    m.add_function(wrap_pyfunction!(anchor::slugify, m)?)?;
+    m.add_function(wrap_pyfunction!(anchor::rename_headings, m)?)?;
+    m.add_function(wrap_pyfunction!(anchor::verify_toc, m)?)?;
+    m.add_function(wrap_pyfunction!(anchor::toc, m)?)?;
+    m.add_function(wrap_pyfunction!(section::sections, m)?)?;
+    m.add_function(wrap_pyfunction!(section::get_section, m)?)?;
+    m.add_function(wrap_pyfunction!(section::replace_section, m)?)?;
+    m.add_function(wrap_pyfunction!(section::remove_section, m)?)?;
+    m.add_function(wrap_pyfunction!(stats::stats, m)?)?;
+    m.add_function(wrap_pyfunction!(sanitize::sanitize, m)?)?;
+    m.add_function(wrap_pyfunction!(transform::inline_footnotes, m)?)?;
+    m.add_function(wrap_pyfunction!(transform::renumber_footnotes, m)?)?;
+    m.add_function(wrap_pyfunction!(transform::convert_callouts, m)?)?;
+    m.add_function(wrap_pyfunction!(transform::apply_link_policies, m)?)?;
+    m.add_function(wrap_pyfunction!(transform::normalize_headings, m)?)?;
+    m.add_function(wrap_pyfunction!(transform::limit_heading_depth, m)?)?;
+    m.add_function(wrap_pyfunction!(transform::normalize_code_languages, m)?)?;
+    m.add_function(wrap_pyfunction!(render::register_renderer, m)?)?;
+    m.add_function(wrap_pyfunction!(render::unregister_renderer, m)?)?;
+    m.add_function(wrap_pyfunction!(render::clear_renderers, m)?)?;
+    nodes::register(m)?;
+    text::register(m)?;
     Ok(())
 }
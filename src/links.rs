@@ -0,0 +1,374 @@
+//! The links and images of a document as plain objects, and rewriting their URLs and style.
+use crate::{Options, content::Content, render, section, transform, value::Position};
+use mq_markdown::Node;
+use pyo3::{exceptions::PyTypeError, prelude::*};
+use std::collections::{HashMap, HashSet};
+
+/// A link or image, as returned by `extract_links` and `extract_images`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct ExtractedLink {
+    /// The URL, resolved through the definition for reference links.
+    #[pyo3(get)]
+    url: String,
+    #[pyo3(get)]
+    title: Option<String>,
+    /// The plain text of a link, or the alt text of an image.
+    #[pyo3(get)]
+    text: String,
+    /// `"inline"`, `"reference"` or `"autolink"`.
+    #[pyo3(get)]
+    kind: &'static str,
+    #[pyo3(get)]
+    position: Option<Position>,
+}
+
+#[pymethods]
+impl ExtractedLink {
+    fn __repr__(&self) -> String {
+        format!("ExtractedLink({:?}, {:?}, {:?})", self.text, self.url, self.kind)
+    }
+}
+
+/// The URL and title of each definition, by lowercased label.
+type Definitions = HashMap<String, (String, Option<String>)>;
+
+fn collect_definitions(node: &Node, definitions: &mut Definitions) {
+    if let Node::Definition(definition) = node {
+        definitions.entry(definition.ident.to_lowercase()).or_insert_with(|| {
+            (
+                definition.url.as_str().to_string(),
+                definition.title.as_ref().map(|title| title.to_value()),
+            )
+        });
+    }
+
+    for child in node.children() {
+        collect_definitions(&child, definitions);
+    }
+}
+
+/// Whether a link was written as its URL, e.g. `<https://example.com>` or a bare URL.
+fn is_autolink(url: &str, text: &str) -> bool {
+    text == url || url.strip_prefix("mailto:") == Some(text)
+}
+
+fn collect(node: &Node, definitions: &Definitions, images: bool, links: &mut Vec<ExtractedLink>) {
+    let (url, title, text, kind, position) = match node {
+        Node::Link(link) if !images => {
+            let text = section::plain_text(node);
+            let url = link.url.as_str().to_string();
+            let kind = match link.title {
+                None if is_autolink(&url, &text) => "autolink",
+                _ => "inline",
+            };
+            (
+                Some(url),
+                link.title.as_ref().map(|t| t.to_value()),
+                text,
+                kind,
+                &link.position,
+            )
+        }
+        Node::LinkRef(link) if !images => {
+            let definition = definitions.get(&link.ident.to_lowercase());
+            (
+                definition.map(|(url, _)| url.clone()),
+                definition.and_then(|(_, title)| title.clone()),
+                link.values.iter().map(section::plain_text).collect(),
+                "reference",
+                &link.position,
+            )
+        }
+        Node::Image(image) if images => (
+            Some(image.url.clone()),
+            image.title.clone(),
+            image.alt.clone(),
+            "inline",
+            &image.position,
+        ),
+        Node::ImageRef(image) if images => {
+            let definition = definitions.get(&image.ident.to_lowercase());
+            (
+                definition.map(|(url, _)| url.clone()),
+                definition.and_then(|(_, title)| title.clone()),
+                image.alt.clone(),
+                "reference",
+                &image.position,
+            )
+        }
+        node => {
+            for child in node.children() {
+                collect(&child, definitions, images, links);
+            }
+
+            return;
+        }
+    };
+
+    // References without a definition are left out, like unresolved references in the document.
+    let Some(url) = url else {
+        return;
+    };
+
+    links.push(ExtractedLink {
+        url,
+        title,
+        text,
+        kind,
+        position: position.clone().map(|position| Position::new(position, None)),
+    });
+}
+
+fn extract(content: Content<'_>, images: bool) -> PyResult<Vec<ExtractedLink>> {
+    let nodes = transform::parse(&content)?;
+    let mut definitions = Definitions::new();
+    nodes
+        .iter()
+        .for_each(|node| collect_definitions(node, &mut definitions));
+
+    let mut links = Vec::new();
+    nodes
+        .iter()
+        .for_each(|node| collect(node, &definitions, images, &mut links));
+    Ok(links)
+}
+
+/// Returns every link of a document in order, with reference links resolved through their definitions.
+///
+/// Links whose text is their URL, such as `<https://example.com>`, are of kind `"autolink"`.
+#[pyfunction]
+pub(crate) fn extract_links(content: Content<'_>) -> PyResult<Vec<ExtractedLink>> {
+    extract(content, false)
+}
+
+/// Returns every image of a document in order, with the alt text as `text`.
+#[pyfunction]
+pub(crate) fn extract_images(content: Content<'_>) -> PyResult<Vec<ExtractedLink>> {
+    extract(content, true)
+}
+
+/// Replaces the URL of every link, image and definition with what `mapper` returns for it, leaving
+/// it as it is when `mapper` returns `None`.
+///
+/// The text of autolinks is updated along with the URL.
+#[pyfunction]
+#[pyo3(signature = (content, mapper, options=None))]
+pub(crate) fn rewrite_links(
+    content: Content<'_>,
+    mapper: &Bound<'_, PyAny>,
+    options: Option<Options>,
+) -> PyResult<String> {
+    let mut error = None;
+    let mut map = |url: &str| -> Option<String> {
+        if error.is_some() {
+            return None;
+        }
+
+        let mapped = mapper.call1((url,)).and_then(|mapped| {
+            mapped
+                .extract::<Option<String>>()
+                .map_err(|_| PyTypeError::new_err("mapper must return a str or None"))
+        });
+
+        match mapped {
+            Ok(mapped) => mapped,
+            Err(e) => {
+                error = Some(e);
+                None
+            }
+        }
+    };
+
+    let nodes = transform::rewrite(transform::parse(&content)?, &mut |node| {
+        vec![match node {
+            Node::Link(mut link) => {
+                let url = link.url.as_str().to_string();
+
+                if let Some(mapped) = map(&url) {
+                    if let [Node::Text(text)] = link.values.as_mut_slice()
+                        && link.title.is_none()
+                        && is_autolink(&url, &text.value)
+                    {
+                        text.value = if text.value == url {
+                            mapped.clone()
+                        } else {
+                            mapped.strip_prefix("mailto:").unwrap_or(&mapped).to_string()
+                        };
+                    }
+
+                    link.url = mq_markdown::Url::new(mapped);
+                }
+
+                Node::Link(link)
+            }
+            Node::Image(mut image) => {
+                if let Some(mapped) = map(&image.url) {
+                    image.url = mapped;
+                }
+
+                Node::Image(image)
+            }
+            Node::Definition(mut definition) => {
+                if let Some(mapped) = map(definition.url.as_str()) {
+                    definition.url = mq_markdown::Url::new(mapped);
+                }
+
+                Node::Definition(definition)
+            }
+            node => node,
+        }]
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => render::to_markdown(nodes, options),
+    }
+}
+
+/// Replaces reference-style links and images with inline ones and removes the definitions they
+/// used, so the document reads the same without them.
+///
+/// Footnotes are inlined too, as `inline_footnotes` does with `footnotes` as its style, unless
+/// `footnotes` is `None`. References without a definition are left as they are.
+#[pyfunction]
+#[pyo3(signature = (content, footnotes=Some("parens"), options=None))]
+pub(crate) fn resolve_references(
+    content: Content<'_>,
+    footnotes: Option<&str>,
+    options: Option<Options>,
+) -> PyResult<String> {
+    let mut nodes = transform::parse(&content)?;
+
+    if let Some(style) = footnotes {
+        nodes = transform::inline_footnote_nodes(nodes, style)?;
+    }
+
+    let mut definitions = Definitions::new();
+    nodes
+        .iter()
+        .for_each(|node| collect_definitions(node, &mut definitions));
+
+    let mut resolved = HashSet::new();
+    let nodes = transform::rewrite(nodes, &mut |node| {
+        vec![match node {
+            Node::LinkRef(link) => match definitions.get(&link.ident.to_lowercase()) {
+                Some((url, title)) => {
+                    resolved.insert(link.ident.to_lowercase());
+                    Node::Link(mq_markdown::Link {
+                        url: mq_markdown::Url::new(url.clone()),
+                        title: title.clone().map(mq_markdown::Title::new),
+                        values: link.values,
+                        position: link.position,
+                    })
+                }
+                None => Node::LinkRef(link),
+            },
+            Node::ImageRef(image) => match definitions.get(&image.ident.to_lowercase()) {
+                Some((url, title)) => {
+                    resolved.insert(image.ident.to_lowercase());
+                    Node::Image(mq_markdown::Image {
+                        alt: image.alt,
+                        url: url.clone(),
+                        title: title.clone(),
+                        position: image.position,
+                    })
+                }
+                None => Node::ImageRef(image),
+            },
+            node => node,
+        }]
+    });
+    let nodes = transform::rewrite(nodes, &mut |node| match node {
+        Node::Definition(definition) if resolved.contains(&definition.ident.to_lowercase()) => Vec::new(),
+        node => vec![node],
+    });
+
+    render::to_markdown(nodes, options)
+}
+
+/// Replaces inline links and images with reference-style ones, adding a definition for each URL
+/// and title at the end of the document.
+///
+/// Links to a URL that already has a definition use its label; new labels are numbered 1, 2, 3...
+/// in order of first use. Autolinks are left as they are.
+#[pyfunction]
+#[pyo3(signature = (content, options=None))]
+pub(crate) fn to_reference_style(content: Content<'_>, options: Option<Options>) -> PyResult<String> {
+    let nodes = transform::parse(&content)?;
+    let mut definitions = Definitions::new();
+    nodes
+        .iter()
+        .for_each(|node| collect_definitions(node, &mut definitions));
+
+    let mut labels = definitions
+        .iter()
+        .map(|(label, target)| (target.clone(), label.clone()))
+        .collect::<HashMap<_, _>>();
+    let mut added = Vec::new();
+    let mut label = |url: String, title: Option<String>| {
+        let target = (url, title);
+
+        if let Some(label) = labels.get(&target) {
+            return label.clone();
+        }
+
+        let label = (1..)
+            .map(|n| n.to_string())
+            .find(|label| !definitions.contains_key(label) && !labels.values().any(|used| used == label))
+            .unwrap_or_default();
+        labels.insert(target.clone(), label.clone());
+        added.push((label.clone(), target));
+        label
+    };
+
+    let mut nodes = transform::rewrite(nodes, &mut |node| {
+        vec![match node {
+            Node::Link(link)
+                if link.title.is_some()
+                    || !is_autolink(link.url.as_str(), &section::plain_text(&Node::Link(link.clone()))) =>
+            {
+                let ident = label(link.url.as_str().to_string(), link.title.as_ref().map(|t| t.to_value()));
+                Node::LinkRef(mq_markdown::LinkRef {
+                    label: Some(ident.clone()),
+                    ident,
+                    values: link.values,
+                    position: link.position,
+                })
+            }
+            Node::Image(image) => {
+                let ident = label(image.url, image.title);
+                Node::ImageRef(mq_markdown::ImageRef {
+                    alt: image.alt,
+                    label: Some(ident.clone()),
+                    ident,
+                    position: image.position,
+                })
+            }
+            node => node,
+        }]
+    });
+
+    // The definitions are placed on consecutive lines after the document, so they render as one block.
+    let last_line = nodes
+        .iter()
+        .filter_map(|node| node.position().map(|position| position.end.line))
+        .max()
+        .unwrap_or(0);
+
+    nodes.extend(added.into_iter().enumerate().map(|(i, (label, (url, title)))| {
+        let line = last_line + 2 + i;
+        Node::Definition(mq_markdown::Definition {
+            position: Some(mq_markdown::Position {
+                start: mq_markdown::Point { line, column: 1 },
+                end: mq_markdown::Point { line, column: 1 },
+            }),
+            url: mq_markdown::Url::new(url),
+            title: title.map(mq_markdown::Title::new),
+            ident: label.clone(),
+            label: Some(label),
+        })
+    }));
+
+    render::to_markdown(nodes, options)
+}
@@ -0,0 +1,608 @@
+//! Structural checks for markdown documents.
+use crate::{Options, content::Content, engine, extract, input, section, transform};
+use mq_lang::RuntimeValue;
+use mq_markdown::Node;
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::collections::{HashMap, HashSet};
+
+const RULES: [&str; 6] = [
+    "duplicate-heading",
+    "skipped-heading-level",
+    "empty-section",
+    "multiple-h1",
+    "trailing-whitespace",
+    "max-heading-depth",
+];
+
+/// The built-in checks of `lint`, with their severities.
+const CHECKS: [(&str, &str); 5] = [
+    ("duplicate-heading", "warning"),
+    ("skipped-heading-level", "warning"),
+    ("bare-url", "warning"),
+    ("table-cell-whitespace", "info"),
+    ("unused-definition", "warning"),
+];
+
+const SEVERITIES: [&str; 3] = ["error", "warning", "info"];
+
+/// A single issue found by `lint_structure`, with the 1-based position it applies to.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct LintFinding {
+    #[pyo3(get)]
+    rule: String,
+    #[pyo3(get)]
+    message: String,
+    #[pyo3(get)]
+    pub(crate) line: usize,
+    #[pyo3(get)]
+    pub(crate) column: usize,
+    /// `"error"`, `"warning"` or `"info"`.
+    #[pyo3(get)]
+    severity: String,
+}
+
+impl LintFinding {
+    pub(crate) fn new(rule: &str, message: String, line: usize, column: usize) -> Self {
+        Self {
+            rule: rule.to_string(),
+            message,
+            line,
+            column,
+            severity: "warning".to_string(),
+        }
+    }
+}
+
+#[pymethods]
+impl LintFinding {
+    fn __repr__(&self) -> String {
+        format!(
+            "LintFinding(rule={:?}, line={}, column={}, message={:?})",
+            self.rule, self.line, self.column, self.message
+        )
+    }
+}
+
+struct Heading {
+    line: usize,
+    column: usize,
+    depth: u8,
+    text: String,
+}
+
+/// Checks a document for common structural issues and returns the findings in document order.
+///
+/// `rules` limits the checks to the given rule names; all rules run by default. The
+/// `max-heading-depth` rule only runs when `max_heading_depth` is given.
+#[pyfunction]
+#[pyo3(signature = (content, rules=None, options=None, max_heading_depth=None))]
+pub(crate) fn lint_structure(
+    content: Content<'_>,
+    rules: Option<Vec<String>>,
+    options: Option<Options>,
+    max_heading_depth: Option<u8>,
+) -> PyResult<Vec<LintFinding>> {
+    let content = content.as_str()?;
+    let rules = match rules {
+        Some(rules) => {
+            if let Some(rule) = rules.iter().find(|rule| !RULES.contains(&rule.as_str())) {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown lint rule '{}', expected one of: {}",
+                    rule,
+                    RULES.join(", ")
+                )));
+            }
+
+            rules
+        }
+        None => RULES.iter().map(|rule| rule.to_string()).collect(),
+    };
+
+    structure(content, &rules, &options.unwrap_or_default(), max_heading_depth)
+}
+
+fn structure(
+    content: &str,
+    rules: &[String],
+    options: &Options,
+    max_heading_depth: Option<u8>,
+) -> PyResult<Vec<LintFinding>> {
+    let enabled = |rule: &str| rules.iter().any(|r| r == rule);
+
+    let nodes = input::parse(content, options)?
+        .into_iter()
+        .filter_map(|value| match value {
+            mq_lang::RuntimeValue::Markdown(node, _) => Some(node),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // Each entry is a heading, or `None` for any other top-level block.
+    let blocks = nodes
+        .iter()
+        .map(|node| match node.as_ref() {
+            Node::Heading(heading) => Some(Heading {
+                line: heading.position.as_ref().map_or(0, |p| p.start.line),
+                column: heading.position.as_ref().map_or(0, |p| p.start.column),
+                depth: heading.depth,
+                text: section::plain_text(node),
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut findings = Vec::new();
+    let mut finding = |rule: &str, line: usize, column: usize, message: String| {
+        if enabled(rule) {
+            findings.push(LintFinding::new(rule, message, line, column));
+        }
+    };
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    let mut previous_depth = None;
+    let mut first_h1 = None;
+
+    for (i, heading) in blocks.iter().enumerate() {
+        let Some(heading) = heading else {
+            continue;
+        };
+
+        match seen.get(&heading.text.to_lowercase()) {
+            Some(first) => finding(
+                "duplicate-heading",
+                heading.line,
+                heading.column,
+                format!("Duplicate heading \"{}\" (first at line {})", heading.text, first),
+            ),
+            None => {
+                seen.insert(heading.text.to_lowercase(), heading.line);
+            }
+        }
+
+        if let Some(previous) = previous_depth
+            && heading.depth > previous + 1
+        {
+            finding(
+                "skipped-heading-level",
+                heading.line,
+                heading.column,
+                format!("Heading level {} follows level {}", heading.depth, previous),
+            );
+        }
+
+        previous_depth = Some(heading.depth);
+
+        if let Some(max) = max_heading_depth
+            && heading.depth > max
+        {
+            finding(
+                "max-heading-depth",
+                heading.line,
+                heading.column,
+                format!("Heading level {} is deeper than the maximum of {}", heading.depth, max),
+            );
+        }
+
+        if heading.depth == 1 {
+            match first_h1 {
+                Some(first) => finding(
+                    "multiple-h1",
+                    heading.line,
+                    heading.column,
+                    format!("Multiple level 1 headings (first at line {})", first),
+                ),
+                None => first_h1 = Some(heading.line),
+            }
+        }
+
+        let empty = match blocks.get(i + 1) {
+            None => true,
+            Some(Some(next)) => next.depth <= heading.depth,
+            Some(None) => false,
+        };
+
+        if empty {
+            finding(
+                "empty-section",
+                heading.line,
+                heading.column,
+                format!("Section \"{}\" has no content", heading.text),
+            );
+        }
+    }
+
+    let mut code_lines = HashSet::new();
+    let mut break_lines = HashSet::new();
+
+    for node in &nodes {
+        collect_lines(node, &mut code_lines, &mut break_lines);
+    }
+
+    for (i, line) in content.lines().enumerate() {
+        let trimmed = line.trim_end_matches([' ', '\t']);
+        let trailing = &line[trimmed.len()..];
+
+        // Exactly two trailing spaces are only exempt where they make a hard line break in a paragraph.
+        let hard_break = trailing == "  " && break_lines.contains(&(i + 1));
+
+        if !trailing.is_empty() && !hard_break && !code_lines.contains(&(i + 1)) {
+            finding(
+                "trailing-whitespace",
+                i + 1,
+                trimmed.chars().count() + 1,
+                "Trailing whitespace".to_string(),
+            );
+        }
+    }
+
+    findings.sort_by_key(|finding| (finding.line, finding.column));
+    Ok(findings)
+}
+
+/// Collects the lines of code blocks, where whitespace is content, and the lines ending in a hard
+/// line break.
+fn collect_lines(node: &Node, code_lines: &mut HashSet<usize>, break_lines: &mut HashSet<usize>) {
+    match node {
+        Node::Code(code) => {
+            if let Some(position) = &code.position {
+                code_lines.extend(position.start.line..=position.end.line);
+            }
+        }
+        Node::Break(hard_break) => {
+            if let Some(position) = &hard_break.position {
+                break_lines.insert(position.start.line);
+            }
+        }
+        node => {
+            for child in node.children() {
+                collect_lines(&child, code_lines, break_lines);
+            }
+        }
+    }
+}
+
+struct Image {
+    alt: String,
+    /// The URL, or the reference label for reference images without a definition.
+    target: String,
+    line: usize,
+    column: usize,
+}
+
+fn collect_images(node: &Node, definitions: &HashMap<String, String>, images: &mut Vec<Image>) {
+    let (alt, target, position) = match node {
+        Node::Image(image) => (&image.alt, image.url.clone(), image.position.as_ref()),
+        Node::ImageRef(image) => (
+            &image.alt,
+            definitions
+                .get(&image.ident.to_lowercase())
+                .cloned()
+                .unwrap_or_else(|| format!("[{}]", image.ident)),
+            image.position.as_ref(),
+        ),
+        node => {
+            for child in node.children() {
+                collect_images(&child, definitions, images);
+            }
+
+            return;
+        }
+    };
+
+    images.push(Image {
+        alt: alt.clone(),
+        target,
+        line: position.map_or(0, |p| p.start.line),
+        column: position.map_or(0, |p| p.start.column),
+    });
+}
+
+/// Checks the alt text of every image for accessibility and returns the findings in document order.
+///
+/// The rules are `missing-alt` for empty alt text, `long-alt` for alt text longer than
+/// `max_alt_length` characters and `duplicate-alt` for alt text already used for another image.
+#[pyfunction]
+#[pyo3(signature = (content, max_alt_length=125))]
+pub(crate) fn audit_images(content: Content<'_>, max_alt_length: usize) -> PyResult<Vec<LintFinding>> {
+    let nodes = transform::parse(&content)?;
+    let mut definitions = HashMap::new();
+    nodes
+        .iter()
+        .for_each(|node| extract::collect_definitions(node, &mut definitions));
+
+    let mut images = Vec::new();
+    nodes
+        .iter()
+        .for_each(|node| collect_images(node, &definitions, &mut images));
+
+    let mut findings = Vec::new();
+    let mut finding = |rule: &str, image: &Image, message: String| {
+        findings.push(LintFinding::new(rule, message, image.line, image.column))
+    };
+    let mut seen: HashMap<String, (&str, usize)> = HashMap::new();
+
+    for image in &images {
+        let alt = image.alt.trim();
+
+        if alt.is_empty() {
+            finding("missing-alt", image, format!("Image \"{}\" has no alt text", image.target));
+            continue;
+        }
+
+        let length = alt.chars().count();
+
+        if length > max_alt_length {
+            finding(
+                "long-alt",
+                image,
+                format!(
+                    "Alt text of image \"{}\" is {} characters long, more than the maximum of {}",
+                    image.target, length, max_alt_length
+                ),
+            );
+        }
+
+        match seen.get(&alt.to_lowercase()) {
+            Some((target, line)) if *target != image.target => finding(
+                "duplicate-alt",
+                image,
+                format!("Alt text \"{}\" is also used for image \"{}\" at line {}", alt, target, line),
+            ),
+            Some(_) => {}
+            None => {
+                seen.insert(alt.to_lowercase(), (&image.target, image.line));
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+/// A lint rule written as an mq query: every node the query returns is reported with `message`.
+#[pyclass(frozen, from_py_object, module = "mq")]
+#[derive(Debug, Clone)]
+pub(crate) struct QueryRule {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    query: String,
+    #[pyo3(get)]
+    message: String,
+    #[pyo3(get)]
+    severity: String,
+}
+
+#[pymethods]
+impl QueryRule {
+    #[new]
+    #[pyo3(signature = (name, query, message, severity="warning"))]
+    fn new(name: String, query: String, message: String, severity: &str) -> PyResult<Self> {
+        Ok(Self {
+            name,
+            query,
+            message,
+            severity: check_severity(severity)?.to_string(),
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("QueryRule({:?}, {:?})", self.name, self.query)
+    }
+}
+
+fn check_severity(severity: &str) -> PyResult<&str> {
+    if SEVERITIES.contains(&severity) {
+        Ok(severity)
+    } else {
+        Err(PyValueError::new_err(format!(
+            "Unknown severity '{}', expected one of: {}",
+            severity,
+            SEVERITIES.join(", ")
+        )))
+    }
+}
+
+#[derive(FromPyObject)]
+pub(crate) enum Rule {
+    Check(String),
+    Query(QueryRule),
+}
+
+fn position(node: &Node) -> (usize, usize) {
+    node.position().map_or((0, 0), |p| (p.start.line, p.start.column))
+}
+
+/// Walks every node, depth first.
+fn visit(node: &Node, f: &mut impl FnMut(&Node)) {
+    f(node);
+
+    for child in node.children() {
+        visit(&child, f);
+    }
+}
+
+/// Links written as their URL without the angle brackets of an autolink.
+fn bare_urls(nodes: &[Node], lines: &[&str], findings: &mut Vec<(usize, usize, String)>) {
+    for node in nodes {
+        visit(node, &mut |node| {
+            let Node::Link(link) = node else {
+                return;
+            };
+            let (line, column) = position(node);
+            let url = link.url.as_str();
+            let bracketed = lines
+                .get(line.wrapping_sub(1))
+                .and_then(|text| text.chars().nth(column.wrapping_sub(1)))
+                .is_some_and(|c| c == '<');
+
+            if link.title.is_none() && !bracketed && section::plain_text(node) == url {
+                findings.push((line, column, format!("Bare URL \"{}\", write it as <{}>", url, url)));
+            }
+        });
+    }
+}
+
+/// Definitions that no link or image refers to.
+fn unused_definitions(nodes: &[Node], findings: &mut Vec<(usize, usize, String)>) {
+    let mut used = HashSet::new();
+    let mut definitions = Vec::new();
+
+    for node in nodes {
+        visit(node, &mut |node| match node {
+            Node::LinkRef(link) => {
+                used.insert(link.ident.to_lowercase());
+            }
+            Node::ImageRef(image) => {
+                used.insert(image.ident.to_lowercase());
+            }
+            Node::Definition(definition) => definitions.push((definition.ident.clone(), position(node))),
+            _ => {}
+        });
+    }
+
+    for (ident, (line, column)) in definitions {
+        if !used.contains(&ident.to_lowercase()) {
+            findings.push((line, column, format!("Link definition \"{}\" is never used", ident)));
+        }
+    }
+}
+
+/// Returns the character columns of the unescaped pipes of a table row.
+fn pipes(line: &str) -> Vec<usize> {
+    let mut escaped = false;
+    let mut pipes = Vec::new();
+
+    for (i, c) in line.chars().enumerate() {
+        if c == '|' && !escaped {
+            pipes.push(i);
+        }
+
+        escaped = c == '\\' && !escaped;
+    }
+
+    pipes
+}
+
+/// Cells with more whitespace before their closing pipe than the single space of padding, unless the
+/// pipe lines up with the same pipe of another row, as in a table padded for alignment.
+fn table_cell_whitespace(nodes: &[Node], lines: &[&str], findings: &mut Vec<(usize, usize, String)>) {
+    let mut rows = nodes
+        .iter()
+        .filter(|node| matches!(node, Node::TableCell(_) | Node::TableAlign(_)))
+        .filter_map(|node| node.position().map(|p| p.start.line))
+        .collect::<Vec<_>>();
+    rows.dedup();
+
+    // Rows on consecutive lines belong to the same table.
+    for table in rows.chunk_by(|a, b| b - a == 1) {
+        let table = table
+            .iter()
+            .filter_map(|line| {
+                let text = lines.get(line - 1)?;
+                Some((*line, text.chars().collect::<Vec<_>>(), pipes(text)))
+            })
+            .collect::<Vec<_>>();
+
+        for (row, (line, chars, pipes)) in table.iter().enumerate() {
+            for (i, pair) in pipes.windows(2).enumerate() {
+                let cell = &chars[pair[0] + 1..pair[1]];
+                let content = cell.iter().rposition(|c| !c.is_whitespace());
+                let aligned = table
+                    .iter()
+                    .enumerate()
+                    .any(|(other, (_, _, pipes))| other != row && pipes.get(i + 1) == Some(&pair[1]));
+
+                match content {
+                    Some(end) if cell.len() - end > 2 && !aligned => findings.push((
+                        *line,
+                        pair[0] + end + 3,
+                        format!("Table cell {} has trailing whitespace", i + 1),
+                    )),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Checks a document with built-in rules and rules written as mq queries, returning the findings
+/// sorted by position.
+///
+/// `rules` lists built-in rule names and `QueryRule`s; all built-in rules run by default.
+#[pyfunction]
+#[pyo3(signature = (content, rules=None, options=None))]
+pub(crate) fn lint(
+    content: Content<'_>,
+    rules: Option<Vec<Rule>>,
+    options: Option<Options>,
+) -> PyResult<Vec<LintFinding>> {
+    let content = content.as_str()?;
+    let options = options.unwrap_or_default();
+    let rules = rules.unwrap_or_else(|| CHECKS.iter().map(|(name, _)| Rule::Check(name.to_string())).collect());
+    let input = input::parse(content, &options)?;
+    let nodes = input
+        .iter()
+        .filter_map(|value| match value {
+            RuntimeValue::Markdown(node, _) => Some(node.as_ref().clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let lines = content.lines().collect::<Vec<_>>();
+    let mut findings = Vec::new();
+
+    for rule in rules {
+        let rule = match rule {
+            Rule::Check(name) => name,
+            Rule::Query(rule) => {
+                for value in engine::eval(&rule.query, input.clone(), &options)? {
+                    // Empty values stand in for nodes the query did not match.
+                    if let RuntimeValue::Markdown(node, _) = value
+                        && !node.is_empty()
+                        && !node.is_empty_fragment()
+                    {
+                        let (line, column) = position(&node);
+                        findings.push(LintFinding {
+                            severity: rule.severity.clone(),
+                            ..LintFinding::new(&rule.name, rule.message.clone(), line, column)
+                        });
+                    }
+                }
+
+                continue;
+            }
+        };
+        let Some((_, severity)) = CHECKS.iter().find(|(name, _)| *name == rule) else {
+            return Err(PyValueError::new_err(format!(
+                "Unknown lint rule '{}', expected a QueryRule or one of: {}",
+                rule,
+                CHECKS.map(|(name, _)| name).join(", ")
+            )));
+        };
+        let mut found = Vec::new();
+
+        match rule.as_str() {
+            "duplicate-heading" | "skipped-heading-level" => {
+                findings.extend(
+                    structure(content, std::slice::from_ref(&rule), &options, None)?
+                        .into_iter()
+                        .map(|finding| LintFinding {
+                            severity: severity.to_string(),
+                            ..finding
+                        }),
+                );
+            }
+            "bare-url" => bare_urls(&nodes, &lines, &mut found),
+            "table-cell-whitespace" => table_cell_whitespace(&nodes, &lines, &mut found),
+            _ => unused_definitions(&nodes, &mut found),
+        }
+
+        findings.extend(found.into_iter().map(|(line, column, message)| LintFinding {
+            severity: severity.to_string(),
+            ..LintFinding::new(&rule, message, line, column)
+        }));
+    }
+
+    findings.sort_by_key(|finding| (finding.line, finding.column));
+    Ok(findings)
+}
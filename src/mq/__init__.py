@@ -1,3 +1,8 @@
+import sys
+
 from .mq import *
 
 __all__ = mq.__all__
+
+sys.modules[f"{__name__}.nodes"] = nodes
+sys.modules[f"{__name__}.text"] = text
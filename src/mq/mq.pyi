@@ -1,6 +1,24 @@
-from typing import List, Optional
+import asyncio
+import os
+from typing import (
+    Any,
+    Callable,
+    ClassVar,
+    Dict,
+    Iterable,
+    Iterator,
+    List,
+    Literal,
+    Optional,
+    Sequence,
+    Tuple,
+    Union,
+    overload,
+)
 from enum import Enum
 
+from .nodes import Children, Node
+
 class InputFormat(Enum):
     """The format of the input document."""
 
@@ -10,6 +28,21 @@ class InputFormat(Enum):
     HTML: 4
     RAW: 5
     NULL: 6
+    CSV: 7
+    """Comma-separated values, parsed into table cells like a markdown table with the first row as header."""
+    TSV: 8
+    """Tab-separated values, parsed like `CSV`."""
+
+class OutputFormat(Enum):
+    """The format `MQResult.render` writes results in."""
+
+    MARKDOWN: 1
+    HTML: 2
+    """Markdown converted to HTML, with GitHub extensions."""
+    JSON: 3
+    """The nodes as a JSON list, as written by `mq -F json`."""
+    TEXT: 4
+    """The plain text of each value on its own line."""
 
 class ListStyle(Enum):
     """Style to use for markdown lists."""
@@ -31,18 +64,352 @@ class UrlSurroundStyle(Enum):
     ANGLE: 1
     NONE: 2
 
+class LinkTitlePolicy(Enum):
+    """How link and image titles are written when rendering."""
+
+    NORMALIZE: 1
+    """Collapse whitespace, escape quotes that would end the title and drop empty titles."""
+    STRIP: 2
+    """Drop titles."""
+
+class MathStyle(Enum):
+    """How math blocks are written when rendering."""
+
+    DOLLAR: 1
+    """Write math blocks, including fenced `math` code blocks, as `$$` blocks."""
+    FENCED: 2
+    """Write math blocks as fenced `math` code blocks."""
+
+class HeadingStyle(Enum):
+    """How level 1 and 2 headings are written when rendering."""
+
+    ATX: 1
+    """Write headings with `#` markers."""
+    SETEXT: 2
+    """Underline level 1 headings with `=` and level 2 headings with `-`; deeper headings keep `#`."""
+
+class EmphasisStyle(Enum):
+    """The delimiter emphasis or strong emphasis is written with."""
+
+    STAR: 1
+    """`*emphasis*` and `**strong**`."""
+    UNDERSCORE: 2
+    """`_emphasis_` and `__strong__`."""
+
+class CodeFenceStyle(Enum):
+    """The character fenced code blocks are written with."""
+
+    BACKTICK: 1
+    TILDE: 2
+
+class HardBreakStyle(Enum):
+    """How hard line breaks are written."""
+
+    BACKSLASH: 1
+    """A backslash at the end of the line."""
+    SPACES: 2
+    """Two spaces at the end of the line."""
+
+class HtmlPolicy(Enum):
+    """How raw HTML inside markdown is represented after parsing."""
+
+    KEEP: 1
+    """Keep raw HTML as opaque Html nodes."""
+    CONVERT: 2
+    """Convert raw HTML blocks and inline elements to markdown nodes where possible, keeping them otherwise."""
+    STRIP: 3
+    """Remove raw HTML tags, keeping the text inside them."""
+
+class SlugStyle(Enum):
+    """How heading text is turned into the slug of its anchor."""
+
+    GITHUB: 1
+    """Lowercase, without punctuation and with spaces as hyphens."""
+    GITLAB: 2
+    """As GitHub, with runs of hyphens collapsed and `anchor-` before slugs of only digits."""
+    CUSTOM: 3
+    """Whatever `Options.slug_function` returns for the text."""
+
+class UnicodeNormalization(Enum):
+    """Unicode normalization form applied to input before parsing."""
+
+    NFC: 1
+    NFKC: 2
+
+class Encoding(Enum):
+    """Encoding of content passed as bytes or a buffer; `str` content is used as it is."""
+
+    UTF8: 1
+    UTF16: 2
+    """UTF-16 with the byte order given by a byte order mark, little-endian without one."""
+    UTF16_LE: 3
+    UTF16_BE: 4
+    LATIN1: 5
+
+class SecurityPolicy:
+    """
+    What queries run with `Options.security` may use. Queries that use anything else raise
+    `SecurityError` without being run.
+
+    Names are checked whether or not the query defines them, so queries cannot define their own
+    functions under the names of builtins they may not call.
+    """
+
+    def __init__(
+        self,
+        allow_env: bool = False,
+        allow_include: bool = False,
+        allowed_functions: Optional[Sequence[str]] = None,
+    ) -> None: ...
+    @property
+    def allow_env(self) -> bool:
+        """Whether queries may read environment variables, with `$NAME`."""
+    @property
+    def allow_include(self) -> bool:
+        """Whether queries may load modules, with `include` and `import`."""
+    @property
+    def allowed_functions(self) -> Optional[List[str]]:
+        """
+        The builtins queries may call, or None to allow all but those with side effects, such as
+        `print`, `input`, `halt` and the file functions, and those that look up names at run time:
+        `get_variable`, `set_variable`, `intern` and `all_symbols`. Functions of imported modules are
+        not builtins.
+        """
+
+FrontMatterFilter = Union[Dict[str, Any], Callable[[Dict[str, Any]], bool]]
+"""The front matter values a document must have, or a predicate on its front matter dict."""
+
+Content = Union[str, bytes, bytearray, memoryview]
+"""
+Document content: text, or bytes from any buffer-protocol object in `Options.encoding`.
+
+`str`, `bytes`, `bytearray` and memoryviews of a whole `bytes` or `bytearray` are read in place. Other
+buffers, e.g. memoryviews of an `mmap` or `multiprocessing.shared_memory`, or slices, are copied first, as
+the stable Python ABI this module is built for gives no access to their memory.
+"""
+
 class Options:
-    """Configuration options for mq processing."""
+    """
+    Configuration options for mq processing.
 
-    def __init__(self) -> None: ...
+    Every option can also be passed to the constructor, e.g.
+    `mq.Options(input_format=mq.InputFormat.HTML, max_results=10)`.
+    """
+
+    def __init__(
+        self,
+        *,
+        input_format: Optional[InputFormat] = None,
+        output_format: Optional[OutputFormat] = None,
+        list_style: Optional[ListStyle] = None,
+        link_title_style: Optional[TitleSurroundStyle] = None,
+        link_url_style: Optional[UrlSurroundStyle] = None,
+        percent_encode_urls: bool = False,
+        link_titles: Optional[LinkTitlePolicy] = None,
+        math_style: Optional[MathStyle] = None,
+        heading_style: Optional[HeadingStyle] = None,
+        emphasis_style: Optional[EmphasisStyle] = None,
+        strong_style: Optional[EmphasisStyle] = None,
+        code_fence_style: Optional[CodeFenceStyle] = None,
+        hard_break_style: Optional[HardBreakStyle] = None,
+        list_marker_spacing: Optional[int] = None,
+        table_padding: bool = False,
+        trim_trailing_whitespace: bool = False,
+        max_blank_lines: Optional[int] = None,
+        final_newline: bool = False,
+        heading_anchors: bool = False,
+        slug_style: Optional[SlugStyle] = None,
+        slug_function: Optional[Callable[[str], str]] = None,
+        html_attributes: Optional[Dict[MarkdownType, Dict[str, str]]] = None,
+        parallel_parse: bool = False,
+        record_provenance: bool = False,
+        collect_timings: bool = False,
+        debug: bool = False,
+        max_results: Optional[int] = None,
+        max_output_bytes: Optional[int] = None,
+        display_limit: Optional[int] = None,
+        html_selector: Optional[str] = None,
+        html_policy: Optional[HtmlPolicy] = None,
+        max_html_depth: Optional[int] = None,
+        unicode_normalization: Optional[UnicodeNormalization] = None,
+        encoding: Optional[Encoding] = None,
+        module_paths: Optional[Sequence[Union[str, "os.PathLike[str]"]]] = None,
+        max_recursion_depth: Optional[int] = None,
+        max_output_nodes: Optional[int] = None,
+        security: Optional[SecurityPolicy] = None,
+    ) -> None:
+        """
+        Raises:
+            TypeError: If an option is unknown or a value has the wrong type
+        """
     @property
     def input_format(self) -> InputFormat: ...
     @property
+    def output_format(self) -> OutputFormat:
+        """The format `MQResult.render` writes results in."""
+    @property
     def list_style(self) -> ListStyle: ...
     @property
     def link_title_style(self) -> TitleSurroundStyle: ...
     @property
     def link_url_style(self) -> UrlSurroundStyle: ...
+    @property
+    def percent_encode_urls(self) -> bool:
+        """Percent-encode spaces, non-ASCII and other unsafe characters in link, image and definition URLs when rendering."""
+    @property
+    def link_titles(self) -> Optional[LinkTitlePolicy]:
+        """Normalize or strip link and image titles when rendering; None keeps them as parsed."""
+    @property
+    def math_style(self) -> Optional[MathStyle]:
+        """Rewrite math blocks as `$$` blocks or fenced `math` code blocks when rendering; None keeps them as parsed."""
+    @property
+    def heading_style(self) -> Optional[HeadingStyle]:
+        """Write level 1 and 2 headings with `#` or underlined when rendering; None keeps `#`."""
+    @property
+    def emphasis_style(self) -> Optional[EmphasisStyle]:
+        """The delimiter emphasis is written with; None writes `*`."""
+    @property
+    def strong_style(self) -> Optional[EmphasisStyle]:
+        """The delimiter strong emphasis is written with; None writes `**`."""
+    @property
+    def code_fence_style(self) -> Optional[CodeFenceStyle]:
+        """
+        The character fenced code blocks are written with, in a fence longer than any run of it in
+        the code; None writes three backticks.
+        """
+    @property
+    def hard_break_style(self) -> Optional[HardBreakStyle]:
+        """How hard line breaks are written; None writes a backslash."""
+    @property
+    def list_marker_spacing(self) -> Optional[int]:
+        """The number of spaces after list markers, e.g. 3 for `-   item`; nested lines are indented to match."""
+    @property
+    def table_padding(self) -> bool:
+        """Pad table cells so that the columns line up, aligned as the delimiter row says."""
+    @property
+    def trim_trailing_whitespace(self) -> bool:
+        """Trim trailing spaces and tabs when rendering, except two-space hard line breaks."""
+    @property
+    def max_blank_lines(self) -> Optional[int]:
+        """Collapse runs of blank lines to at most this many when rendering; None keeps them."""
+    @property
+    def final_newline(self) -> bool:
+        """End rendered documents with exactly one newline."""
+    @property
+    def heading_anchors(self) -> bool:
+        """Insert an `<a id="slug"></a>` anchor at the start of each heading when rendering markdown."""
+    @property
+    def slug_style(self) -> Optional[SlugStyle]:
+        """How heading anchors are slugged; None slugs them as GitHub does."""
+    @property
+    def slug_function(self) -> Optional[Callable[[str], str]]:
+        """The function `SlugStyle.CUSTOM` calls with the heading text to get its slug."""
+    @property
+    def html_attributes(self) -> Optional[Dict[MarkdownType, Dict[str, str]]]:
+        """
+        Attributes to add to the HTML elements of each node type when rendering with `OutputFormat.HTML`,
+        e.g. `{MarkdownType.Heading: {"class": "docs-h{level}"}}`, where `{level}` is the heading level.
+        Classes are added to those an element already has. Elements written as raw HTML in the document
+        get the attributes too.
+
+        Raises:
+            ValueError: If set for a node type without an element of its own, e.g. `Text`, or with an
+                invalid attribute name
+        """
+    @property
+    def parallel_parse(self) -> bool:
+        """Parse large markdown inputs in parallel chunks split at top-level block boundaries."""
+    @property
+    def record_provenance(self) -> bool:
+        """Record on each value which stage of the query pipeline produced it, see `MQValue.stage`."""
+    @property
+    def collect_timings(self) -> bool:
+        """Measure how long each phase of the query takes, see `MQResult.timings`."""
+    @property
+    def debug(self) -> bool:
+        """Trace how many values each stage of the query pipeline leaves, see `MQResult.trace`."""
+    @property
+    def html_selector(self) -> Optional[str]:
+        """For HTML input, a CSS selector; only the matching elements are converted to markdown."""
+    @property
+    def html_policy(self) -> HtmlPolicy:
+        """How raw HTML inside markdown and MDX input is handled."""
+    @property
+    def max_html_depth(self) -> Optional[int]:
+        """
+        For HTML input, the deepest element nesting accepted, 512 if None. Deeper input raises
+        `MQParseInputError` instead of exhausting the stack.
+        """
+    @property
+    def unicode_normalization(self) -> Optional[UnicodeNormalization]:
+        """Normalize the input to NFC or NFKC before parsing, e.g. for NFD text saved by macOS tools."""
+    @property
+    def encoding(self) -> Optional[Encoding]:
+        """
+        Decode bytes content with this encoding, UTF-8 when None. Files read by `run_file` and
+        `run_glob` are decoded by their byte order mark when None.
+        """
+    @property
+    def max_results(self) -> Optional[int]:
+        """Keep at most this many non-empty values; later ones are dropped with a warning."""
+    @property
+    def max_output_bytes(self) -> Optional[int]:
+        """Stop adding values once their combined text would exceed this many bytes, with a warning."""
+    @property
+    def display_limit(self) -> Optional[int]:
+        """Show at most this many values when a result is displayed in Jupyter, see `MQResult.display_limit`."""
+    @property
+    def module_paths(self) -> Optional[List[os.PathLike]]:
+        """The directories `import` and `include` search for `.mq` modules, instead of the defaults."""
+    @property
+    def max_recursion_depth(self) -> Optional[int]:
+        """
+        Raise `LimitExceededError` when function calls nest deeper than this.
+
+        Raises:
+            ValueError: If set above mq's own limit of 192 calls, as deeper calls would overflow the stack
+        """
+    @property
+    def max_output_nodes(self) -> Optional[int]:
+        """Raise `LimitExceededError` when the query returns more values than this, counting array items."""
+    @property
+    def security(self) -> Optional[SecurityPolicy]:
+        """Raise `SecurityError` for queries the policy does not allow, before they run."""
+
+class MQParseInputError(RuntimeError):
+    """Raised when an input document cannot be parsed."""
+
+InputError = MQParseInputError
+
+class ParseError(RuntimeError):
+    """Raised when a query, or a module it imports, cannot be parsed."""
+
+    line: Optional[int]
+    """The 1-based line of the error in the query, None if unknown. Errors at the end of the query point past it."""
+    column: Optional[int]
+    """The 1-based column of the error in the query, None if unknown."""
+    token: Optional[str]
+    """The token the error was found at, None if unknown or at the end of the query."""
+    snippet: Optional[str]
+    """The line of the query the error is on, None if unknown."""
+
+class EvalError(RuntimeError):
+    """Raised when a query fails while it runs."""
+
+    line: Optional[int]
+    """The 1-based line of the error in the query, None if unknown."""
+    column: Optional[int]
+    """The 1-based column of the error in the query, None if unknown."""
+    token: Optional[str]
+    """The token the error was found at, None if unknown."""
+    snippet: Optional[str]
+    """The line of the query the error is on, None if unknown."""
+
+class LimitExceededError(EvalError):
+    """Raised when a query exceeds `max_recursion_depth` or `max_output_nodes`."""
+
+class SecurityError(RuntimeError):
+    """Raised when a query uses something the `SecurityPolicy` of its options does not allow."""
 
 class MarkdownType(Enum):
     """Types of Markdown elements."""
@@ -79,12 +446,40 @@ class MarkdownType(Enum):
     MdxJsEsm: int = 30
     Text: int = 31
     Empty: int = 32
+    OrderedList: int = 33
+
+class Position:
+    """
+    Where a node is in the input document. Lines and columns start at 1, columns count bytes and
+    the end is exclusive.
+    """
+
+    start_line: int
+    start_column: int
+    end_line: int
+    end_column: int
+    start_offset: Optional[int]
+    """The byte offset of the start in the document, or None when unknown."""
+    end_offset: Optional[int]
+    """The byte offset of the end in the document, or None when unknown."""
 
 class MQValue:
     """
     Represents a value in the mq query result.
+
+    Markdown values keep the node the engine produced alongside their rendered text, so
+    `node`, `to_markdown()` and the `with_*` updaters never re-parse `text`.
+
+    Strings, numbers, booleans and `None` from the query are the `MQValue.String`, `MQValue.Number`,
+    `MQValue.Bool` and `MQValue.Null` variants, whose `value` is the `str`, `int` or `float`, or `bool`.
+
+    Dict keys from a query are sorted by name. mq keeps no insertion order, so the order the query
+    built the dict in is lost, but sorting makes it the same on every run.
     """
 
+    NONE: ClassVar["MQValue"]
+    """The engine's `None`, e.g. what a selector returns for a node it does not match."""
+
     @property
     def text(self) -> str:
         """
@@ -94,6 +489,16 @@ class MQValue:
             str: The text representation of the value
         """
 
+    @property
+    def values(self) -> List["MQValue"]:
+        """The elements of an array, the values of a dict, or a list holding just this value for other values."""
+
+    def keys(self) -> List[str]:
+        """The keys of a dict, sorted for dicts a query built. Raises TypeError for other values."""
+
+    def items(self) -> List[Tuple[str, "MQValue"]]:
+        """The `(key, value)` pairs of a dict, in `keys()` order. Raises TypeError for other values."""
+
     @property
     def array(self) -> List["MQValue"]:
         """
@@ -112,6 +517,236 @@ class MQValue:
             Optional[MarkdownType]: The markdown type of the document, or None if not applicable.
         """
 
+    @property
+    def node(self) -> Optional[Node]:
+        """
+        Get the markdown node this value was produced from.
+
+        Returns:
+            Optional[Node]: The node, or None for arrays, dicts and plain text values.
+        """
+
+    @property
+    def annotations(self) -> Optional[Dict[str, Any]]:
+        """
+        Get everything recorded about a markdown value beyond its node, keyed like the properties
+        that read it: `section_path`, `source`, `start`, `table_index`, `column_alignment`, `stage`,
+        `stage_name`, `offsets` and `anchor`.
+
+        Only markdown values have this attribute. It is None when nothing was recorded.
+        """
+
+    @property
+    def section_path(self) -> List[str]:
+        """
+        Get the texts of the headings enclosing this value in the input document, outermost first.
+
+        For a heading, only the headings above its level are included. Values that do not come
+        from a position in the input document have an empty path.
+        """
+
+    @property
+    def anchor(self) -> Optional[str]:
+        """Get the anchor slug of a heading from the input document, in `Options.slug_style`."""
+
+    @property
+    def source(self) -> Optional[str]:
+        """Get the name of the document this value came from, as passed to `run(source=...)`."""
+
+    @property
+    def stage(self) -> Optional[int]:
+        """
+        Get the zero-based index of the pipeline stage that produced the value, when
+        `Options.record_provenance` is set.
+
+        This is the last stage that changed the value, or the last stage when none did. An empty value
+        records the stage that dropped it.
+        """
+
+    @property
+    def stage_name(self) -> Optional[str]:
+        """Get the name of the stage that produced the value, e.g. `"select"` or `".h"`."""
+
+    @property
+    def position(self) -> Optional[Position]:
+        """
+        Get where the value's node is in the input document, or None for arrays and values built
+        by the query. Byte offsets are set for values from markdown and MDX input.
+        """
+
+    @property
+    def level(self) -> Optional[int]:
+        """Get the depth of this heading or the nesting level of this list item, or None."""
+
+    @property
+    def checked(self) -> Optional[bool]:
+        """Get whether this task list item is checked, or None."""
+
+    @property
+    def url(self) -> Optional[str]:
+        """Get the URL of this link, image or link definition, or None."""
+
+    @property
+    def title(self) -> Optional[str]:
+        """Get the title of this link, image or link definition, or None."""
+
+    @property
+    def lang(self) -> Optional[str]:
+        """Get the language of this code block, or None."""
+
+    @property
+    def meta(self) -> Optional[str]:
+        """Get the info string of this code block after the language, or None."""
+
+    @property
+    def meta_dict(self) -> Optional[Dict[str, Union[str, bool]]]:
+        """
+        Get the attributes of this code block's meta string, or None for other values.
+
+        `title="x" linenums {1,3}` gives `{"title": "x", "linenums": True, "{1,3}": True}`: quoted
+        and bare `key=value` pairs map to strings, other words to True.
+        """
+
+    @property
+    def math(self) -> Optional[str]:
+        """Get the content of a math value (or fenced `math` code block) without its delimiters, or None."""
+
+    @property
+    def component(self) -> Optional[str]:
+        """Get the component name of an MDX JSX element, or None for other values and fragments."""
+
+    @property
+    def attributes(self) -> Optional[Dict[str, Union[str, bool]]]:
+        """
+        Get the attributes of an MDX JSX element, or None for other values.
+
+        Literal values are strings, expression values their source in braces, e.g. `"{1 + 1}"`, and
+        attributes without a value are True. Spread attributes such as `{...props}` are left out.
+        """
+
+    @property
+    def expression(self) -> Optional[str]:
+        """Get the source of an MDX expression or ESM block without the braces, or None."""
+
+    @property
+    def row_index(self) -> Optional[int]:
+        """Get the row of this table cell or row, where row 0 is the header, or None for other values."""
+
+    @property
+    def column_index(self) -> Optional[int]:
+        """Get the zero-based column of this table cell, or None for other values."""
+
+    @property
+    def column_alignment(self) -> Optional[Literal["left", "right", "center"]]:
+        """Get the alignment of this table cell's column, or None when the column is not aligned."""
+
+    @property
+    def table_index(self) -> Optional[int]:
+        """Get the zero-based index of the table this cell or row belongs to in the input document."""
+
+    @property
+    def ordered(self) -> Optional[bool]:
+        """Get whether this list item belongs to an ordered list, or None for other values."""
+
+    @property
+    def index(self) -> Optional[int]:
+        """Get the zero-based index of this list item within its list, or None for other values."""
+
+    @property
+    def start(self) -> Optional[int]:
+        """Get the number the ordered list of this item starts at in the input document, or None."""
+
+    def with_text(self, text: str) -> "MQValue":
+        """
+        Return a copy of this value with its text content replaced.
+
+        Raises:
+            TypeError: If the value has no text content (e.g. arrays or horizontal rules)
+        """
+
+    def with_level(self, level: int) -> "MQValue":
+        """
+        Return a copy of this heading with its depth replaced, or of this list item with its nesting level replaced.
+
+        Raises:
+            TypeError: If the value is not a heading or list item
+            ValueError: If the heading depth is not between 1 and 6
+        """
+
+    def with_url(self, url: str) -> "MQValue":
+        """
+        Return a copy of this link, image or definition with its URL replaced.
+
+        Raises:
+            TypeError: If the value is not a link, image or definition
+        """
+
+    def equals_normalized(self, other: Union["MQValue", str]) -> bool:
+        """
+        Compare the markdown of two values ignoring whitespace and marker style.
+
+        Both sides are NFC-normalized and re-rendered before comparing, so `* item` equals `- item`,
+        `_a_` equals `*a*` and setext headings equal ATX headings. Use this in tests instead of exact
+        string equality.
+        """
+
+    def to_markdown(self, options: Optional[Options] = None) -> str:
+        """
+        Render the value as a markdown document.
+
+        Arrays render each element in turn; table cells and table values render as tables.
+
+        Args:
+            options: Style options for list markers and links, overriding those used by `run`
+
+        Raises:
+            TypeError: If the value is a dict that is not a table
+        """
+
+    def is_table(self) -> bool:
+        """
+        Check if this value is a table dict, with `type`, `align`, `header` and `rows` keys, as taken by
+        `add_row` and the other table methods. To read tables as rows, use `MQResult.to_table` or
+        `extract_tables`.
+
+        Returns:
+            True if this value is a table, False otherwise
+        """
+
+    def add_row(self, cells: Sequence[Any]) -> "MQValue":
+        """
+        Return a copy of this table with a row appended.
+
+        Raises:
+            TypeError: If the value is not a table
+            ValueError: If the number of cells does not match the number of columns
+        """
+
+    def set_cell(self, row: int, column: int, text: Children) -> "MQValue":
+        """
+        Return a copy of this table with a cell replaced. Row 0 is the header row.
+
+        Raises:
+            TypeError: If the value is not a table
+            IndexError: If the row or column is out of range
+        """
+
+    def sort_by(self, column: Union[int, str], reverse: bool = False) -> "MQValue":
+        """
+        Return a copy of this table with its rows sorted by a column.
+
+        Args:
+            column: The column index or header text
+            reverse: Sort in descending order
+
+        Cells are compared as numbers when both parse as numbers, otherwise as text.
+
+        Raises:
+            TypeError: If the value is not a table
+            IndexError: If the column index is out of range
+            KeyError: If no header cell matches the column name
+        """
+
     def is_array(self) -> bool:
         """
         Check if this value is an array.
@@ -128,8 +763,58 @@ class MQValue:
             True if this value is a markdown node, False otherwise
         """
 
+    def is_string(self) -> bool:
+        """Check if this value is a string built by the query, e.g. by `to_text()`, rather than a text node."""
+
+    def is_number(self) -> bool:
+        """Check if this value is a number, e.g. from `len()`. Its `value` is an `int` or a `float`."""
+
+    def is_bool(self) -> bool:
+        """Check if this value is a boolean. Its `value` is a `bool`."""
+
+    def is_none(self) -> bool:
+        """
+        Check if this value is the engine's `None`, as opposed to an empty string.
+
+        `value == MQValue.NONE` checks the same. Functions and modules, which have no Python
+        equivalent, convert to `NONE` too.
+        """
+
+    def to_json(self) -> str:
+        """
+        Serialize the value to JSON, as one of the values written by `MQResult.dumps`: its `type`, its
+        `markdown` text, its `node` with attributes and positions, and annotations such as `sectionPath`.
+        Read it back with `mq.from_json`.
+        """
+
+    def to_python(self) -> Any:
+        """
+        Convert the value into plain Python objects: arrays into lists, dicts into dicts, numbers and
+        booleans into int, float and bool, `NONE` into None and anything else into its text.
+
+        For markdown input, results that are not nodes are written into the input node as text, so
+        numbers and booleans are only kept by queries after `nodes` or on `InputFormat.NULL` input.
+        """
+
+    @overload
+    def __getitem__(self, idx: str) -> MQValue:
+        """Get the value of a dict at a key, raising KeyError when it is missing."""
+    @overload
+    def __getitem__(self, idx: int) -> MQValue:
+        """Get an element of an array, counting from the end when negative; other values act as a one-element array."""
+    @overload
+    def __getitem__(self, idx: slice) -> MQValue:
+        """Get an array of the elements of a slice."""
+    def __iter__(self) -> Iterator[Union[MQValue, str]]:
+        """Iterate over the keys of a dict, the elements of an array, or over just this value for other values."""
+    def __contains__(self, item: Union[str, "MQValue"]) -> bool:
+        """Whether a dict has the key, or an array holds the value or a value with that text."""
     def __str__(self) -> str: ...
     def __repr__(self) -> str: ...
+    def _repr_markdown_(self) -> str:
+        """Render the value as markdown for Jupyter, or its text when it is not markdown."""
+    def _repr_html_(self) -> str:
+        """Render the value as HTML for Jupyter, showing raw HTML in it as text."""
     def __bool__(self) -> bool: ...
     def __len__(self) -> int: ...
     def __eq__(self, other: "MQValue") -> bool: ...
@@ -137,14 +822,55 @@ class MQValue:
     def __lt__(self, other: "MQValue") -> bool: ...
     def __gt__(self, other: "MQValue") -> bool: ...
 
+class TraceStep:
+    """
+    One stage of a query pipeline, as traced with `Options.debug`.
+
+    Attributes:
+        index: The position of the stage in the pipeline, from 0
+        name: The function or keyword of the stage, or the stage itself for selectors
+        code: The code of the stage
+        input_count: The number of non-empty values the stage received
+        output_count: The number of non-empty values the stage left, None when the pipeline up to
+            the stage does not run on its own, e.g. a lone `let`
+        duration: The seconds the stage added to the pipeline before it, approximately
+        error: Why `output_count` is None: the error the pipeline up to the stage raised, or that
+            it was not run again because the query calls a builtin with side effects
+
+    The pipeline up to each stage is run again, with the same arguments and definitions as the
+    query, so tracing a query of n stages runs it n times.
+    """
+
+    index: int
+    name: str
+    code: str
+    input_count: Optional[int]
+    output_count: Optional[int]
+    duration: float
+    error: Optional[str]
+
+ResultKey = Literal["markdown", "text", "url", "domain", "type", "level", "position"]
+"""A built-in key for comparing values in `MQResult.unique` and `MQResult.sorted`."""
+
 class MQResult:
     """
     Result of a query execution.
     Attributes:
         values: A list of MQValue objects returned by the query
+        warnings: Non-fatal issues found while parsing the input and converting the results
+        source: The document name passed to `run`, if any
+        timings: The seconds spent parsing the input, compiling and evaluating the query and converting the
+            results, keyed by "parse", "compile", "eval" and "convert", when `Options.collect_timings` is set
+        trace: The stages of the query pipeline with the values each left, when `Options.debug` is set
+        display_limit: The most values shown in notebooks, from `Options.display_limit`; None shows them all
     """
 
     values: List[MQValue]
+    warnings: List[str]
+    source: Optional[str]
+    timings: Optional[Dict[str, float]]
+    trace: Optional[List[TraceStep]]
+    display_limit: Optional[int]
 
     @property
     def text(self) -> str:
@@ -167,18 +893,219 @@ class MQResult:
             List of non-empty text values as strings
         """
 
-    def __contains__(self, item: str) -> bool: ...
-    def __getitem__(self, idx: int) -> MQValue: ...
-    def __len__(self) -> int: ...
-    def __str__(self) -> str: ...
-    def __repr__(self) -> str: ...
-    def __eq__(self, other: "MQResult") -> bool: ...
-    def __ne__(self, other: "MQResult") -> bool: ...
-    def __lt__(self, other: "MQResult") -> bool: ...
-    def __gt__(self, other: "MQResult") -> bool: ...
+    def by_section(self) -> Dict[Tuple[str, ...], List[MQValue]]:
+        """
+        Group the non-empty values by the heading path of the section they appear in.
 
-# Function to run mq queries
-def run(code: str, content: str, options: Optional[Options] = None) -> MQResult:
+        Sections are ordered by first appearance. Headings are grouped under their own section,
+        and values before the first heading under the empty path `()`.
+
+        Example:
+            ```python
+            result = mq.run(".link", content)
+            for path, links in result.by_section().items():
+                print(" > ".join(path), len(links))
+            ```
+        """
+
+    def take(self, n: int) -> MQResult:
+        """Return a result with the first `n` non-empty values."""
+
+    def skip(self, n: int) -> MQResult:
+        """Return a result without the first `n` non-empty values."""
+
+    def window(self, start: int, size: int) -> MQResult:
+        """
+        Return a result with at most `size` non-empty values, starting at the `start`-th one.
+
+        Like `values`, paging skips the empty values that stand in for unmatched nodes.
+
+        Example:
+            ```python
+            links = mq.run(".link", content)
+            page = links.window(page_number * 20, 20)
+            ```
+        """
+
+    def unique(self, key: Union[ResultKey, Callable[[MQValue], Any], None] = None) -> MQResult:
+        """
+        Return a result with the non-empty values, dropping those whose key was already seen.
+
+        Args:
+            key: Compare values by their `"markdown"` (default), plain `"text"`, link/image `"url"` or
+                its `"domain"`, markdown `"type"`, heading `"level"` or `"position"`, or by the
+                hashable result of a callable. Values without the key are kept.
+
+        Example:
+            ```python
+            links = mq.run(".link", content).unique("url")
+            ```
+        """
+
+    def sorted(self, key: Union[ResultKey, Callable[[MQValue], Any]] = "markdown", reverse: bool = False) -> MQResult:
+        """
+        Return a result with the non-empty values sorted by a key.
+
+        Args:
+            key: A key as for `unique`, where `"position"` sorts by document order, or a callable
+                returning a sortable value. Values without the key sort last.
+            reverse: Sort in descending order
+
+        Example:
+            ```python
+            headings = mq.run(".h", content).sorted("text")
+            ```
+        """
+
+    def count_by(self, key: Union[ResultKey, Callable[[MQValue], Any]]) -> Dict[Any, int]:
+        """
+        Count the non-empty values by a key, in order of first appearance.
+
+        Args:
+            key: A key as for `unique`, or a callable returning a hashable value. Values without the
+                key are not counted. Heading levels are counted as integers.
+
+        Example:
+            ```python
+            mq.run(".link", content).count_by("domain")  # {'example.com': 3, 'github.com': 1}
+            mq.run(".h", content).count_by("level")       # {1: 1, 2: 4}
+            ```
+        """
+
+    def to_markdown(self, options: Optional[Options] = None) -> str:
+        """
+        Render the non-empty values as one markdown document.
+
+        The stored nodes are re-rendered, so a different output style does not need another run.
+
+        Example:
+            ```python
+            options = mq.Options()
+            options.list_style = mq.ListStyle.STAR
+            print(mq.run(".list", content).to_markdown(options))
+            ```
+        """
+
+    def to_commonmark_strict(self, options: Optional[Options] = None) -> str:
+        """
+        Render the non-empty values like `to_markdown`, escaped for embedding in another document.
+
+        Markdown syntax in text and pipes in table cells are escaped. Code gets fences and backtick
+        strings longer than any inside it, and indented code is fenced, so the output keeps its
+        meaning inside a blockquote, a list item or a table.
+        """
+
+    def to_test_string(self) -> str:
+        """
+        Return a stable plain-text form of the non-empty values for snapshot tests.
+
+        Each value is written as `[index] Type: text`. Arrays and dicts are expanded one item per
+        line with dict keys sorted, so the output does not change with `__repr__` or key order.
+
+        Example:
+            ```python
+            assert mq.run(".h", "# Title").to_test_string() == "[0] Heading: # Title\n"
+            ```
+        """
+
+    def render(self, options: Optional[Options] = None) -> str:
+        """
+        Render the non-empty values in the `output_format` of `options`, by default the options the query ran with.
+
+        Example:
+            ```python
+            options = mq.Options()
+            options.output_format = mq.OutputFormat.HTML
+            html = mq.run(".h", content).render(options)
+            ```
+        """
+
+    def dumps(self) -> str:
+        """
+        Serialize the result to JSON, e.g. to cache a parsed document between pipeline stages.
+
+        Nodes, annotations such as `section_path`, warnings and the source are kept; timings are not.
+        """
+
+    def to_json(self) -> str:
+        """Serialize the result to JSON, the same as `dumps`. Read it back with `mq.from_json`."""
+
+    @staticmethod
+    def loads(data: Content) -> MQResult:
+        """
+        Read a result written by `dumps`.
+
+        Raises:
+            ValueError: If `data` is not a serialized result.
+        """
+
+    def to_python(self) -> List[Any]:
+        """Convert the non-empty values into plain Python objects, see `MQValue.to_python`."""
+
+    def to_records(self) -> List[Dict[str, Any]]:
+        """
+        Return a flat dict for each non-empty value, e.g. for `pandas.DataFrame(result.to_records())`.
+
+        Every record has the same keys, with None where a key does not apply to the value: `text`, `type`
+        (the `MarkdownType` name, or "Array" or "Dict"), `level`, `url`, `title`, `lang`, `line`, `column`,
+        `end_line`, `end_column`, `section_path` and `source`.
+        """
+
+    def to_table(self) -> List[Dict[str, str]]:
+        """
+        Convert a table into rows keyed by the header cells' text, with cells read as plain text.
+
+        The result must hold the cells of one table, e.g. from `.table`, or a single table value.
+        Rows shorter than the header get empty strings, and repeated headers are numbered as by
+        `extract_tables`, which reads every table of a document. Raises ValueError for other values
+        or cells of more than one table.
+        """
+
+    def compact(self) -> MQResult:
+        """
+        Return a result without empty values, with adjacent text values merged into one.
+
+        Text values are merged when the query built them or when they follow each other without a
+        gap in the document, so separate paragraphs stay apart. Arrays and dicts are compacted
+        too, and dropped when nothing is left in them. Indexing and iterating a result include
+        empty values; `compact` removes them explicitly.
+        """
+
+    def is_empty(self) -> bool:
+        """
+        Return True when every value is empty, e.g. when the query matched nothing.
+
+        `bool(result)` is the inverse, so `if mq.run(query, content):` checks for a match.
+        """
+
+    def __bool__(self) -> bool: ...
+    def __contains__(self, item: str) -> bool: ...
+    @overload
+    def __getitem__(self, idx: int) -> MQValue: ...
+    @overload
+    def __getitem__(self, idx: slice) -> MQResult: ...
+    def __iter__(self) -> Iterator[MQValue]: ...
+    def __len__(self) -> int: ...
+    def __str__(self) -> str: ...
+    def __repr__(self) -> str: ...
+    def _repr_markdown_(self) -> str:
+        """Render the first `display_limit` non-empty values as markdown for Jupyter, noting how many were left out."""
+    def _repr_html_(self) -> str:
+        """Render `_repr_markdown_` as HTML for Jupyter, showing raw HTML in it as text."""
+    def __eq__(self, other: "MQResult") -> bool: ...
+    def __ne__(self, other: "MQResult") -> bool: ...
+    def __lt__(self, other: "MQResult") -> bool: ...
+    def __gt__(self, other: "MQResult") -> bool: ...
+
+# Function to run mq queries
+def run(
+    code: str,
+    content: Content,
+    options: Optional[Options] = None,
+    source: Optional[str] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    args: Optional[Dict[str, Any]] = None,
+) -> MQResult:
     """
     Run an mq query against markdown content with the specified options.
 
@@ -188,14 +1115,24 @@ def run(code: str, content: str, options: Optional[Options] = None) -> MQResult:
 
     Args:
         code: The mq query string to run against the content
-        content: The markdown content to process (or text depending on options)
+        content: The markdown content to process (or text depending on options). Bytes-like
+            objects are decoded as UTF-8 without copying where possible.
         options: Configuration options for processing. If None, default options are used.
+        source: The name of the document, e.g. its file name. It is exposed as `result.source` and
+            `value.source` and prefixed to error messages.
+        errors: How invalid UTF-8 in bytes content is handled: "strict" raises and "replace" replaces
+            invalid sequences with U+FFFD.
+        args: Variables bound before the query runs, by name. Values can be None, booleans, numbers,
+            strings, lists, dicts with string keys and `MQValue`s, which keep their nodes.
 
     Returns:
         MQResult object containing the query results
 
     Raises:
         RuntimeError: If there's an error parsing the markdown or evaluating the query
+        UnicodeDecodeError: If bytes content is not valid UTF-8 and `errors` is "strict"
+        ValueError: If a name in `args` is not an identifier
+        TypeError: If a value in `args` cannot be used in a query
 
     Example:
         ```python
@@ -215,3 +1152,1774 @@ def run(code: str, content: str, options: Optional[Options] = None) -> MQResult:
         # Output: "# Title\n## Subtitle"
         ```
     """
+
+def run_async(
+    code: str,
+    content: Content,
+    options: Optional[Options] = None,
+    source: Optional[str] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    args: Optional[Dict[str, Any]] = None,
+) -> "asyncio.Future[MQResult]":
+    """
+    Run a query like `run`, with the same arguments, in the running event loop's default executor,
+    without blocking the loop.
+
+    Must be called from a coroutine or callback of a running event loop.
+
+    Example:
+        ```python
+        async def handle(upload: bytes) -> list[str]:
+            result = await mq.run_async(".h", upload)
+            return result.values
+        ```
+
+    Raises:
+        RuntimeError: If no event loop is running
+    """
+
+def run_file(
+    code: str,
+    path: Union[str, "os.PathLike[str]"],
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+) -> MQResult:
+    """
+    Read a file and run an mq query against it, with the path as `result.source` and `value.source`.
+
+    A UTF-8 or UTF-16 byte order mark selects the encoding and is dropped; files without one are
+    read as UTF-8. Error messages other than decoding errors are prefixed with the path.
+
+    Raises:
+        OSError: If the file cannot be read
+        RuntimeError: If there's an error parsing the markdown or evaluating the query
+        UnicodeDecodeError: If the file cannot be decoded and `errors` is "strict"
+    """
+
+class GlobResults(Iterator[Tuple[str, MQResult]]):
+    """
+    An iterator over the path and result of each file matching a glob, from `run_glob` with `lazy=True`.
+
+    Files are found and queried as they are reached, in the order their directories list them, so only
+    one document is held in memory at a time.
+    """
+
+    def __iter__(self) -> GlobResults: ...
+    def __next__(self) -> Tuple[str, MQResult]: ...
+
+@overload
+def run_glob(
+    code: str,
+    pattern: str,
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    front_matter: Optional[FrontMatterFilter] = None,
+    lazy: Literal[False] = False,
+) -> Dict[str, MQResult]: ...
+@overload
+def run_glob(
+    code: str,
+    pattern: str,
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    front_matter: Optional[FrontMatterFilter] = None,
+    *,
+    lazy: Literal[True],
+) -> GlobResults: ...
+def run_glob(
+    code: str,
+    pattern: str,
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    front_matter: Optional[FrontMatterFilter] = None,
+    lazy: bool = False,
+) -> Union[Dict[str, MQResult], GlobResults]:
+    """
+    Run an mq query against every file matching a glob pattern, where `**` matches any number of
+    directories, as `run_file` does.
+
+    Args:
+        front_matter: Only query the files whose front matter has all the values of this dict, or for
+            which this callable returns true when given the front matter dict. The others are left
+            out of the results.
+        lazy: Return a `GlobResults` iterator that finds and queries each file as it is reached, for
+            trees too large to hold every result at once. Errors are raised by the iterator.
+
+    Returns:
+        The result of each file in sorted order, keyed by path, or the iterator with `lazy=True`
+    """
+
+def run_multi(
+    code: str,
+    documents: Dict[str, Content],
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    name_var: Optional[str] = None,
+    args: Optional[Dict[str, Any]] = None,
+) -> Dict[str, MQResult]:
+    """
+    Run an mq query against each of the named documents, with the names as the results' sources.
+
+    Args:
+        code: The mq query to run
+        documents: The documents to run the query against by name, e.g. file name
+        options: Options applied to every document
+        errors: How to handle invalid bytes, as in `run`
+        name_var: Bind the name of each document to a variable of this name
+        args: Values bound as variables for every document, as in `run`
+
+    Returns:
+        The result of each document, keyed by name in the order of `documents`
+    """
+
+class Engine:
+    """
+    A query compiled once, to be run against many documents.
+
+    `mq.run` parses the query on every call; an engine parses it once, which is faster when the same
+    query runs over many files. An engine can be shared between threads: each thread evaluates on an
+    environment of its own, with the definitions made by `eval` and `load_module` on any thread.
+
+    Example:
+        ```python
+        engine = mq.Engine(".h | to_text()")
+        for path in paths:
+            print(engine.eval(open(path).read(), source=path).values)
+        ```
+    """
+
+    code: str
+    """The query the engine runs."""
+
+    def __init__(self, code: str = "self", options: Optional[Options] = None, cache_size: Optional[int] = None) -> None:
+        """
+        Compile `code`, to be run with `options` by `eval`. Without a query, the engine returns its input.
+
+        With a `cache_size`, the results of the compiled query for up to that many documents are kept,
+        least recently used first, and returned again for the same content. They are discarded when
+        `eval` or `load_module` defines names.
+
+        Raises:
+            ParseError: If the query cannot be parsed
+        """
+
+    def load_module(self, module: Union[str, os.PathLike]) -> None:
+        """
+        Load the functions and variables defined by an mq module, so the query can call them.
+
+        Args:
+            module: The path of a `.mq` file, or the source of a module if it names no file
+
+        Raises:
+            OSError: If the file cannot be read
+            RuntimeError: If the module cannot be parsed or fails while loading. For files, the
+                message is prefixed with the path.
+        """
+
+    @overload
+    def eval(
+        self, content: Content, *, source: Optional[str] = None, errors: Literal["strict", "replace"] = "strict"
+    ) -> MQResult:
+        """Run the compiled query against `content`, with the same arguments and result as `run`."""
+    @overload
+    def eval(
+        self,
+        code: str,
+        content: Optional[Content],
+        *,
+        source: Optional[str] = None,
+        errors: Literal["strict", "replace"] = "strict",
+    ) -> MQResult:
+        """
+        Run `code` against `content`, or against no input if `content` is None.
+
+        The functions and variables `code` defines stay available to later calls, e.g. in a REPL:
+
+        ```python
+        engine = mq.Engine()
+        engine.eval("def shout(x): upcase(x);", None)
+        engine.eval(".h | shout()", "# hi").values  # ['# HI']
+        ```
+        """
+
+    def reset(self) -> None:
+        """Discard the names defined by `eval` and `load_module`, starting over with a fresh engine."""
+
+    def defined_names(self) -> List[str]:
+        """Return the functions, macros, variables and modules defined by `eval` and `load_module`, in order."""
+
+    def cache_info(self) -> CacheInfo:
+        """Return the hits and misses of the result cache, like `functools.lru_cache`."""
+
+    def cache_clear(self) -> None:
+        """Discard the cached results and reset the hits and misses."""
+
+class CacheInfo:
+    """The hits and misses of an engine's result cache, as returned by `Engine.cache_info`."""
+
+    hits: int
+    misses: int
+    maxsize: int
+    """The `cache_size` of the engine, 0 if it has no cache."""
+    currsize: int
+
+@overload
+def run_many(
+    code: str,
+    contents: Sequence[Content],
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    front_matter: None = None,
+) -> List[MQResult]: ...
+@overload
+def run_many(
+    code: str,
+    contents: Sequence[Content],
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    *,
+    front_matter: FrontMatterFilter,
+) -> List[Optional[MQResult]]: ...
+def run_many(
+    code: str,
+    contents: Sequence[Content],
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    front_matter: Optional[FrontMatterFilter] = None,
+) -> List[Optional[MQResult]]:
+    """
+    Run an mq query against each of `contents` in parallel, returning the results in order.
+
+    The documents are spread over one thread per CPU, each compiling the query once, and the GIL is
+    released while they run.
+
+    Args:
+        front_matter: Only query the documents whose front matter has all the values of this dict, or
+            for which this callable returns true when given the front matter dict. The others get
+            `None` in place of a result.
+
+    Raises:
+        RuntimeError: If the query cannot be parsed, or fails on a document. The message is prefixed
+            with the document's index, e.g. "contents[3]: ...".
+        MQParseInputError: If `front_matter` is given and a document's front matter is invalid
+        UnicodeDecodeError: If bytes content is not valid UTF-8 and `errors` is "strict"
+    """
+
+@overload
+def run_text(
+    code: str,
+    content: Content,
+    options: Optional[Options] = None,
+    join: Literal[True] = True,
+    source: Optional[str] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    args: Optional[Dict[str, Any]] = None,
+) -> str: ...
+@overload
+def run_text(
+    code: str,
+    content: Content,
+    options: Optional[Options] = None,
+    *,
+    join: Literal[False],
+    source: Optional[str] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    args: Optional[Dict[str, Any]] = None,
+) -> List[str]: ...
+def run_text(
+    code: str,
+    content: Content,
+    options: Optional[Options] = None,
+    join: bool = True,
+    source: Optional[str] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    args: Optional[Dict[str, Any]] = None,
+) -> Union[str, List[str]]:
+    """
+    Run an mq query and return the text of its non-empty values, without creating `MQValue` objects.
+
+    This is a faster alternative to `run(...).text` and `run(...).values` for simple extraction.
+    `max_results` and `max_output_bytes` truncate the output silently.
+
+    Args:
+        code: The mq query string to run against the content
+        content: The markdown content to process
+        options: Configuration options for processing. If None, default options are used.
+        join: Return the values joined by newlines like `MQResult.text`, or as a list of strings like
+            `MQResult.values` when False
+        source: The name of the document, prefixed to error messages as in `run`.
+        errors: How invalid UTF-8 in bytes content is handled, as in `run`.
+        args: Variables bound before the query runs, by name, as in `run`.
+
+    Raises:
+        RuntimeError: If there's an error parsing the markdown or evaluating the query
+        UnicodeDecodeError: If bytes content is not valid UTF-8 and `errors` is "strict"
+        ValueError: If a name in `args` is not an identifier
+        TypeError: If a value in `args` cannot be used in a query
+    """
+
+class ValueIterator(Iterator[MQValue]):
+    """An iterator over the non-empty values of a query, from `run_iter`. Bound to the creating thread."""
+
+    def __iter__(self) -> ValueIterator: ...
+    def __next__(self) -> MQValue: ...
+
+def run_iter(
+    code: str,
+    content: Content,
+    options: Optional[Options] = None,
+    errors: Literal["strict", "replace"] = "strict",
+) -> ValueIterator:
+    """
+    Run an mq query and return an iterator that creates each non-empty `MQValue` as it is reached.
+
+    The query runs to completion first; only converting the values is deferred. Values are annotated
+    like those of `run`, but conversion warnings are not reported.
+
+    Raises:
+        RuntimeError: If there's an error parsing the markdown or evaluating the query
+    """
+
+class Document:
+    """
+    A document parsed once, to be queried repeatedly and edited in place, from `load`.
+
+    Example:
+        ```python
+        doc = mq.load("# Title\n\nSome text.\n")
+        doc.apply_edit((9, 13), "More")
+        doc.run(".h").values  # ['# Title']
+        ```
+    """
+
+    content: str
+    """The text of the document, with every edit applied."""
+
+    def apply_edit(self, range: Tuple[int, int], new_text: str) -> Tuple[int, int]:
+        """
+        Replace the bytes from `range[0]` up to `range[1]` with `new_text`, as in `Position.start_offset`,
+        and re-parse the top-level blocks the edit touched.
+
+        Returns:
+            The byte range of the new content that was re-parsed
+
+        Raises:
+            ValueError: If the range is outside the document or not on character boundaries
+            MQParseInputError: If the new content cannot be parsed
+        """
+
+    def run(self, code: str, args: Optional[Dict[str, Any]] = None) -> MQResult:
+        """Run an mq query against the document without parsing it again, as `run` would."""
+
+def load(
+    content: Content, options: Optional[Options] = None, errors: Literal["strict", "replace"] = "strict"
+) -> Document:
+    """
+    Parse a markdown or MDX document into a `Document`, for editors that query a document after each change.
+
+    Markdown documents are split into top-level blocks, so `Document.apply_edit` re-parses only the blocks
+    an edit touches. Documents with reference definitions or footnotes, MDX documents and normalized input
+    are parsed whole after every edit.
+
+    Raises:
+        ValueError: If `options` sets an input format other than markdown or MDX
+        MQParseInputError: If the content cannot be parsed
+    """
+
+def parse_input(content: Content, options: Optional[Options] = None) -> MQResult:
+    """
+    Parse a document into the values a query would run on, without running a query.
+
+    The values carry the same details as query results, e.g. `section_path` and `node`.
+
+    Args:
+        content: The document to parse
+        options: Options controlling how the content is parsed
+
+    Raises:
+        MQParseInputError: If the content cannot be parsed
+    """
+
+def from_json(data: Content) -> Union[MQResult, MQValue]:
+    """
+    Read a result written by `MQResult.to_json` or `dumps`, or a value written by `MQValue.to_json`.
+
+    Raises:
+        ValueError: If `data` is not a serialized result or value.
+    """
+
+def parse(content: Content, input_format: Optional[InputFormat] = None) -> List[Node]:
+    """
+    Parse a document into a tree of `mq.nodes` objects, one per top-level node, without running a query.
+
+    Each node has its `markdown_type`, `position` in the document and `children`. Values other than
+    nodes, e.g. the content of `InputFormat.RAW`, become `Text` nodes.
+
+    Raises:
+        MQParseInputError: If the content cannot be parsed
+    """
+
+def render(tree: Children, options: Optional[Options] = None) -> str:
+    """
+    Render a tree of nodes, e.g. from `parse`, in the output format of `options`, markdown by default.
+    """
+
+class QueryAnalysis:
+    """
+    What a query does, as found by `analyze` without running it.
+
+    Attributes:
+        read_only: Whether the query only selects, without building or changing nodes
+        selectors: The selectors the query uses, e.g. `.h2`, in order of first use
+        functions: The builtin functions the query calls or passes by name, in order of first use
+        mutations: The functions and constructs that make the query not read-only
+    """
+
+    read_only: bool
+    selectors: List[str]
+    functions: List[str]
+    mutations: List[str]
+
+def analyze(code: str) -> QueryAnalysis:
+    """
+    Parse a query and report the selectors and functions it uses, without running it.
+
+    A query is read-only when it calls none of the builtins that build or change nodes, such as
+    `to_strong` or `update`. Imports, includes, module functions, string interpolation and calls
+    through values cannot be checked, so queries using them are never read-only.
+
+    Example:
+        ```python
+        analysis = mq.analyze('.h | select(contains("API"))')
+        analysis.read_only  # True
+        analysis.selectors  # ['.h']
+        ```
+
+    Raises:
+        ParseError: If the query cannot be parsed
+    """
+
+class Diagnostic:
+    """
+    A problem found in a query by `check_syntax`.
+
+    Attributes:
+        message: What is wrong with the query
+        span: Where the problem is in the query, None if unknown. Offsets count bytes of the query
+        severity: How serious the problem is, always `"error"` for now
+    """
+
+    message: str
+    span: Optional[Position]
+    severity: str
+
+def check_syntax(code: str) -> List[Diagnostic]:
+    """
+    Compile a query without running it and return the problems found, which is empty if it is valid.
+
+    Parsing stops at the first error, so at most one diagnostic is returned for a query that does not
+    compile. One that does gets a diagnostic for each function it calls that is neither a builtin nor
+    defined by the query, unless it includes or imports modules, which may define them.
+
+    Example:
+        ```python
+        mq.check_syntax("let x = ;")  # [Diagnostic("error", "Unexpected token `;`", Position(1:9-1:10, offsets=8..9))]
+        ```
+    """
+
+class Builtin:
+    """
+    A builtin function, macro or selector, as listed by `builtins`.
+
+    Attributes:
+        name: The name, e.g. `contains` or `.h`
+        signature: The name with its parameters, e.g. `contains(haystack, needle)`
+        doc: A description from mq-lang's documentation
+        category: "function" for functions implemented by the engine, "module" for functions and
+            macros defined by the builtin module and "selector" for selectors
+    """
+
+    name: str
+    signature: str
+    doc: str
+    category: Literal["function", "module", "selector"]
+
+def builtins() -> List[Builtin]:
+    """
+    Return every builtin function, macro and selector queries can use, e.g. for completion in an editor.
+
+    The engine's functions come first, then those of the builtin module and then the selectors,
+    each sorted by name. Internal functions are left out.
+    """
+
+def compile(code: str, options: Optional[Options] = None) -> Engine:
+    """
+    Compile a query without running it, like `Engine(code, options)`, checking that the functions it
+    calls are builtins or defined by the query, as `check_syntax` does.
+
+    Raises:
+        ParseError: If the query cannot be parsed or calls an unknown function
+    """
+
+class UpdateResult:
+    """
+    The outcome of `update`, `update_file` or `update_glob`.
+
+    Attributes:
+        content: The updated document, or the original content unchanged if nothing changed
+        changed: Whether the query changed any node
+        changed_positions: The `(line, column)` of every node the query changed
+        path: The updated file, or None for `update`
+    """
+
+    content: str
+    changed: bool
+    changed_positions: List[Tuple[int, int]]
+    path: Optional[str]
+
+    def diff(self, context: int = 3) -> str:
+        """Return a unified diff from the original content to the updated one, empty if nothing changed."""
+
+    def __bool__(self) -> bool: ...
+    def __str__(self) -> str: ...
+
+def update(code: str, content: Content, options: Optional[Options] = None) -> UpdateResult:
+    """
+    Apply a query to every node of a document and replace each node with its result.
+
+    Nodes the query yields nothing for are kept unchanged. When no node changes, the original
+    content is returned as is instead of being re-rendered.
+
+    Example:
+        ```python
+        result = mq.update(".h2 | upcase()", content)
+        if result.changed:
+            print(result.content)
+        ```
+    """
+
+def replace(
+    code: str,
+    content: Content,
+    replacer: Callable[[MQValue], Union[str, Node, MQValue, None]],
+    options: Optional[Options] = None,
+) -> UpdateResult:
+    """
+    Replace every node a query matches with what `replacer` returns for it, keeping the rest of the
+    document unchanged.
+
+    Args:
+        code: The query selecting the nodes to replace
+        content: The document to rewrite
+        replacer: Called with each match. A string replaces the node's text, a `Node` or `MQValue`
+            replaces the node, and None keeps it.
+        options: Options controlling how the content is parsed and rendered
+
+    Example:
+        ```python
+        result = mq.replace(".link", content, lambda v: mq.nodes.Link(v.url.replace("http:", "https:"), "docs"))
+        ```
+    """
+
+def update_file(code: str, path: Union[str, "os.PathLike[str]"], options: Optional[Options] = None) -> UpdateResult:
+    """
+    Update a markdown file in place, writing it atomically and only when the query changed a node.
+
+    Example:
+        ```python
+        import sys
+
+        changed = [path for path in paths if mq.update_file(".h2 | upcase()", path)]
+        sys.exit(1 if changed else 0)
+        ```
+    """
+
+def update_glob(
+    code: str,
+    pattern: str,
+    options: Optional[Options] = None,
+    dry_run: bool = False,
+    all_or_nothing: bool = False,
+) -> Dict[str, UpdateResult]:
+    """
+    Update every file matching a glob pattern, where `**` matches any number of directories.
+
+    Files are processed in sorted order and only written when the query changed a node. Each file
+    is replaced atomically through a temporary file. With `dry_run=True` nothing is written, so the
+    changes can be reviewed with `UpdateResult.diff()`.
+
+    With `all_or_nothing=True` every file is updated before any is written, and the files already
+    written are restored if a later write fails, so a failed batch leaves the tree unchanged.
+
+    Returns:
+        The result of each file, keyed by path
+
+    Example:
+        ```python
+        for path, result in mq.update_glob(".h2 | upcase()", "docs/**/*.md", dry_run=True).items():
+            print(result.diff())
+        ```
+    """
+
+ChangeKind = Literal["added", "removed", "modified"]
+
+class Change:
+    """
+    A block added, removed or modified between two documents, from `diff`.
+
+    Attributes:
+        kind: Whether the block was added, removed or modified
+        type: The kind of block, e.g. "heading", "paragraph", "list_item", "table" or "code"
+        text: The rendered block in the new document, or in the old one if it was removed
+        position: Where `text` is, or None if the input has no positions
+        old_text: The rendered block in the old document, for modified blocks
+        old_position: Where `old_text` is, for modified blocks
+    """
+
+    kind: ChangeKind
+    type: str
+    text: str
+    position: Optional[Position]
+    old_text: Optional[str]
+    old_position: Optional[Position]
+
+    def __str__(self) -> str:
+        """A one-line description, e.g. `heading modified: "# Setup" -> "# Installation"`."""
+
+def diff(old_content: Content, new_content: Content, options: Optional[Options] = None) -> List[Change]:
+    """
+    Compare two documents block by block and return the blocks added, removed or modified, in document order.
+
+    Blocks are compared by their rendered markdown, so changes in formatting alone, such as `*` for `_`,
+    are not reported. Changed blocks of the same type are paired as modified.
+
+    Example:
+        ```python
+        for change in mq.diff(old, new):
+            print(change)  # heading modified: "# Setup" -> "# Installation"
+        ```
+
+    Raises:
+        MQParseInputError: If either document cannot be parsed
+    """
+
+class ConversionOptions:
+    """Options for converting HTML to Markdown."""
+
+    def __init__(
+        self,
+        *,
+        extract_scripts_as_code_blocks: bool = False,
+        generate_front_matter: bool = False,
+        use_title_as_h1: bool = False,
+        max_depth: Optional[int] = None,
+        root_selector: Optional[str] = None,
+        exclude_selectors: List[str] = [],
+        keep_html_for: List[str] = [],
+    ) -> None:
+        """
+        Raises:
+            TypeError: If an option is unknown or a value has the wrong type
+        """
+    @property
+    def extract_scripts_as_code_blocks(self) -> bool: ...
+    @property
+    def generate_front_matter(self) -> bool: ...
+    @property
+    def use_title_as_h1(self) -> bool: ...
+    @property
+    def max_depth(self) -> Optional[int]:
+        """The deepest element nesting accepted, 512 if None. Deeper input raises `MQParseInputError`."""
+    @property
+    def root_selector(self) -> Optional[str]:
+        """A CSS selector for the elements to convert, e.g. `"article.main"`, instead of the whole body."""
+    @property
+    def exclude_selectors(self) -> List[str]:
+        """CSS selectors for elements to leave out, e.g. `["nav", "footer", ".ad"]`."""
+    @property
+    def keep_html_for(self) -> List[str]:
+        """Tag names of elements to keep as HTML in the markdown, e.g. `["table", "details"]`."""
+    @staticmethod
+    def from_dict(config: Dict[str, Any]) -> ConversionOptions:
+        """
+        Build options from a mapping of option names to values.
+
+        Raises:
+            ValueError: If a name is unknown or `max_depth` is less than 1
+            TypeError: If a value has the wrong type
+        """
+
+    @staticmethod
+    def from_json(data: str) -> ConversionOptions:
+        """
+        Build options from a JSON object, validated as by `from_dict`.
+
+        Raises:
+            ValueError: If `data` is not a JSON object or an option is invalid
+            TypeError: If a value has the wrong type
+        """
+
+def html_to_markdown(
+    content: Content,
+    options: Optional[ConversionOptions] = None,
+    errors: Literal["strict", "replace"] = "strict",
+) -> str:
+    """
+    Convert HTML content to Markdown.
+
+    Args:
+        content: The HTML to convert. Bytes-like objects are decoded as UTF-8.
+        options: Conversion options. If None, default options are used.
+        errors: How invalid UTF-8 in bytes content is handled: "strict" raises and "replace" replaces
+            invalid sequences with U+FFFD.
+
+    Returns:
+        The converted Markdown document
+
+    Raises:
+        MQParseInputError: If elements nest deeper than `options.max_depth`
+        RuntimeError: If the HTML cannot be converted
+        UnicodeDecodeError: If bytes content is not valid UTF-8 and `errors` is "strict"
+        ValueError: If a selector of `options` is not a valid CSS selector
+    """
+
+class HtmlConverter:
+    """
+    An HTML to Markdown converter with handlers for elements the fixed conversion cannot express,
+    such as galleries, callouts or admonitions.
+
+    Example:
+        ```python
+        converter = mq.HtmlConverter()
+        converter.on_element("div.note", lambda attrs, markdown: "> [!NOTE]\n> " + markdown)
+        converter.convert('<div class="note"><p>Be careful</p></div>')  # '> [!NOTE]\n> Be careful'
+        ```
+    """
+
+    def __init__(self, options: Optional[ConversionOptions] = None) -> None: ...
+    def on_element(self, tag: str, handler: Callable[[Dict[str, str], str], Optional[str]]) -> None:
+        """
+        Register `handler` for the elements matching `tag`, a tag name or CSS selector, replacing the
+        handler registered for the same `tag` before. When several handlers match an element, the
+        one registered last is used.
+
+        The handler is called with the attributes of the element and the markdown of its content, and
+        returns the markdown to replace the element with, or None to convert it as usual. Elements
+        inside a handled element are converted with the handlers too.
+
+        Raises:
+            TypeError: If `handler` is not callable
+            ValueError: If `tag` is not a valid CSS selector
+        """
+
+    def convert(self, content: Content, errors: Literal["strict", "replace"] = "strict") -> str:
+        """
+        Convert HTML content to Markdown, as `html_to_markdown` does but with the handlers applied.
+
+        Raises:
+            TypeError: If a handler returns something other than a str or None
+        """
+
+class MarkdownChunks(Iterator[str]):
+    """An iterator over the markdown converted from an HTML stream, from `html_to_markdown_stream`."""
+
+    def __iter__(self) -> MarkdownChunks: ...
+    def __next__(self) -> str: ...
+
+def html_to_markdown_stream(
+    source: Union[Any, Iterable[Union[str, bytes]]],
+    options: Optional[ConversionOptions] = None,
+    errors: Literal["strict", "replace"] = "strict",
+    chunk_size: int = 65536,
+) -> MarkdownChunks:
+    """
+    Convert HTML read incrementally to Markdown, without holding the whole document in memory.
+
+    The HTML is converted a run of complete top-level blocks at a time, at least `chunk_size` bytes
+    of it unless the document ends first. Joining the chunks gives the same document as `html_to_markdown`.
+
+    Args:
+        source: A file-like object with a `read` method, in text or binary mode, or an iterable of
+            str or bytes chunks. Bytes are decoded as UTF-8.
+        options: Conversion options. `root_selector` is not supported, as the elements may span chunks.
+        errors: How invalid UTF-8 in bytes is handled: "strict" raises and "replace" replaces invalid
+            sequences with U+FFFD.
+        chunk_size: The number of characters or bytes read at a time.
+
+    Example:
+        ```python
+        with open("export.html", "rb") as f, open("export.md", "w") as out:
+            for chunk in mq.html_to_markdown_stream(f):
+                out.write(chunk)
+        ```
+
+    Raises:
+        MQParseInputError: If elements nest deeper than `options.max_depth`
+        RuntimeError: If the HTML cannot be converted
+        TypeError: If a chunk is not str or bytes
+        UnicodeDecodeError: If bytes are not valid UTF-8 and `errors` is "strict"
+        ValueError: If `options.root_selector` is set or `chunk_size` is 0
+    """
+
+def html_fragment_to_value(html: str, options: Optional[ConversionOptions] = None) -> MQValue:
+    """
+    Convert an HTML fragment into a markdown value, e.g. to insert a scraped snippet with `DocumentBuilder`.
+
+    A fragment with a single top-level node, such as one `<h2>`, becomes that node. Otherwise the value
+    is an array of the top-level nodes in order, e.g. the items of a `<ul>` or the inline nodes of a `<p>`.
+
+    Example:
+        ```python
+        value = mq.html_fragment_to_value("<h2>Install <em>now</em></h2>")
+        doc = mq.DocumentBuilder().node(value).build()  # '## Install *now*\n'
+        ```
+
+    Raises:
+        MQParseInputError: If elements nest deeper than `options.max_depth`
+        RuntimeError: If the HTML cannot be converted
+    """
+
+def get_front_matter(content: Content) -> Dict[str, Any]:
+    """
+    Return the YAML (`---`) or TOML (`+++`) front matter of a document as a dict.
+
+    Args:
+        content: The document to read
+
+    Returns:
+        The front matter, or an empty dict if the document has none. TOML dates and times are strings.
+
+    Raises:
+        MQParseInputError: If the front matter is not valid YAML or TOML, or is not a mapping
+    """
+
+def set_front_matter(
+    content: Content, front_matter: Dict[str, Any], format: Optional[Literal["yaml", "toml"]] = None
+) -> str:
+    """
+    Replace the front matter of a document, leaving the body untouched.
+
+    Args:
+        content: The document to rewrite
+        front_matter: The new front matter. An empty dict removes it.
+        format: "yaml" or "toml". Defaults to the format of the existing front matter, or YAML.
+
+    Returns:
+        The rewritten document
+
+    Example:
+        ```python
+        meta = mq.get_front_matter(content)
+        meta["draft"] = False
+        content = mq.set_front_matter(content, meta)
+        ```
+
+    Raises:
+        TypeError: If a value cannot be written in the format, e.g. None in TOML
+    """
+
+class CodeBlock:
+    """
+    A code block, as returned by `code_blocks` and passed to `update_code_blocks`.
+
+    It also unpacks and compares as the `(lang, value, meta, (line, column))` tuple `code_blocks`
+    used to return.
+    """
+
+    lang: Optional[str]
+    meta: Optional[str]
+    value: str
+    position: Optional[Position]
+
+    def __len__(self) -> int: ...
+    def __getitem__(self, index: int) -> Any: ...
+    def __iter__(self) -> Iterator[Any]: ...
+    def __eq__(self, other: object) -> bool: ...
+
+@overload
+def code_blocks(
+    content: Content,
+    lang: Optional[str] = None,
+    concat: Literal[False] = False,
+    options: Optional[Options] = None,
+) -> List[CodeBlock]: ...
+@overload
+def code_blocks(
+    content: Content, lang: Optional[str] = None, *, concat: Literal[True], options: Optional[Options] = None
+) -> str: ...
+def code_blocks(
+    content: Content,
+    lang: Optional[str] = None,
+    concat: bool = False,
+    options: Optional[Options] = None,
+) -> Union[List[CodeBlock], str]:
+    """
+    Extract the code blocks of a document, including those nested in lists and blockquotes.
+
+    Args:
+        content: The document to scan
+        lang: Only return code blocks in this language. If None, all code blocks are returned.
+        concat: Return the code of all matching blocks joined into a single string instead
+        options: Options controlling how the content is parsed
+
+    Returns:
+        A list of code blocks, or a string when `concat` is True
+
+    Example:
+        ```python
+        for block in mq.code_blocks(content, lang="python"):
+            print(f"line {block.position.start_line}: {block.value}")
+        ```
+    """
+
+def update_code_blocks(
+    content: Content,
+    f: Callable[[CodeBlock], Union[str, Node, None]],
+    lang: Optional[str] = None,
+    options: Optional[Options] = None,
+) -> str:
+    """
+    Call `f` with each code block and return the document with the blocks updated.
+
+    Args:
+        content: The document to update
+        f: Returns the new code as a string, a node to replace the block with, or None to leave
+            the block as it is
+        lang: Only pass code blocks in this language to `f`
+        options: Options controlling how the markdown is rendered
+
+    Raises:
+        TypeError: If `f` returns anything else
+
+    Example:
+        ```python
+        content = mq.update_code_blocks(content, lambda block: sqlfmt(block.value), lang="sql")
+        ```
+    """
+
+def mdx_components(
+    content: Content, name: Optional[str] = None, options: Optional[Options] = None
+) -> List[MQValue]:
+    """
+    Return the MDX JSX elements of a document in order, including nested ones.
+
+    The content is parsed as MDX whatever the input format of `options`.
+
+    Args:
+        content: The document to scan
+        name: Only return elements of this component
+        options: Options controlling how the content is parsed
+
+    Example:
+        ```python
+        titles = [card.attributes.get("title") for card in mq.mdx_components(content, name="Card")]
+        ```
+    """
+
+def extract_tables(content: Content, options: Optional[Options] = None) -> List[List[Dict[str, str]]]:
+    """
+    Extract the tables of a document, each as a list of rows keyed by the header cells' text.
+
+    Cells are read as plain text, so emphasis, code and link markup is dropped. Rows shorter than
+    the header get empty strings. A repeated header is numbered from its second column on, e.g.
+    `a`, `a_2`, so that no column is lost.
+
+    Example:
+        ```python
+        for row in mq.extract_tables(content)[0]:
+            print(row["Package"], row["Version"])
+        ```
+    """
+
+SectionLink = Tuple[str, str, Optional[Tuple[int, int]]]
+"""A link as `(text, url, (line, column))`."""
+
+def links_by_section(content: Content) -> Dict[Tuple[str, ...], List[SectionLink]]:
+    """
+    Return the outgoing links of each section by heading path, in document order.
+
+    Every section is included, with an empty list when it has no links. Links before the first
+    heading are under the empty path `()`. Reference links are resolved through their definitions;
+    `#fragment` links and unresolved references are left out.
+
+    Example:
+        ```python
+        for path, links in mq.links_by_section(content).items():
+            print(" > ".join(path), [url for _, url, _ in links])
+        ```
+    """
+
+LinkKind = Literal["inline", "reference", "autolink"]
+
+class ExtractedLink:
+    """A link or image, as returned by `extract_links` and `extract_images`."""
+
+    url: str
+    """The URL, resolved through the definition for reference links."""
+    title: Optional[str]
+    text: str
+    """The plain text of a link, or the alt text of an image."""
+    kind: LinkKind
+    position: Optional[Position]
+
+def extract_links(content: Content) -> List[ExtractedLink]:
+    """
+    Return every link of a document in order, with reference links resolved through their definitions.
+
+    Links whose text is their URL, such as `<https://example.com>`, are of kind `"autolink"`.
+
+    Example:
+        ```python
+        external = [link.url for link in mq.extract_links(content) if link.url.startswith("https://")]
+        ```
+    """
+
+def extract_images(content: Content) -> List[ExtractedLink]:
+    """Return every image of a document in order, with the alt text as `text`."""
+
+def rewrite_links(
+    content: Content, mapper: Callable[[str], Optional[str]], options: Optional[Options] = None
+) -> str:
+    """
+    Replace the URL of every link, image and definition with what `mapper` returns for it.
+
+    URLs for which `mapper` returns None are left as they are. The text of autolinks is updated
+    along with the URL.
+
+    Example:
+        ```python
+        content = mq.rewrite_links(content, lambda url: url.replace("/docs/v1/", "/docs/v2/"))
+        ```
+    """
+
+def resolve_references(
+    content: Content,
+    footnotes: Optional[Literal["parens", "link"]] = "parens",
+    options: Optional[Options] = None,
+) -> str:
+    """
+    Replace reference-style links and images with inline ones, removing the definitions they used.
+
+    Footnotes are inlined as `inline_footnotes` does with `footnotes` as its style, or kept if
+    `footnotes` is None. References without a definition are left as they are.
+
+    Raises:
+        ValueError: If `footnotes` is not a valid style
+    """
+
+def to_reference_style(content: Content, options: Optional[Options] = None) -> str:
+    """
+    Replace inline links and images with reference-style ones, adding their definitions at the end.
+
+    URLs that already have a definition use its label; new labels are numbered 1, 2, 3... in order of
+    first use. Autolinks are left as they are.
+
+    Example:
+        ```python
+        mq.to_reference_style("[docs](https://example.com)")
+        # '[docs][1]\n\n[1]: https://example.com\n'
+        ```
+    """
+
+class Snippet:
+    """A query match with the blocks around it, as markdown."""
+
+    value: MQValue
+    block: Optional[str]
+    """The block containing the match, or None when the match has no position."""
+    before: List[str]
+    after: List[str]
+
+    def to_markdown(self) -> str:
+        """Return the context blocks and the block containing the match as one document."""
+
+def extract_with_context(
+    content: Content, query: str, before: int = 1, after: int = 1, options: Optional[Options] = None
+) -> List[Snippet]:
+    """
+    Run a query and return each match with up to `before` and `after` blocks around it.
+
+    Blocks are the runs of the document separated by blank lines, such as paragraphs, whole
+    lists and tables. Empty matches are left out.
+
+    Example:
+        ```python
+        for snippet in mq.extract_with_context(content, '.link | select(contains("docs"))'):
+            print(snippet.to_markdown())
+        ```
+    """
+
+LintRule = Literal[
+    "duplicate-heading",
+    "skipped-heading-level",
+    "empty-section",
+    "multiple-h1",
+    "trailing-whitespace",
+    "max-heading-depth",
+]
+
+ImageAuditRule = Literal["missing-alt", "long-alt", "duplicate-alt"]
+
+TocRule = Literal["missing-toc", "missing-entry", "stale-entry", "text-mismatch", "level-mismatch", "wrong-order"]
+
+LintCheck = Literal[
+    "duplicate-heading",
+    "skipped-heading-level",
+    "bare-url",
+    "table-cell-whitespace",
+    "unused-definition",
+]
+
+Severity = Literal["error", "warning", "info"]
+
+class LintFinding:
+    """
+    An issue found by `lint`, `lint_structure`, `audit_images` or `verify_toc`.
+
+    Attributes:
+        rule: The name of the rule that produced the finding
+        message: A human-readable description of the issue
+        line: The 1-based line the issue starts on
+        column: The 1-based column the issue starts at
+        severity: How serious the issue is, "warning" except for `lint` rules with another severity
+    """
+
+    rule: Union[LintRule, ImageAuditRule, TocRule, LintCheck, str]
+    message: str
+    line: int
+    column: int
+    severity: Severity
+
+class QueryRule:
+    """
+    A lint rule written as an mq query, for `lint`. Every node the query returns is reported with `message`.
+
+    Example:
+        ```python
+        rule = mq.QueryRule("no-h4", ".h4", "Use at most three heading levels", severity="error")
+        ```
+    """
+
+    name: str
+    query: str
+    message: str
+    severity: Severity
+
+    def __init__(self, name: str, query: str, message: str, severity: Severity = "warning") -> None:
+        """
+        Raises:
+            ValueError: If the severity is unknown
+        """
+
+def lint(
+    content: Content,
+    rules: Optional[Sequence[Union[LintCheck, QueryRule]]] = None,
+    options: Optional[Options] = None,
+) -> List[LintFinding]:
+    """
+    Check a document with built-in rules and rules written as mq queries.
+
+    The built-in rules are:
+        duplicate-heading: A heading with the same text (ignoring case) as an earlier one
+        skipped-heading-level: A heading more than one level deeper than the previous heading
+        bare-url: A URL written as text rather than as an `<...>` autolink or a link
+        table-cell-whitespace: More than one space before a cell's closing pipe, unless it lines up with another row
+        unused-definition: A link definition that no link or image refers to
+
+    `table-cell-whitespace` findings have severity "info", the other built-in ones "warning".
+
+    Args:
+        content: The document to check
+        rules: The built-in rule names and `QueryRule`s to run. If None, all built-in rules run.
+        options: Options controlling how the content is parsed and queries run
+
+    Returns:
+        The findings sorted by position
+
+    Raises:
+        ValueError: If a rule name is unknown
+        RuntimeError: If a query rule fails to parse or evaluate
+
+    Example:
+        ```python
+        rules = ["bare-url", "unused-definition", mq.QueryRule("no-h4", ".h4", "Avoid H4", severity="error")]
+        for finding in mq.lint(content, rules):
+            print(f"{finding.line}:{finding.column} {finding.severity} {finding.rule}: {finding.message}")
+        ```
+    """
+
+def lint_structure(
+    content: Content,
+    rules: Optional[Sequence[LintRule]] = None,
+    options: Optional[Options] = None,
+    max_heading_depth: Optional[int] = None,
+) -> List[LintFinding]:
+    """
+    Check a document for common structural issues in one pass.
+
+    The rules are:
+        duplicate-heading: A heading with the same text (ignoring case) as an earlier one
+        skipped-heading-level: A heading more than one level deeper than the previous heading
+        empty-section: A heading followed directly by a heading of the same or a higher level
+        multiple-h1: Any level 1 heading after the first
+        trailing-whitespace: Trailing spaces or tabs, except a two-space hard line break
+            in a paragraph. Lines in code blocks are not checked.
+        max-heading-depth: A heading deeper than `max_heading_depth`, if given
+
+    Args:
+        content: The document to check
+        rules: Only run these rules. If None, all rules run.
+        options: Options controlling how the content is parsed
+        max_heading_depth: The deepest heading level allowed by the max-heading-depth rule
+
+    Returns:
+        The findings sorted by position
+
+    Raises:
+        ValueError: If a rule name is unknown
+
+    Example:
+        ```python
+        for finding in mq.lint_structure(content):
+            print(f"{finding.line}:{finding.column} {finding.rule} {finding.message}")
+        ```
+    """
+
+def audit_images(content: Content, max_alt_length: int = 125) -> List[LintFinding]:
+    """
+    Check the alt text of every image for accessibility.
+
+    The rules are:
+        missing-alt: An image with empty alt text
+        long-alt: Alt text longer than `max_alt_length` characters
+        duplicate-alt: Alt text (ignoring case) already used for an image with another URL
+
+    Args:
+        content: The document to check
+        max_alt_length: The longest alt text allowed by the long-alt rule
+
+    Returns:
+        The findings in document order
+    """
+
+class Anchor:
+    """A heading that can be linked to with `#slug`."""
+
+    slug: str
+    text: str
+    """The heading text without markdown syntax."""
+    depth: int
+    line: int
+    column: int
+
+def anchors(content: Content, options: Optional[Options] = None) -> Dict[str, Anchor]:
+    """
+    Get the anchors of a document's headings by slug, in document order.
+
+    Slugs are generated in `Options.slug_style`, by default the way GitHub does: lowercased,
+    without punctuation and with each space turned into a hyphen. Repeated headings get `-1`,
+    `-2`... suffixes.
+
+    Example:
+        ```python
+        mq.anchors("# Intro\n\n## Intro\n")
+        # {'intro': Anchor(slug="intro", ...), 'intro-1': Anchor(slug="intro-1", ...)}
+        ```
+    """
+
+def slugify(text: str, style: SlugStyle = SlugStyle.GITHUB, function: Optional[Callable[[str], str]] = None) -> str:
+    """
+    Get the anchor slug of `text` in `style`, as the first heading of a document with that text gets.
+
+    Example:
+        ```python
+        mq.slugify("Hello, World!")                      # "hello-world"
+        mq.slugify("2024", mq.SlugStyle.GITLAB)          # "anchor-2024"
+        mq.slugify("Intro", mq.SlugStyle.CUSTOM, str.upper)  # "INTRO"
+        ```
+
+    Raises:
+        ValueError: If `style` is `SlugStyle.CUSTOM` and no function is given
+        TypeError: If the function does not return a str
+    """
+
+def rename_headings(content: Content, renames: Dict[str, str], options: Optional[Options] = None) -> str:
+    """
+    Rename headings and update the `#fragment` links that point to them.
+
+    Headings whose text is a key of `renames` get the new text. Headings that already have the
+    new text are recognized too, so links still pointing to their old anchors are updated.
+
+    Args:
+        content: The markdown document
+        renames: New heading text by old heading text
+        options: Options controlling how the result is rendered
+
+    Returns:
+        The rewritten markdown document
+    """
+
+TocEntry = Dict[str, Any]
+"""A heading in a table of contents from `toc(..., format="list")`, with its `title`, `level`, `slug`,
+`line`, `column` and the `children` nested under it."""
+
+@overload
+def toc(
+    content: Content, max_level: int = 3, format: Literal["markdown"] = "markdown", options: Optional[Options] = None
+) -> str: ...
+@overload
+def toc(
+    content: Content, max_level: int = 3, *, format: Literal["list"], options: Optional[Options] = None
+) -> List[TocEntry]: ...
+def toc(
+    content: Content,
+    max_level: int = 3,
+    format: Literal["markdown", "list"] = "markdown",
+    options: Optional[Options] = None,
+) -> Union[str, List[TocEntry]]:
+    """
+    Build a table of contents of the headings up to `max_level`, linking to their anchors.
+
+    Each heading is nested under the closest heading before it with a smaller level.
+
+    Returns:
+        A nested markdown list as accepted by `verify_toc`, or with `format="list"` a list of
+        `TocEntry` dicts
+    """
+
+def verify_toc(
+    content: Content, marker: str = "<!-- toc -->", options: Optional[Options] = None
+) -> List[LintFinding]:
+    """
+    Check that the table of contents after `marker` matches the headings that follow it.
+
+    The table of contents is the list right after the marker, linking to the anchors of the headings
+    in `Options.slug_style`. Entry levels are relative to the highest heading level after it. The
+    rules are:
+        missing-toc: The marker is not in the document
+        missing-entry: A heading without an entry
+        stale-entry: An entry linking to an anchor that is not a heading
+        text-mismatch: An entry whose text differs from its heading
+        level-mismatch: An entry nested at a different level than its heading
+        wrong-order: An entry out of document order
+
+    Returns:
+        The findings sorted by position, empty if the table of contents is up to date
+    """
+
+class DocumentStats:
+    """The statistics of a document, as returned by `stats`."""
+
+    words: int
+    """The words of the text, without markdown syntax, code blocks, HTML or front matter."""
+    characters: int
+    """The characters of the same text, spaces included."""
+    headings: Dict[int, int]
+    """The number of headings of each level present, e.g. `{1: 1, 2: 4}`."""
+    code_blocks: int
+    code_lines: int
+    """The lines of all code blocks."""
+    links: int
+    images: int
+    reading_minutes: float
+    """The estimated reading time of the words in minutes."""
+
+def stats(content: Content, options: Optional[Options] = None, words_per_minute: int = 200) -> DocumentStats:
+    """
+    Count the words, characters, headings by level, code blocks and lines, links and images of a
+    document in one pass, and estimate its reading time at `words_per_minute`.
+
+    Words are counted in the text as it reads, so markdown syntax, HTML, code blocks and front
+    matter are left out; inline code counts.
+
+    Raises:
+        ValueError: If `words_per_minute` is 0
+    """
+
+class Section:
+    """A heading and everything under it up to the next heading of the same or a higher level."""
+
+    heading: str
+    """The heading text without markdown syntax."""
+    slug: str
+    """The GitHub anchor of the heading, as listed by `anchors`."""
+    level: int
+    nodes: List[MQValue]
+    """The top-level nodes after the heading, including those of subsections."""
+    start: int
+    """The byte offset of the start of the heading line."""
+    end: int
+    """The byte offset after the section, where the next heading of its level or above starts."""
+    text: str
+    """The source of the section, heading included."""
+    body: str
+    """The source after the heading."""
+
+def sections(content: Content) -> List[Section]:
+    """
+    Get every section of a document in order, one for each heading, nested sections included.
+
+    Content before the first heading belongs to no section.
+    """
+
+def get_section(content: Content, title_or_slug: str) -> Optional[Section]:
+    """Get the first section whose heading text or slug is `title_or_slug`, or None."""
+
+def replace_section(content: Content, title_or_slug: str, markdown: str, keep_heading: bool = True) -> str:
+    """
+    Replace the body of a section, its subsections included, with `markdown`.
+
+    With `keep_heading=False`, the heading is replaced too. The rest of the document is kept as it is
+    written, and blank lines are added around the new markdown.
+
+    Raises:
+        ValueError: If no heading has the text or slug `title_or_slug`
+    """
+
+def remove_section(content: Content, title_or_slug: str) -> str:
+    """
+    Remove a section, its heading and its subsections.
+
+    Raises:
+        ValueError: If no heading has the text or slug `title_or_slug`
+    """
+
+def sanitize(
+    content: Content, policy: Literal["strip", "escape"] = "strip", options: Optional[Options] = None
+) -> str:
+    """
+    Remove or escape dangerous raw HTML in a markdown document.
+
+    Script-like elements (`<script>`, `<style>`, `<iframe>`, `<object>`, `<embed>` and similar),
+    event handler attributes such as `onclick`, and `javascript:` URLs are dangerous.
+
+    Args:
+        content: The markdown document
+        policy: "strip" removes dangerous elements with their content and drops unsafe attributes;
+            "escape" escapes the offending tags so they display as text
+        options: Options controlling how the result is rendered
+
+    Returns:
+        The sanitized document, or the unchanged content if it has no dangerous HTML
+
+    Raises:
+        ValueError: If the policy is unknown
+    """
+
+def inline_footnotes(
+    content: Content, style: Literal["parens", "link"] = "parens", options: Optional[Options] = None
+) -> str:
+    """
+    Replace footnote references with the footnote text and remove the footnote definitions.
+
+    Useful when exporting to targets that do not support footnotes.
+
+    Args:
+        content: The markdown document
+        style: "parens" inlines the text in parentheses. "link" inlines footnotes consisting of a
+            single link as that link, and falls back to parentheses otherwise.
+        options: Rendering options for the output document
+
+    Returns:
+        The rewritten markdown document
+
+    Raises:
+        ValueError: If the style is not recognized
+    """
+
+def renumber_footnotes(content: Content, options: Optional[Options] = None) -> str:
+    """
+    Renumber footnotes as 1, 2, 3... in order of first reference.
+
+    Unreferenced footnote definitions are removed, and the remaining definitions are moved to the
+    end of the document in numeric order.
+
+    Returns:
+        The rewritten markdown document
+    """
+
+def convert_callouts(content: Content, to: Literal["gfm", "mkdocs", "blockquote"] = "gfm") -> str:
+    """
+    Convert callouts between GFM alerts, MkDocs admonitions and labelled blockquotes.
+
+    Recognized callouts are GFM alerts (`> [!NOTE]`), MkDocs admonitions (`!!! note "Title"`,
+    including collapsible `???` blocks) and blockquotes starting with a bold label such as
+    `> **Warning:**`. Callouts already in the target style and content inside fenced code blocks
+    are left unchanged.
+
+    Args:
+        content: The markdown document
+        to: The callout style to produce
+
+    Returns:
+        The rewritten markdown document
+
+    Raises:
+        ValueError: If the target style is not recognized
+    """
+
+LinkAction = Literal["nofollow", "https", "block"]
+
+def apply_link_policies(
+    content: Content,
+    policies: Dict[str, Union[LinkAction, Sequence[LinkAction]]],
+    options: Optional[Options] = None,
+) -> str:
+    """
+    Apply link policies by URL domain, e.g. when republishing third-party content.
+
+    Patterns match the lowercased host of absolute URLs, with `*` matching any run of characters,
+    so `"*.example.com"` matches subdomains but not `example.com` itself. The actions of every
+    matching pattern apply:
+        https: Rewrite `http://` URLs to `https://`
+        nofollow: Write the link as an HTML `<a>` with `rel="nofollow"`
+        block: Replace the link with its text
+
+    Args:
+        content: The markdown document
+        policies: Actions by domain pattern, e.g. `{"*.example.com": ["https", "nofollow"]}`
+        options: Options controlling how the result is rendered
+
+    Returns:
+        The rewritten markdown document
+
+    Raises:
+        ValueError: If an action is not recognized
+    """
+
+def limit_heading_depth(content: Content, max_depth: int, options: Optional[Options] = None) -> str:
+    """
+    Raise every heading deeper than `max_depth` to that level, e.g. H5 and H6 to H4 for `max_depth=4`.
+
+    Use `lint_structure(content, max_heading_depth=...)` to report such headings instead.
+
+    Raises:
+        ValueError: If max_depth is not between 1 and 6
+    """
+
+def normalize_code_languages(
+    content: Content,
+    aliases: Optional[Dict[str, str]] = None,
+    default: Optional[str] = None,
+    known: Optional[Sequence[str]] = None,
+    options: Optional[Options] = None,
+) -> Tuple[str, List[str]]:
+    """
+    Normalize the languages of code blocks, e.g. `js` to `javascript` and `sh` to `bash`.
+
+    Languages are lowercased and mapped through built-in aliases, which `aliases` extends or
+    overrides. Blocks without a language get `default`, if given.
+
+    Args:
+        content: The markdown document
+        aliases: Extra language names by alias
+        default: The language of code blocks that have none
+        known: Extra languages not to report as unknown
+        options: Options controlling how the result is rendered
+
+    Returns:
+        The rewritten document and the unknown languages, in order of first appearance
+    """
+
+def normalize_headings(
+    content: Content,
+    case: Literal["sentence", "title"] = "sentence",
+    strip_trailing_punct: bool = True,
+    options: Optional[Options] = None,
+) -> str:
+    """
+    Rewrite every heading in sentence or title case in a single pass.
+
+    Sentence case capitalizes only the first word; title case capitalizes every word except
+    short words such as "a", "of" and "the" in the middle of the heading. Words with capitals
+    after their first letter, such as "API" or "GitHub", and inline code are kept as they are.
+
+    Args:
+        content: The markdown document
+        case: The heading case to produce
+        strip_trailing_punct: Remove trailing `.,;:!` from headings
+        options: Options controlling how the result is rendered
+
+    Returns:
+        The rewritten markdown document
+
+    Raises:
+        ValueError: If the case is not recognized
+    """
+
+Renderer = Callable[[Node], Union[str, Node, None]]
+
+def register_renderer(markdown_type: MarkdownType, callback: Renderer) -> None:
+    """
+    Override how nodes of a given type are rendered.
+
+    The renderer applies everywhere markdown is produced: query results, `mq.nodes` instances,
+    `MQValue.to_markdown()` and `DocumentBuilder.build()`. Nested nodes, such as links inside a
+    heading, are rendered through the callback as well.
+
+    Args:
+        markdown_type: The node type to override
+        callback: Called with the node; returns the markdown to emit in its place, a replacement
+            node, or None to use the default rendering. Registering a renderer for a type that
+            already has one replaces it.
+
+    Raises:
+        TypeError: If the callback is not callable
+
+    Example:
+        ```python
+        import mq
+
+        def mermaid(node):
+            if node.lang == "mermaid":
+                return "![diagram](diagram.svg)"
+
+        mq.register_renderer(mq.MarkdownType.Code, mermaid)
+        ```
+    """
+
+def unregister_renderer(markdown_type: MarkdownType) -> bool:
+    """
+    Remove the renderer registered for a node type.
+
+    Returns:
+        True if a renderer was registered, False otherwise
+    """
+
+def clear_renderers() -> None:
+    """Remove all registered renderers."""
+
+class Selection:
+    """
+    A fluent selection over a document that compiles to an mq query.
+
+    Selection methods return a new Selection with one more query step; terminal methods such as
+    `text()` run the query.
+    """
+
+    @property
+    def query(self) -> str:
+        """The mq query this selection compiles to."""
+
+    def headings(self, level: Optional[int] = None) -> "Selection":
+        """Select headings, optionally only those of the given level (1-6)."""
+
+    def links(self) -> "Selection": ...
+    def images(self) -> "Selection": ...
+    def code(self, lang: Optional[str] = None) -> "Selection":
+        """Select code blocks, optionally only those in the given language."""
+
+    def lists(self) -> "Selection": ...
+    def blockquotes(self) -> "Selection": ...
+    def table_cells(self) -> "Selection": ...
+    def contains(self, text: str) -> "Selection":
+        """Keep only values whose text contains `text`."""
+
+    def select(self, query: str) -> "Selection":
+        """Append an arbitrary mq query step."""
+
+    def run(self) -> MQResult:
+        """Run the query and return the full result."""
+
+    def values(self) -> List[MQValue]:
+        """Run the query and return the non-empty values."""
+
+    def first(self) -> Optional[MQValue]:
+        """Run the query and return the first non-empty value, if any."""
+
+    def markdown(self) -> List[str]:
+        """Run the query and return the values as markdown."""
+
+    def text(self) -> List[str]:
+        """Run the query and return the plain text of each value."""
+
+    def urls(self) -> List[str]:
+        """Run the query and return the URL of each link or image."""
+
+    def __len__(self) -> int: ...
+    def __iter__(self) -> Iterator[MQValue]: ...
+
+def doc(content: Content, options: Optional[Options] = None) -> Selection:
+    """
+    Start a fluent selection over a document.
+
+    Example:
+        ```python
+        import mq
+
+        doc = mq.doc(content)
+        print(doc.headings(level=2).text())
+        print(doc.links().urls())
+        print(doc.headings().contains("API").query)  # '.h | select(contains(to_text(self), "API"))'
+        ```
+    """
+
+QueryArg = Union[str, int, float, bool, None, Sequence["QueryArg"]]
+
+def Q(template: str, *args: QueryArg) -> str:
+    """
+    Build a query by replacing each `?` outside string literals with the next argument.
+
+    Arguments are written as mq literals, with strings quoted and escaped, so user input embedded
+    in a query cannot change its structure.
+
+    Raises:
+        ValueError: If the number of arguments does not match the number of placeholders
+        TypeError: If an argument cannot be written as an mq literal
+
+    Example:
+        ```python
+        query = mq.Q(".h | select(contains(to_text(self), ?))", user_input)
+        result = mq.run(query, content)
+        ```
+    """
+
+class DocumentBuilder:
+    """
+    Incrementally builds a Markdown document.
+
+    Plain strings are escaped so they render literally; `mq.nodes` instances and `MQValue`s, e.g. from
+    `html_fragment_to_value`, are inserted unchanged. Every append method returns the builder so calls can be chained.
+    """
+
+    def __init__(self) -> None: ...
+    def heading(self, depth: int, text: Union[Children, MQValue]) -> "DocumentBuilder":
+        """Append a heading of the given depth (1-6)."""
+
+    def paragraph(self, text: Union[Children, MQValue]) -> "DocumentBuilder":
+        """Append a paragraph."""
+
+    def list(self, items: Sequence[Any], ordered: bool = False) -> "DocumentBuilder":
+        """Append a list. A nested list in `items` becomes a sub-list of the preceding item."""
+
+    def table(
+        self,
+        rows: Sequence[Sequence[Any]],
+        header: Optional[Sequence[Any]] = None,
+        align: Optional[Sequence[Optional[str]]] = None,
+    ) -> "DocumentBuilder":
+        """
+        Append a table.
+
+        Args:
+            rows: The table rows. Cells may be strings, nodes or any object convertible with `str()`.
+            header: The header row. If None, the first row of `rows` is used.
+            align: Per-column alignment: "left", "right", "center", "none" or None.
+        """
+
+    def code(self, value: str, lang: Optional[str] = None, meta: Optional[str] = None) -> "DocumentBuilder":
+        """Append a fenced code block."""
+
+    def blockquote(self, text: Union[Children, MQValue]) -> "DocumentBuilder":
+        """Append a blockquote."""
+
+    def horizontal_rule(self) -> "DocumentBuilder":
+        """Append a horizontal rule."""
+
+    def node(self, node: Union[Node, MQValue]) -> "DocumentBuilder":
+        """Append a node, or the nodes of a value, as its own block."""
+
+    def build(self, options: Optional[Options] = None) -> str:
+        """Render the document like `mq.render` with the settings of `options`, separating blocks with blank lines."""
+
+    def __len__(self) -> int: ...
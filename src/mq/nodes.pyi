@@ -0,0 +1,154 @@
+"""Constructible markdown nodes for building documents from Python."""
+
+from typing import Optional, Sequence, Union
+
+from .mq import MarkdownType, MQValue, Position
+
+Children = Union[str, "Node", Sequence[Union[str, "Node"]]]
+"""Child content: plain text, a node, or a sequence of them."""
+
+class Node:
+    """Base class of all constructible nodes."""
+
+    @property
+    def markdown_type(self) -> MarkdownType: ...
+    @property
+    def position(self) -> Optional[Position]:
+        """Where the node is in the input document, without byte offsets; None for nodes built in Python."""
+
+    @property
+    def children(self) -> "list[Node]":
+        """The child nodes, e.g. the text and links of a heading."""
+
+    def with_children(self, children: Children) -> "Node":
+        """Return a copy of the node with `children` in place of its child nodes; nodes without children are unchanged."""
+
+    def to_markdown(self) -> str:
+        """Render the node as markdown."""
+
+    def to_value(self) -> MQValue:
+        """Convert the node into an MQValue, as returned by queries."""
+
+    def __str__(self) -> str: ...
+    def __repr__(self) -> str: ...
+    def __eq__(self, other: object) -> bool: ...
+
+class Text(Node):
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+class Heading(Node):
+    def __init__(self, depth: int, children: Children) -> None: ...
+    @property
+    def depth(self) -> int: ...
+
+class Strong(Node):
+    def __init__(self, children: Children) -> None: ...
+
+class Emphasis(Node):
+    def __init__(self, children: Children) -> None: ...
+
+class Delete(Node):
+    def __init__(self, children: Children) -> None: ...
+
+class Blockquote(Node):
+    def __init__(self, children: Children) -> None: ...
+
+class Code(Node):
+    def __init__(self, lang: Optional[str], value: str, meta: Optional[str] = None) -> None: ...
+    @property
+    def lang(self) -> Optional[str]: ...
+    @property
+    def meta(self) -> Optional[str]: ...
+    @property
+    def value(self) -> str: ...
+
+class CodeInline(Node):
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+class Math(Node):
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+class MathInline(Node):
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+class Html(Node):
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+class Link(Node):
+    def __init__(self, url: str, children: Children, title: Optional[str] = None) -> None: ...
+    @property
+    def url(self) -> str: ...
+    @property
+    def title(self) -> Optional[str]: ...
+
+class Image(Node):
+    def __init__(self, url: str, alt: str = "", title: Optional[str] = None) -> None: ...
+    @property
+    def url(self) -> str: ...
+    @property
+    def alt(self) -> str: ...
+    @property
+    def title(self) -> Optional[str]: ...
+
+class List(Node):
+    def __init__(
+        self,
+        children: Children,
+        ordered: bool = False,
+        level: int = 0,
+        index: int = 0,
+        checked: Optional[bool] = None,
+    ) -> None: ...
+    @property
+    def ordered(self) -> bool: ...
+    @property
+    def level(self) -> int: ...
+    @property
+    def index(self) -> int: ...
+    @property
+    def checked(self) -> Optional[bool]: ...
+
+class Footnote(Node):
+    def __init__(self, ident: str, children: Children) -> None: ...
+    @property
+    def ident(self) -> str: ...
+
+class FootnoteRef(Node):
+    def __init__(self, ident: str) -> None: ...
+    @property
+    def ident(self) -> str: ...
+
+class Definition(Node):
+    def __init__(self, ident: str, url: str, title: Optional[str] = None) -> None: ...
+    @property
+    def ident(self) -> str: ...
+    @property
+    def url(self) -> str: ...
+    @property
+    def title(self) -> Optional[str]: ...
+
+class Yaml(Node):
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+class Toml(Node):
+    def __init__(self, value: str) -> None: ...
+    @property
+    def value(self) -> str: ...
+
+class HorizontalRule(Node):
+    def __init__(self) -> None: ...
+
+class Break(Node):
+    def __init__(self) -> None: ...
@@ -0,0 +1,21 @@
+"""String builtins of the mq engine, with the same semantics as in queries."""
+
+def slugify(s: str, separator: str = "-") -> str:
+    """
+    Convert `s` to a URL-friendly slug: lowercased, with runs of characters other than `a-z` and
+    `0-9` replaced by `separator`. Heading anchors use GitHub's slugs instead, see `mq.anchors`.
+    """
+
+def trim(s: str) -> str: ...
+def ltrim(s: str) -> str: ...
+def rtrim(s: str) -> str: ...
+def ltrimstr(s: str, prefix: str) -> str:
+    """Remove `prefix` from the start of `s` if it is there."""
+
+def rtrimstr(s: str, suffix: str) -> str:
+    """Remove `suffix` from the end of `s` if it is there."""
+
+def upcase(s: str) -> str: ...
+def downcase(s: str) -> str: ...
+def url_encode(s: str) -> str:
+    """Percent-encode `s` for use in a URL."""
@@ -0,0 +1,705 @@
+//! Constructible markdown nodes exposed as the `mq.nodes` module.
+//!
+//! Every class derives from [`Node`], which renders to markdown and converts into an [`MQValue`],
+//! so generated documents go through the same value model used for query results.
+use crate::{
+    render,
+    value::{MQValue, MarkdownType, Position},
+};
+use mq_markdown::{Title, Url};
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyList, PyString, PyTuple},
+};
+
+#[pyclass(subclass, skip_from_py_object, module = "mq.nodes")]
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub(crate) node: mq_markdown::Node,
+}
+
+impl From<mq_markdown::Node> for Node {
+    fn from(node: mq_markdown::Node) -> Self {
+        Self { node }
+    }
+}
+
+#[pymethods]
+impl Node {
+    #[getter]
+    pub fn markdown_type(&self) -> MarkdownType {
+        self.node.clone().into()
+    }
+
+    /// Where the node is in the input document, or `None` for nodes built in Python.
+    #[getter]
+    fn position(&self) -> Option<Position> {
+        Some(Position::new(self.node.position()?, None))
+    }
+
+    /// The child nodes, each wrapped in its most specific class.
+    #[getter]
+    fn children<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        self.node.children().into_iter().map(|child| to_py(py, child)).collect()
+    }
+
+    /// Returns a copy of the node with `children` in place of its child nodes. Nodes without
+    /// children are returned unchanged.
+    fn with_children<'py>(&self, py: Python<'py>, children: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        let mut node = self.node.clone();
+        node.set_children(extract_children(children)?);
+        to_py(py, node)
+    }
+
+    pub fn to_markdown(&self) -> PyResult<String> {
+        let nodes = render::lengthen_fences(vec![render::apply(self.node.clone())?]);
+        Ok(nodes.iter().map(|node| node.to_string()).collect())
+    }
+
+    pub fn to_value(&self) -> PyResult<MQValue> {
+        mq_lang::RuntimeValue::from(self.node.clone()).try_into()
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        self.to_markdown()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}({:?})", self.markdown_type(), self.node.to_string())
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+/// Converts a `str`, a `Node` or a list/tuple of them into child nodes.
+pub(crate) fn extract_children(obj: &Bound<'_, PyAny>) -> PyResult<Vec<mq_markdown::Node>> {
+    if let Ok(s) = obj.cast::<PyString>() {
+        return Ok(vec![text(s.to_string())]);
+    }
+
+    if let Ok(node) = obj.cast::<Node>() {
+        return Ok(vec![node.borrow().node.clone()]);
+    }
+
+    if obj.is_instance_of::<PyList>() || obj.is_instance_of::<PyTuple>() {
+        return obj
+            .try_iter()?
+            .map(|item| extract_children(&item?))
+            .collect::<PyResult<Vec<_>>>()
+            .map(|children| children.into_iter().flatten().collect());
+    }
+
+    Err(PyTypeError::new_err(format!(
+        "expected str, mq.nodes.Node or a list of them, not {}",
+        obj.get_type().name()?
+    )))
+}
+
+/// Wraps a node in the most specific `mq.nodes` class available for its type.
+pub(crate) fn to_py(py: Python<'_>, node: mq_markdown::Node) -> PyResult<Bound<'_, PyAny>> {
+    fn new<T: pyo3::PyClass<BaseType = Node>>(
+        py: Python<'_>,
+        class: T,
+        node: mq_markdown::Node,
+    ) -> PyResult<Bound<'_, PyAny>> {
+        Ok(Bound::new(py, init(class, node))?.into_any())
+    }
+
+    match node {
+        mq_markdown::Node::Text(_) => new(py, Text, node),
+        mq_markdown::Node::Heading(_) => new(py, Heading, node),
+        mq_markdown::Node::Strong(_) => new(py, Strong, node),
+        mq_markdown::Node::Emphasis(_) => new(py, Emphasis, node),
+        mq_markdown::Node::Delete(_) => new(py, Delete, node),
+        mq_markdown::Node::Blockquote(_) => new(py, Blockquote, node),
+        mq_markdown::Node::Code(_) => new(py, Code, node),
+        mq_markdown::Node::CodeInline(_) => new(py, CodeInline, node),
+        mq_markdown::Node::Math(_) => new(py, Math, node),
+        mq_markdown::Node::MathInline(_) => new(py, MathInline, node),
+        mq_markdown::Node::Html(_) => new(py, Html, node),
+        mq_markdown::Node::Link(_) => new(py, Link, node),
+        mq_markdown::Node::Image(_) => new(py, Image, node),
+        mq_markdown::Node::List(_) => new(py, List, node),
+        mq_markdown::Node::Footnote(_) => new(py, Footnote, node),
+        mq_markdown::Node::FootnoteRef(_) => new(py, FootnoteRef, node),
+        mq_markdown::Node::Definition(_) => new(py, Definition, node),
+        mq_markdown::Node::Yaml(_) => new(py, Yaml, node),
+        mq_markdown::Node::Toml(_) => new(py, Toml, node),
+        mq_markdown::Node::HorizontalRule(_) => new(py, HorizontalRule, node),
+        mq_markdown::Node::Break(_) => new(py, Break, node),
+        node => Ok(Bound::new(py, Node::from(node))?.into_any()),
+    }
+}
+
+fn init<T: pyo3::PyClass<BaseType = Node>>(class: T, node: mq_markdown::Node) -> PyClassInitializer<T> {
+    PyClassInitializer::from(Node::from(node)).add_subclass(class)
+}
+
+fn text(value: String) -> mq_markdown::Node {
+    mq_markdown::Node::Text(mq_markdown::Text { value, position: None })
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Text;
+
+#[pymethods]
+impl Text {
+    #[new]
+    fn new(value: String) -> PyClassInitializer<Self> {
+        init(Self, text(value))
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Heading;
+
+#[pymethods]
+impl Heading {
+    #[new]
+    fn new(depth: u8, children: &Bound<'_, PyAny>) -> PyResult<PyClassInitializer<Self>> {
+        if !(1..=6).contains(&depth) {
+            return Err(PyValueError::new_err(format!(
+                "Heading depth must be between 1 and 6, got {}",
+                depth
+            )));
+        }
+
+        Ok(init(
+            Self,
+            mq_markdown::Node::Heading(mq_markdown::Heading {
+                depth,
+                values: extract_children(children)?,
+                position: None,
+            }),
+        ))
+    }
+
+    #[getter]
+    fn depth(slf: PyRef<'_, Self>) -> u8 {
+        match &slf.as_super().node {
+            mq_markdown::Node::Heading(heading) => heading.depth,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Strong;
+
+#[pymethods]
+impl Strong {
+    #[new]
+    fn new(children: &Bound<'_, PyAny>) -> PyResult<PyClassInitializer<Self>> {
+        Ok(init(
+            Self,
+            mq_markdown::Node::Strong(mq_markdown::Strong {
+                values: extract_children(children)?,
+                position: None,
+            }),
+        ))
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Emphasis;
+
+#[pymethods]
+impl Emphasis {
+    #[new]
+    fn new(children: &Bound<'_, PyAny>) -> PyResult<PyClassInitializer<Self>> {
+        Ok(init(
+            Self,
+            mq_markdown::Node::Emphasis(mq_markdown::Emphasis {
+                values: extract_children(children)?,
+                position: None,
+            }),
+        ))
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Delete;
+
+#[pymethods]
+impl Delete {
+    #[new]
+    fn new(children: &Bound<'_, PyAny>) -> PyResult<PyClassInitializer<Self>> {
+        Ok(init(
+            Self,
+            mq_markdown::Node::Delete(mq_markdown::Delete {
+                values: extract_children(children)?,
+                position: None,
+            }),
+        ))
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Blockquote;
+
+#[pymethods]
+impl Blockquote {
+    #[new]
+    fn new(children: &Bound<'_, PyAny>) -> PyResult<PyClassInitializer<Self>> {
+        Ok(init(
+            Self,
+            mq_markdown::Node::Blockquote(mq_markdown::Blockquote {
+                values: extract_children(children)?,
+                position: None,
+            }),
+        ))
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Code;
+
+#[pymethods]
+impl Code {
+    #[new]
+    #[pyo3(signature = (lang, value, meta=None))]
+    fn new(lang: Option<String>, value: String, meta: Option<String>) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::Code(mq_markdown::Code {
+                value,
+                lang,
+                meta,
+                fence: true,
+                position: None,
+            }),
+        )
+    }
+
+    #[getter]
+    fn lang(slf: PyRef<'_, Self>) -> Option<String> {
+        match &slf.as_super().node {
+            mq_markdown::Node::Code(code) => code.lang.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn meta(slf: PyRef<'_, Self>) -> Option<String> {
+        match &slf.as_super().node {
+            mq_markdown::Node::Code(code) => code.meta.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct CodeInline;
+
+#[pymethods]
+impl CodeInline {
+    #[new]
+    fn new(value: &str) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::CodeInline(mq_markdown::CodeInline {
+                value: value.into(),
+                position: None,
+            }),
+        )
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Math;
+
+#[pymethods]
+impl Math {
+    #[new]
+    fn new(value: String) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::Math(mq_markdown::Math { value, position: None }),
+        )
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct MathInline;
+
+#[pymethods]
+impl MathInline {
+    #[new]
+    fn new(value: &str) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::MathInline(mq_markdown::MathInline {
+                value: value.into(),
+                position: None,
+            }),
+        )
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Html;
+
+#[pymethods]
+impl Html {
+    #[new]
+    fn new(value: String) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::Html(mq_markdown::Html { value, position: None }),
+        )
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Link;
+
+#[pymethods]
+impl Link {
+    #[new]
+    #[pyo3(signature = (url, children, title=None))]
+    fn new(url: String, children: &Bound<'_, PyAny>, title: Option<String>) -> PyResult<PyClassInitializer<Self>> {
+        Ok(init(
+            Self,
+            mq_markdown::Node::Link(mq_markdown::Link {
+                url: Url::new(url),
+                title: title.map(Title::new),
+                values: extract_children(children)?,
+                position: None,
+            }),
+        ))
+    }
+
+    #[getter]
+    fn url(slf: PyRef<'_, Self>) -> String {
+        match &slf.as_super().node {
+            mq_markdown::Node::Link(link) => link.url.as_str().to_string(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn title(slf: PyRef<'_, Self>) -> Option<String> {
+        match &slf.as_super().node {
+            mq_markdown::Node::Link(link) => link.title.as_ref().map(Title::to_value),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Image;
+
+#[pymethods]
+impl Image {
+    #[new]
+    #[pyo3(signature = (url, alt=String::new(), title=None))]
+    fn new(url: String, alt: String, title: Option<String>) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::Image(mq_markdown::Image {
+                alt,
+                url,
+                title,
+                position: None,
+            }),
+        )
+    }
+
+    #[getter]
+    fn url(slf: PyRef<'_, Self>) -> String {
+        match &slf.as_super().node {
+            mq_markdown::Node::Image(image) => image.url.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn alt(slf: PyRef<'_, Self>) -> String {
+        match &slf.as_super().node {
+            mq_markdown::Node::Image(image) => image.alt.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn title(slf: PyRef<'_, Self>) -> Option<String> {
+        match &slf.as_super().node {
+            mq_markdown::Node::Image(image) => image.title.clone(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct List;
+
+#[pymethods]
+impl List {
+    #[new]
+    #[pyo3(signature = (children, ordered=false, level=0, index=0, checked=None))]
+    fn new(
+        children: &Bound<'_, PyAny>,
+        ordered: bool,
+        level: u8,
+        index: usize,
+        checked: Option<bool>,
+    ) -> PyResult<PyClassInitializer<Self>> {
+        Ok(init(
+            Self,
+            mq_markdown::Node::List(mq_markdown::List {
+                values: extract_children(children)?,
+                index,
+                level,
+                ordered,
+                checked,
+                position: None,
+            }),
+        ))
+    }
+
+    #[getter]
+    fn ordered(slf: PyRef<'_, Self>) -> bool {
+        match &slf.as_super().node {
+            mq_markdown::Node::List(list) => list.ordered,
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn level(slf: PyRef<'_, Self>) -> u8 {
+        match &slf.as_super().node {
+            mq_markdown::Node::List(list) => list.level,
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn index(slf: PyRef<'_, Self>) -> usize {
+        match &slf.as_super().node {
+            mq_markdown::Node::List(list) => list.index,
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn checked(slf: PyRef<'_, Self>) -> Option<bool> {
+        match &slf.as_super().node {
+            mq_markdown::Node::List(list) => list.checked,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Footnote;
+
+#[pymethods]
+impl Footnote {
+    #[new]
+    fn new(ident: String, children: &Bound<'_, PyAny>) -> PyResult<PyClassInitializer<Self>> {
+        Ok(init(
+            Self,
+            mq_markdown::Node::Footnote(mq_markdown::Footnote {
+                ident,
+                values: extract_children(children)?,
+                position: None,
+            }),
+        ))
+    }
+
+    #[getter]
+    fn ident(slf: PyRef<'_, Self>) -> String {
+        match &slf.as_super().node {
+            mq_markdown::Node::Footnote(footnote) => footnote.ident.clone(),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct FootnoteRef;
+
+#[pymethods]
+impl FootnoteRef {
+    #[new]
+    fn new(ident: String) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::FootnoteRef(mq_markdown::FootnoteRef {
+                label: Some(ident.clone()),
+                ident,
+                position: None,
+            }),
+        )
+    }
+
+    #[getter]
+    fn ident(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Definition;
+
+#[pymethods]
+impl Definition {
+    #[new]
+    #[pyo3(signature = (ident, url, title=None))]
+    fn new(ident: String, url: String, title: Option<String>) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::Definition(mq_markdown::Definition {
+                label: Some(ident.clone()),
+                ident,
+                url: Url::new(url),
+                title: title.map(Title::new),
+                position: None,
+            }),
+        )
+    }
+
+    #[getter]
+    fn ident(slf: PyRef<'_, Self>) -> String {
+        match &slf.as_super().node {
+            mq_markdown::Node::Definition(definition) => definition.ident.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[getter]
+    fn url(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+
+    #[getter]
+    fn title(slf: PyRef<'_, Self>) -> Option<String> {
+        match &slf.as_super().node {
+            mq_markdown::Node::Definition(definition) => definition.title.as_ref().map(Title::to_value),
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Yaml;
+
+#[pymethods]
+impl Yaml {
+    #[new]
+    fn new(value: String) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::Yaml(mq_markdown::Yaml { value, position: None }),
+        )
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Toml;
+
+#[pymethods]
+impl Toml {
+    #[new]
+    fn new(value: String) -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::Toml(mq_markdown::Toml { value, position: None }),
+        )
+    }
+
+    #[getter]
+    fn value(slf: PyRef<'_, Self>) -> String {
+        slf.as_super().node.value()
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct HorizontalRule;
+
+#[pymethods]
+impl HorizontalRule {
+    #[new]
+    fn new() -> PyClassInitializer<Self> {
+        init(
+            Self,
+            mq_markdown::Node::HorizontalRule(mq_markdown::HorizontalRule { position: None }),
+        )
+    }
+}
+
+#[pyclass(extends = Node, module = "mq.nodes")]
+pub struct Break;
+
+#[pymethods]
+impl Break {
+    #[new]
+    fn new() -> PyClassInitializer<Self> {
+        init(Self, mq_markdown::Node::Break(mq_markdown::Break { position: None }))
+    }
+}
+
+pub(crate) fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let nodes = PyModule::new(m.py(), "nodes")?;
+    nodes.add_class::<Node>()?;
+    nodes.add_class::<Text>()?;
+    nodes.add_class::<Heading>()?;
+    nodes.add_class::<Strong>()?;
+    nodes.add_class::<Emphasis>()?;
+    nodes.add_class::<Delete>()?;
+    nodes.add_class::<Blockquote>()?;
+    nodes.add_class::<Code>()?;
+    nodes.add_class::<CodeInline>()?;
+    nodes.add_class::<Math>()?;
+    nodes.add_class::<MathInline>()?;
+    nodes.add_class::<Html>()?;
+    nodes.add_class::<Link>()?;
+    nodes.add_class::<Image>()?;
+    nodes.add_class::<List>()?;
+    nodes.add_class::<Footnote>()?;
+    nodes.add_class::<FootnoteRef>()?;
+    nodes.add_class::<Definition>()?;
+    nodes.add_class::<Yaml>()?;
+    nodes.add_class::<Toml>()?;
+    nodes.add_class::<HorizontalRule>()?;
+    nodes.add_class::<Break>()?;
+    m.add_submodule(&nodes)
+}
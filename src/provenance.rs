@@ -0,0 +1,312 @@
+//! Recording which stage of a query pipeline produced each result value, and tracing how many
+//! values each stage leaves.
+use crate::{analyze, engine, security};
+use mq_lang::{MqResult, RuntimeValue};
+use pyo3::prelude::*;
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+/// The values left by the prefix of a pipeline ending at a stage, or why they are unknown, e.g. for
+/// a lone `let` that does not run on its own, and how long running it took.
+pub(crate) type Prefix = (Result<Vec<RuntimeValue>, String>, Duration);
+
+/// One stage of a query pipeline, as traced with `Options.debug`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct TraceStep {
+    #[pyo3(get)]
+    index: usize,
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    code: String,
+    /// The number of non-empty values the stage received, None when unknown.
+    #[pyo3(get)]
+    input_count: Option<usize>,
+    /// The number of non-empty values the stage left, None when the pipeline up to the stage does
+    /// not run on its own.
+    #[pyo3(get)]
+    output_count: Option<usize>,
+    /// The seconds the stage added to the pipeline before it.
+    #[pyo3(get)]
+    duration: f64,
+    /// Why `output_count` is None: the error the pipeline up to the stage raised on its own, or
+    /// that it was not run again.
+    #[pyo3(get)]
+    error: Option<String>,
+}
+
+#[pymethods]
+impl TraceStep {
+    fn __repr__(&self) -> String {
+        let count = |count: Option<usize>| count.map_or("None".to_string(), |count| count.to_string());
+
+        format!(
+            "TraceStep({}, {:?}, input_count={}, output_count={}, duration={:e})",
+            self.index,
+            self.code,
+            count(self.input_count),
+            count(self.output_count),
+            self.duration
+        )
+    }
+}
+
+/// Keywords whose body runs until `;` or `end`, so pipes inside it belong to the body.
+const BLOCK_KEYWORDS: [&str; 8] = ["def", "fn", "do", "foreach", "while", "loop", "module", "macro"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Block {
+    /// Closed by `;` or `end`.
+    Body,
+    /// A `match`, whose arms start with `|` and which is closed by `end` only.
+    Match,
+}
+
+/// Splits `code` at its top-level pipes, returning the byte range of each stage without the
+/// surrounding whitespace.
+pub(crate) fn stages(code: &str) -> Vec<Range<usize>> {
+    let bytes = code.as_bytes();
+    let mut stages = Vec::new();
+    let mut blocks = Vec::new();
+    // Blocks whose keyword was read but whose `:` or `do` was not, with the bracket depth of the keyword.
+    let mut pending = Vec::<(Block, usize)>::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => depth -= 1,
+            b':' if pending.last().is_some_and(|(_, d)| *d == depth) => blocks.extend(pending.pop().map(|(b, _)| b)),
+            b';' if blocks.last() == Some(&Block::Body) => {
+                blocks.pop();
+            }
+            b'|' if depth == 0 && blocks.is_empty() && !matches!(bytes.get(i + 1), Some(b'|' | b'=')) => {
+                stages.push(start..i);
+                start = i + 1;
+            }
+            b'|' if bytes.get(i + 1) == Some(&b'|') => i += 1,
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                let word_start = i;
+
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+
+                // Selectors such as `.code` are not keywords.
+                if word_start > 0 && bytes[word_start - 1] == b'.' {
+                    continue;
+                }
+
+                match &code[word_start..i] {
+                    "do" if pending.last().is_some_and(|(_, d)| *d == depth) => {
+                        blocks.extend(pending.pop().map(|(b, _)| b))
+                    }
+                    "do" => blocks.push(Block::Body),
+                    "match" => pending.push((Block::Match, depth)),
+                    "end" => {
+                        blocks.pop();
+                    }
+                    word if BLOCK_KEYWORDS.contains(&word) => pending.push((Block::Body, depth)),
+                    _ => {}
+                }
+
+                continue;
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    stages.push(start..code.len());
+    stages
+        .into_iter()
+        .map(|range| {
+            let stage = &code[range.clone()];
+            let start = range.start + (stage.len() - stage.trim_start().len());
+            start..range.start + stage.trim_end().len()
+        })
+        .collect()
+}
+
+/// Returns the name of a stage: its function or keyword, or the stage itself for selectors.
+pub(crate) fn stage_name(stage: &str) -> &str {
+    stage
+        .split(|c: char| c == '(' || c.is_whitespace())
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(stage)
+}
+
+/// Runs the prefixes of `code` that end at each of `stages` but the last on `input`, as the last
+/// one is the whole query, with `run`, which runs them with the arguments and definitions of the
+/// query itself.
+///
+/// Every prefix runs from the start of the query, so a query of n stages is run n - 1 more times,
+/// its first stages the most. Queries calling builtins with side effects, such as `print`, are not
+/// run again, so their output is not repeated.
+pub(crate) fn prefixes(
+    code: &str,
+    stages: &[Range<usize>],
+    input: &[RuntimeValue],
+    run: &mut impl FnMut(Option<&str>, engine::Input) -> (MqResult, Duration, Duration),
+) -> Vec<Prefix> {
+    let stages = &stages[..stages.len().saturating_sub(1)];
+    let names = analyze::used_names(code).map_or_else(|_| Vec::new(), |(names, _)| names);
+
+    if let Some(name) = names.iter().find(|name| security::has_side_effects(name)) {
+        let error = format!("not run on its own, as the query calls `{}`", name);
+        return stages.iter().map(|_| (Err(error.clone()), Duration::ZERO)).collect();
+    }
+
+    stages
+        .iter()
+        .map(|stage| {
+            let start = Instant::now();
+            let input = Vec::from(input).into_iter();
+            let (values, ..) = run(Some(&code[..stage.end]), input);
+            let values = values
+                .map(|values| values.into_iter().collect())
+                .map_err(|e| e.to_string());
+            (values, start.elapsed())
+        })
+        .collect()
+}
+
+/// Returns a warning for each prefix of `stages` that failed, for results that cannot show them
+/// as `TraceStep.error`.
+pub(crate) fn prefix_warnings(code: &str, stages: &[Range<usize>], prefixes: &[Prefix]) -> Vec<String> {
+    stages
+        .iter()
+        .zip(prefixes)
+        .enumerate()
+        .filter_map(|(index, (stage, (values, _)))| {
+            let error = values.as_ref().err()?;
+            let name = stage_name(&code[stage.clone()]);
+            Some(format!("Stage {} `{}` could not be traced: {}", index, name, error))
+        })
+        .collect()
+}
+
+/// Returns the index of the stage that produced each of `values`, the result of running all of
+/// `stages` on `input`, given the `prefixes` before the last stage.
+///
+/// That is the last stage that changed the value, or the last stage for values that passed every
+/// stage unchanged. A value that was dropped is attributed to the stage that dropped it, since
+/// later stages leave it empty.
+pub(crate) fn trace(
+    stages: &[Range<usize>],
+    prefixes: &[Prefix],
+    input: &[RuntimeValue],
+    values: &[RuntimeValue],
+) -> Vec<Option<usize>> {
+    let Some(last) = stages.len().checked_sub(1) else {
+        return vec![None; values.len()];
+    };
+
+    // A prefix that does not run on its own is folded into the next stage.
+    let outputs = prefixes
+        .iter()
+        .map(|(values, _)| values.clone().ok())
+        .chain(std::iter::once(Some(values.to_vec())))
+        .collect::<Vec<_>>();
+
+    (0..values.len())
+        .map(|i| {
+            let mut previous = input;
+            let mut produced = None;
+
+            for (stage, output) in outputs.iter().enumerate() {
+                let Some(output) = output else {
+                    continue;
+                };
+                let unchanged =
+                    output.len() == values.len() && previous.len() == output.len() && previous[i] == output[i];
+
+                if !unchanged {
+                    produced = Some(stage);
+                }
+
+                previous = output;
+            }
+
+            if matches!(values[i], RuntimeValue::None) {
+                produced
+            } else {
+                Some(produced.unwrap_or(last))
+            }
+        })
+        .collect()
+}
+
+/// Returns the steps of a pipeline whose stages ended in `values` after compiling and running for
+/// `elapsed`, given the `prefixes` before the last stage.
+///
+/// Each prefix is compiled and run on its own, so the duration of a stage is the difference to the
+/// prefix before it and only approximate.
+pub(crate) fn steps(
+    code: &str,
+    stages: &[Range<usize>],
+    prefixes: &[Prefix],
+    input: &[RuntimeValue],
+    values: &[RuntimeValue],
+    elapsed: Duration,
+) -> Vec<TraceStep> {
+    let count = |values: &[RuntimeValue]| values.iter().filter(|value| !is_empty(value)).count();
+    let outputs = prefixes
+        .iter()
+        .map(|(values, duration)| (values.as_deref().map(count), *duration))
+        .chain(std::iter::once((Ok(count(values)), elapsed)));
+    let mut previous = (Some(count(input)), Duration::ZERO);
+
+    stages
+        .iter()
+        .zip(outputs)
+        .enumerate()
+        .map(|(index, (stage, (output, elapsed)))| {
+            let (output_count, error) = match output {
+                Ok(count) => (Some(count), None),
+                Err(error) => (None, Some(error.clone())),
+            };
+            let code = &code[stage.clone()];
+            let step = TraceStep {
+                index,
+                name: stage_name(code).to_string(),
+                code: code.to_string(),
+                input_count: previous.0,
+                output_count,
+                duration: elapsed.saturating_sub(previous.1).as_secs_f64(),
+                error,
+            };
+
+            previous = (output_count, elapsed);
+            step
+        })
+        .collect()
+}
+
+/// Whether a value stands in for a node the query did not match.
+fn is_empty(value: &RuntimeValue) -> bool {
+    match value {
+        RuntimeValue::None => true,
+        RuntimeValue::Markdown(node, _) => node.is_empty() || node.is_empty_fragment(),
+        _ => false,
+    }
+}
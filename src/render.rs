@@ -0,0 +1,824 @@
+//! Python callbacks that override how individual node types are rendered.
+//!
+//! Renderers are registered process-wide and applied wherever nodes are turned into markdown:
+//! query results, `mq.nodes` instances, `MQValue.to_markdown()` and `DocumentBuilder.build()`.
+use crate::{
+    CodeFenceStyle, EmphasisStyle, HardBreakStyle, HeadingStyle, LinkTitlePolicy, MathStyle, Options, OutputFormat,
+    TitleSurroundStyle, anchor, input, nodes, sanitize, transform, value::MarkdownType,
+};
+use indexmap::IndexMap;
+use mq_markdown::{Markdown, Node};
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use std::sync::RwLock;
+
+static RENDERERS: RwLock<Vec<(MarkdownType, Py<PyAny>)>> = RwLock::new(Vec::new());
+
+/// Registers `callback` to render nodes of `markdown_type`, replacing any existing renderer.
+///
+/// The callback receives an `mq.nodes.Node` and returns the markdown to emit in its place, or
+/// `None` to fall back to the default rendering.
+#[pyfunction]
+pub fn register_renderer(markdown_type: MarkdownType, callback: Bound<'_, PyAny>) -> PyResult<()> {
+    if !callback.is_callable() {
+        return Err(PyTypeError::new_err(format!(
+            "renderer must be callable, not {}",
+            callback.get_type().name()?
+        )));
+    }
+
+    let mut renderers = RENDERERS.write().unwrap_or_else(|e| e.into_inner());
+    renderers.retain(|(t, _)| *t != markdown_type);
+    renderers.push((markdown_type, callback.unbind()));
+    Ok(())
+}
+
+/// Removes the renderer registered for `markdown_type`, returning whether one was registered.
+#[pyfunction]
+pub fn unregister_renderer(markdown_type: MarkdownType) -> bool {
+    let mut renderers = RENDERERS.write().unwrap_or_else(|e| e.into_inner());
+    let len = renderers.len();
+    renderers.retain(|(t, _)| *t != markdown_type);
+    renderers.len() != len
+}
+
+#[pyfunction]
+pub fn clear_renderers() {
+    RENDERERS.write().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Replaces every node that has a registered renderer with the callback's output.
+pub(crate) fn apply(node: Node) -> PyResult<Node> {
+    if RENDERERS.read().unwrap_or_else(|e| e.into_inner()).is_empty() {
+        return Ok(node);
+    }
+
+    Python::attach(|py| {
+        // Callbacks run without the lock held, so they can themselves register renderers.
+        let renderers = RENDERERS
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(t, callback)| (*t, callback.clone_ref(py)))
+            .collect::<Vec<_>>();
+
+        apply_with(py, node, &renderers)
+    })
+}
+
+pub(crate) fn apply_all(nodes: Vec<Node>) -> PyResult<Vec<Node>> {
+    nodes.into_iter().map(apply).collect()
+}
+
+/// Renders `nodes` as a markdown document with the style settings of `options`.
+pub(crate) fn to_markdown(nodes: Vec<Node>, options: Option<Options>) -> PyResult<String> {
+    let options = options.unwrap_or_default();
+    let nodes = anchor::insert_anchors(apply_all(nodes)?, &options)?;
+    Ok(write(apply_options(nodes, &options), &options))
+}
+
+/// Renders `nodes` in the `output_format` of `options`: markdown with its style settings, HTML
+/// converted from that markdown, the nodes as JSON, or the plain text of each node on its own line.
+pub(crate) fn to_output(nodes: Vec<Node>, options: Option<Options>) -> PyResult<String> {
+    let options = options.unwrap_or_default();
+
+    match options.output_format.unwrap_or_default() {
+        OutputFormat::Markdown => to_markdown(nodes, Some(options)),
+        OutputFormat::Html => {
+            let html = mq_markdown::to_html(&to_markdown(nodes, Some(options.clone()))?);
+
+            Ok(match &options.html_attributes {
+                Some(attributes) => attributes.add_to(&html),
+                None => html,
+            })
+        }
+        OutputFormat::Json => Markdown::new(apply_all(nodes)?)
+            .to_json()
+            .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Error writing JSON: {}", e))),
+        OutputFormat::Text => Ok(Markdown::new(nodes).to_text()),
+    }
+}
+
+/// The attributes `Options.html_attributes` adds to the HTML elements of each node type.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct HtmlAttributes(IndexMap<MarkdownType, IndexMap<String, String>>);
+
+impl HtmlAttributes {
+    /// The tags of the elements that nodes of `markdown_type` are rendered as.
+    fn tags(markdown_type: MarkdownType) -> &'static [&'static str] {
+        match markdown_type {
+            MarkdownType::Heading => &["h1", "h2", "h3", "h4", "h5", "h6"],
+            MarkdownType::Blockquote => &["blockquote"],
+            MarkdownType::Code => &["pre"],
+            MarkdownType::CodeInline => &["code"],
+            MarkdownType::Link => &["a"],
+            MarkdownType::Image => &["img"],
+            MarkdownType::Emphasis => &["em"],
+            MarkdownType::Strong => &["strong"],
+            MarkdownType::Delete => &["del"],
+            MarkdownType::HorizontalRule => &["hr"],
+            MarkdownType::Break => &["br"],
+            MarkdownType::List => &["li"],
+            MarkdownType::TableRow => &["tr"],
+            MarkdownType::TableCell => &["th", "td"],
+            _ => &[],
+        }
+    }
+
+    /// Adds the attributes to the elements of `html` rendered from markdown, merging classes into
+    /// those an element already has and keeping its other attributes.
+    fn add_to(&self, html: &str) -> String {
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        // The `<code>` of a code block follows its `<pre>`, and is not inline code.
+        let mut in_pre = false;
+
+        while let Some(start) = rest.find('<') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let Some(tag) = sanitize::parse_tag(rest) else {
+                out.push('<');
+                rest = &rest[1..];
+                continue;
+            };
+            let (text, after) = rest.split_at(tag.len);
+            rest = after;
+
+            let code_block = std::mem::replace(&mut in_pre, tag.name == "pre" && !tag.closing);
+            let attributes = self.0.iter().find(|(markdown_type, _)| {
+                Self::tags(**markdown_type).contains(&tag.name.as_str())
+                    && !(**markdown_type == MarkdownType::CodeInline && code_block)
+            });
+
+            match attributes {
+                Some((markdown_type, attributes)) if !tag.closing => {
+                    // Headings are the only elements with a level, from the digit of their tag.
+                    let level = match markdown_type {
+                        MarkdownType::Heading => &tag.name[1..],
+                        _ => "",
+                    };
+                    out.push_str(&with_attributes(text, tag.self_closing, attributes, level));
+                }
+                _ => out.push_str(text),
+            }
+        }
+
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Adds `attributes` to the opening tag `text`, with `{level}` in their values replaced by `level`.
+fn with_attributes(text: &str, self_closing: bool, attributes: &IndexMap<String, String>, level: &str) -> String {
+    let end = if self_closing { " />" } else { ">" };
+    let mut tag = text[..text.len() - end.trim_start().len()].trim_end().to_string();
+
+    for (name, value) in attributes {
+        let value = transform::escape_attribute(&value.replace("{level}", level));
+        let existing = format!(" {}=\"", name);
+
+        match tag.find(&existing) {
+            Some(i) if name == "class" => tag.insert_str(i + existing.len(), &format!("{} ", value)),
+            Some(_) => {}
+            None => tag.push_str(&format!(" {}=\"{}\"", name, value)),
+        }
+    }
+
+    tag.push_str(end);
+    tag
+}
+
+impl<'a, 'py> FromPyObject<'a, 'py> for HtmlAttributes {
+    type Error = PyErr;
+
+    fn extract(obj: Borrowed<'a, 'py, PyAny>) -> PyResult<Self> {
+        let attributes = obj.extract::<IndexMap<MarkdownType, IndexMap<String, String>>>()?;
+
+        for (markdown_type, attributes) in &attributes {
+            if HtmlAttributes::tags(*markdown_type).is_empty() {
+                return Err(PyValueError::new_err(format!(
+                    "html_attributes cannot be set for {:?} nodes",
+                    markdown_type
+                )));
+            }
+
+            for name in attributes.keys() {
+                let valid = !name.is_empty()
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':'));
+
+                if !valid {
+                    return Err(PyValueError::new_err(format!("Invalid HTML attribute name {:?}", name)));
+                }
+            }
+        }
+
+        Ok(HtmlAttributes(attributes))
+    }
+}
+
+impl<'py> IntoPyObject<'py> for HtmlAttributes {
+    type Target = PyDict;
+    type Output = Bound<'py, PyDict>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> PyResult<Self::Output> {
+        self.0.into_pyobject(py)
+    }
+}
+
+/// Converts `markdown` to HTML for display in a notebook, showing raw HTML in it as text so that
+/// scripts from the queried document do not run and its unclosed tags do not swallow the page.
+pub(crate) fn to_display_html(markdown: &str) -> String {
+    let Ok(parsed) = Markdown::from_markdown_str(markdown) else {
+        return mq_markdown::to_html(&escape_text(markdown));
+    };
+    let nodes = transform::rewrite(parsed.nodes, &mut |node| match node {
+        Node::Html(html) => vec![Node::Text(mq_markdown::Text {
+            value: escape_text(&html.value),
+            position: html.position,
+        })],
+        node => vec![node],
+    });
+
+    mq_markdown::to_html(&Markdown::new(nodes).to_string())
+}
+
+/// Like `to_markdown`, but escapes text and picks code delimiters so that the output parses back to
+/// the same nodes, also when embedded in a blockquote, a list item or a table.
+pub(crate) fn to_commonmark_strict(nodes: Vec<Node>, options: Option<Options>) -> PyResult<String> {
+    let options = options.unwrap_or_default();
+    let nodes = apply_all(transform::rewrite(nodes, &mut |node| vec![escape_node(node)]))?;
+    let nodes = anchor::insert_anchors(nodes, &options)?;
+    let nodes = transform::rewrite(apply_options(nodes, &options), &mut |node| vec![delimit_code(node)]);
+    Ok(write(nodes, &options))
+}
+
+fn write(nodes: Vec<Node>, options: &Options) -> String {
+    tidy(write_blocks(nodes, options), options)
+}
+
+/// Writes `nodes` with the settings of `options` that `write` applies before tidying whitespace,
+/// for callers that join several rendered blocks before tidying them together.
+pub(crate) fn write_blocks(nodes: Vec<Node>, options: &Options) -> String {
+    let mut markdown = render_nodes(lengthen_fences(nodes), options);
+
+    if let Some(spacing) = options.list_marker_spacing {
+        markdown = space_list_markers(&markdown, spacing);
+    }
+
+    if options.table_padding {
+        markdown = pad_tables(&markdown);
+    }
+
+    markdown
+}
+
+fn render_nodes(nodes: Vec<Node>, options: &Options) -> String {
+    let mut markdown = Markdown::new(nodes);
+    markdown.set_options(options.render_options());
+    markdown.to_string()
+}
+
+/// Renders the children of an inline node or heading as they would appear inside it.
+fn render_inline(values: Vec<Node>, options: &Options) -> String {
+    render_nodes(values, options).trim_end_matches('\n').to_string()
+}
+
+/// Returns the marker of a list item line without its indentation, e.g. `-` or `1.`.
+fn list_marker(line: &str) -> Option<&str> {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let len = match line[digits..].chars().next()? {
+        '-' | '+' | '*' if digits == 0 => 1,
+        '.' | ')' if (1..=9).contains(&digits) => digits + 1,
+        _ => return None,
+    };
+
+    matches!(line[len..].chars().next(), None | Some(' ')).then(|| &line[..len])
+}
+
+/// Writes `spacing` spaces after each list marker, moving the lines nested in an item to its new
+/// content column.
+fn space_list_markers(markdown: &str, spacing: usize) -> String {
+    let literal = input::literal_lines(markdown);
+    // The indent of each open item, with its content column as rendered and as rewritten.
+    let mut items: Vec<(usize, usize, usize)> = Vec::new();
+
+    let lines = markdown
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if literal[i] || line.trim().is_empty() {
+                return line.to_string();
+            }
+
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            let rest = &line[indent..];
+
+            while items.last().is_some_and(|(item_indent, _, _)| *item_indent >= indent) {
+                items.pop();
+            }
+
+            match list_marker(rest) {
+                Some(marker) => {
+                    let new_indent = items.last().map_or(indent, |(_, _, new_content)| *new_content);
+                    let text = rest[marker.len()..].trim_start_matches(' ');
+                    items.push((indent, indent + marker.len() + 1, new_indent + marker.len() + spacing));
+
+                    if text.is_empty() {
+                        format!("{}{}", " ".repeat(new_indent), marker)
+                    } else {
+                        format!("{}{}{}{}", " ".repeat(new_indent), marker, " ".repeat(spacing), text)
+                    }
+                }
+                None => match items.last() {
+                    Some(&(_, content, new_content)) => {
+                        format!("{}{}", " ".repeat(new_content + indent.saturating_sub(content)), rest)
+                    }
+                    None => line.to_string(),
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    join_lines(lines, markdown)
+}
+
+fn join_lines(lines: Vec<String>, markdown: &str) -> String {
+    let mut joined = lines.join("\n");
+
+    if markdown.ends_with('\n') {
+        joined.push('\n');
+    }
+
+    joined
+}
+
+/// Splits a table row into its trimmed cells, at the pipes that are not escaped.
+fn table_cells(line: &str) -> Vec<String> {
+    let line = line.trim().strip_prefix('|').unwrap_or(line);
+    let mut cells = vec![String::new()];
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                cells.last_mut().unwrap().push(c);
+                cells.last_mut().unwrap().extend(chars.next());
+            }
+            '|' => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+    }
+
+    if cells.last().is_some_and(|cell| cell.trim().is_empty()) && line.trim_end().ends_with('|') {
+        cells.pop();
+    }
+
+    cells.into_iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+/// The alignment of each column of a table delimiter row as `(left, right)` colons, or `None` when
+/// `line` is not one.
+fn table_alignments(line: &str) -> Option<Vec<(bool, bool)>> {
+    table_cells(line)
+        .iter()
+        .map(|cell| {
+            let inner = cell.strip_prefix(':').unwrap_or(cell);
+            let inner = inner.strip_suffix(':').unwrap_or(inner);
+            (!inner.is_empty() && inner.chars().all(|c| c == '-')).then(|| (cell.starts_with(':'), cell.ends_with(':')))
+        })
+        .collect()
+}
+
+/// Pads the cells of every table so that its columns line up, aligned as their delimiter row says.
+fn pad_tables(markdown: &str) -> String {
+    let literal = input::literal_lines(markdown);
+    let lines = markdown.lines().collect::<Vec<_>>();
+    let is_row = |i: usize| !literal[i] && lines[i].starts_with('|');
+    let mut padded = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let alignments = (is_row(i) && i + 1 < lines.len() && is_row(i + 1))
+            .then(|| table_alignments(lines[i + 1]))
+            .flatten();
+
+        let Some(alignments) = alignments else {
+            padded.push(lines[i].to_string());
+            i += 1;
+            continue;
+        };
+
+        let end = (i + 2..lines.len()).find(|&j| !is_row(j)).unwrap_or(lines.len());
+        let rows = std::iter::once(i)
+            .chain(i + 2..end)
+            .map(|j| table_cells(lines[j]))
+            .collect::<Vec<_>>();
+        let widths = (0..alignments.len())
+            .map(|column| {
+                rows.iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.chars().count())
+                    .fold(3, usize::max)
+            })
+            .collect::<Vec<_>>();
+
+        let row = |cells: &[String]| {
+            let cells = alignments
+                .iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(column, (&(left, right), &width))| {
+                    let cell = cells.get(column).map_or("", String::as_str);
+                    let space = width - cell.chars().count();
+                    let (before, after) = match (left, right) {
+                        (true, true) => (space / 2, space - space / 2),
+                        (false, true) => (space, 0),
+                        _ => (0, space),
+                    };
+                    format!("{}{}{}", " ".repeat(before), cell, " ".repeat(after))
+                });
+            format!("| {} |", cells.collect::<Vec<_>>().join(" | "))
+        };
+
+        padded.push(row(&rows[0]));
+        padded.push(format!(
+            "| {} |",
+            alignments
+                .iter()
+                .zip(&widths)
+                .map(|(&(left, right), &width)| {
+                    let dashes = "-".repeat(width - usize::from(left) - usize::from(right));
+                    format!(
+                        "{}{}{}",
+                        if left { ":" } else { "" },
+                        dashes,
+                        if right { ":" } else { "" }
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" | ")
+        ));
+        padded.extend(rows[1..].iter().map(|cells| row(cells)));
+        i = end;
+    }
+
+    join_lines(padded, markdown)
+}
+
+/// Escapes the characters of text that markdown would otherwise read as syntax.
+fn escape_node(node: Node) -> Node {
+    match node {
+        Node::Text(mut text) => {
+            text.value = escape_text(&text.value);
+            Node::Text(text)
+        }
+        // A table cell ends at the first `|`, even inside inline code.
+        Node::TableCell(mut cell) => {
+            cell.values = transform::rewrite(cell.values, &mut |node| match node {
+                Node::CodeInline(mut code) => {
+                    code.value = code.value.replace('|', "\\|").into();
+                    vec![Node::CodeInline(code)]
+                }
+                node => vec![node],
+            });
+            Node::TableCell(cell)
+        }
+        node => node,
+    }
+}
+
+/// Escapes the characters of `text` that would otherwise be read as markdown syntax.
+pub(crate) fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut line_start = true;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if line_start && c.is_ascii_digit() {
+            escaped.push(c);
+
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                escaped.push(digit);
+            }
+
+            // `1.` and `1)` at the start of a line begin an ordered list.
+            if let Some(marker) = chars.next_if(|c| matches!(c, '.' | ')')) {
+                escaped.push('\\');
+                escaped.push(marker);
+            }
+
+            line_start = false;
+            continue;
+        }
+
+        if "\\`*_[]<>|~$&#".contains(c) || line_start && matches!(c, '-' | '+' | '=') {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+        line_start = c == '\n' || line_start && c == ' ';
+    }
+
+    escaped
+}
+
+/// Writes code with fences and backtick strings longer than any it contains, and indented code as
+/// fenced code, which would otherwise lose its indentation inside a list item.
+fn delimit_code(node: Node) -> Node {
+    match node {
+        Node::Code(code) => fence(code, '`'),
+        Node::CodeInline(code) => {
+            let ticks = "`".repeat(longest_run(&code.value, '`') + 1);
+            let value = code.value.as_str();
+            // One space is stripped from each side when both sides have one, so such values are padded.
+            let pad = value.starts_with('`')
+                || value.ends_with('`')
+                || value.starts_with(' ') && value.ends_with(' ') && !value.trim().is_empty();
+            let pad = if pad { " " } else { "" };
+
+            Node::Text(mq_markdown::Text {
+                value: format!("{}{}{}{}{}", ticks, pad, value, pad, ticks),
+                position: code.position,
+            })
+        }
+        node => node,
+    }
+}
+
+/// Writes a code block fenced with `marker`, or with `~` when `marker` is a backtick and the info
+/// string has one, using a fence longer than any run of the marker in the code.
+fn fence(code: mq_markdown::Code, marker: char) -> Node {
+    let info = format!(
+        "{}{}",
+        code.lang.as_deref().unwrap_or_default(),
+        code.meta.as_deref().map(|meta| format!(" {}", meta)).unwrap_or_default()
+    );
+    // Backtick fences cannot have backticks in their info string.
+    let marker = if info.contains('`') { '~' } else { marker };
+    let fence = marker.to_string().repeat(longest_run(&code.value, marker).max(2) + 1);
+
+    Node::Text(mq_markdown::Text {
+        value: format!("{}{}\n{}\n{}", fence, info, code.value, fence),
+        position: code.position,
+    })
+}
+
+/// Writes fenced code that contains a run of three or more backticks with a longer fence, so that
+/// the run does not close the block early.
+pub(crate) fn lengthen_fences(nodes: Vec<Node>) -> Vec<Node> {
+    transform::rewrite(nodes, &mut |node| match node {
+        Node::Code(code) if code.fence && longest_run(&code.value, '`') >= 3 => vec![fence(code, '`')],
+        node => vec![node],
+    })
+}
+
+/// Returns the length of the longest run of `c` in `s`.
+fn longest_run(s: &str, c: char) -> usize {
+    s.split(|ch| ch != c).map(str::len).max().unwrap_or(0)
+}
+
+/// Applies the whitespace settings of `options` to rendered markdown: trailing whitespace, runs of
+/// blank lines and the final newline. Code, math, raw HTML blocks and front matter are kept as is.
+pub(crate) fn tidy(markdown: String, options: &Options) -> String {
+    if !options.trim_trailing_whitespace && options.max_blank_lines.is_none() && !options.final_newline {
+        return markdown;
+    }
+
+    let literal = input::literal_lines(&markdown);
+    let mut lines: Vec<&str> = Vec::new();
+    let mut blank_lines = 0;
+
+    for (i, line) in markdown.lines().enumerate() {
+        if literal[i] {
+            blank_lines = 0;
+            lines.push(line);
+            continue;
+        }
+
+        let mut line = line;
+
+        if options.trim_trailing_whitespace {
+            let trimmed = line.trim_end_matches([' ', '\t']);
+
+            // Exactly two trailing spaces after text are a hard line break.
+            let hard_break = &line[trimmed.len()..] == "  " && !trimmed.trim().is_empty();
+
+            if !hard_break {
+                line = trimmed;
+            }
+        }
+
+        if line.trim().is_empty() {
+            blank_lines += 1;
+
+            if options.max_blank_lines.is_some_and(|max| blank_lines > max) {
+                continue;
+            }
+        } else {
+            blank_lines = 0;
+        }
+
+        lines.push(line);
+    }
+
+    let mut tidied = lines.join("\n");
+
+    if options.final_newline {
+        let len = tidied.trim_end_matches(['\n', '\r', ' ', '\t']).len();
+        tidied.truncate(len);
+
+        if !tidied.is_empty() {
+            tidied.push('\n');
+        }
+    } else if markdown.ends_with('\n') {
+        tidied.push('\n');
+    }
+
+    tidied
+}
+
+/// Applies the render settings of `options` that rewrite nodes rather than change how they are
+/// written: the math style, URL encoding, link title handling and the styles mq-markdown has no
+/// setting for, which are written as text.
+pub(crate) fn apply_options(nodes: Vec<Node>, options: &Options) -> Vec<Node> {
+    if options.math_style.is_none()
+        && !options.percent_encode_urls
+        && options.link_titles.is_none()
+        && options.heading_style.is_none()
+        && options.emphasis_style.is_none()
+        && options.strong_style.is_none()
+        && options.code_fence_style.is_none()
+        && options.hard_break_style.is_none()
+    {
+        return nodes;
+    }
+
+    let url = |url: String| {
+        if options.percent_encode_urls {
+            percent_encode(&url)
+        } else {
+            url
+        }
+    };
+    let title = |title: Option<String>| match options.link_titles {
+        Some(LinkTitlePolicy::Strip) => None,
+        Some(LinkTitlePolicy::Normalize) => title
+            .map(|title| normalize_title(&title, options.link_title_style.unwrap_or_default()))
+            .filter(|title| !title.is_empty()),
+        None => title,
+    };
+
+    transform::rewrite(nodes, &mut |node| match (options.math_style, node) {
+        (Some(MathStyle::Fenced), Node::Math(math)) => vec![Node::Code(mq_markdown::Code {
+            value: math.value,
+            lang: Some("math".to_string()),
+            position: math.position,
+            meta: None,
+            fence: true,
+        })],
+        (Some(MathStyle::Dollar), Node::Code(code)) if code.lang.as_deref() == Some("math") => {
+            vec![Node::Math(mq_markdown::Math {
+                value: code.value,
+                position: code.position,
+            })]
+        }
+        (_, Node::Heading(heading))
+            if options.heading_style == Some(HeadingStyle::Setext) && matches!(heading.depth, 1 | 2) =>
+        {
+            let text = render_inline(heading.values.clone(), options);
+
+            if text.is_empty() || text.contains('\n') {
+                return vec![Node::Heading(heading)];
+            }
+
+            let underline = if heading.depth == 1 { "=" } else { "-" };
+            vec![Node::Text(mq_markdown::Text {
+                value: format!("{}\n{}", text, underline.repeat(text.chars().count())),
+                position: heading.position,
+            })]
+        }
+        (_, Node::Emphasis(emphasis)) if options.emphasis_style == Some(EmphasisStyle::Underscore) => {
+            vec![Node::Text(mq_markdown::Text {
+                value: format!("_{}_", render_inline(emphasis.values, options)),
+                position: emphasis.position,
+            })]
+        }
+        (_, Node::Strong(strong)) if options.strong_style == Some(EmphasisStyle::Underscore) => {
+            vec![Node::Text(mq_markdown::Text {
+                value: format!("__{}__", render_inline(strong.values, options)),
+                position: strong.position,
+            })]
+        }
+        (_, Node::Code(code)) if code.fence && options.code_fence_style.is_some() => {
+            let marker = match options.code_fence_style {
+                Some(CodeFenceStyle::Tilde) => '~',
+                _ => '`',
+            };
+            vec![fence(code, marker)]
+        }
+        (_, Node::Break(line_break)) if options.hard_break_style.is_some() => {
+            // The break ends on the next line, so the line ending is written here.
+            let value = match options.hard_break_style {
+                Some(HardBreakStyle::Spaces) => "  \n",
+                _ => "\\\n",
+            };
+            vec![Node::Text(mq_markdown::Text {
+                value: value.to_string(),
+                position: line_break.position,
+            })]
+        }
+        (_, Node::Link(mut link)) => {
+            link.url = mq_markdown::Url::new(url(link.url.as_str().to_string()));
+            link.title = title(link.title.map(|title| title.to_value())).map(mq_markdown::Title::new);
+            vec![Node::Link(link)]
+        }
+        (_, Node::Image(mut image)) => {
+            image.url = url(image.url);
+            image.title = title(image.title);
+            vec![Node::Image(image)]
+        }
+        (_, Node::Definition(mut definition)) => {
+            definition.url = mq_markdown::Url::new(url(definition.url.as_str().to_string()));
+            definition.title = title(definition.title.map(|title| title.to_value())).map(mq_markdown::Title::new);
+            vec![Node::Definition(definition)]
+        }
+        (_, node) => vec![node],
+    })
+}
+
+/// Percent-encodes the characters of `url` that are not allowed unescaped in a link destination.
+///
+/// Existing `%XX` escapes are kept, so encoding an encoded URL does not change it.
+fn percent_encode(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut encoded = String::new();
+
+    for (i, byte) in bytes.iter().enumerate() {
+        let escape = *byte == b'%' && bytes.get(i + 1..i + 3).is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+
+        if escape || byte.is_ascii_graphic() && !b"<>\"`{}|\\^()".contains(byte) && *byte != b'%' {
+            encoded.push(*byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    encoded
+}
+
+/// Collapses whitespace in a link title and escapes the quotes that would end it in `style`.
+fn normalize_title(title: &str, style: TitleSurroundStyle) -> String {
+    let delimiters: &[char] = match style {
+        TitleSurroundStyle::Double => &['"'],
+        TitleSurroundStyle::Single => &['\''],
+        TitleSurroundStyle::PAREN => &['(', ')'],
+    };
+
+    title.split_whitespace().collect::<Vec<_>>().join(" ").chars().fold(String::new(), |mut out, c| {
+        if c == '\\' || delimiters.contains(&c) {
+            out.push('\\');
+        }
+
+        out.push(c);
+        out
+    })
+}
+
+fn apply_with(py: Python<'_>, mut node: Node, renderers: &[(MarkdownType, Py<PyAny>)]) -> PyResult<Node> {
+    let markdown_type = MarkdownType::from(node.clone());
+
+    if let Some((_, callback)) = renderers.iter().find(|(t, _)| *t == markdown_type) {
+        let rendered = callback.call1(py, (nodes::to_py(py, node.clone())?,))?;
+        let rendered = rendered.bind(py);
+
+        if let Ok(replacement) = rendered.cast::<nodes::Node>() {
+            return Ok(replacement.borrow().node.clone());
+        }
+
+        if !rendered.is_none() {
+            // Text nodes are rendered verbatim, so the callback output is emitted as-is.
+            return Ok(Node::Text(mq_markdown::Text {
+                value: rendered.extract()?,
+                position: node.position(),
+            }));
+        }
+    }
+
+    let children = node.children();
+
+    if !children.is_empty() {
+        node.set_children(
+            children
+                .into_iter()
+                .map(|child| apply_with(py, child, renderers))
+                .collect::<PyResult<Vec<_>>>()?,
+        );
+    }
+
+    Ok(node)
+}
@@ -1,10 +1,95 @@
-use crate::value::MQValue;
+use crate::{
+    Options,
+    content::Content,
+    provenance, render, section, serialize,
+    table::{self, Table},
+    value::{MQValue, MarkdownNode, MarkdownType},
+};
 
-use pyo3::prelude::*;
+use indexmap::IndexMap;
+use mq_markdown::Node;
+use pyo3::{
+    IntoPyObjectExt,
+    exceptions::PyValueError,
+    prelude::*,
+    types::{PyDict, PyIterator, PyList, PySet, PySlice, PyTuple},
+};
+use std::{cmp::Ordering, collections::HashSet};
 
-#[pyclass]
+/// A key to compare values by: the name of a built-in key or a Python callable.
+#[derive(FromPyObject)]
+pub enum Key<'py> {
+    Name(String),
+    Callable(Bound<'py, PyAny>),
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Position(usize, usize),
+    Text(String),
+}
+
+/// Returns the named key of `value`, or `None` when the value has no such key, e.g. no URL.
+fn named_key(value: &MQValue, name: &str) -> PyResult<Option<String>> {
+    match name {
+        "markdown" => Ok(Some(value.text())),
+        "text" => Ok(Some(match value.as_node() {
+            Some(node) => section::plain_text(&node),
+            None => value.text(),
+        })),
+        "position" => Ok(value
+            .as_node()
+            .and_then(|node| node.position())
+            .map(|position| format!("{}:{}", position.start.line, position.start.column))),
+        "url" => Ok(value.url()),
+        "domain" => Ok(value.url().and_then(|url| domain(&url))),
+        "type" => Ok(value.markdown_type().map(|markdown_type| format!("{:?}", markdown_type))),
+        "level" => Ok(match value.as_node() {
+            Some(Node::Heading(heading)) => Some(heading.depth.to_string()),
+            _ => None,
+        }),
+        name => Err(PyValueError::new_err(format!(
+            "Unknown key '{}', expected one of 'markdown', 'text', 'url', 'domain', 'type', 'level', 'position' \
+             or a callable",
+            name
+        ))),
+    }
+}
+
+/// Returns the lowercased host of an absolute URL, without credentials or port.
+pub(crate) fn domain(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => host,
+    };
+
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+#[pyclass(skip_from_py_object)]
+#[derive(Clone)]
 pub struct MQResult {
     pub values: Vec<MQValue>,
+    /// Non-fatal issues found while parsing the input and converting the results.
+    #[pyo3(get)]
+    pub warnings: Vec<String>,
+    /// The name of the document the query ran against, if one was given.
+    #[pyo3(get)]
+    pub source: Option<String>,
+    /// The seconds spent in each phase of the query, when `Options.collect_timings` is set.
+    #[pyo3(get)]
+    pub timings: Option<IndexMap<String, f64>>,
+    /// The stages of the query's pipeline, when `Options.debug` is set.
+    #[pyo3(get)]
+    pub(crate) trace: Option<Vec<provenance::TraceStep>>,
+    /// The most values shown in notebooks, from `Options.display_limit`.
+    #[pyo3(get, set)]
+    pub(crate) display_limit: Option<usize>,
+    /// The options the query ran with, which `render` uses when given none.
+    pub(crate) options: Option<Options>,
 }
 
 #[pymethods]
@@ -16,10 +101,318 @@ impl MQResult {
 
     #[getter]
     pub fn values(&self) -> Vec<String> {
-        self.values
-            .iter()
-            .filter_map(|value| if value.__len__() == 0 { None } else { Some(value.text()) })
-            .collect::<Vec<String>>()
+        self.non_empty().map(MQValue::text).collect::<Vec<String>>()
+    }
+
+    /// Groups the non-empty values by the heading path of the section they appear in.
+    ///
+    /// Headings are grouped under their own section rather than their parent's.
+    pub fn by_section<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let sections = PyDict::new(py);
+
+        for value in self.non_empty() {
+            let mut path = value.section_path();
+
+            if let Some(heading @ mq_markdown::Node::Heading(_)) = value.as_node() {
+                path.push(section::plain_text(&heading));
+            }
+
+            let key = PyTuple::new(py, path)?;
+
+            match sections.get_item(&key)? {
+                Some(values) => values.cast::<PyList>()?.append(value.clone())?,
+                None => sections.set_item(key, PyList::new(py, [value.clone()])?)?,
+            }
+        }
+
+        Ok(sections)
+    }
+
+    /// Returns a result with the first `n` non-empty values.
+    pub fn take(&self, n: usize) -> MQResult {
+        self.window(0, n)
+    }
+
+    /// Returns a result without the first `n` non-empty values.
+    pub fn skip(&self, n: usize) -> MQResult {
+        self.window(n, self.values.len())
+    }
+
+    /// Returns a result with at most `size` non-empty values, starting at the `start`-th one.
+    pub fn window(&self, start: usize, size: usize) -> MQResult {
+        self.with_values(self.non_empty().skip(start).take(size).cloned().collect())
+    }
+
+    /// Returns a result with the non-empty values, dropping those whose key was already seen.
+    ///
+    /// Values are compared by their markdown unless `key` names another key or is a callable.
+    /// With `key="url"`, values without a URL are always kept.
+    #[pyo3(signature = (key=None))]
+    pub fn unique(&self, py: Python<'_>, key: Option<Key<'_>>) -> PyResult<MQResult> {
+        let mut seen = HashSet::new();
+        let seen_objects = PySet::empty(py)?;
+        let mut values = Vec::new();
+
+        for value in self.non_empty() {
+            let keep = match &key {
+                None => seen.insert(value.text()),
+                Some(Key::Name(name)) => named_key(value, name)?.is_none_or(|key| seen.insert(key)),
+                Some(Key::Callable(f)) => {
+                    let key = f.call1((value.clone(),))?;
+
+                    if seen_objects.contains(&key)? {
+                        false
+                    } else {
+                        seen_objects.add(key)?;
+                        true
+                    }
+                }
+            };
+
+            if keep {
+                values.push(value.clone());
+            }
+        }
+
+        Ok(self.with_values(values))
+    }
+
+    /// Returns a result with the non-empty values sorted by `key`.
+    ///
+    /// `key` is `"position"` for document order, a named key as for `unique`, or a callable. Values
+    /// without the key, e.g. without a position, sort last. The sort is stable.
+    #[pyo3(signature = (key=Key::Name("markdown".to_string()), reverse=false))]
+    pub fn sorted(&self, py: Python<'_>, key: Key<'_>, reverse: bool) -> PyResult<MQResult> {
+        let values = self.non_empty().cloned().collect::<Vec<_>>();
+
+        let name = match key {
+            Key::Name(name) => name,
+            Key::Callable(f) => {
+                let kwargs = PyDict::new(py);
+                kwargs.set_item("key", f)?;
+                kwargs.set_item("reverse", reverse)?;
+
+                let sorted = py
+                    .import("builtins")?
+                    .getattr("sorted")?
+                    .call((PyList::new(py, values)?,), Some(&kwargs))?;
+
+                return Ok(self.with_values(sorted.extract()?));
+            }
+        };
+
+        let mut keyed = values
+            .into_iter()
+            .map(|value| {
+                let key = match name.as_str() {
+                    "position" => value
+                        .as_node()
+                        .and_then(|node| node.position())
+                        .map(|position| SortKey::Position(position.start.line, position.start.column)),
+                    name => named_key(&value, name)?.map(SortKey::Text),
+                };
+
+                Ok((key, value))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+
+        keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Some(a), Some(b)) if reverse => b.cmp(a),
+            (Some(a), Some(b)) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+
+        Ok(self.with_values(keyed.into_iter().map(|(_, value)| value).collect()))
+    }
+
+    /// Counts the non-empty values by `key`, in order of first appearance.
+    ///
+    /// `key` is a named key as for `unique` or a callable; values without the key are not
+    /// counted. Heading levels are counted as integers.
+    pub fn count_by<'py>(&self, py: Python<'py>, key: Key<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let counts = PyDict::new(py);
+
+        for value in self.non_empty() {
+            let key = match &key {
+                Key::Name(name) => match named_key(value, name)? {
+                    Some(key) if name == "level" => key.parse::<u8>()?.into_bound_py_any(py)?,
+                    Some(key) => key.into_bound_py_any(py)?,
+                    None => continue,
+                },
+                Key::Callable(f) => f.call1((value.clone(),))?,
+            };
+
+            let count = match counts.get_item(&key)? {
+                Some(count) => count.extract::<usize>()? + 1,
+                None => 1,
+            };
+
+            counts.set_item(key, count)?;
+        }
+
+        Ok(counts)
+    }
+
+    /// Renders the non-empty values as one markdown document with the given style options.
+    ///
+    /// The stored nodes are re-rendered, so the query does not need to run again.
+    #[pyo3(signature = (options=None))]
+    pub(crate) fn to_markdown(&self, options: Option<Options>) -> PyResult<String> {
+        render::to_markdown(self.markdown_nodes()?, options)
+    }
+
+    /// Renders the non-empty values in the `output_format` of `options`, by default the options the
+    /// query ran with.
+    #[pyo3(signature = (options=None))]
+    pub(crate) fn render(&self, options: Option<Options>) -> PyResult<String> {
+        render::to_output(self.markdown_nodes()?, options.or_else(|| self.options.clone()))
+    }
+
+    /// Renders the non-empty values like `to_markdown`, escaped so that the output can be embedded
+    /// in another markdown document, e.g. in a blockquote, a list item or a table cell.
+    #[pyo3(signature = (options=None))]
+    pub(crate) fn to_commonmark_strict(&self, options: Option<Options>) -> PyResult<String> {
+        render::to_commonmark_strict(self.markdown_nodes()?, options)
+    }
+
+    /// Returns a stable plain-text form of the non-empty values for snapshot tests.
+    ///
+    /// Each value is written as `[index] Type: text`, arrays and dicts are expanded one item per
+    /// line and dict keys are sorted, so the output does not depend on `__repr__` or key order.
+    pub fn to_test_string(&self) -> String {
+        let mut out = String::new();
+
+        for (i, value) in self.non_empty().enumerate() {
+            out.push_str(&format!("[{}] ", i));
+            value.write_test_string(&mut out, 2);
+        }
+
+        out
+    }
+
+    /// Converts the non-empty values into plain Python objects, as `MQValue.to_python` does.
+    pub fn to_python<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        self.non_empty().map(|value| value.to_python(py)).collect()
+    }
+
+    /// Returns a flat dict for each non-empty value, e.g. for `pandas.DataFrame(result.to_records())`.
+    ///
+    /// Every record has the same keys, with `None` where a key does not apply to the value: `text`,
+    /// `type`, `level`, `url`, `title`, `lang`, `line`, `column`, `end_line`, `end_column`,
+    /// `section_path` and `source`.
+    pub fn to_records<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.non_empty()
+            .map(|value| {
+                let record = PyDict::new(py);
+                let position = value.as_node().and_then(|node| node.position());
+
+                record.set_item("text", value.text())?;
+                record.set_item("type", value.type_name())?;
+                record.set_item("level", value.level())?;
+                record.set_item("url", value.url())?;
+                record.set_item("title", value.title())?;
+                record.set_item("lang", value.lang())?;
+                record.set_item("line", position.as_ref().map(|p| p.start.line))?;
+                record.set_item("column", position.as_ref().map(|p| p.start.column))?;
+                record.set_item("end_line", position.as_ref().map(|p| p.end.line))?;
+                record.set_item("end_column", position.as_ref().map(|p| p.end.column))?;
+                record.set_item("section_path", value.section_path())?;
+                record.set_item("source", value.source())?;
+                Ok(record)
+            })
+            .collect()
+    }
+
+    /// Serializes the result to JSON, e.g. to cache a parsed document between pipeline stages.
+    ///
+    /// Nodes, annotations, warnings and the source are kept; timings are not.
+    pub fn dumps(&self) -> PyResult<String> {
+        serialize::dumps(self)
+    }
+
+    /// Serializes the result to JSON, the same as `dumps`.
+    pub fn to_json(&self) -> PyResult<String> {
+        serialize::dumps(self)
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, (String,))> {
+        serialize::reduce(py, self.to_json()?)
+    }
+
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone()
+    }
+
+    /// Reads a result written by `dumps`.
+    #[staticmethod]
+    pub(crate) fn loads(data: Content<'_>) -> PyResult<MQResult> {
+        serialize::loads(data)
+    }
+
+    /// Returns the rows of a table keyed by the header cells' text, from the cells of one table as
+    /// selected by `.[]` or `.table`, or from a single table value. `mq.extract_tables` reads every
+    /// table of a document.
+    pub fn to_table(&self) -> PyResult<Vec<table::Record>> {
+        let values = self.values.iter().filter(|value| value.__bool__()).collect::<Vec<_>>();
+
+        if let [value] = values[..]
+            && let Some(table) = Table::from_value(value)
+        {
+            return Ok(table.records());
+        }
+
+        let mut tables = HashSet::new();
+        let mut cells = Vec::new();
+
+        for value in values {
+            match value.as_node() {
+                Some(Node::TableCell(cell)) => {
+                    tables.insert(value.table_index());
+                    cells.push(cell);
+                }
+                Some(Node::TableAlign(_)) => {}
+                _ => {
+                    return Err(PyValueError::new_err(format!(
+                        "to_table() requires table cells or a single table value, got {}",
+                        value.__repr__()
+                    )));
+                }
+            }
+        }
+
+        if tables.len() > 1 {
+            return Err(PyValueError::new_err(format!(
+                "to_table() got cells of {} tables, use mq.extract_tables() for more than one",
+                tables.len()
+            )));
+        }
+
+        Ok(table::records(cells))
+    }
+
+    /// Returns a result without empty values, with adjacent text values merged into one.
+    ///
+    /// Text values are merged when they were built by the query or follow each other without a gap
+    /// in the document, so separate paragraphs stay apart. Arrays and dicts are compacted too, and
+    /// dropped when nothing is left in them. The other methods skip empty values on their own;
+    /// `compact` makes that explicit for indexing and iteration.
+    pub fn compact(&self) -> MQResult {
+        self.with_values(compact(self.values.clone()))
+    }
+
+    /// Returns `True` when no value is non-empty, e.g. when a query matched nothing.
+    pub fn is_empty(&self) -> bool {
+        !self.values.iter().any(|value| value.__bool__())
+    }
+
+    fn __bool__(&self) -> bool {
+        !self.is_empty()
     }
 
     pub fn __len__(&self) -> usize {
@@ -30,18 +423,21 @@ impl MQResult {
         Ok(self.values.iter().any(|v| v == value))
     }
 
-    pub fn __getitem__(&self, idx: usize) -> PyResult<MQValue> {
-        if idx < self.values.len() {
-            Ok(self.values[idx].clone())
-        } else {
-            Err(pyo3::exceptions::PyIndexError::new_err(format!(
-                "Index {} out of range for MQResult with length {}",
-                idx,
-                self.values.len()
-            )))
+    /// Returns the value at `index`, counting from the end when negative, or a result with the
+    /// values of a slice.
+    pub(crate) fn __getitem__<'py>(&self, py: Python<'py>, index: Index<'py>) -> PyResult<Bound<'py, PyAny>> {
+        match index {
+            Index::Int(i) => self.values[position(i, self.values.len(), "MQResult")?]
+                .clone()
+                .into_bound_py_any(py),
+            Index::Slice(slice) => self.with_values(slice_values(&self.values, &slice)?).into_bound_py_any(py),
         }
     }
 
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        PyList::new(py, self.values.clone())?.try_iter()
+    }
+
     fn __repr__(&self) -> String {
         format!("MQResult({} items)", self.values.len())
     }
@@ -50,6 +446,26 @@ impl MQResult {
         self.text()
     }
 
+    /// Renders the first `display_limit` non-empty values as markdown for Jupyter, followed by how
+    /// many were left out.
+    fn _repr_markdown_(&self) -> String {
+        let total = self.non_empty().count();
+        let shown = self.window(0, self.display_limit.unwrap_or(total));
+        let markdown = shown.to_markdown(None).unwrap_or_else(|_| shown.text());
+        let hidden = total - shown.values.len();
+
+        match hidden {
+            0 => markdown,
+            1 => format!("{}\n\n*… 1 more value*\n", markdown.trim_end()),
+            hidden => format!("{}\n\n*… {} more values*\n", markdown.trim_end(), hidden),
+        }
+    }
+
+    /// Renders `_repr_markdown_` as HTML for Jupyter, with raw HTML shown as text.
+    fn _repr_html_(&self) -> String {
+        crate::render::to_display_html(&self._repr_markdown_())
+    }
+
     fn __eq__(&self, other: &Self) -> bool {
         if self.values.len() != other.values.len() {
             return false;
@@ -79,8 +495,149 @@ impl MQResult {
     }
 }
 
+impl MQResult {
+    fn non_empty(&self) -> impl Iterator<Item = &MQValue> {
+        self.values.iter().filter(|value| value.__len__() > 0)
+    }
+
+    fn markdown_nodes(&self) -> PyResult<Vec<Node>> {
+        let nodes = self
+            .non_empty()
+            .map(MQValue::markdown_nodes)
+            .collect::<PyResult<Vec<_>>>()?;
+
+        Ok(nodes.into_iter().flatten().collect())
+    }
+
+    /// Records `name` as the source document of the result and its values.
+    pub(crate) fn set_source(&mut self, name: &str) {
+        self.source = Some(name.to_string());
+        self.values.iter_mut().for_each(|value| value.set_source(name));
+    }
+
+    /// Returns a result with `values` and the warnings of this one.
+    pub(crate) fn with_values(&self, values: Vec<MQValue>) -> MQResult {
+        MQResult {
+            values,
+            warnings: self.warnings.clone(),
+            source: self.source.clone(),
+            timings: self.timings.clone(),
+            trace: self.trace.clone(),
+            display_limit: self.display_limit,
+            options: self.options.clone(),
+        }
+    }
+}
+
+/// An index into a result or an array value: an integer, negative to count from the end, or a slice.
+#[derive(FromPyObject)]
+pub(crate) enum Index<'py> {
+    Int(isize),
+    Slice(Bound<'py, PySlice>),
+}
+
+/// Resolves `index` to a position in a sequence of `len` values, counting from the end when negative.
+pub(crate) fn position(index: isize, len: usize, name: &str) -> PyResult<usize> {
+    let resolved = if index < 0 { index + len as isize } else { index };
+
+    if (0..len as isize).contains(&resolved) {
+        Ok(resolved as usize)
+    } else {
+        Err(pyo3::exceptions::PyIndexError::new_err(format!(
+            "Index {} out of range for {} with length {}",
+            index, name, len
+        )))
+    }
+}
+
+pub(crate) fn slice_values(values: &[MQValue], slice: &Bound<'_, PySlice>) -> PyResult<Vec<MQValue>> {
+    let indices = slice.indices(values.len() as isize)?;
+
+    Ok((0..indices.slicelength)
+        .map(|i| values[(indices.start + i as isize * indices.step) as usize].clone())
+        .collect())
+}
+
+/// Returns whether `next` continues the text of `node`: both were built by the query, or `next`
+/// starts where `node` ends in the document.
+fn adjacent(node: &MarkdownNode, next: &MarkdownNode) -> bool {
+    let position = |node: &MarkdownNode| node.0.as_deref().and_then(Node::position);
+
+    match (position(node), position(next)) {
+        (Some(position), Some(next)) => position.end == next.start,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn compact(values: Vec<MQValue>) -> Vec<MQValue> {
+    let mut compacted: Vec<MQValue> = Vec::with_capacity(values.len());
+
+    for value in values {
+        let value = match value {
+            MQValue::Array { value } => MQValue::Array { value: compact(value) },
+            MQValue::Dict { value } => MQValue::Dict {
+                value: value
+                    .into_iter()
+                    .filter_map(|(key, value)| compact(vec![value]).pop().map(|value| (key, value)))
+                    .collect(),
+            },
+            value => value,
+        };
+
+        if value.__len__() == 0 {
+            continue;
+        }
+
+        match (compacted.last_mut(), value) {
+            (
+                Some(MQValue::Markdown {
+                    value: text,
+                    markdown_type: MarkdownType::Text,
+                    node,
+                    ..
+                }),
+                MQValue::Markdown {
+                    value: next,
+                    markdown_type: MarkdownType::Text,
+                    node: next_node,
+                    ..
+                },
+            ) if adjacent(node, &next_node) => {
+                text.push_str(&next);
+
+                let position = node
+                    .0
+                    .as_deref()
+                    .and_then(Node::position)
+                    .zip(next_node.0.as_deref().and_then(Node::position));
+
+                *node = MarkdownNode(Some(Box::new(Node::Text(mq_markdown::Text {
+                    value: text.clone(),
+                    position: position.map(|(position, next)| mq_markdown::Position {
+                        start: position.start,
+                        end: next.end,
+                    }),
+                }))));
+            }
+            (Some(MQValue::String { value: text, .. }), MQValue::String { value: next, .. }) => text.push_str(&next),
+            (_, value) => compacted.push(value),
+        }
+    }
+
+    compacted
+}
+
 impl From<Vec<MQValue>> for MQResult {
     fn from(values: Vec<MQValue>) -> Self {
-        Self { values }
+        Self {
+            values,
+            warnings: Vec::new(),
+            source: None,
+            timings: None,
+            trace: None,
+            display_limit: None,
+            options: None,
+        }
     }
 }
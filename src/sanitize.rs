@@ -0,0 +1,300 @@
+//! Removing or escaping dangerous raw HTML inside markdown documents.
+use crate::{Options, content::Content, render, transform};
+use mq_markdown::Node;
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+/// Elements that can run scripts, embed other documents or change how the page loads.
+const DANGEROUS_ELEMENTS: [&str; 11] = [
+    "script", "style", "iframe", "frame", "frameset", "object", "embed", "applet", "base", "meta", "link",
+];
+
+/// Dangerous elements without a closing tag.
+const VOID_ELEMENTS: [&str; 4] = ["embed", "base", "meta", "link"];
+
+const URL_ATTRIBUTES: [&str; 5] = ["href", "src", "action", "formaction", "xlink:href"];
+
+const UNSAFE_SCHEMES: [&str; 3] = ["javascript:", "vbscript:", "data:text/html"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Policy {
+    Strip,
+    Escape,
+}
+
+pub(crate) struct Tag {
+    pub(crate) name: String,
+    pub(crate) closing: bool,
+    pub(crate) self_closing: bool,
+    /// The byte ranges of unsafe attributes, including their leading whitespace.
+    unsafe_attributes: Vec<(usize, usize)>,
+    pub(crate) len: usize,
+}
+
+/// Removes or escapes dangerous raw HTML in a markdown document: script-like elements such as
+/// `<script>` and `<iframe>`, event handler attributes and `javascript:` URLs.
+///
+/// `policy="strip"` removes dangerous elements with their content and drops unsafe attributes;
+/// `policy="escape"` escapes the offending tags so they display as text. A document without
+/// dangerous HTML is returned unchanged.
+#[pyfunction]
+#[pyo3(signature = (content, policy="strip", options=None))]
+pub(crate) fn sanitize(content: Content<'_>, policy: &str, options: Option<Options>) -> PyResult<String> {
+    let policy = match policy {
+        "strip" => Policy::Strip,
+        "escape" => Policy::Escape,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "invalid sanitize policy {:?}, expected 'strip' or 'escape'",
+                policy
+            )));
+        }
+    };
+
+    let mut changed = false;
+    let nodes = sanitize_nodes(transform::parse(&content)?, policy, &mut changed);
+
+    if !changed {
+        return Ok(content.as_str()?.to_string());
+    }
+
+    render::to_markdown(nodes, options)
+}
+
+fn sanitize_nodes(nodes: Vec<Node>, policy: Policy, changed: &mut bool) -> Vec<Node> {
+    // The dangerous element being stripped, whose content may span sibling nodes of inline HTML.
+    let mut skip = None;
+    let mut sanitized = Vec::new();
+
+    for mut node in nodes {
+        match node {
+            Node::Html(html) => {
+                let mut value = sanitize_html(&html.value, policy, &mut skip);
+
+                if value != html.value {
+                    *changed = true;
+
+                    // A blank line would end the HTML block, so drop the lines emptied by stripping.
+                    if !html.value.lines().any(|line| line.trim().is_empty()) {
+                        value = value
+                            .lines()
+                            .filter(|line| !line.trim().is_empty())
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                    }
+                }
+
+                if !value.is_empty() {
+                    sanitized.push(Node::Html(mq_markdown::Html {
+                        value,
+                        position: html.position,
+                    }));
+                }
+            }
+            _ if skip.is_some() => *changed = true,
+            _ => {
+                let children = node.children();
+
+                if !children.is_empty() {
+                    node.set_children(sanitize_nodes(children, policy, changed));
+                }
+
+                sanitized.push(node);
+            }
+        }
+    }
+
+    sanitized
+}
+
+fn sanitize_html(html: &str, policy: Policy, skip: &mut Option<String>) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if skip.is_none() {
+            out.push_str(&rest[..start]);
+        }
+
+        rest = &rest[start..];
+
+        let Some(tag) = parse_tag(rest) else {
+            if skip.is_none() {
+                out.push('<');
+            }
+
+            rest = &rest[1..];
+            continue;
+        };
+        let text = &rest[..tag.len];
+        rest = &rest[tag.len..];
+
+        if let Some(name) = skip {
+            if tag.closing && tag.name == *name {
+                *skip = None;
+            }
+
+            continue;
+        }
+
+        if DANGEROUS_ELEMENTS.contains(&tag.name.as_str()) {
+            match policy {
+                Policy::Strip if !tag.closing && !tag.self_closing && !VOID_ELEMENTS.contains(&tag.name.as_str()) => {
+                    *skip = Some(tag.name)
+                }
+                Policy::Strip => {}
+                Policy::Escape => out.push_str(&escape(text)),
+            }
+        } else if tag.unsafe_attributes.is_empty() {
+            out.push_str(text);
+        } else {
+            match policy {
+                Policy::Strip => {
+                    let mut end = 0;
+
+                    for (from, to) in &tag.unsafe_attributes {
+                        out.push_str(&text[end..*from]);
+                        end = *to;
+                    }
+
+                    out.push_str(&text[end..]);
+                }
+                Policy::Escape => out.push_str(&escape(text)),
+            }
+        }
+    }
+
+    if skip.is_none() {
+        out.push_str(rest);
+    }
+
+    out
+}
+
+/// Parses the tag at the start of `html`, or returns `None` for anything else, e.g. a comment.
+pub(crate) fn parse_tag(html: &str) -> Option<Tag> {
+    let bytes = html.as_bytes();
+    let mut i = 1;
+    let closing = bytes.get(i) == Some(&b'/');
+
+    if closing {
+        i += 1;
+    }
+
+    if !bytes.get(i)?.is_ascii_alphabetic() {
+        return None;
+    }
+
+    let name_start = i;
+
+    while bytes.get(i).is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'-') {
+        i += 1;
+    }
+
+    let name = html[name_start..i].to_ascii_lowercase();
+    let mut unsafe_attributes = Vec::new();
+
+    loop {
+        let attribute_start = i;
+
+        while bytes.get(i)?.is_ascii_whitespace() {
+            i += 1;
+        }
+
+        match bytes.get(i)? {
+            b'>' => {
+                return Some(Tag {
+                    name,
+                    closing,
+                    self_closing: false,
+                    unsafe_attributes,
+                    len: i + 1,
+                });
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'>') => {
+                return Some(Tag {
+                    name,
+                    closing,
+                    self_closing: true,
+                    unsafe_attributes,
+                    len: i + 2,
+                });
+            }
+            _ => {}
+        }
+
+        let key_start = i;
+
+        while bytes
+            .get(i)
+            .is_some_and(|b| !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/'))
+        {
+            i += 1;
+        }
+
+        // A stray '/' inside the tag.
+        if i == key_start {
+            i += 1;
+            continue;
+        }
+
+        let key = html[key_start..i].to_ascii_lowercase();
+        let mut j = i;
+
+        while bytes.get(j).is_some_and(u8::is_ascii_whitespace) {
+            j += 1;
+        }
+
+        let mut value = None;
+
+        if bytes.get(j) == Some(&b'=') {
+            j += 1;
+
+            while bytes.get(j)?.is_ascii_whitespace() {
+                j += 1;
+            }
+
+            let value_start = j;
+
+            match bytes.get(j)? {
+                quote @ (b'"' | b'\'') => {
+                    j += 1 + html[j + 1..].find(*quote as char)?;
+                    value = Some(&html[value_start + 1..j]);
+                    j += 1;
+                }
+                _ => {
+                    while bytes.get(j).is_some_and(|b| !b.is_ascii_whitespace() && *b != b'>') {
+                        j += 1;
+                    }
+
+                    value = Some(&html[value_start..j]);
+                }
+            }
+
+            i = j;
+        }
+
+        if is_unsafe_attribute(&key, value) {
+            unsafe_attributes.push((attribute_start, i));
+        }
+    }
+}
+
+fn is_unsafe_attribute(key: &str, value: Option<&str>) -> bool {
+    if key.starts_with("on") {
+        return true;
+    }
+
+    // Browsers ignore whitespace and control characters inside the scheme.
+    let value = value
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    URL_ATTRIBUTES.contains(&key) && UNSAFE_SCHEMES.iter().any(|scheme| value.starts_with(scheme))
+}
+
+pub(crate) fn escape(html: &str) -> String {
+    html.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
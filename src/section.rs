@@ -0,0 +1,271 @@
+//! The heading sections of a document: the outline used to place query results in their sections,
+//! and reading and splicing whole sections.
+use crate::{Options, anchor::Slugger, content::Content, input, value::MQValue};
+use mq_lang::RuntimeValue;
+use mq_markdown::Node;
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+/// The headings of a document in source order as `(line, depth, text)`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Outline(Vec<(usize, u8, String)>);
+
+impl Outline {
+    pub(crate) fn new(input: &[RuntimeValue]) -> Self {
+        Self(
+            input
+                .iter()
+                .filter_map(|value| match value {
+                    RuntimeValue::Markdown(node, _) => match node.as_ref() {
+                        Node::Heading(heading) => heading
+                            .position
+                            .as_ref()
+                            .map(|position| (position.start.line, heading.depth, plain_text(node))),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the texts of the headings enclosing `line`, outermost first.
+    ///
+    /// Content on a heading line belongs to that heading's section. For the heading itself, pass its
+    /// `depth` so that only headings above its level are included.
+    pub(crate) fn path_at(&self, line: usize, depth: Option<u8>) -> Vec<String> {
+        let mut path: Vec<(u8, &str)> = Vec::new();
+
+        for (_, level, text) in self.0.iter().take_while(|(l, _, _)| *l <= line) {
+            while path.last().is_some_and(|(d, _)| d >= level) {
+                path.pop();
+            }
+
+            path.push((*level, text));
+        }
+
+        if let Some(depth) = depth {
+            while path.last().is_some_and(|(d, _)| *d >= depth) {
+                path.pop();
+            }
+        }
+
+        path.into_iter().map(|(_, text)| text.to_string()).collect()
+    }
+
+    /// Sets the section path of every positioned value, recursing into arrays and dicts.
+    pub(crate) fn assign(&self, value: &mut MQValue) {
+        match value {
+            MQValue::Array { value } => value.iter_mut().for_each(|value| self.assign(value)),
+            MQValue::Dict { value } => value.values_mut().for_each(|value| self.assign(value)),
+            MQValue::Markdown { node, annotations, .. } => {
+                let Some(node) = node.0.as_deref() else {
+                    return;
+                };
+
+                if let Some(position) = node.position() {
+                    let depth = match node {
+                        Node::Heading(heading) => Some(heading.depth),
+                        _ => None,
+                    };
+
+                    annotations.get_or_insert_default().section_path = self.path_at(position.start.line, depth);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the text of a node without markdown syntax.
+pub(crate) fn plain_text(node: &Node) -> String {
+    match node {
+        Node::Text(text) => text.value.clone(),
+        Node::CodeInline(code) => code.value.to_string(),
+        Node::MathInline(math) => math.value.to_string(),
+        Node::Image(image) => image.alt.clone(),
+        Node::Break(_) => " ".to_string(),
+        Node::Html(_) => String::new(),
+        node => {
+            let children = node.children();
+
+            if children.is_empty() {
+                node.value()
+            } else {
+                children.iter().map(plain_text).collect()
+            }
+        }
+    }
+}
+
+/// A heading and everything under it up to the next heading of the same or a higher level, as
+/// returned by `sections`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct Section {
+    /// The plain text of the heading.
+    #[pyo3(get)]
+    heading: String,
+    /// The GitHub anchor of the heading, as listed by `anchors`.
+    #[pyo3(get)]
+    slug: String,
+    #[pyo3(get)]
+    level: u8,
+    /// The top-level nodes after the heading, including those of subsections.
+    #[pyo3(get)]
+    nodes: Vec<MQValue>,
+    /// The byte offset of the start of the heading line.
+    #[pyo3(get)]
+    start: usize,
+    /// The byte offset after the section, where the next heading of its level or above starts.
+    #[pyo3(get)]
+    end: usize,
+    /// The source of the section, from `start` to `end`.
+    #[pyo3(get)]
+    text: String,
+    /// The source after the heading.
+    #[pyo3(get)]
+    body: String,
+    body_start: usize,
+}
+
+#[pymethods]
+impl Section {
+    fn __repr__(&self) -> String {
+        format!(
+            "Section({:?}, level={}, range={}..{})",
+            self.heading, self.level, self.start, self.end
+        )
+    }
+}
+
+fn parse_sections(content: &str) -> PyResult<Vec<Section>> {
+    let options = Options::default();
+    let input = input::parse(content, &options)?;
+    let annotations = input::Annotations::new(&input, content, &options)?;
+    let line_starts = input::line_starts(content, &options);
+    let line_start = |line: usize| {
+        line.checked_sub(1)
+            .and_then(|line| line_starts.get(line).copied())
+            .unwrap_or(content.len())
+    };
+
+    let headings = input
+        .iter()
+        .enumerate()
+        .filter_map(|(index, value)| match value {
+            RuntimeValue::Markdown(node, _) => match node.as_ref() {
+                Node::Heading(heading) => Some((index, heading.depth, heading.position.clone()?, plain_text(node))),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut slugger = Slugger::default();
+
+    headings
+        .iter()
+        .enumerate()
+        .map(|(n, (index, depth, position, heading))| {
+            let next = headings[n + 1..]
+                .iter()
+                .find(|(_, next_depth, _, _)| next_depth <= depth);
+            let end = next.map_or(content.len(), |(_, _, position, _)| line_start(position.start.line));
+            let start = line_start(position.start.line);
+            let body_start = line_start(position.end.line + 1).min(end);
+            let nodes = input[index + 1..next.map_or(input.len(), |(index, ..)| *index)]
+                .iter()
+                .map(|value| {
+                    let mut value = MQValue::try_from(value.clone())?;
+                    annotations.apply(&mut value);
+                    Ok(value)
+                })
+                .collect::<PyResult<Vec<_>>>()?;
+
+            Ok(Section {
+                heading: heading.clone(),
+                slug: slugger.slug(heading)?,
+                level: *depth,
+                nodes,
+                start,
+                end,
+                text: content[start..end].to_string(),
+                body: content[body_start..end].to_string(),
+                body_start,
+            })
+        })
+        .collect()
+}
+
+fn find(content: &str, title_or_slug: &str) -> PyResult<Option<Section>> {
+    Ok(parse_sections(content)?
+        .into_iter()
+        .find(|section| section.heading == title_or_slug || section.slug == title_or_slug))
+}
+
+fn find_required(content: &str, title_or_slug: &str) -> PyResult<Section> {
+    find(content, title_or_slug)?
+        .ok_or_else(|| PyValueError::new_err(format!("No section with the heading or slug {:?}", title_or_slug)))
+}
+
+/// Returns every section of a document in order, one for each heading. Content before the first
+/// heading belongs to no section.
+#[pyfunction]
+pub(crate) fn sections(content: Content<'_>) -> PyResult<Vec<Section>> {
+    parse_sections(content.as_str()?)
+}
+
+/// Returns the first section whose heading text or slug is `title_or_slug`, or `None`.
+#[pyfunction]
+pub(crate) fn get_section(content: Content<'_>, title_or_slug: &str) -> PyResult<Option<Section>> {
+    find(content.as_str()?, title_or_slug)
+}
+
+/// Replaces the body of a section with `markdown`, keeping its heading, or the whole section with
+/// `keep_heading=False`. The rest of the document is left as it is written.
+#[pyfunction]
+#[pyo3(signature = (content, title_or_slug, markdown, keep_heading=true))]
+pub(crate) fn replace_section(
+    content: Content<'_>,
+    title_or_slug: &str,
+    markdown: &str,
+    keep_heading: bool,
+) -> PyResult<String> {
+    let content = content.as_str()?;
+    let section = find_required(content, title_or_slug)?;
+    let start = if keep_heading {
+        section.body_start
+    } else {
+        section.start
+    };
+    let markdown = markdown.trim_matches('\n');
+    let mut replacement = String::new();
+
+    // The heading may be the last line, without a line break.
+    if !content[..start].ends_with('\n') && start > 0 {
+        replacement.push('\n');
+    }
+
+    if !markdown.is_empty() {
+        if keep_heading {
+            replacement.push('\n');
+        }
+
+        replacement.push_str(markdown);
+        replacement.push('\n');
+    }
+
+    if section.end < content.len() && (keep_heading || !replacement.is_empty()) {
+        replacement.push('\n');
+    }
+
+    Ok(format!("{}{}{}", &content[..start], replacement, &content[section.end..]))
+}
+
+/// Removes a section, its heading and its subsections from a document.
+#[pyfunction]
+pub(crate) fn remove_section(content: Content<'_>, title_or_slug: &str) -> PyResult<String> {
+    let content = content.as_str()?;
+    let section = find_required(content, title_or_slug)?;
+
+    Ok(format!("{}{}", &content[..section.start], &content[section.end..]))
+}
@@ -0,0 +1,208 @@
+//! Restricting what queries may do before they run, for services that evaluate untrusted queries.
+use crate::{Options, analyze, builtins, error::SecurityError};
+use pyo3::prelude::*;
+
+/// Builtins that reach outside the query and its input: files, standard input and output, and the
+/// process itself.
+const SIDE_EFFECT_FUNCTIONS: [&str; 12] = [
+    "collection",
+    "debug",
+    "file_exists",
+    "halt",
+    "halt_error",
+    "input",
+    "inspect",
+    "load_markdown",
+    "print",
+    "read_file",
+    "read_file_bytes",
+    "stderr",
+];
+
+/// Builtins that look up or define names at run time, e.g. `get_variable(intern("halt"))`, so the
+/// functions they reach cannot be checked before the query runs.
+const DYNAMIC_FUNCTIONS: [&str; 4] = ["all_symbols", "get_variable", "intern", "set_variable"];
+
+/// Whether calling the builtin `name` may have effects outside the query, directly or by looking up
+/// a builtin that does.
+pub(crate) fn has_side_effects(name: &str) -> bool {
+    SIDE_EFFECT_FUNCTIONS.contains(&name) || DYNAMIC_FUNCTIONS.contains(&name)
+}
+
+/// What queries run with `Options.security` may use. Queries that use anything else raise
+/// `SecurityError` without being run.
+#[pyclass(eq, from_py_object, module = "mq")]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct SecurityPolicy {
+    /// Whether queries may read environment variables, with `$NAME`.
+    #[pyo3(get, set)]
+    allow_env: bool,
+    /// Whether queries may load modules, with `include` and `import`.
+    #[pyo3(get, set)]
+    allow_include: bool,
+    /// The builtins queries may call, or None to allow all but those with side effects and those
+    /// that look up names at run time.
+    #[pyo3(get, set)]
+    allowed_functions: Option<Vec<String>>,
+}
+
+#[pymethods]
+impl SecurityPolicy {
+    #[new]
+    #[pyo3(signature = (allow_env=false, allow_include=false, allowed_functions=None))]
+    fn new(allow_env: bool, allow_include: bool, allowed_functions: Option<Vec<String>>) -> Self {
+        Self {
+            allow_env,
+            allow_include,
+            allowed_functions,
+        }
+    }
+
+    fn __reduce__<'py>(
+        slf: &Bound<'py, Self>,
+    ) -> PyResult<(Bound<'py, pyo3::types::PyType>, (), Bound<'py, pyo3::types::PyDict>)> {
+        Ok((slf.get_type(), (), crate::fields(slf.as_any())?))
+    }
+
+    fn __setstate__(slf: &Bound<'_, Self>, state: &Bound<'_, pyo3::types::PyDict>) -> PyResult<()> {
+        crate::set_fields(slf.as_any(), "SecurityPolicy", Some(state))
+    }
+
+    fn __repr__(&self) -> String {
+        let allowed_functions = match &self.allowed_functions {
+            Some(names) => format!(
+                "[{}]",
+                names
+                    .iter()
+                    .map(|name| format!("'{}'", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => "None".to_string(),
+        };
+        let python_bool = |value: bool| if value { "True" } else { "False" };
+
+        format!(
+            "SecurityPolicy(allow_env={}, allow_include={}, allowed_functions={})",
+            python_bool(self.allow_env),
+            python_bool(self.allow_include),
+            allowed_functions
+        )
+    }
+}
+
+/// What `scan` finds in the text of a query.
+#[derive(Default)]
+struct Scan<'a> {
+    /// The environment variables read with `$NAME`, inside interpolated strings or not.
+    env: Vec<&'a str>,
+    /// The expressions interpolated into `s"..."` strings.
+    interpolations: Vec<&'a str>,
+}
+
+fn is_ident(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Finds the environment variables and interpolated expressions of `code`, which mq-lang reads
+/// while parsing the query and does not keep in its syntax tree.
+fn scan<'a>(code: &'a str, found: &mut Scan<'a>) {
+    let bytes = code.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let interpolated = i > 0 && bytes[i - 1] == b's' && (i < 2 || !is_ident(bytes[i - 2]));
+                i += 1;
+
+                while i < bytes.len() && bytes[i] != b'"' {
+                    if interpolated && bytes[i..].starts_with(b"$$") {
+                        i += 2;
+                    } else if interpolated && bytes[i..].starts_with(b"${") {
+                        let start = i + 2;
+                        let end = code[start..].find('}').map_or(code.len(), |end| start + end);
+                        found.interpolations.push(&code[start..end]);
+                        scan(&code[start..end], found);
+                        i = end + 1;
+                    } else {
+                        i += if bytes[i] == b'\\' { 2 } else { 1 };
+                    }
+                }
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'$' => {
+                let start = i + 1;
+                i = start;
+
+                while i < bytes.len() && is_ident(bytes[i]) {
+                    i += 1;
+                }
+
+                if i > start {
+                    found.env.push(&code[start..i]);
+                }
+
+                continue;
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+}
+
+/// Raises `SecurityError` if `code` uses something the `security` policy of `options` does not
+/// allow, doing nothing when there is no policy.
+///
+/// Names are checked whether or not the query defines them, so a query cannot hide a call to a
+/// builtin behind a parameter of the same name.
+pub(crate) fn check(code: &str, options: &Options) -> PyResult<()> {
+    let Some(policy) = &options.security else {
+        return Ok(());
+    };
+    let mut found = Scan::default();
+    scan(code, &mut found);
+
+    if !policy.allow_env
+        && let Some(name) = found.env.first()
+    {
+        return Err(SecurityError::new_err(format!(
+            "Reading the environment variable ${} is not allowed",
+            name
+        )));
+    }
+
+    let (mut names, mut opaque) = analyze::used_names(code)?;
+
+    for expr in found.interpolations {
+        let (expr_names, expr_opaque) = analyze::used_names(expr)?;
+        names.extend(expr_names);
+        opaque.extend(expr_opaque);
+    }
+
+    if !policy.allow_include
+        && let Some(statement) = opaque.iter().find(|kind| matches!(kind.as_str(), "include" | "import"))
+    {
+        return Err(SecurityError::new_err(format!("`{}` is not allowed", statement)));
+    }
+
+    let denied = match &policy.allowed_functions {
+        Some(allowed) => {
+            let builtins = builtins::function_names();
+            names.into_iter().find(|name| {
+                (builtins.contains(name) || SIDE_EFFECT_FUNCTIONS.contains(&name.as_str())) && !allowed.contains(name)
+            })
+        }
+        None => names.into_iter().find(|name| has_side_effects(name)),
+    };
+
+    match denied {
+        Some(name) => Err(SecurityError::new_err(format!("Calling `{}` is not allowed", name))),
+        None => Ok(()),
+    }
+}
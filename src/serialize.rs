@@ -0,0 +1,436 @@
+//! A JSON form of results, so a parsed document can be cached on disk or sent to another process.
+//!
+//! Nodes are written with their `type`, and read back by dispatching on it, since mq-markdown's own
+//! deserializer cannot tell nodes of the same shape apart, e.g. `Strong` and `Emphasis`.
+use crate::{
+    content::Content,
+    result::MQResult,
+    value::{Annotations, MQValue, MarkdownNode, MarkdownType, Number},
+};
+use mq_markdown::Node;
+use pyo3::{IntoPyObjectExt, exceptions::PyValueError, prelude::*};
+use serde_json::{Map, Value, json};
+
+/// The version of the format written by `dumps`, checked by `loads`.
+///
+/// Version 1 wrote strings, numbers, booleans and `None` as text values, which are still read.
+const VERSION: u64 = 2;
+
+const MARKDOWN_TYPES: [MarkdownType; 33] = [
+    MarkdownType::Blockquote,
+    MarkdownType::Break,
+    MarkdownType::Definition,
+    MarkdownType::Delete,
+    MarkdownType::Heading,
+    MarkdownType::Emphasis,
+    MarkdownType::Footnote,
+    MarkdownType::FootnoteRef,
+    MarkdownType::Html,
+    MarkdownType::Yaml,
+    MarkdownType::Toml,
+    MarkdownType::Image,
+    MarkdownType::ImageRef,
+    MarkdownType::CodeInline,
+    MarkdownType::MathInline,
+    MarkdownType::Link,
+    MarkdownType::LinkRef,
+    MarkdownType::Math,
+    MarkdownType::List,
+    MarkdownType::TableHeader,
+    MarkdownType::TableRow,
+    MarkdownType::TableCell,
+    MarkdownType::Code,
+    MarkdownType::Strong,
+    MarkdownType::HorizontalRule,
+    MarkdownType::MdxFlowExpression,
+    MarkdownType::MdxJsxFlowElement,
+    MarkdownType::MdxJsxTextElement,
+    MarkdownType::MdxTextExpression,
+    MarkdownType::MdxJsEsm,
+    MarkdownType::Text,
+    MarkdownType::Empty,
+    MarkdownType::OrderedList,
+];
+
+pub(crate) fn dumps(result: &MQResult) -> PyResult<String> {
+    let values = result
+        .values
+        .iter()
+        .map(encode_value)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Error serializing result: {}", e)))?;
+
+    Ok(json!({
+        "version": VERSION,
+        "values": values,
+        "warnings": result.warnings,
+        "source": result.source,
+    })
+    .to_string())
+}
+
+pub(crate) fn loads(data: Content<'_>) -> PyResult<MQResult> {
+    serde_json::from_str(data.as_str()?)
+        .map_err(|e| e.to_string())
+        .and_then(|data| decode_result(&data))
+        .map_err(|e| PyValueError::new_err(format!("Invalid serialized result: {}", e)))
+}
+
+/// Writes a single value in the form of the values of `dumps`.
+pub(crate) fn value_dumps(value: &MQValue) -> PyResult<String> {
+    encode_value(value)
+        .map(|value| value.to_string())
+        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!("Error serializing value: {}", e)))
+}
+
+/// What results and values are pickled as: a call of `from_json` with their JSON.
+pub(crate) fn reduce(py: Python<'_>, json: String) -> PyResult<(Bound<'_, PyAny>, (String,))> {
+    Ok((py.import("mq")?.getattr("from_json")?, (json,)))
+}
+
+/// Reads a result written by `MQResult.to_json`, or a value written by `MQValue.to_json`.
+#[pyfunction]
+pub(crate) fn from_json<'py>(py: Python<'py>, data: Content<'_>) -> PyResult<Bound<'py, PyAny>> {
+    let data = serde_json::from_str::<Value>(data.as_str()?)
+        .map_err(|e| PyValueError::new_err(format!("Invalid JSON: {}", e)))?;
+
+    // Only results carry a version.
+    if data.get("version").is_some() {
+        decode_result(&data)
+            .map_err(|e| PyValueError::new_err(format!("Invalid serialized result: {}", e)))?
+            .into_bound_py_any(py)
+    } else {
+        decode_value(&data)
+            .map_err(|e| PyValueError::new_err(format!("Invalid serialized value: {}", e)))?
+            .into_bound_py_any(py)
+    }
+}
+
+fn encode_value(value: &MQValue) -> Result<Value, serde_json::Error> {
+    Ok(match value {
+        MQValue::Array { value } => json!({ "array": value.iter().map(encode_value).collect::<Result<Vec<_>, _>>()? }),
+        MQValue::Dict { value } => json!({
+            "dict": value
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), encode_value(v)?)))
+                .collect::<Result<Map<_, _>, serde_json::Error>>()?,
+        }),
+        MQValue::Markdown {
+            value,
+            markdown_type,
+            node,
+            annotations,
+        } => {
+            let Annotations {
+                section_path,
+                source,
+                start,
+                table_index,
+                column_alignment,
+                stage,
+                stage_name,
+                offsets,
+                anchor,
+            } = annotations.as_deref().cloned().unwrap_or_default();
+
+            json!({
+                "markdown": value,
+                "type": format!("{:?}", markdown_type),
+                "node": node.0.as_deref().map(serde_json::to_value).transpose()?,
+                "sectionPath": section_path,
+                "source": source,
+                "start": start,
+                "tableIndex": table_index,
+                "columnAlignment": column_alignment,
+                "stage": stage,
+                "stageName": stage_name,
+                "offsets": offsets,
+                "anchor": anchor,
+            })
+        }
+        MQValue::String {
+            value,
+            source,
+            stage,
+            stage_name,
+        } => json!({ "string": value, "source": source, "stage": stage, "stageName": stage_name }),
+        MQValue::Number {
+            value,
+            source,
+            stage,
+            stage_name,
+        } => json!({ "number": value.0, "source": source, "stage": stage, "stageName": stage_name }),
+        MQValue::Bool {
+            value,
+            source,
+            stage,
+            stage_name,
+        } => json!({ "bool": value, "source": source, "stage": stage, "stageName": stage_name }),
+        MQValue::None {
+            source,
+            stage,
+            stage_name,
+        } => json!({ "none": null, "source": source, "stage": stage, "stageName": stage_name }),
+    })
+}
+
+fn decode_result(data: &Value) -> Result<MQResult, String> {
+    match data.get("version").and_then(Value::as_u64) {
+        Some(1 | VERSION) => {}
+        Some(version) => return Err(format!("unsupported version {}", version)),
+        None => return Err("missing version".to_string()),
+    }
+
+    Ok(MQResult {
+        values: array(data, "values")?
+            .iter()
+            .map(decode_value)
+            .collect::<Result<_, _>>()?,
+        warnings: strings(data, "warnings")?,
+        source: string(data, "source")?,
+        timings: None,
+        trace: None,
+        display_limit: None,
+        options: None,
+    })
+}
+
+fn decode_value(value: &Value) -> Result<MQValue, String> {
+    if let Some(values) = value.get("array") {
+        return Ok(MQValue::Array {
+            value: values
+                .as_array()
+                .ok_or("array must be a list")?
+                .iter()
+                .map(decode_value)
+                .collect::<Result<_, _>>()?,
+        });
+    }
+
+    if let Some(values) = value.get("dict") {
+        return Ok(MQValue::Dict {
+            value: values
+                .as_object()
+                .ok_or("dict must be an object")?
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), decode_value(v)?)))
+                .collect::<Result<_, String>>()?,
+        });
+    }
+
+    let (source, stage, stage_name) = (
+        string(value, "source")?,
+        number(value, "stage")?,
+        string(value, "stageName")?,
+    );
+
+    if let Some(s) = value.get("string") {
+        return Ok(MQValue::String {
+            value: s.as_str().ok_or("string must be a string")?.to_string(),
+            source,
+            stage,
+            stage_name,
+        });
+    }
+
+    if let Some(n) = value.get("number") {
+        return Ok(MQValue::Number {
+            value: Number(n.as_f64().ok_or("number must be a number")?),
+            source,
+            stage,
+            stage_name,
+        });
+    }
+
+    if let Some(b) = value.get("bool") {
+        return Ok(MQValue::Bool {
+            value: b.as_bool().ok_or("bool must be a boolean")?,
+            source,
+            stage,
+            stage_name,
+        });
+    }
+
+    if value.get("none").is_some() {
+        return Ok(MQValue::None {
+            source,
+            stage,
+            stage_name,
+        });
+    }
+
+    let markdown_type = value.get("type").and_then(Value::as_str).ok_or("missing type")?;
+    let markdown = string(value, "markdown")?.ok_or("missing markdown")?;
+
+    // Version 1 kept the number or boolean a text value was converted from as its scalar.
+    match value.get("scalar") {
+        Some(Value::Bool(b)) => {
+            return Ok(MQValue::Bool {
+                value: *b,
+                source,
+                stage,
+                stage_name,
+            });
+        }
+        Some(Value::Number(n)) => {
+            return Ok(MQValue::Number {
+                value: Number(n.as_f64().ok_or("scalar must be a number or a boolean")?),
+                source,
+                stage,
+                stage_name,
+            });
+        }
+        Some(Value::Null) if markdown_type == "Empty" && markdown.is_empty() => {
+            return Ok(MQValue::None {
+                source,
+                stage,
+                stage_name,
+            });
+        }
+        Some(Value::Null) if markdown_type == "Text" && value.get("node").is_none_or(Value::is_null) => {
+            return Ok(MQValue::String {
+                value: markdown,
+                source,
+                stage,
+                stage_name,
+            });
+        }
+        _ => {}
+    }
+
+    Ok(MQValue::Markdown {
+        value: markdown,
+        markdown_type: MARKDOWN_TYPES
+            .into_iter()
+            .find(|t| format!("{:?}", t) == markdown_type)
+            .ok_or_else(|| format!("unknown type {:?}", markdown_type))?,
+        node: MarkdownNode(match value.get("node") {
+            None | Some(Value::Null) => None,
+            Some(node) => Some(Box::new(decode_node(node.clone())?)),
+        }),
+        annotations: Some(Box::new(Annotations {
+            section_path: strings(value, "sectionPath")?,
+            source,
+            start: number(value, "start")?,
+            table_index: number(value, "tableIndex")?,
+            column_alignment: string(value, "columnAlignment")?,
+            stage,
+            stage_name,
+            offsets: match value.get("offsets") {
+                None | Some(Value::Null) => None,
+                Some(offsets) => {
+                    Some(serde_json::from_value(offsets.clone()).map_err(|_| "offsets must be a pair of integers")?)
+                }
+            },
+            anchor: string(value, "anchor")?,
+        })),
+    })
+}
+
+fn decode_node(mut value: Value) -> Result<Node, String> {
+    let Value::Object(object) = &mut value else {
+        return Err("node must be an object".to_string());
+    };
+    let name = object
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or("node is missing its type")?
+        .to_string();
+    // Children are decoded here, as the struct's own deserializer would guess their types.
+    let children = ["values", "children"]
+        .into_iter()
+        .find(|key| object.contains_key(*key))
+        .and_then(|key| object.insert(key.to_string(), Value::Array(Vec::new())));
+
+    let mut node = match name.as_str() {
+        "Blockquote" => serde_json::from_value(value).map(Node::Blockquote),
+        "Break" => serde_json::from_value(value).map(Node::Break),
+        "Callout" => serde_json::from_value(value).map(Node::Callout),
+        "Embed" => serde_json::from_value(value).map(Node::Embed),
+        "Definition" => serde_json::from_value(value).map(Node::Definition),
+        "Delete" => serde_json::from_value(value).map(Node::Delete),
+        "Heading" => serde_json::from_value(value).map(Node::Heading),
+        "Emphasis" => serde_json::from_value(value).map(Node::Emphasis),
+        "Footnote" => serde_json::from_value(value).map(Node::Footnote),
+        "FootnoteRef" => serde_json::from_value(value).map(Node::FootnoteRef),
+        "Html" => serde_json::from_value(value).map(Node::Html),
+        "Yaml" => serde_json::from_value(value).map(Node::Yaml),
+        "Toml" => serde_json::from_value(value).map(Node::Toml),
+        "Image" => serde_json::from_value(value).map(Node::Image),
+        "ImageRef" => serde_json::from_value(value).map(Node::ImageRef),
+        "CodeInline" => serde_json::from_value(value).map(Node::CodeInline),
+        "MathInline" => serde_json::from_value(value).map(Node::MathInline),
+        "Link" => serde_json::from_value(value).map(Node::Link),
+        "LinkRef" => serde_json::from_value(value).map(Node::LinkRef),
+        "WikiLink" => serde_json::from_value(value).map(Node::WikiLink),
+        "Math" => serde_json::from_value(value).map(Node::Math),
+        "List" => serde_json::from_value(value).map(Node::List),
+        "TableAlign" => serde_json::from_value(value).map(Node::TableAlign),
+        "TableRow" => serde_json::from_value(value).map(Node::TableRow),
+        "TableCell" => serde_json::from_value(value).map(Node::TableCell),
+        "Code" => serde_json::from_value(value).map(Node::Code),
+        "Strong" => serde_json::from_value(value).map(Node::Strong),
+        "HorizontalRule" => serde_json::from_value(value).map(Node::HorizontalRule),
+        "MdxFlowExpression" => serde_json::from_value(value).map(Node::MdxFlowExpression),
+        "MdxJsxFlowElement" => serde_json::from_value(value).map(Node::MdxJsxFlowElement),
+        "MdxJsxTextElement" => serde_json::from_value(value).map(Node::MdxJsxTextElement),
+        "MdxTextExpression" => serde_json::from_value(value).map(Node::MdxTextExpression),
+        "MdxJsEsm" => serde_json::from_value(value).map(Node::MdxJsEsm),
+        "Text" => serde_json::from_value(value).map(Node::Text),
+        "Fragment" => serde_json::from_value(value).map(Node::Fragment),
+        name => return Err(format!("unknown node type {:?}", name)),
+    }
+    .map_err(|e| format!("{} node: {}", name, e))?;
+
+    if let Some(children) = children {
+        node.set_children(
+            children
+                .as_array()
+                .ok_or("node children must be a list")?
+                .iter()
+                .map(|child| match child {
+                    Value::Null => Ok(Node::Empty),
+                    child => decode_node(child.clone()),
+                })
+                .collect::<Result<_, _>>()?,
+        );
+    }
+
+    Ok(node)
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> Result<&'a Vec<Value>, String> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .ok_or_else(|| format!("{} must be a list", key))
+}
+
+fn strings(value: &Value, key: &str) -> Result<Vec<String>, String> {
+    array(value, key)?
+        .iter()
+        .map(|s| {
+            s.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| format!("{} must be a list of strings", key))
+        })
+        .collect()
+}
+
+fn string(value: &Value, key: &str) -> Result<Option<String>, String> {
+    match value.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("{} must be a string", key)),
+    }
+}
+
+fn number(value: &Value, key: &str) -> Result<Option<usize>, String> {
+    match value.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(n) => n
+            .as_u64()
+            .map(|n| Some(n as usize))
+            .ok_or_else(|| format!("{} must be a non-negative integer", key)),
+    }
+}
@@ -0,0 +1,150 @@
+//! Counts of the words, headings, code, links and images of a document, gathered in one pass.
+use crate::{Options, content::Content, input};
+use mq_lang::RuntimeValue;
+use mq_markdown::Node;
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+
+/// The statistics of a document, as returned by `stats`.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DocumentStats {
+    /// The words of the text, without markdown syntax, code blocks, HTML or front matter.
+    #[pyo3(get)]
+    words: usize,
+    /// The characters of the same text, spaces included.
+    #[pyo3(get)]
+    characters: usize,
+    /// The number of headings of each level present, e.g. `{1: 1, 2: 4}`.
+    #[pyo3(get)]
+    headings: BTreeMap<u8, usize>,
+    #[pyo3(get)]
+    code_blocks: usize,
+    /// The lines of all code blocks.
+    #[pyo3(get)]
+    code_lines: usize,
+    #[pyo3(get)]
+    links: usize,
+    #[pyo3(get)]
+    images: usize,
+    /// The estimated reading time of the words in minutes.
+    #[pyo3(get)]
+    reading_minutes: f64,
+}
+
+#[pymethods]
+impl DocumentStats {
+    fn __repr__(&self) -> String {
+        format!(
+            "DocumentStats(words={}, headings={}, code_blocks={}, links={}, images={}, reading_minutes={:.1})",
+            self.words,
+            self.headings.values().sum::<usize>(),
+            self.code_blocks,
+            self.links,
+            self.images,
+            self.reading_minutes
+        )
+    }
+}
+
+impl DocumentStats {
+    /// Counts `node` and its children, adding its text to `text`.
+    fn count(&mut self, node: &Node, text: &mut String) {
+        // Blocks are separated, while the inline nodes of a paragraph, e.g. the text around a link,
+        // may join into one word.
+        if !is_inline(node) {
+            text.push(' ');
+        }
+
+        match node {
+            Node::Text(value) => self.count_text(&value.value, text),
+            Node::CodeInline(code) => self.count_text(&code.value, text),
+            Node::Break(_) => {
+                self.characters += 1;
+                text.push(' ');
+            }
+            Node::Code(code) => {
+                self.code_blocks += 1;
+                self.code_lines += code.value.lines().count();
+            }
+            Node::Html(_) | Node::Yaml(_) | Node::Toml(_) | Node::Definition(_) | Node::MathInline(_) => {}
+            node => {
+                match node {
+                    Node::Heading(heading) => *self.headings.entry(heading.depth).or_default() += 1,
+                    Node::Link(_) | Node::LinkRef(_) => self.links += 1,
+                    Node::Image(_) | Node::ImageRef(_) => self.images += 1,
+                    _ => {}
+                }
+
+                node.children().iter().for_each(|child| self.count(child, text));
+            }
+        }
+    }
+
+    fn count_text(&mut self, value: &str, text: &mut String) {
+        self.characters += value.chars().count();
+        text.push_str(value);
+    }
+}
+
+fn is_inline(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Text(_)
+            | Node::CodeInline(_)
+            | Node::MathInline(_)
+            | Node::Strong(_)
+            | Node::Emphasis(_)
+            | Node::Delete(_)
+            | Node::Link(_)
+            | Node::LinkRef(_)
+            | Node::Image(_)
+            | Node::ImageRef(_)
+            | Node::Break(_)
+            | Node::FootnoteRef(_)
+    )
+}
+
+/// Counts the words, characters, headings by level, code blocks and lines, links and images of a
+/// document, and estimates its reading time at `words_per_minute`.
+///
+/// Words are counted in the text as it reads, so markdown syntax, HTML, code blocks and front
+/// matter are left out; inline code counts.
+#[pyfunction]
+#[pyo3(signature = (content, options=None, words_per_minute=200))]
+pub(crate) fn stats(
+    content: Content<'_>,
+    options: Option<Options>,
+    words_per_minute: usize,
+) -> PyResult<DocumentStats> {
+    if words_per_minute == 0 {
+        return Err(pyo3::exceptions::PyValueError::new_err(
+            "words_per_minute must be at least 1",
+        ));
+    }
+
+    let options = options.unwrap_or_default();
+    let mut stats = DocumentStats::default();
+    let mut text = String::new();
+    let mut last_line = 0;
+
+    for value in input::parse(content.as_str()?, &options)? {
+        if let RuntimeValue::Markdown(node, _) = value {
+            // The inline nodes of a paragraph are not in a node of their own, so paragraphs are told
+            // apart by their lines.
+            if let Some(position) = node.position() {
+                if position.start.line > last_line {
+                    text.push(' ');
+                }
+
+                last_line = position.end.line;
+            }
+
+            stats.count(&node, &mut text);
+        }
+    }
+
+    stats.words = text.split_whitespace().count();
+    stats.reading_minutes = stats.words as f64 / words_per_minute as f64;
+    Ok(stats)
+}
@@ -0,0 +1,436 @@
+//! Results produced one at a time, as they are consumed: the values of a query, the results of the
+//! files matching a glob and the markdown converted from an HTML stream.
+use crate::{
+    ConversionOptions, Detached, Options, content::Content, convert_html, engine, front_matter, input,
+    result::MQResult, value::MQValue,
+};
+use mq_lang::RuntimeValue;
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyIterator, PyString},
+};
+
+/// An iterator over the non-empty values of a query, from `run_iter`.
+///
+/// The values hold the engine's reference-counted nodes, so the iterator is bound to the thread
+/// that created it.
+#[pyclass(unsendable)]
+pub(crate) struct ValueIterator {
+    values: std::vec::IntoIter<RuntimeValue>,
+    annotations: input::Annotations,
+}
+
+#[pymethods]
+impl ValueIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<MQValue>> {
+        for value in self.values.by_ref() {
+            let mut value = MQValue::try_from(value)?;
+
+            // Empty values stand in for nodes the query did not match.
+            if value.__len__() > 0 {
+                self.annotations.apply(&mut value);
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Runs `code` against `content` and returns an iterator that converts the values as they are
+/// consumed, so stopping after the first few matches skips converting the rest.
+///
+/// The engine evaluates the whole query before the first value is returned. Conversion warnings
+/// are not reported, as there is no result to add them to.
+#[pyfunction]
+#[pyo3(signature = (code, content, options=None, errors="strict"))]
+pub(crate) fn run_iter(
+    py: Python<'_>,
+    code: &str,
+    content: Content<'_>,
+    options: Option<Options>,
+    errors: &str,
+) -> PyResult<ValueIterator> {
+    let options = options.unwrap_or_default();
+    let content = content.decode_detached(errors, options.encoding)?;
+    let Detached((values, annotations)) = py.detach(|| {
+        let input = input::parse(&content, &options)?;
+        let annotations = input::Annotations::new(&input, &content, &options)?;
+        let values = engine::eval(code, input, &options)?;
+
+        Ok::<_, PyErr>(Detached((values, annotations)))
+    })?;
+
+    Ok(ValueIterator {
+        values: values.into_iter(),
+        annotations,
+    })
+}
+
+/// Elements that only group other blocks, so they are not kept together when a stream is split.
+const TRANSPARENT_TAGS: &[&str] = &["html", "body", "div", "main", "article", "section"];
+
+/// Elements after which a top-level block is complete.
+const BLOCK_TAGS: &[&str] = &[
+    "address", "aside", "blockquote", "details", "dl", "fieldset", "figure", "footer", "form", "h1", "h2", "h3", "h4",
+    "h5", "h6", "head", "header", "hr", "nav", "ol", "p", "pre", "table", "ul",
+];
+
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// Elements whose content is text up to the closing tag.
+const RAW_TEXT_TAGS: &[&str] = &["script", "style", "textarea", "title", "xmp", "noscript", "iframe"];
+
+/// Where the next complete top-level block of a partially read document ends.
+#[derive(Default)]
+struct Splitter {
+    /// How far the HTML has been read.
+    offset: usize,
+    /// The elements open at `offset`, outermost first, without the transparent ones.
+    open: Vec<String>,
+    /// The end of the last complete top-level block.
+    boundary: usize,
+}
+
+impl Splitter {
+    /// Reads as much of `html` as is complete, moving `boundary` past every top-level block closed in it.
+    fn scan(&mut self, html: &str) {
+        while let Some(start) = html[self.offset..].find('<').map(|start| self.offset + start) {
+            let Some(end) = tag_end(html, start) else {
+                return;
+            };
+            let tag = &html[start + 1..end - 1];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                let name = tag_name(name);
+
+                if let Some(index) = self.open.iter().rposition(|open| *open == name) {
+                    self.open.truncate(index);
+                    self.close(&name, end);
+                }
+
+                self.offset = end;
+                continue;
+            }
+
+            let name = tag_name(tag);
+
+            if name.is_empty() || TRANSPARENT_TAGS.contains(&name.as_str()) {
+                self.offset = end;
+                continue;
+            }
+
+            // A paragraph ends where a block starts, with or without its closing tag.
+            if BLOCK_TAGS.contains(&name.as_str()) && self.open.last().is_some_and(|open| open == "p") {
+                self.open.pop();
+                self.close("p", start);
+            }
+
+            if VOID_TAGS.contains(&name.as_str()) || tag.ends_with('/') {
+                self.offset = end;
+                self.close(&name, end);
+                continue;
+            }
+
+            if RAW_TEXT_TAGS.contains(&name.as_str()) {
+                let Some(close) = html[end..].to_ascii_lowercase().find(&format!("</{}", name)) else {
+                    return;
+                };
+                self.offset = end + close;
+            } else {
+                self.offset = end;
+            }
+
+            self.open.push(name);
+        }
+
+        self.offset = html.len();
+    }
+
+    fn close(&mut self, name: &str, end: usize) {
+        if self.open.is_empty() && BLOCK_TAGS.contains(&name) {
+            self.boundary = end;
+        }
+    }
+
+    /// Moves the offsets back by the `len` bytes taken from the start of the HTML.
+    fn consume(&mut self, len: usize) {
+        self.offset = self.offset.saturating_sub(len);
+        self.boundary = self.boundary.saturating_sub(len);
+    }
+}
+
+/// Returns the offset after the `>` ending the tag, comment or declaration starting at `start`, if it
+/// has been read.
+fn tag_end(html: &str, start: usize) -> Option<usize> {
+    let rest = &html[start..];
+
+    if rest.starts_with("<!--") {
+        return rest.find("-->").map(|end| start + end + 3);
+    }
+
+    let mut quote = None;
+
+    for (i, c) in rest.char_indices().skip(1) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(start + i + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The lowercased name of a tag, empty for comments and declarations.
+fn tag_name(tag: &str) -> String {
+    tag.chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// An iterator over the path and result of each file matching a glob, from `run_glob` with
+/// `lazy=True`.
+///
+/// Files are found and queried as they are reached, in the order their directories list them, so
+/// only one document is held at a time.
+#[pyclass]
+pub(crate) struct GlobResults {
+    paths: Py<PyIterator>,
+    code: String,
+    options: Options,
+    errors: String,
+    front_matter: Option<Py<PyAny>>,
+}
+
+impl GlobResults {
+    pub(crate) fn new(
+        paths: Bound<'_, PyIterator>,
+        code: &str,
+        options: Options,
+        errors: &str,
+        front_matter: Option<Bound<'_, PyAny>>,
+    ) -> Self {
+        GlobResults {
+            paths: paths.unbind(),
+            code: code.to_string(),
+            options,
+            errors: errors.to_string(),
+            front_matter: front_matter.map(Bound::unbind),
+        }
+    }
+}
+
+#[pymethods]
+impl GlobResults {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<(String, MQResult)>> {
+        let filter = self
+            .front_matter
+            .as_ref()
+            .map(|front_matter| front_matter::Filter::new(front_matter.bind(py).clone()))
+            .transpose()?;
+
+        while let Some(path) = self.paths.bind(py).clone().next() {
+            let path = path?.extract::<std::path::PathBuf>()?;
+
+            if !path.is_file() {
+                continue;
+            }
+
+            let result = crate::eval_glob_file(py, &self.code, &path, &self.options, &self.errors, filter.as_ref())?;
+
+            if result.is_some() {
+                return Ok(result);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+enum Source {
+    Reader(Py<PyAny>),
+    Chunks(Py<PyIterator>),
+}
+
+/// An iterator over the markdown converted from an HTML stream, from `html_to_markdown_stream`.
+#[pyclass]
+pub(crate) struct MarkdownChunks {
+    source: Option<Source>,
+    options: Option<ConversionOptions>,
+    errors: String,
+    chunk_size: usize,
+    /// Bytes read but not yet decoded, the start of a character split between chunks.
+    undecoded: Vec<u8>,
+    html: String,
+    splitter: Splitter,
+    started: bool,
+}
+
+impl MarkdownChunks {
+    /// Reads the next chunk of the stream, returning false at its end.
+    fn read(&mut self, py: Python<'_>) -> PyResult<bool> {
+        let chunk = match &self.source {
+            Some(Source::Reader(reader)) => reader.bind(py).call_method1("read", (self.chunk_size,))?,
+            Some(Source::Chunks(chunks)) => match chunks.bind(py).clone().next() {
+                Some(chunk) => chunk?,
+                None => return Ok(false),
+            },
+            None => return Ok(false),
+        };
+
+        if let Ok(text) = chunk.cast::<PyString>() {
+            self.undecoded.extend_from_slice(text.to_cow()?.as_bytes());
+        } else if let Ok(bytes) = chunk.cast::<PyBytes>() {
+            self.undecoded.extend_from_slice(bytes.as_bytes());
+        } else {
+            return Err(PyTypeError::new_err(format!(
+                "HTML chunks must be str or bytes, not {}",
+                chunk.get_type().name()?
+            )));
+        }
+
+        if chunk.len()? == 0 && matches!(self.source, Some(Source::Reader(_))) {
+            return Ok(false);
+        }
+
+        // A character split between chunks is decoded once the rest of it has been read.
+        let split = incomplete_char(&self.undecoded);
+        let rest = self.undecoded.split_off(split);
+        let bytes = std::mem::replace(&mut self.undecoded, rest);
+        self.decode(py, &bytes)?;
+        Ok(true)
+    }
+
+    fn decode(&mut self, py: Python<'_>, bytes: &[u8]) -> PyResult<()> {
+        let content = Content::Bytes(PyBytes::new(py, bytes));
+        self.html.push_str(&content.decode(&self.errors, None)?);
+        Ok(())
+    }
+
+    /// Converts the first `len` bytes of the HTML read so far.
+    fn convert(&mut self, len: usize) -> PyResult<Option<String>> {
+        let html = self.html.drain(..len).collect::<String>();
+        self.splitter.consume(len);
+        let markdown = convert_html(&html, self.options.as_ref())?;
+        let markdown = markdown.trim_matches('\n');
+
+        if markdown.is_empty() {
+            return Ok(None);
+        }
+
+        let separator = if self.started { "\n\n" } else { "" };
+        self.started = true;
+        Ok(Some(format!("{}{}", separator, markdown)))
+    }
+}
+
+/// Returns the length of `bytes` without a UTF-8 character cut off at the end.
+fn incomplete_char(bytes: &[u8]) -> usize {
+    for (i, byte) in bytes.iter().enumerate().rev().take(3) {
+        let len = match byte {
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            0x80..=0xBF => continue,
+            _ => return bytes.len(),
+        };
+
+        return if bytes.len() - i < len { i } else { bytes.len() };
+    }
+
+    bytes.len()
+}
+
+#[pymethods]
+impl MarkdownChunks {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<String>> {
+        loop {
+            if self.source.is_none() {
+                return match self.html.len() {
+                    0 => Ok(None),
+                    len => self.convert(len),
+                };
+            }
+
+            if !self.read(py)? {
+                self.source = None;
+
+                if !self.undecoded.is_empty() {
+                    let bytes = std::mem::take(&mut self.undecoded);
+                    self.decode(py, &bytes)?;
+                }
+
+                continue;
+            }
+
+            self.splitter.scan(&self.html);
+
+            if self.splitter.boundary >= self.chunk_size
+                && let Some(markdown) = self.convert(self.splitter.boundary)?
+            {
+                return Ok(Some(markdown));
+            }
+        }
+    }
+}
+
+/// Converts HTML read from a file-like object or an iterable of chunks to markdown, returning an
+/// iterator of markdown chunks so that the whole document is never held in memory.
+///
+/// The HTML is converted a run of complete top-level blocks at a time, at least `chunk_size` bytes
+/// of it unless the document ends first. Joining the chunks gives the whole document.
+#[pyfunction]
+#[pyo3(signature = (source, options=None, errors="strict", chunk_size=65536))]
+pub(crate) fn html_to_markdown_stream(
+    source: &Bound<'_, PyAny>,
+    options: Option<ConversionOptions>,
+    errors: &str,
+    chunk_size: usize,
+) -> PyResult<MarkdownChunks> {
+    if options.as_ref().is_some_and(|options| options.root_selector.is_some()) {
+        return Err(PyValueError::new_err(
+            "root_selector is not supported when streaming, as the matching elements may span chunks",
+        ));
+    }
+
+    if chunk_size == 0 {
+        return Err(PyValueError::new_err("chunk_size must be at least 1"));
+    }
+
+    let source = if source.is_instance_of::<PyString>() || source.is_instance_of::<PyBytes>() {
+        Source::Chunks(pyo3::types::PyTuple::new(source.py(), [source])?.try_iter()?.unbind())
+    } else if source.hasattr("read")? {
+        Source::Reader(source.clone().unbind())
+    } else {
+        Source::Chunks(source.try_iter()?.unbind())
+    };
+
+    Ok(MarkdownChunks {
+        source: Some(source),
+        options,
+        errors: errors.to_string(),
+        chunk_size,
+        undecoded: Vec::new(),
+        html: String::new(),
+        splitter: Splitter::default(),
+        started: false,
+    })
+}
@@ -0,0 +1,236 @@
+//! Table values as produced by the `table::tables` module function.
+//!
+//! A table is a dict with `type`, `align`, `header` and `rows` keys, where the header is an array
+//! of table cells and the rows an array of arrays of table cells.
+use crate::value::MQValue;
+use indexmap::IndexMap;
+use mq_markdown::Node;
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::{cmp::Ordering, collections::BTreeMap};
+
+/// A body row keyed by the text of the header cells.
+pub(crate) type Record = IndexMap<String, String>;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Table {
+    align: Option<Node>,
+    header: Vec<Node>,
+    rows: Vec<Vec<Node>>,
+}
+
+impl Table {
+    pub(crate) fn from_value(value: &MQValue) -> Option<Self> {
+        let MQValue::Dict { value } = value else {
+            return None;
+        };
+
+        if value.get("type").map(MQValue::text).as_deref() != Some("table") {
+            return None;
+        }
+
+        let cells = |value: &MQValue| -> Option<Vec<Node>> { value.values().iter().map(MQValue::as_node).collect() };
+
+        Some(Self {
+            align: value.get("align").and_then(MQValue::as_node),
+            header: cells(value.get("header")?)?,
+            rows: value
+                .get("rows")?
+                .values()
+                .iter()
+                .map(cells)
+                .collect::<Option<Vec<_>>>()?,
+        })
+    }
+
+    pub(crate) fn columns(&self) -> usize {
+        self.header.len()
+    }
+
+    pub(crate) fn add_row(&mut self, cells: Vec<Vec<Node>>) -> PyResult<()> {
+        if cells.len() != self.columns() {
+            return Err(PyValueError::new_err(format!(
+                "row has {} cells but the table has {} columns",
+                cells.len(),
+                self.columns()
+            )));
+        }
+
+        self.rows.push(cells.into_iter().map(cell).collect());
+        Ok(())
+    }
+
+    /// Replaces the content of a cell. Row 0 is the header row.
+    pub(crate) fn set_cell(&mut self, row: usize, column: usize, values: Vec<Node>) -> PyResult<()> {
+        let rows = self.rows.len() + 1;
+        let columns = self.columns();
+        let cells = match row {
+            0 => &mut self.header,
+            row => self.rows.get_mut(row - 1).ok_or_else(|| {
+                pyo3::exceptions::PyIndexError::new_err(format!(
+                    "row {} out of range for table with {} rows",
+                    row, rows
+                ))
+            })?,
+        };
+
+        if column >= columns {
+            return Err(pyo3::exceptions::PyIndexError::new_err(format!(
+                "column {} out of range for table with {} columns",
+                column, columns
+            )));
+        }
+
+        // Rows parsed from markdown can be shorter than the header.
+        cells.resize_with(columns.max(cells.len()), || cell(Vec::new()));
+        cells[column] = cell(values);
+        Ok(())
+    }
+
+    /// Sorts the body rows by a column, comparing numerically when both cells are numbers.
+    pub(crate) fn sort_by(&mut self, column: usize, reverse: bool) {
+        let key = |row: &Vec<Node>| row.get(column).map(Node::value).unwrap_or_default();
+
+        self.rows.sort_by(|a, b| {
+            let (a, b) = (key(a), key(b));
+            let ordering = match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+                (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                _ => a.cmp(&b),
+            };
+
+            if reverse { ordering.reverse() } else { ordering }
+        });
+    }
+
+    pub(crate) fn column_index(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|cell| cell.value().trim() == name)
+    }
+
+    /// Returns the header followed by the body rows, with cell coordinates renumbered.
+    fn numbered_rows(&self) -> Vec<Vec<Node>> {
+        std::iter::once(&self.header)
+            .chain(self.rows.iter())
+            .enumerate()
+            .map(|(row, cells)| {
+                cells
+                    .iter()
+                    .enumerate()
+                    .map(|(column, node)| match node {
+                        Node::TableCell(cell) => Node::TableCell(mq_markdown::TableCell {
+                            column,
+                            row,
+                            values: cell.values.clone(),
+                            position: None,
+                        }),
+                        node => node.clone(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub(crate) fn records(&self) -> Vec<Record> {
+        let cells = self.numbered_rows().into_iter().flatten().filter_map(|node| match node {
+            Node::TableCell(cell) => Some(cell),
+            _ => None,
+        });
+
+        records(cells)
+    }
+
+    /// Returns the table as the flat node sequence used by markdown documents.
+    pub(crate) fn to_nodes(&self) -> Vec<Node> {
+        let mut nodes = Vec::with_capacity((self.rows.len() + 1) * self.columns() + 1);
+
+        for (row, cells) in self.numbered_rows().into_iter().enumerate() {
+            nodes.extend(cells);
+
+            if row == 0 {
+                nodes.extend(self.align.clone());
+            }
+        }
+
+        nodes
+    }
+}
+
+impl TryFrom<Table> for MQValue {
+    type Error = PyErr;
+
+    fn try_from(table: Table) -> PyResult<Self> {
+        let node = |node: Node| -> PyResult<MQValue> { mq_lang::RuntimeValue::from(node).try_into() };
+        let array = |cells: Vec<Node>| -> PyResult<MQValue> {
+            Ok(MQValue::Array {
+                value: cells.into_iter().map(node).collect::<PyResult<_>>()?,
+            })
+        };
+        let mut rows = table.numbered_rows().into_iter();
+        let header = rows.next().unwrap_or_default();
+
+        // Keys are sorted, as in the dicts queries return.
+        Ok(MQValue::Dict {
+            value: IndexMap::from([
+                (
+                    "align".to_string(),
+                    match table.align {
+                        Some(align) => node(align)?,
+                        None => MQValue::Array { value: Vec::new() },
+                    },
+                ),
+                ("header".to_string(), array(header)?),
+                (
+                    "rows".to_string(),
+                    MQValue::Array {
+                        value: rows.map(array).collect::<PyResult<_>>()?,
+                    },
+                ),
+                ("type".to_string(), MQValue::string("table")),
+            ]),
+        })
+    }
+}
+
+/// Builds the body rows of a table from its cells, row 0 being the header, with the cells read as
+/// plain text. Rows shorter than the header get empty strings and cells past it are dropped.
+///
+/// A header repeated in the same table is numbered from its second column on, e.g. `a`, `a_2`, so
+/// that no column is lost.
+pub(crate) fn records(cells: impl IntoIterator<Item = mq_markdown::TableCell>) -> Vec<Record> {
+    let mut rows: BTreeMap<usize, BTreeMap<usize, String>> = BTreeMap::new();
+
+    for cell in cells {
+        let text = cell.values.iter().map(crate::section::plain_text).collect::<String>();
+        rows.entry(cell.row)
+            .or_default()
+            .insert(cell.column, text.trim().to_string());
+    }
+
+    let mut header = rows.remove(&0).unwrap_or_default();
+    let mut keys = Vec::<String>::with_capacity(header.len());
+
+    for name in header.values_mut() {
+        let key = (1..)
+            .map(|n| if n == 1 { name.clone() } else { format!("{}_{}", name, n) })
+            .find(|key| !keys.contains(key))
+            .unwrap_or_default();
+        keys.push(key.clone());
+        *name = key;
+    }
+
+    rows.into_values()
+        .map(|mut row| {
+            header
+                .iter()
+                .map(|(column, name)| (name.clone(), row.remove(column).unwrap_or_default()))
+                .collect()
+        })
+        .collect()
+}
+
+fn cell(values: Vec<Node>) -> Node {
+    Node::TableCell(mq_markdown::TableCell {
+        values,
+        column: 0,
+        row: 0,
+        position: None,
+    })
+}
@@ -0,0 +1,93 @@
+//! String builtins of the mq engine, exposed as the `mq.text` module.
+//!
+//! Each function runs the builtin of the same name, so Python code and queries share identical
+//! semantics, e.g. for slugs.
+use crate::{Options, engine, fluent};
+use mq_lang::RuntimeValue;
+use pyo3::prelude::*;
+
+/// Calls the builtin `name` with `s` as its first argument and `args` as string arguments.
+fn call(name: &str, s: &str, args: &[&str]) -> PyResult<String> {
+    let code = std::iter::once("self".to_string())
+        .chain(args.iter().map(|arg| fluent::string_literal(arg)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let values = engine::eval(
+        &format!("{}({})", name, code),
+        [RuntimeValue::String(s.to_string())],
+        &Options::default(),
+    )?;
+
+    Ok(match values.into_iter().next() {
+        Some(RuntimeValue::String(s)) => s,
+        Some(RuntimeValue::None) | None => String::new(),
+        Some(value) => value.to_string(),
+    })
+}
+
+/// Converts `s` to a URL-friendly slug as the `slugify` builtin does: lowercased, with runs of
+/// characters other than `a-z` and `0-9` replaced by `separator`.
+///
+/// Heading anchors use GitHub's slugs instead, see `mq.anchors`.
+#[pyfunction]
+#[pyo3(signature = (s, separator="-"))]
+fn slugify(s: &str, separator: &str) -> PyResult<String> {
+    call("slugify", s, &[separator])
+}
+
+#[pyfunction]
+fn trim(s: &str) -> PyResult<String> {
+    call("trim", s, &[])
+}
+
+#[pyfunction]
+fn ltrim(s: &str) -> PyResult<String> {
+    call("ltrim", s, &[])
+}
+
+#[pyfunction]
+fn rtrim(s: &str) -> PyResult<String> {
+    call("rtrim", s, &[])
+}
+
+/// Removes `prefix` from the start of `s` if it is there.
+#[pyfunction]
+fn ltrimstr(s: &str, prefix: &str) -> PyResult<String> {
+    call("ltrimstr", s, &[prefix])
+}
+
+/// Removes `suffix` from the end of `s` if it is there.
+#[pyfunction]
+fn rtrimstr(s: &str, suffix: &str) -> PyResult<String> {
+    call("rtrimstr", s, &[suffix])
+}
+
+#[pyfunction]
+fn upcase(s: &str) -> PyResult<String> {
+    call("upcase", s, &[])
+}
+
+#[pyfunction]
+fn downcase(s: &str) -> PyResult<String> {
+    call("downcase", s, &[])
+}
+
+/// Percent-encodes `s` for use in a URL.
+#[pyfunction]
+fn url_encode(s: &str) -> PyResult<String> {
+    call("url_encode", s, &[])
+}
+
+pub(crate) fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let text = PyModule::new(m.py(), "text")?;
+    text.add_function(wrap_pyfunction!(slugify, &text)?)?;
+    text.add_function(wrap_pyfunction!(trim, &text)?)?;
+    text.add_function(wrap_pyfunction!(ltrim, &text)?)?;
+    text.add_function(wrap_pyfunction!(rtrim, &text)?)?;
+    text.add_function(wrap_pyfunction!(ltrimstr, &text)?)?;
+    text.add_function(wrap_pyfunction!(rtrimstr, &text)?)?;
+    text.add_function(wrap_pyfunction!(upcase, &text)?)?;
+    text.add_function(wrap_pyfunction!(downcase, &text)?)?;
+    text.add_function(wrap_pyfunction!(url_encode, &text)?)?;
+    m.add_submodule(&text)
+}
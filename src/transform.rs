@@ -0,0 +1,789 @@
+//! Whole-document rewrites that take markdown in and return markdown out.
+use crate::{Options, content::Content, render, result};
+use indexmap::IndexMap;
+use mq_markdown::{Markdown, Node};
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::collections::HashMap;
+
+pub(crate) fn parse(content: &Content<'_>) -> PyResult<Vec<Node>> {
+    Markdown::from_markdown_str(content.as_str()?)
+        .map(|markdown| markdown.nodes)
+        .map_err(|e| crate::MQParseInputError::new_err(format!("Error parsing markdown: {}", e)))
+}
+
+/// Applies `f` to every node, depth first, replacing each node with the nodes it returns.
+pub(crate) fn rewrite(nodes: Vec<Node>, f: &mut impl FnMut(Node) -> Vec<Node>) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .flat_map(|mut node| {
+            let children = node.children();
+
+            if !children.is_empty() {
+                node.set_children(rewrite(children, f));
+            }
+
+            f(node)
+        })
+        .collect()
+}
+
+/// Replaces footnote references with the footnote text and removes the footnote definitions.
+///
+/// `style="parens"` inlines the text in parentheses; `style="link"` inlines footnotes that consist
+/// of a single link as that link, falling back to parentheses otherwise.
+#[pyfunction]
+#[pyo3(signature = (content, style="parens", options=None))]
+pub(crate) fn inline_footnotes(content: Content<'_>, style: &str, options: Option<Options>) -> PyResult<String> {
+    render::to_markdown(inline_footnote_nodes(parse(&content)?, style)?, options)
+}
+
+/// Inlines the footnotes of `nodes` as `inline_footnotes` does.
+pub(crate) fn inline_footnote_nodes(nodes: Vec<Node>, style: &str) -> PyResult<Vec<Node>> {
+    if !matches!(style, "parens" | "link") {
+        return Err(PyValueError::new_err(format!(
+            "invalid footnote style {:?}, expected 'parens' or 'link'",
+            style
+        )));
+    }
+
+    let footnotes = nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Footnote(footnote) => Some((footnote.ident.clone(), footnote.values.clone())),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    Ok(rewrite(nodes, &mut |node| match node {
+        Node::Footnote(_) => Vec::new(),
+        Node::FootnoteRef(reference) => match footnotes.get(&reference.ident) {
+            Some(values) if style == "link" && matches!(values.as_slice(), [Node::Link(_)]) => {
+                let mut link = values[0].clone();
+                link.set_position(reference.position.clone());
+                vec![
+                    Node::Text(mq_markdown::Text {
+                        value: " ".to_string(),
+                        position: reference.position,
+                    }),
+                    link,
+                ]
+            }
+            Some(values) => vec![Node::Text(mq_markdown::Text {
+                value: format!(
+                    " ({})",
+                    values.iter().map(|value| value.to_string()).collect::<String>().trim()
+                ),
+                position: reference.position,
+            })],
+            None => vec![Node::FootnoteRef(reference)],
+        },
+        node => vec![node],
+    }))
+}
+
+/// Renumbers footnotes as 1, 2, 3... in order of first reference and drops unreferenced ones.
+#[pyfunction]
+#[pyo3(signature = (content, options=None))]
+pub(crate) fn renumber_footnotes(content: Content<'_>, options: Option<Options>) -> PyResult<String> {
+    let nodes = parse(&content)?;
+    let mut numbers = HashMap::new();
+
+    let nodes = rewrite(nodes, &mut |node| match node {
+        Node::FootnoteRef(mut reference) => {
+            let next = numbers.len() + 1;
+            reference.ident = numbers.entry(reference.ident).or_insert(next).to_string();
+            reference.label = Some(reference.ident.clone());
+            vec![Node::FootnoteRef(reference)]
+        }
+        node => vec![node],
+    });
+
+    let mut footnotes = Vec::new();
+    let mut nodes = nodes
+        .into_iter()
+        .filter_map(|node| match node {
+            Node::Footnote(mut footnote) => {
+                if let Some(number) = numbers.get(&footnote.ident) {
+                    footnote.ident = number.to_string();
+                    footnotes.push((*number, footnote));
+                }
+
+                None
+            }
+            node => Some(node),
+        })
+        .collect::<Vec<_>>();
+
+    // Definitions move to the end of the document, each in its own block, so they read in numeric order.
+    let last_line = nodes
+        .iter()
+        .filter_map(|node| node.position().map(|position| position.end.line))
+        .max()
+        .unwrap_or(0);
+
+    footnotes.sort_by_key(|(number, _)| *number);
+    nodes.extend(footnotes.into_iter().enumerate().map(|(i, (_, mut footnote))| {
+        let line = last_line + 2 * (i + 1);
+        footnote.position = Some(mq_markdown::Position {
+            start: mq_markdown::Point { line, column: 1 },
+            end: mq_markdown::Point { line, column: 1 },
+        });
+        Node::Footnote(footnote)
+    }));
+
+    render::to_markdown(nodes, options)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CalloutStyle {
+    Blockquote,
+    Gfm,
+    Mkdocs,
+}
+
+/// A GFM alert, MkDocs admonition or blockquote starting with a bold label such as `**Note:**`.
+#[derive(Debug)]
+struct Callout {
+    style: CalloutStyle,
+    kind: String,
+    title: Option<String>,
+    body: Vec<String>,
+}
+
+const CALLOUT_KINDS: &[(&str, &str)] = &[
+    ("note", "NOTE"),
+    ("abstract", "NOTE"),
+    ("summary", "NOTE"),
+    ("info", "NOTE"),
+    ("question", "NOTE"),
+    ("example", "NOTE"),
+    ("quote", "NOTE"),
+    ("tip", "TIP"),
+    ("hint", "TIP"),
+    ("success", "TIP"),
+    ("important", "IMPORTANT"),
+    ("warning", "WARNING"),
+    ("attention", "WARNING"),
+    ("caution", "CAUTION"),
+    ("danger", "CAUTION"),
+    ("error", "CAUTION"),
+    ("failure", "CAUTION"),
+    ("bug", "CAUTION"),
+];
+
+impl Callout {
+    fn render(&self, style: CalloutStyle, output: &mut Vec<String>) {
+        match style {
+            CalloutStyle::Gfm => {
+                let kind = CALLOUT_KINDS
+                    .iter()
+                    .find(|(kind, _)| *kind == self.kind)
+                    .map_or("NOTE", |(_, alert)| *alert);
+                output.push(format!("> [!{}]", kind));
+                output.extend(self.title.iter().map(|title| format!("> **{}**", title)));
+                output.extend(self.body.iter().map(|line| quote(line)));
+            }
+            CalloutStyle::Mkdocs => {
+                let kind = match self.kind.as_str() {
+                    "caution" => "danger",
+                    kind => kind,
+                };
+                output.push(match &self.title {
+                    Some(title) => format!("!!! {} \"{}\"", kind, title),
+                    None => format!("!!! {}", kind),
+                });
+                output.extend(self.body.iter().map(|line| {
+                    if line.is_empty() {
+                        String::new()
+                    } else {
+                        format!("    {}", line)
+                    }
+                }));
+            }
+            CalloutStyle::Blockquote => {
+                let label = self.title.clone().unwrap_or_else(|| capitalize(&self.kind));
+                output.push(format!("> **{}**", label));
+
+                if !self.body.is_empty() {
+                    output.push(">".to_string());
+                }
+
+                output.extend(self.body.iter().map(|line| quote(line)));
+            }
+        }
+    }
+}
+
+fn quote(line: &str) -> String {
+    if line.is_empty() {
+        ">".to_string()
+    } else {
+        format!("> {}", line)
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Strips the blockquote marker from `line`, returning `None` if it is not a blockquote line.
+fn unquote(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+
+    let rest = trimmed.strip_prefix('>')?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+fn parse_callout(lines: &[&str]) -> Option<(Callout, usize)> {
+    let first = lines[0];
+
+    if let Some(quoted) = unquote(first) {
+        let len = lines.iter().take_while(|line| unquote(line).is_some()).count();
+        let mut body = lines[..len].iter().filter_map(|line| unquote(line)).map(str::to_string);
+        let head = body.next().unwrap_or_default();
+
+        if let Some(rest) = quoted.trim_start().strip_prefix("[!") {
+            let (kind, title) = rest.split_once(']')?;
+            let title = title.trim();
+            return Some((
+                Callout {
+                    style: CalloutStyle::Gfm,
+                    kind: kind.to_ascii_lowercase(),
+                    title: (!title.is_empty()).then(|| title.to_string()),
+                    body: body.collect(),
+                },
+                len,
+            ));
+        }
+
+        // A blockquote whose first line starts with a bold label naming a callout kind.
+        let label = head.trim_start().strip_prefix("**")?;
+        let (label, rest) = label.split_once("**")?;
+        let label = label.trim_end_matches(':').trim();
+        let rest = rest.trim_start_matches(':').trim();
+        let kind = label.to_ascii_lowercase();
+        CALLOUT_KINDS.iter().find(|(k, _)| *k == kind)?;
+
+        let mut body = body.collect::<Vec<_>>();
+
+        if rest.is_empty() {
+            while body.first().is_some_and(|line| line.trim().is_empty()) {
+                body.remove(0);
+            }
+        } else {
+            body.insert(0, rest.to_string());
+        }
+
+        return Some((
+            Callout {
+                style: CalloutStyle::Blockquote,
+                kind,
+                title: None,
+                body,
+            },
+            len,
+        ));
+    }
+
+    let rest = first
+        .strip_prefix("!!!")
+        .or_else(|| first.strip_prefix("???+"))
+        .or_else(|| first.strip_prefix("???"))?;
+
+    if !rest.starts_with(' ') {
+        return None;
+    }
+
+    let rest = rest.trim();
+    let (kind, title) = rest.split_once(' ').unwrap_or((rest, ""));
+    let title = title.trim().trim_matches('"');
+
+    if kind.is_empty() {
+        return None;
+    }
+
+    let mut len = 1 + lines[1..]
+        .iter()
+        .take_while(|line| line.trim().is_empty() || line.starts_with("    ") || line.starts_with('\t'))
+        .count();
+
+    while len > 1 && lines[len - 1].trim().is_empty() {
+        len -= 1;
+    }
+
+    let body = lines[1..len]
+        .iter()
+        .map(|line| {
+            line.strip_prefix("    ")
+                .or_else(|| line.strip_prefix('\t'))
+                .unwrap_or(line.trim())
+                .to_string()
+        })
+        .collect();
+
+    Some((
+        Callout {
+            style: CalloutStyle::Mkdocs,
+            kind: kind.to_ascii_lowercase(),
+            title: (!title.is_empty()).then(|| title.to_string()),
+            body,
+        },
+        len,
+    ))
+}
+
+/// Rewrites GFM alerts, MkDocs admonitions and labelled blockquotes into a single callout style.
+///
+/// Blocks already in the target style, and anything inside fenced code, are left untouched.
+#[pyfunction]
+#[pyo3(signature = (content, to="gfm"))]
+pub(crate) fn convert_callouts(content: Content<'_>, to: &str) -> PyResult<String> {
+    let target = match to {
+        "gfm" => CalloutStyle::Gfm,
+        "mkdocs" => CalloutStyle::Mkdocs,
+        "blockquote" => CalloutStyle::Blockquote,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "invalid callout style {:?}, expected 'gfm', 'mkdocs' or 'blockquote'",
+                to
+            )));
+        }
+    };
+
+    let content = content.as_str()?;
+    let lines = content.lines().collect::<Vec<_>>();
+    let mut output = Vec::with_capacity(lines.len());
+    let mut fence: Option<(char, usize)> = None;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+        let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'));
+        let marker_len = marker.map_or(0, |c| trimmed.chars().take_while(|ch| *ch == c).count());
+
+        match (fence, marker) {
+            (Some((ch, len)), Some(c)) if c == ch && marker_len >= len && trimmed[marker_len..].trim().is_empty() => {
+                fence = None;
+            }
+            (None, Some(c)) if marker_len >= 3 => fence = Some((c, marker_len)),
+            _ => {}
+        }
+
+        if fence.is_some() || marker_len >= 3 {
+            output.push(line.to_string());
+            i += 1;
+            continue;
+        }
+
+        match parse_callout(&lines[i..]) {
+            Some((callout, len)) => {
+                if callout.style == target {
+                    output.extend(lines[i..i + len].iter().map(|line| line.to_string()));
+                } else {
+                    callout.render(target, &mut output);
+                }
+
+                i += len;
+            }
+            None => {
+                // Keep other blockquotes whole so a label further down is not mistaken for a callout.
+                let len = lines[i..].iter().take_while(|line| unquote(line).is_some()).count().max(1);
+                output.extend(lines[i..i + len].iter().map(|line| line.to_string()));
+                i += len;
+            }
+        }
+    }
+
+    let mut result = output.join("\n");
+
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+/// The actions of a link policy: one action name or a list of them.
+#[derive(FromPyObject)]
+pub(crate) enum LinkActions {
+    One(String),
+    Many(Vec<String>),
+}
+
+const LINK_ACTIONS: [&str; 3] = ["nofollow", "https", "block"];
+
+/// Applies link policies by URL domain, e.g. `{"*.example.com": ["https", "nofollow"], "ads.net": "block"}`.
+///
+/// Patterns match the lowercased host of absolute URLs, with `*` matching any run of characters;
+/// the actions of every matching pattern apply. `"https"` rewrites `http://` URLs, `"nofollow"`
+/// writes the link as an HTML `<a>` with `rel="nofollow"` and `"block"` replaces the link with its
+/// text.
+#[pyfunction]
+#[pyo3(signature = (content, policies, options=None))]
+pub(crate) fn apply_link_policies(
+    content: Content<'_>,
+    policies: IndexMap<String, LinkActions>,
+    options: Option<Options>,
+) -> PyResult<String> {
+    let policies = policies
+        .into_iter()
+        .map(|(pattern, actions)| {
+            let actions = match actions {
+                LinkActions::One(action) => vec![action],
+                LinkActions::Many(actions) => actions,
+            };
+
+            if let Some(action) = actions.iter().find(|action| !LINK_ACTIONS.contains(&action.as_str())) {
+                return Err(PyValueError::new_err(format!(
+                    "invalid link action {:?}, expected one of: {}",
+                    action,
+                    LINK_ACTIONS.join(", ")
+                )));
+            }
+
+            Ok((pattern.to_ascii_lowercase(), actions))
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+
+    let nodes = rewrite(parse(&content)?, &mut |node| {
+        let Node::Link(mut link) = node else {
+            return vec![node];
+        };
+        let Some(domain) = result::domain(link.url.as_str()) else {
+            return vec![Node::Link(link)];
+        };
+        let actions = policies
+            .iter()
+            .filter(|(pattern, _)| wildcard_match(pattern, &domain))
+            .flat_map(|(_, actions)| actions.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+
+        if actions.contains(&"block") {
+            return link.values;
+        }
+
+        if actions.contains(&"https")
+            && let Some(rest) = link.url.as_str().strip_prefix("http://")
+        {
+            link.url = mq_markdown::Url::new(format!("https://{}", rest));
+        }
+
+        if !actions.contains(&"nofollow") {
+            return vec![Node::Link(link)];
+        }
+
+        let title = match &link.title {
+            Some(title) => format!(" title=\"{}\"", escape_attribute(&title.to_value())),
+            None => String::new(),
+        };
+        let html = |value: String| {
+            Node::Html(mq_markdown::Html {
+                value,
+                position: link.position.clone(),
+            })
+        };
+
+        let mut nodes = vec![html(format!(
+            "<a href=\"{}\"{} rel=\"nofollow\">",
+            escape_attribute(link.url.as_str()),
+            title
+        ))];
+        nodes.extend(link.values.clone());
+        nodes.push(html("</a>".to_string()));
+        nodes
+    });
+
+    render::to_markdown(nodes, options)
+}
+
+/// Matches `text` against `pattern`, where `*` matches any run of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => {
+            text.starts_with(prefix)
+                && (0..=text.len() - prefix.len())
+                    .filter(|i| text.is_char_boundary(prefix.len() + i))
+                    .any(|i| wildcard_match(rest, &text[prefix.len() + i..]))
+        }
+    }
+}
+
+pub(crate) fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Raises every heading deeper than `max_depth` to that level, e.g. H5 and H6 to H4 for `max_depth=4`.
+#[pyfunction]
+#[pyo3(signature = (content, max_depth, options=None))]
+pub(crate) fn limit_heading_depth(content: Content<'_>, max_depth: u8, options: Option<Options>) -> PyResult<String> {
+    if !(1..=6).contains(&max_depth) {
+        return Err(PyValueError::new_err(format!(
+            "max_depth must be between 1 and 6, got {}",
+            max_depth
+        )));
+    }
+
+    let nodes = rewrite(parse(&content)?, &mut |node| match node {
+        Node::Heading(mut heading) => {
+            heading.depth = heading.depth.min(max_depth);
+            vec![Node::Heading(heading)]
+        }
+        node => vec![node],
+    });
+
+    render::to_markdown(nodes, options)
+}
+
+/// Words kept lowercase in title case unless they start or end the heading.
+const SMALL_WORDS: [&str; 18] = [
+    "a", "an", "and", "as", "at", "but", "by", "for", "in", "nor", "of", "on", "or", "the", "to", "via", "vs", "with",
+];
+
+/// Rewrites every heading in sentence or title case, optionally dropping trailing punctuation.
+///
+/// Words with capitals after their first letter, such as `API` or `GitHub`, are kept as they are,
+/// as is text in inline code and HTML.
+#[pyfunction]
+#[pyo3(signature = (content, case="sentence", strip_trailing_punct=true, options=None))]
+pub(crate) fn normalize_headings(
+    content: Content<'_>,
+    case: &str,
+    strip_trailing_punct: bool,
+    options: Option<Options>,
+) -> PyResult<String> {
+    let title = match case {
+        "sentence" => false,
+        "title" => true,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "invalid heading case {:?}, expected 'sentence' or 'title'",
+                case
+            )));
+        }
+    };
+
+    let nodes = rewrite(parse(&content)?, &mut |node| {
+        let Node::Heading(mut heading) = node else {
+            return vec![node];
+        };
+
+        if strip_trailing_punct && let Some(Node::Text(text)) = heading.values.last_mut() {
+            text.value = text.value.trim_end_matches(['.', ',', ';', ':', '!']).trim_end().to_string();
+        }
+
+        let words = heading.values.iter().map(count_words).sum();
+        let mut index = 0;
+        heading.values = heading
+            .values
+            .into_iter()
+            .map(|node| recase(node, title, words, &mut index))
+            .collect();
+
+        vec![Node::Heading(heading)]
+    });
+
+    render::to_markdown(nodes, options)
+}
+
+fn count_words(node: &Node) -> usize {
+    match node {
+        Node::Text(text) => text.value.split_whitespace().count(),
+        Node::CodeInline(_) | Node::MathInline(_) | Node::Html(_) => 0,
+        node => node.children().iter().map(count_words).sum(),
+    }
+}
+
+/// Recases the words of `node`, where `index` counts the words of the heading seen so far.
+fn recase(mut node: Node, title: bool, words: usize, index: &mut usize) -> Node {
+    match node {
+        Node::Text(ref mut text) => {
+            let mut value = String::new();
+            let mut rest = text.value.as_str();
+
+            while !rest.is_empty() {
+                let space = rest.len() - rest.trim_start().len();
+                value.push_str(&rest[..space]);
+                rest = &rest[space..];
+
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+
+                if end > 0 {
+                    value.push_str(&recase_word(&rest[..end], title, *index == 0, *index + 1 == words));
+                    *index += 1;
+                }
+
+                rest = &rest[end..];
+            }
+
+            text.value = value;
+            node
+        }
+        Node::CodeInline(_) | Node::MathInline(_) | Node::Html(_) => node,
+        _ => {
+            let children = node.children();
+
+            if !children.is_empty() {
+                node.set_children(
+                    children
+                        .into_iter()
+                        .map(|child| recase(child, title, words, index))
+                        .collect(),
+                );
+            }
+
+            node
+        }
+    }
+}
+
+fn recase_word(word: &str, title: bool, first: bool, last: bool) -> String {
+    let letters = word.chars().filter(|c| c.is_alphabetic()).collect::<Vec<_>>();
+
+    // Acronyms and names such as `API`, `GitHub` or `iOS`.
+    if letters.iter().skip(1).any(|c| c.is_uppercase()) {
+        return word.to_string();
+    }
+
+    let lower = word.to_lowercase();
+    let small = SMALL_WORDS.contains(&lower.trim_matches(|c: char| !c.is_alphanumeric()));
+    let capitalize = first || (title && (last || !small));
+
+    if !capitalize {
+        return lower;
+    }
+
+    match lower.char_indices().find(|(_, c)| c.is_alphabetic()) {
+        Some((i, c)) => format!("{}{}{}", &lower[..i], c.to_uppercase(), &lower[i + c.len_utf8()..]),
+        None => lower,
+    }
+}
+
+/// Common aliases of code block languages and the names they normalize to.
+const LANGUAGE_ALIASES: [(&str, &str); 27] = [
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("cjs", "javascript"),
+    ("ts", "typescript"),
+    ("py", "python"),
+    ("py3", "python"),
+    ("python3", "python"),
+    ("sh", "bash"),
+    ("shell", "bash"),
+    ("yml", "yaml"),
+    ("md", "markdown"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("kt", "kotlin"),
+    ("cs", "csharp"),
+    ("c#", "csharp"),
+    ("c++", "cpp"),
+    ("cc", "cpp"),
+    ("hpp", "cpp"),
+    ("golang", "go"),
+    ("ps1", "powershell"),
+    ("pwsh", "powershell"),
+    ("docker", "dockerfile"),
+    ("htm", "html"),
+    ("txt", "text"),
+    ("plaintext", "text"),
+    ("patch", "diff"),
+];
+
+/// Languages that highlighters commonly know, besides the targets of `LANGUAGE_ALIASES`.
+const KNOWN_LANGUAGES: [&str; 28] = [
+    "c",
+    "console",
+    "css",
+    "csv",
+    "graphql",
+    "haskell",
+    "ini",
+    "java",
+    "json",
+    "jsonc",
+    "jsx",
+    "lua",
+    "makefile",
+    "math",
+    "mermaid",
+    "mq",
+    "nginx",
+    "perl",
+    "php",
+    "r",
+    "scala",
+    "scss",
+    "sql",
+    "swift",
+    "toml",
+    "tsx",
+    "xml",
+    "zsh",
+];
+
+/// Normalizes code block languages: lowercased and mapped through the built-in aliases and
+/// `aliases`, with `default` for blocks without a language.
+///
+/// Returns the document and the languages that are neither a known language nor an alias target,
+/// in order of first appearance.
+#[pyfunction]
+#[pyo3(signature = (content, aliases=None, default=None, known=None, options=None))]
+pub(crate) fn normalize_code_languages(
+    content: Content<'_>,
+    aliases: Option<HashMap<String, String>>,
+    default: Option<String>,
+    known: Option<Vec<String>>,
+    options: Option<Options>,
+) -> PyResult<(String, Vec<String>)> {
+    let mut table = LANGUAGE_ALIASES
+        .iter()
+        .map(|(alias, name)| (alias.to_string(), name.to_string()))
+        .collect::<HashMap<_, _>>();
+    table.extend(aliases.unwrap_or_default().into_iter().map(|(alias, name)| (alias.to_lowercase(), name)));
+
+    let known = KNOWN_LANGUAGES
+        .iter()
+        .map(|name| name.to_string())
+        .chain(table.values().cloned())
+        .chain(known.unwrap_or_default())
+        .collect::<std::collections::HashSet<_>>();
+    let mut unknown = Vec::new();
+
+    let nodes = rewrite(parse(&content)?, &mut |node| {
+        let Node::Code(mut code) = node else {
+            return vec![node];
+        };
+
+        code.lang = match code.lang.take().filter(|lang| !lang.trim().is_empty()) {
+            Some(lang) => {
+                let lang = lang.to_lowercase();
+                let lang = table.get(&lang).cloned().unwrap_or(lang);
+
+                if !known.contains(&lang) && !unknown.contains(&lang) {
+                    unknown.push(lang.clone());
+                }
+
+                Some(lang)
+            }
+            None => default.clone(),
+        };
+
+        vec![Node::Code(code)]
+    });
+
+    Ok((render::to_markdown(nodes, options)?, unknown))
+}
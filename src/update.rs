@@ -0,0 +1,240 @@
+//! Applying a query to a document in place, as `mq --update` does.
+use crate::{Options, content::Content, engine, glob, input, nodes, render, value::{self, MQValue}, with_source};
+use mq_lang::{RuntimeValue, RuntimeValues};
+use mq_markdown::Node;
+use indexmap::IndexMap;
+use pyo3::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// The outcome of an update, with the positions of the nodes the query changed.
+#[pyclass(frozen, skip_from_py_object)]
+#[derive(Debug, Clone)]
+pub(crate) struct UpdateResult {
+    #[pyo3(get)]
+    content: String,
+    #[pyo3(get)]
+    changed: bool,
+    #[pyo3(get)]
+    changed_positions: Vec<(usize, usize)>,
+    /// The content before the update, for `diff`.
+    original: String,
+    /// The file the update applies to, if any.
+    #[pyo3(get)]
+    path: Option<String>,
+}
+
+#[pymethods]
+impl UpdateResult {
+    /// Returns a unified diff from the original content to the updated one, empty if nothing changed.
+    #[pyo3(signature = (context=3))]
+    fn diff(&self, context: usize) -> String {
+        if !self.changed {
+            return String::new();
+        }
+
+        let path = self.path.as_deref().unwrap_or("content");
+        similar::TextDiff::from_lines(&self.original, &self.content)
+            .unified_diff()
+            .context_radius(context)
+            .header(path, path)
+            .to_string()
+    }
+
+    fn __bool__(&self) -> bool {
+        self.changed
+    }
+
+    fn __str__(&self) -> String {
+        self.content.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "UpdateResult(changed={}, changed_positions={:?})",
+            if self.changed { "True" } else { "False" },
+            self.changed_positions
+        )
+    }
+}
+
+pub(crate) fn apply(code: &str, content: &str, options: &Options) -> PyResult<UpdateResult> {
+    let input = input::parse(content, options)?;
+    let values = engine::eval(code, input.clone(), options)?;
+    write_back(content, input, values.into(), options)
+}
+
+/// Replaces each node of `input` with its value in `values`, rendering the document only if one changed.
+fn write_back(
+    content: &str,
+    input: Vec<RuntimeValue>,
+    values: RuntimeValues,
+    options: &Options,
+) -> PyResult<UpdateResult> {
+    let updated = RuntimeValues::from(input.clone()).update_with(values);
+
+    let changed_positions = input
+        .iter()
+        .zip(updated.values())
+        .filter(|(before, after)| before != after)
+        .filter_map(|(before, _)| before.position())
+        .map(|position| (position.start.line, position.start.column))
+        .collect::<Vec<_>>();
+
+    // An unchanged document is returned as is rather than re-rendered, which could reformat it.
+    if changed_positions.is_empty() {
+        return Ok(UpdateResult {
+            content: content.to_string(),
+            changed: false,
+            changed_positions,
+            original: content.to_string(),
+            path: None,
+        });
+    }
+
+    let mut nodes = Vec::new();
+    updated.into_iter().for_each(|value| collect_nodes(value, &mut nodes));
+
+    Ok(UpdateResult {
+        content: render::to_markdown(nodes, Some(options.clone()))?,
+        changed: true,
+        changed_positions,
+        original: content.to_string(),
+        path: None,
+    })
+}
+
+fn collect_nodes(value: RuntimeValue, nodes: &mut Vec<Node>) {
+    match value {
+        RuntimeValue::Markdown(node, _) => nodes.push(*node),
+        RuntimeValue::Array(values) => values.into_iter().for_each(|value| collect_nodes(value, nodes)),
+        _ => {}
+    }
+}
+
+/// Applies `code` to every node of `content`, replacing each node with the query's result for it.
+///
+/// Nodes the query yields nothing for are kept unchanged.
+#[pyfunction]
+#[pyo3(signature = (code, content, options=None))]
+pub(crate) fn update(code: &str, content: Content<'_>, options: Option<Options>) -> PyResult<UpdateResult> {
+    apply(code, content.as_str()?, &options.unwrap_or_default())
+}
+
+/// Selects nodes of `content` with `code` and replaces each match with what `replacer` returns
+/// for it, leaving the rest of the document as it is.
+///
+/// `replacer` is called with the `MQValue` of every match and may return a string, which replaces
+/// the node's text, an `mq.nodes.Node` or `MQValue` to replace the node, or None to keep it.
+#[pyfunction]
+#[pyo3(signature = (code, content, replacer, options=None))]
+pub(crate) fn replace(
+    code: &str,
+    content: Content<'_>,
+    replacer: &Bound<'_, PyAny>,
+    options: Option<Options>,
+) -> PyResult<UpdateResult> {
+    let options = options.unwrap_or_default();
+    let content = content.as_str()?;
+    let input = input::parse(content, &options)?;
+    let values = engine::eval(code, input.clone(), &options)?
+        .into_iter()
+        .map(|value| {
+            let matched = MQValue::try_from(value.clone())?;
+
+            // Empty values stand in for nodes the query did not match.
+            if matched.__len__() == 0 {
+                return Ok(value);
+            }
+
+            let replacement = replacer.call1((matched,))?;
+
+            match replacement.cast::<nodes::Node>() {
+                Ok(node) => Ok(RuntimeValue::Markdown(Box::new(node.borrow().node.clone()), None)),
+                Err(_) => value::runtime_value(&replacement, "replacer result"),
+            }
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+
+    write_back(content, input, values.into(), &options)
+}
+
+/// Updates the file at `path` in place, writing it only when the query changed something.
+#[pyfunction]
+#[pyo3(signature = (code, path, options=None))]
+pub(crate) fn update_file(code: &str, path: PathBuf, options: Option<Options>) -> PyResult<UpdateResult> {
+    apply_file(code, &path, &options.unwrap_or_default(), false)
+}
+
+fn apply_file(code: &str, path: &Path, options: &Options, dry_run: bool) -> PyResult<UpdateResult> {
+    let content = std::fs::read_to_string(path)?;
+    let mut result = apply(code, &content, options)?;
+    result.path = Some(path.to_string_lossy().into_owned());
+
+    if result.changed && !dry_run {
+        write_atomic(path, &result.content)?;
+    }
+
+    Ok(result)
+}
+
+/// Replaces the file at `path` with `content` through a temporary file in the same directory, so
+/// an interrupted write never leaves a truncated file behind.
+fn write_atomic(path: &Path, content: &str) -> std::io::Result<()> {
+    let name = path.file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+
+    let written = std::fs::write(&temp, content)
+        .and_then(|_| std::fs::set_permissions(&temp, std::fs::metadata(path)?.permissions()))
+        .and_then(|_| std::fs::rename(&temp, path));
+
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+
+    written
+}
+
+/// Updates every file matching the glob `pattern`, where `**` matches any number of directories.
+///
+/// With `dry_run=True` nothing is written, so the results and their diffs can be reviewed first.
+/// With `all_or_nothing=True` every file is updated before any is written, and files already
+/// written are restored if a later write fails.
+#[pyfunction]
+#[pyo3(signature = (code, pattern, options=None, dry_run=false, all_or_nothing=false))]
+pub(crate) fn update_glob(
+    py: Python<'_>,
+    code: &str,
+    pattern: &str,
+    options: Option<Options>,
+    dry_run: bool,
+    all_or_nothing: bool,
+) -> PyResult<IndexMap<String, UpdateResult>> {
+    let options = options.unwrap_or_default();
+    let results = glob(py, pattern)?
+        .into_iter()
+        .map(|path| {
+            let path = path.to_string_lossy().into_owned();
+            let result = apply_file(code, path.as_ref(), &options, dry_run || all_or_nothing)
+                .map_err(|e| with_source(e, Some(&path)))?;
+            Ok((path, result))
+        })
+        .collect::<PyResult<IndexMap<_, _>>>()?;
+
+    if all_or_nothing && !dry_run {
+        let mut written = Vec::new();
+
+        for (path, result) in results.iter().filter(|(_, result)| result.changed) {
+            if let Err(e) = write_atomic(path.as_ref(), &result.content) {
+                for (path, original) in written {
+                    let _ = write_atomic(Path::new(path), original);
+                }
+
+                return Err(with_source(e.into(), Some(path)));
+            }
+
+            written.push((path, &result.original));
+        }
+    }
+
+    Ok(results)
+}
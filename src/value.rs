@@ -1,4 +1,13 @@
-use pyo3::pyclass;
+use crate::{
+    Options, UnicodeNormalization, builder, extract, input, nodes, render,
+    result::{Index, position, slice_values},
+    table::Table,
+};
+use indexmap::IndexMap;
+use pyo3::{
+    IntoPyObjectExt, pyclass,
+    types::{PyBool, PyDict, PyFloat, PyInt, PyIterator, PyList, PyNone, PyString, PyTuple},
+};
 use std::{collections::HashMap, fmt};
 
 #[pyclass(from_py_object)]
@@ -8,12 +17,224 @@ pub enum MQValue {
         value: Vec<MQValue>,
     },
     Dict {
-        value: HashMap<String, MQValue>,
+        value: IndexMap<String, MQValue>,
     },
+    #[pyo3(constructor = (value, markdown_type, node = MarkdownNode::default(), annotations = None))]
     Markdown {
         value: String,
         markdown_type: MarkdownType,
+        node: MarkdownNode,
+        annotations: Option<Box<Annotations>>,
     },
+    /// A string built by the query, e.g. by `to_text()`, as opposed to a text node.
+    #[pyo3(constructor = (value, source = None, stage = None, stage_name = None))]
+    String {
+        value: String,
+        source: Option<String>,
+        stage: Option<usize>,
+        stage_name: Option<String>,
+    },
+    #[pyo3(constructor = (value, source = None, stage = None, stage_name = None))]
+    Number {
+        value: Number,
+        source: Option<String>,
+        stage: Option<usize>,
+        stage_name: Option<String>,
+    },
+    #[pyo3(constructor = (value, source = None, stage = None, stage_name = None))]
+    Bool {
+        value: bool,
+        source: Option<String>,
+        stage: Option<usize>,
+        stage_name: Option<String>,
+    },
+    /// The engine's `None`, named `Null` in Python as `None` is a keyword there.
+    #[pyo3(name = "Null", constructor = (source = None, stage = None, stage_name = None))]
+    None {
+        source: Option<String>,
+        stage: Option<usize>,
+        stage_name: Option<String>,
+    },
+}
+
+/// A number from the engine, an `int` in Python when it is integral.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Number(pub(crate) f64);
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", mq_lang::RuntimeValue::Number(self.0.into()))
+    }
+}
+
+impl<'py> IntoPyObject<'py> for Number {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> PyResult<Self::Output> {
+        match self.0 {
+            // Integral numbers come back as `int`, as Python users would write them.
+            n if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => (n as i64).into_bound_py_any(py),
+            n => n.into_bound_py_any(py),
+        }
+    }
+}
+
+impl<'a, 'py> FromPyObject<'a, 'py> for Number {
+    type Error = PyErr;
+
+    fn extract(obj: Borrowed<'a, 'py, PyAny>) -> PyResult<Self> {
+        if obj.is_instance_of::<PyBool>() {
+            return Err(pyo3::exceptions::PyTypeError::new_err("MQValue.Number requires an int or a float"));
+        }
+
+        Ok(Self(obj.extract()?))
+    }
+}
+
+/// The markdown node a value was produced from, exposed to Python as an `mq.nodes.Node`.
+///
+/// Keeping the node lets values be modified structurally instead of by editing rendered text.
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownNode(pub(crate) Option<Box<mq_markdown::Node>>);
+
+impl<'py> IntoPyObject<'py> for MarkdownNode {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> PyResult<Self::Output> {
+        match self.0 {
+            Some(node) => nodes::to_py(py, *node),
+            None => Ok(PyNone::get(py).to_owned().into_any()),
+        }
+    }
+}
+
+impl<'a, 'py> FromPyObject<'a, 'py> for MarkdownNode {
+    type Error = PyErr;
+
+    fn extract(obj: Borrowed<'a, 'py, PyAny>) -> PyResult<Self> {
+        if obj.is_none() {
+            return Ok(Self::default());
+        }
+
+        Ok(Self(Some(Box::new(obj.cast::<nodes::Node>()?.borrow().node.clone()))))
+    }
+}
+
+/// What is known about a markdown value beyond its node: where it came from in the input and which
+/// query stage produced it. Boxed on the value, as most values built by the query have none.
+#[derive(Debug, Clone, Default)]
+pub struct Annotations {
+    pub(crate) section_path: Vec<String>,
+    pub(crate) source: Option<String>,
+    pub(crate) start: Option<usize>,
+    pub(crate) table_index: Option<usize>,
+    pub(crate) column_alignment: Option<String>,
+    pub(crate) stage: Option<usize>,
+    pub(crate) stage_name: Option<String>,
+    pub(crate) offsets: Option<(usize, usize)>,
+    pub(crate) anchor: Option<String>,
+}
+
+impl<'py> IntoPyObject<'py> for Box<Annotations> {
+    type Target = PyDict;
+    type Output = Bound<'py, PyDict>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> PyResult<Self::Output> {
+        let dict = PyDict::new(py);
+        dict.set_item("section_path", self.section_path)?;
+        dict.set_item("source", self.source)?;
+        dict.set_item("start", self.start)?;
+        dict.set_item("table_index", self.table_index)?;
+        dict.set_item("column_alignment", self.column_alignment)?;
+        dict.set_item("stage", self.stage)?;
+        dict.set_item("stage_name", self.stage_name)?;
+        dict.set_item("offsets", self.offsets)?;
+        dict.set_item("anchor", self.anchor)?;
+        Ok(dict)
+    }
+}
+
+impl<'a, 'py> FromPyObject<'a, 'py> for Box<Annotations> {
+    type Error = PyErr;
+
+    fn extract(obj: Borrowed<'a, 'py, PyAny>) -> PyResult<Self> {
+        let mut annotations = Annotations::default();
+
+        for (key, value) in obj.cast::<PyDict>()?.iter() {
+            match key.extract::<String>()?.as_str() {
+                "section_path" => annotations.section_path = value.extract()?,
+                "source" => annotations.source = value.extract()?,
+                "start" => annotations.start = value.extract()?,
+                "table_index" => annotations.table_index = value.extract()?,
+                "column_alignment" => annotations.column_alignment = value.extract()?,
+                "stage" => annotations.stage = value.extract()?,
+                "stage_name" => annotations.stage_name = value.extract()?,
+                "offsets" => annotations.offsets = value.extract()?,
+                "anchor" => annotations.anchor = value.extract()?,
+                key => {
+                    return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                        "unknown markdown annotation {:?}",
+                        key
+                    )));
+                }
+            }
+        }
+
+        Ok(Box::new(annotations))
+    }
+}
+
+/// Where a node is in the input document. Lines and columns start at 1 and columns count bytes;
+/// the end is exclusive.
+#[pyclass(frozen, eq, skip_from_py_object, module = "mq")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    #[pyo3(get)]
+    start_line: usize,
+    #[pyo3(get)]
+    start_column: usize,
+    #[pyo3(get)]
+    end_line: usize,
+    #[pyo3(get)]
+    end_column: usize,
+    /// The byte offsets of the start and end in the document, when the node came from the input.
+    #[pyo3(get)]
+    start_offset: Option<usize>,
+    #[pyo3(get)]
+    end_offset: Option<usize>,
+}
+
+impl Position {
+    pub(crate) fn new(position: mq_markdown::Position, offsets: Option<(usize, usize)>) -> Self {
+        Self {
+            start_line: position.start.line,
+            start_column: position.start.column,
+            end_line: position.end.line,
+            end_column: position.end.column,
+            start_offset: offsets.map(|(start, _)| start),
+            end_offset: offsets.map(|(_, end)| end),
+        }
+    }
+}
+
+#[pymethods]
+impl Position {
+    pub(crate) fn __repr__(&self) -> String {
+        let offsets = match (self.start_offset, self.end_offset) {
+            (Some(start), Some(end)) => format!(", offsets={}..{}", start, end),
+            _ => String::new(),
+        };
+
+        format!(
+            "Position({}:{}-{}:{}{})",
+            self.start_line, self.start_column, self.end_line, self.end_column, offsets
+        )
+    }
 }
 
 impl fmt::Display for MQValue {
@@ -37,7 +258,10 @@ impl fmt::Display for MQValue {
                     .collect::<Vec<String>>()
                     .join("\n")
             ),
-            MQValue::Markdown { value, .. } => write!(f, "{}", value),
+            MQValue::Markdown { value, .. } | MQValue::String { value, .. } => write!(f, "{}", value),
+            MQValue::Number { value, .. } => write!(f, "{}", value),
+            MQValue::Bool { value, .. } => write!(f, "{}", value),
+            MQValue::None { .. } => Ok(()),
         }
     }
 }
@@ -50,19 +274,25 @@ impl PartialEq for MQValue {
                 MQValue::Markdown {
                     value: a,
                     markdown_type: at,
+                    ..
                 },
                 MQValue::Markdown {
                     value: b,
                     markdown_type: bt,
+                    ..
                 },
             ) => a == b && at == bt,
+            (MQValue::String { value: a, .. }, MQValue::String { value: b, .. }) => a == b,
+            (MQValue::Number { value: a, .. }, MQValue::Number { value: b, .. }) => a == b,
+            (MQValue::Bool { value: a, .. }, MQValue::Bool { value: b, .. }) => a == b,
+            (MQValue::None { .. }, MQValue::None { .. }) => true,
             _ => false,
         }
     }
 }
 
-#[pyclass(eq, eq_int, from_py_object)]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[pyclass(eq, eq_int, hash, frozen, from_py_object, module = "mq")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MarkdownType {
     Blockquote,
     Break,
@@ -96,59 +326,148 @@ pub enum MarkdownType {
     MdxJsEsm,
     Text,
     Empty,
+    OrderedList,
 }
 
-impl From<mq_lang::RuntimeValue> for MQValue {
-    fn from(value: mq_lang::RuntimeValue) -> Self {
-        match value {
+impl TryFrom<mq_lang::RuntimeValue> for MQValue {
+    type Error = PyErr;
+
+    fn try_from(value: mq_lang::RuntimeValue) -> PyResult<Self> {
+        Ok(match value {
             mq_lang::RuntimeValue::Array(arr) => MQValue::Array {
-                value: arr.into_iter().map(|v| v.into()).collect(),
-            },
-            mq_lang::RuntimeValue::Dict(map) => MQValue::Dict {
-                value: map
+                value: arr
                     .into_iter()
-                    .map(|(k, v)| (k.as_str(), v.into()))
-                    .collect(),
-            },
-            mq_lang::RuntimeValue::Markdown(node, _) => MQValue::Markdown {
-                value: node.to_string(),
-                markdown_type: (*node).into(),
-            },
-            mq_lang::RuntimeValue::String(s) => MQValue::Markdown {
-                value: s,
-                markdown_type: MarkdownType::Text,
-            },
-            mq_lang::RuntimeValue::Symbol(i) => MQValue::Markdown {
-                value: i.as_str(),
-                markdown_type: MarkdownType::Text,
+                    .map(MQValue::try_from)
+                    .collect::<PyResult<_>>()?,
             },
-            mq_lang::RuntimeValue::Number(n) => MQValue::Markdown {
-                value: n.to_string(),
-                markdown_type: MarkdownType::Text,
+            // mq-lang orders keys by when the process first saw each name and keeps no insertion order,
+            // so keys are sorted to be the same on every run.
+            mq_lang::RuntimeValue::Dict(map) => {
+                let mut value = map
+                    .into_iter()
+                    .map(|(k, v)| Ok((k.as_str(), v.try_into()?)))
+                    .collect::<PyResult<IndexMap<_, _>>>()?;
+                value.sort_unstable_keys();
+                MQValue::Dict { value }
+            }
+            mq_lang::RuntimeValue::Markdown(node, _) => {
+                let value = render::apply((*node).clone())?.to_string();
+                let markdown_type = MarkdownType::from((*node).clone());
+
+                // Empty nodes and fragments stand in for `None` in the results of markdown input.
+                if value.is_empty() && markdown_type == MarkdownType::Empty {
+                    return Ok(MQValue::none());
+                }
+
+                MQValue::Markdown {
+                    value,
+                    markdown_type,
+                    node: MarkdownNode(Some(node)),
+                    annotations: None,
+                }
+            }
+            mq_lang::RuntimeValue::String(s) => MQValue::string(s),
+            mq_lang::RuntimeValue::Symbol(i) => MQValue::string(i.as_str()),
+            mq_lang::RuntimeValue::Number(n) => MQValue::Number {
+                value: Number(n.value()),
+                source: None,
+                stage: None,
+                stage_name: None,
             },
-            mq_lang::RuntimeValue::Boolean(b) => MQValue::Markdown {
-                value: b.to_string(),
-                markdown_type: MarkdownType::Text,
+            mq_lang::RuntimeValue::Boolean(b) => MQValue::Bool {
+                value: b,
+                source: None,
+                stage: None,
+                stage_name: None,
             },
             mq_lang::RuntimeValue::Function(..)
             | mq_lang::RuntimeValue::NativeFunction(..)
             | mq_lang::RuntimeValue::Module(..)
-            | mq_lang::RuntimeValue::Ast(..) => MQValue::Markdown {
-                value: "".to_string(),
-                markdown_type: MarkdownType::Empty,
-            },
-            mq_lang::RuntimeValue::Bytes(b) => MQValue::Markdown {
-                value: String::from_utf8_lossy(&b).to_string(),
-                markdown_type: MarkdownType::Text,
-            },
-            mq_lang::RuntimeValue::None => MQValue::Markdown {
-                value: "".to_string(),
-                markdown_type: MarkdownType::Empty,
-            },
-        }
+            | mq_lang::RuntimeValue::Ast(..) => MQValue::none(),
+            mq_lang::RuntimeValue::Bytes(b) => MQValue::string(String::from_utf8_lossy(&b).to_string()),
+            mq_lang::RuntimeValue::None => MQValue::none(),
+        })
     }
 }
 
+/// Converts a Python value into an mq value, for binding as a query variable. `path` names the
+/// value in errors.
+///
+/// `MQValue`s keep their nodes, so a value from one query can be passed to the next.
+pub(crate) fn runtime_value(value: &Bound<'_, PyAny>, path: &str) -> PyResult<mq_lang::RuntimeValue> {
+    if value.is_none() {
+        Ok(mq_lang::RuntimeValue::None)
+    } else if let Ok(b) = value.cast::<PyBool>() {
+        Ok(mq_lang::RuntimeValue::Boolean(b.is_true()))
+    } else if value.is_instance_of::<PyInt>() {
+        Ok(mq_lang::RuntimeValue::Number(value.extract::<i64>()?.into()))
+    } else if value.is_instance_of::<PyFloat>() {
+        Ok(mq_lang::RuntimeValue::Number(value.extract::<f64>()?.into()))
+    } else if value.is_instance_of::<PyString>() {
+        Ok(mq_lang::RuntimeValue::String(value.extract::<String>()?))
+    } else if let Ok(value) = value.cast::<MQValue>() {
+        Ok(value.borrow().to_runtime_value())
+    } else if let Ok(dict) = value.cast::<PyDict>() {
+        dict.iter()
+            .map(|(k, v)| {
+                let key = k.extract::<String>().map_err(|_| {
+                    pyo3::exceptions::PyTypeError::new_err(format!("{}: dict keys must be strings", path))
+                })?;
+                let value = runtime_value(&v, &format!("{}[{:?}]", path, key))?;
+                Ok((mq_lang::Ident::new(&key), value))
+            })
+            .collect::<PyResult<_>>()
+            .map(mq_lang::RuntimeValue::Dict)
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        value
+            .try_iter()?
+            .enumerate()
+            .map(|(i, item)| runtime_value(&item?, &format!("{}[{}]", path, i)))
+            .collect::<PyResult<_>>()
+            .map(mq_lang::RuntimeValue::Array)
+    } else {
+        Err(pyo3::exceptions::PyTypeError::new_err(format!(
+            "{}: cannot use a value of type {} in a query",
+            path,
+            value.get_type().name()?
+        )))
+    }
+}
+
+/// Collects the non-fatal issues that converting `value` to an `MQValue` runs into, labelled
+/// with `path`.
+pub(crate) fn conversion_warnings(value: &mq_lang::RuntimeValue, path: &str, warnings: &mut Vec<String>) {
+    let dropped = match value {
+        mq_lang::RuntimeValue::Array(arr) => {
+            arr.iter()
+                .enumerate()
+                .for_each(|(i, v)| conversion_warnings(v, &format!("{}[{}]", path, i), warnings));
+            return;
+        }
+        mq_lang::RuntimeValue::Dict(map) => {
+            map.iter()
+                .for_each(|(k, v)| conversion_warnings(v, &format!("{}[{:?}]", path, k.as_str()), warnings));
+            return;
+        }
+        mq_lang::RuntimeValue::Bytes(b) if std::str::from_utf8(b).is_err() => {
+            warnings.push(format!(
+                "{}: bytes are not valid UTF-8, invalid sequences were replaced",
+                path
+            ));
+            return;
+        }
+        mq_lang::RuntimeValue::Function(..) | mq_lang::RuntimeValue::NativeFunction(..) => "function",
+        mq_lang::RuntimeValue::Module(..) => "module",
+        mq_lang::RuntimeValue::Ast(..) => "quoted expression",
+        _ => return,
+    };
+
+    warnings.push(format!(
+        "{}: {} has no Python equivalent and was converted to an empty value",
+        path, dropped
+    ));
+}
+
 impl From<mq_markdown::Node> for MarkdownType {
     fn from(node: mq_markdown::Node) -> Self {
         match node {
@@ -170,6 +489,7 @@ impl From<mq_markdown::Node> for MarkdownType {
             mq_markdown::Node::Link(_) => MarkdownType::Link,
             mq_markdown::Node::LinkRef(_) => MarkdownType::LinkRef,
             mq_markdown::Node::Math(_) => MarkdownType::Math,
+            mq_markdown::Node::List(list) if list.ordered => MarkdownType::OrderedList,
             mq_markdown::Node::List(_) => MarkdownType::List,
             mq_markdown::Node::TableAlign(_) => MarkdownType::TableHeader,
             mq_markdown::Node::TableRow(_) => MarkdownType::TableRow,
@@ -190,8 +510,438 @@ impl From<mq_markdown::Node> for MarkdownType {
 
 use pyo3::prelude::*;
 
+impl MQValue {
+    /// Converts the value back into the engine's value, the node for nodes.
+    pub(crate) fn to_runtime_value(&self) -> mq_lang::RuntimeValue {
+        match self {
+            MQValue::Array { value } => {
+                mq_lang::RuntimeValue::Array(value.iter().map(Self::to_runtime_value).collect())
+            }
+            MQValue::Dict { value } => mq_lang::RuntimeValue::Dict(
+                value
+                    .iter()
+                    .map(|(k, v)| (mq_lang::Ident::new(k), v.to_runtime_value()))
+                    .collect(),
+            ),
+            MQValue::Markdown {
+                node: MarkdownNode(Some(node)),
+                ..
+            } => mq_lang::RuntimeValue::Markdown(node.clone(), None),
+            MQValue::Markdown { value, .. } | MQValue::String { value, .. } => {
+                mq_lang::RuntimeValue::String(value.clone())
+            }
+            MQValue::Number { value, .. } => mq_lang::RuntimeValue::Number(value.0.into()),
+            MQValue::Bool { value, .. } => mq_lang::RuntimeValue::Boolean(*value),
+            MQValue::None { .. } => mq_lang::RuntimeValue::None,
+        }
+    }
+
+    /// Returns the value the engine's `None` converts to.
+    pub(crate) fn none() -> Self {
+        MQValue::None {
+            source: None,
+            stage: None,
+            stage_name: None,
+        }
+    }
+
+    pub(crate) fn string(value: impl Into<String>) -> Self {
+        MQValue::String {
+            value: value.into(),
+            source: None,
+            stage: None,
+            stage_name: None,
+        }
+    }
+
+    /// The name of the value's variant, or of its markdown type for markdown values.
+    pub(crate) fn type_name(&self) -> String {
+        match self {
+            MQValue::Array { .. } => "Array".to_string(),
+            MQValue::Dict { .. } => "Dict".to_string(),
+            MQValue::Markdown { markdown_type, .. } => format!("{:?}", markdown_type),
+            MQValue::String { .. } => "String".to_string(),
+            MQValue::Number { .. } => "Number".to_string(),
+            MQValue::Bool { .. } => "Bool".to_string(),
+            MQValue::None { .. } => "None".to_string(),
+        }
+    }
+
+    /// Returns the node behind a markdown value, building a text node for strings, numbers and
+    /// booleans.
+    pub(crate) fn as_node(&self) -> Option<mq_markdown::Node> {
+        match self {
+            MQValue::Markdown {
+                node: MarkdownNode(Some(node)),
+                ..
+            } => Some(*node.clone()),
+            MQValue::Markdown {
+                value,
+                markdown_type: MarkdownType::Text,
+                ..
+            } => Some(mq_markdown::Node::Text(mq_markdown::Text {
+                value: value.clone(),
+                position: None,
+            })),
+            MQValue::String { .. } | MQValue::Number { .. } | MQValue::Bool { .. } => {
+                Some(mq_markdown::Node::Text(mq_markdown::Text {
+                    value: self.to_string(),
+                    position: None,
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn map_node(
+        &self,
+        method: &str,
+        f: impl FnOnce(mq_markdown::Node) -> Option<PyResult<mq_markdown::Node>>,
+    ) -> PyResult<MQValue> {
+        let unsupported = || {
+            pyo3::exceptions::PyTypeError::new_err(format!(
+                "{}() is not supported for {}",
+                method,
+                self.type_name()
+            ))
+        };
+
+        let node = self.as_node().ok_or_else(unsupported)?;
+        let node = f(node).ok_or_else(unsupported)??;
+
+        mq_lang::RuntimeValue::from(node).try_into()
+    }
+
+    fn dict(&self, method: &str) -> PyResult<&IndexMap<String, MQValue>> {
+        match self {
+            MQValue::Dict { value } => Ok(value),
+            _ => Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                "{}() requires a dict value",
+                method
+            ))),
+        }
+    }
+
+    fn map_table(&self, method: &str, f: impl FnOnce(&mut Table) -> PyResult<()>) -> PyResult<MQValue> {
+        let mut table = Table::from_value(self).ok_or_else(|| {
+            pyo3::exceptions::PyTypeError::new_err(format!(
+                "{}() requires a table value; use mq.extract_tables() or MQResult.to_table() to read tables as rows",
+                method
+            ))
+        })?;
+
+        f(&mut table)?;
+        table.try_into()
+    }
+
+    pub(crate) fn markdown_nodes(&self) -> PyResult<Vec<mq_markdown::Node>> {
+        match self {
+            MQValue::Array { value } => value
+                .iter()
+                .map(MQValue::markdown_nodes)
+                .collect::<PyResult<Vec<_>>>()
+                .map(|nodes| nodes.into_iter().flatten().collect()),
+            MQValue::Dict { .. } => Table::from_value(self).map(|table| table.to_nodes()).ok_or_else(|| {
+                pyo3::exceptions::PyTypeError::new_err("to_markdown() is not supported for dict values")
+            }),
+            _ => Ok(self.as_node().into_iter().collect()),
+        }
+    }
+
+    /// The annotations of a markdown value, or `None` for other values and values without any.
+    pub(crate) fn annotations(&self) -> Option<&Annotations> {
+        match self {
+            MQValue::Markdown { annotations, .. } => annotations.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Sets the start number of every ordered list item from `starts`, keyed by item position.
+    pub(crate) fn set_list_start(&mut self, starts: &HashMap<(usize, usize), usize>) {
+        match self {
+            MQValue::Array { value } => value.iter_mut().for_each(|value| value.set_list_start(starts)),
+            MQValue::Dict { value } => value.values_mut().for_each(|value| value.set_list_start(starts)),
+            MQValue::Markdown { node, annotations, .. } => {
+                if let Some(mq_markdown::Node::List(list)) = node.0.as_deref()
+                    && let Some(position) = &list.position
+                    && let Some(start) = starts.get(&(position.start.line, position.start.column))
+                {
+                    annotations.get_or_insert_default().start = Some(*start);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the byte offsets of every positioned node from `line_starts`, the offset of each line of
+    /// the input.
+    pub(crate) fn set_offsets(&mut self, line_starts: &[usize]) {
+        match self {
+            MQValue::Array { value } => value.iter_mut().for_each(|value| value.set_offsets(line_starts)),
+            MQValue::Dict { value } => value.values_mut().for_each(|value| value.set_offsets(line_starts)),
+            MQValue::Markdown { node, annotations, .. } => {
+                let offset = |point: &mq_markdown::Point| {
+                    line_starts
+                        .get(point.line.checked_sub(1)?)
+                        .map(|start| start + point.column.saturating_sub(1))
+                };
+
+                if let Some(offsets) = node
+                    .0
+                    .as_ref()
+                    .and_then(|node| node.position())
+                    .and_then(|position| Some((offset(&position.start)?, offset(&position.end)?)))
+                {
+                    annotations.get_or_insert_default().offsets = Some(offsets);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the table index and column alignment of every table cell and row from `cells`, keyed
+    /// by cell position.
+    pub(crate) fn set_table_cell(&mut self, cells: &HashMap<(usize, usize), (usize, Option<String>)>) {
+        match self {
+            MQValue::Array { value } => value.iter_mut().for_each(|value| value.set_table_cell(cells)),
+            MQValue::Dict { value } => value.values_mut().for_each(|value| value.set_table_cell(cells)),
+            MQValue::Markdown { node, annotations, .. } => {
+                let position = match node.0.as_deref() {
+                    Some(mq_markdown::Node::TableCell(cell)) => cell.position.as_ref(),
+                    Some(mq_markdown::Node::TableRow(row)) => row.values.first().and_then(|cell| match cell {
+                        mq_markdown::Node::TableCell(cell) => cell.position.as_ref(),
+                        _ => None,
+                    }),
+                    _ => None,
+                };
+                let Some((table, alignment)) =
+                    position.and_then(|position| cells.get(&(position.start.line, position.start.column)))
+                else {
+                    return;
+                };
+
+                let annotations = annotations.get_or_insert_default();
+                annotations.table_index = Some(*table);
+
+                // A row spans all columns, so it has no single alignment.
+                if matches!(node.0.as_deref(), Some(mq_markdown::Node::TableCell(_))) {
+                    annotations.column_alignment = alignment.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the anchor slug of every heading from `anchors`, keyed by heading position.
+    pub(crate) fn set_anchor(&mut self, anchors: &HashMap<(usize, usize), String>) {
+        match self {
+            MQValue::Array { value } => value.iter_mut().for_each(|value| value.set_anchor(anchors)),
+            MQValue::Dict { value } => value.values_mut().for_each(|value| value.set_anchor(anchors)),
+            MQValue::Markdown { node, annotations, .. } => {
+                if let Some(mq_markdown::Node::Heading(heading)) = node.0.as_deref()
+                    && let Some(position) = &heading.position
+                    && let Some(anchor) = anchors.get(&(position.start.line, position.start.column))
+                {
+                    annotations.get_or_insert_default().anchor = Some(anchor.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sets the source document name of every value other than arrays and dicts, recursing into them.
+    pub(crate) fn set_source(&mut self, name: &str) {
+        match self {
+            MQValue::Array { value } => value.iter_mut().for_each(|value| value.set_source(name)),
+            MQValue::Dict { value } => value.values_mut().for_each(|value| value.set_source(name)),
+            MQValue::Markdown { annotations, .. } => {
+                annotations.get_or_insert_default().source = Some(name.to_string())
+            }
+            MQValue::String { source, .. }
+            | MQValue::Number { source, .. }
+            | MQValue::Bool { source, .. }
+            | MQValue::None { source, .. } => *source = Some(name.to_string()),
+        }
+    }
+
+    /// Records the query stage that produced every value other than arrays and dicts, recursing into
+    /// them.
+    pub(crate) fn set_stage(&mut self, index: usize, name: &str) {
+        match self {
+            MQValue::Array { value } => value.iter_mut().for_each(|value| value.set_stage(index, name)),
+            MQValue::Dict { value } => value.values_mut().for_each(|value| value.set_stage(index, name)),
+            MQValue::Markdown { annotations, .. } => {
+                let annotations = annotations.get_or_insert_default();
+                annotations.stage = Some(index);
+                annotations.stage_name = Some(name.to_string());
+            }
+            MQValue::String { stage, stage_name, .. }
+            | MQValue::Number { stage, stage_name, .. }
+            | MQValue::Bool { stage, stage_name, .. }
+            | MQValue::None { stage, stage_name, .. } => {
+                *stage = Some(index);
+                *stage_name = Some(name.to_string());
+            }
+        }
+    }
+
+    /// Writes the stable form used by `MQResult.to_test_string()`, with dict keys sorted and
+    /// multi-line text indented below its type.
+    pub(crate) fn write_test_string(&self, out: &mut String, indent: usize) {
+        let pad = " ".repeat(indent + 2);
+
+        match self {
+            MQValue::Array { value } => {
+                out.push_str("Array:\n");
+
+                for value in value {
+                    out.push_str(&pad);
+                    out.push_str("- ");
+                    value.write_test_string(out, indent + 4);
+                }
+            }
+            MQValue::Dict { value } => {
+                out.push_str("Dict:\n");
+
+                let mut keys = value.keys().collect::<Vec<_>>();
+                keys.sort();
+
+                for key in keys {
+                    out.push_str(&format!("{}{}: ", pad, key));
+                    value[key].write_test_string(out, indent + 4);
+                }
+            }
+            _ => {
+                let value = self.text();
+
+                if value.contains('\n') {
+                    out.push_str(&format!("{}:\n", self.type_name()));
+
+                    for line in value.trim_end_matches('\n').lines() {
+                        out.push_str(format!("{}{}", pad, line).trim_end());
+                        out.push('\n');
+                    }
+                } else {
+                    out.push_str(format!("{}: {}", self.type_name(), value).trim_end());
+                    out.push('\n');
+                }
+            }
+        }
+    }
+}
+
+#[derive(FromPyObject)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(FromPyObject)]
+pub enum ValueOrText<'py> {
+    Value(PyRef<'py, MQValue>),
+    Text(String),
+}
+
+/// A key into a dict value, or an index or slice into an array.
+#[derive(FromPyObject)]
+pub(crate) enum ValueIndex<'py> {
+    Key(String),
+    Index(Index<'py>),
+}
+
+/// Re-renders `text` as NFC-normalized markdown and collapses whitespace, so equivalent markup
+/// compares equal.
+fn normalized(text: &str) -> PyResult<String> {
+    let text = input::normalize_unicode(text, UnicodeNormalization::Nfc)?;
+
+    Ok(mq_markdown::Markdown::from_markdown_str(&text)
+        .map(|markdown| markdown.to_string())
+        .unwrap_or_else(|_| text.to_string())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
 #[pymethods]
 impl MQValue {
+    /// Returns a copy of this value with its text content replaced.
+    pub fn with_text(&self, text: &str) -> PyResult<MQValue> {
+        self.map_node("with_text", |mut node| match node {
+            mq_markdown::Node::Heading(_)
+            | mq_markdown::Node::Strong(_)
+            | mq_markdown::Node::Emphasis(_)
+            | mq_markdown::Node::Delete(_)
+            | mq_markdown::Node::Blockquote(_)
+            | mq_markdown::Node::Link(_)
+            | mq_markdown::Node::List(_)
+            | mq_markdown::Node::TableCell(_)
+            | mq_markdown::Node::Footnote(_) => {
+                node.set_children(vec![mq_markdown::Node::Text(mq_markdown::Text {
+                    value: text.to_string(),
+                    position: None,
+                })]);
+                Some(Ok(node))
+            }
+            mq_markdown::Node::Image(ref mut image) => {
+                image.alt = text.to_string();
+                Some(Ok(node))
+            }
+            mq_markdown::Node::Code(ref mut code) => {
+                code.value = text.to_string();
+                Some(Ok(node))
+            }
+            mq_markdown::Node::Text(_)
+            | mq_markdown::Node::CodeInline(_)
+            | mq_markdown::Node::MathInline(_)
+            | mq_markdown::Node::Math(_)
+            | mq_markdown::Node::Html(_)
+            | mq_markdown::Node::Yaml(_)
+            | mq_markdown::Node::Toml(_) => Some(Ok(node.with_value(text))),
+            _ => None,
+        })
+    }
+
+    /// Returns a copy of this value with its heading depth or list nesting level replaced.
+    pub fn with_level(&self, level: u8) -> PyResult<MQValue> {
+        self.map_node("with_level", |mut node| match node {
+            mq_markdown::Node::Heading(ref mut heading) => {
+                if !(1..=6).contains(&level) {
+                    return Some(Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "Heading depth must be between 1 and 6, got {}",
+                        level
+                    ))));
+                }
+
+                heading.depth = level;
+                Some(Ok(node))
+            }
+            mq_markdown::Node::List(ref mut list) => {
+                list.level = level;
+                Some(Ok(node))
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns a copy of this link, image or definition with its URL replaced.
+    pub fn with_url(&self, url: &str) -> PyResult<MQValue> {
+        self.map_node("with_url", |mut node| match node {
+            mq_markdown::Node::Link(ref mut link) => {
+                link.url = mq_markdown::Url::new(url.to_string());
+                Some(Ok(node))
+            }
+            mq_markdown::Node::Image(ref mut image) => {
+                image.url = url.to_string();
+                Some(Ok(node))
+            }
+            mq_markdown::Node::Definition(ref mut definition) => {
+                definition.url = mq_markdown::Url::new(url.to_string());
+                Some(Ok(node))
+            }
+            _ => None,
+        })
+    }
+
     #[getter]
     pub fn text(&self) -> String {
         self.to_string()
@@ -201,10 +951,25 @@ impl MQValue {
     pub fn values(&self) -> Vec<Self> {
         match self {
             MQValue::Array { value } => value.clone(),
+            MQValue::Dict { value } => value.values().cloned().collect(),
             a => vec![a.clone()],
         }
     }
 
+    /// Returns the keys of a dict in order.
+    pub fn keys(&self) -> PyResult<Vec<String>> {
+        Ok(self.dict("keys")?.keys().cloned().collect())
+    }
+
+    /// Returns the `(key, value)` pairs of a dict in order.
+    pub fn items(&self) -> PyResult<Vec<(String, Self)>> {
+        Ok(self
+            .dict("items")?
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
     #[getter]
     pub fn markdown_type(&self) -> Option<MarkdownType> {
         match self {
@@ -213,6 +978,331 @@ impl MQValue {
         }
     }
 
+    /// Renders the value as a markdown document; arrays of table cells and table dicts render as tables.
+    #[pyo3(signature = (options=None))]
+    pub(crate) fn to_markdown(&self, options: Option<Options>) -> PyResult<String> {
+        render::to_markdown(self.markdown_nodes()?, options)
+    }
+
+    pub fn is_table(&self) -> bool {
+        Table::from_value(self).is_some()
+    }
+
+    /// Returns a copy of this table with a row appended.
+    pub fn add_row(&self, cells: Vec<Bound<'_, PyAny>>) -> PyResult<MQValue> {
+        let cells = cells
+            .iter()
+            .map(|cell| builder::extract_inline(cell, true))
+            .collect::<PyResult<Vec<_>>>()?;
+
+        self.map_table("add_row", |table| table.add_row(cells))
+    }
+
+    /// Returns a copy of this table with a cell replaced. Row 0 is the header row.
+    pub fn set_cell(&self, row: usize, column: usize, text: &Bound<'_, PyAny>) -> PyResult<MQValue> {
+        let values = builder::extract_inline(text, true)?;
+        self.map_table("set_cell", |table| table.set_cell(row, column, values))
+    }
+
+    /// Returns a copy of this table with its rows sorted by a column index or header name.
+    #[pyo3(signature = (column, reverse=false))]
+    pub fn sort_by(&self, column: Column, reverse: bool) -> PyResult<MQValue> {
+        self.map_table("sort_by", |table| {
+            let index = match column {
+                Column::Index(index) if index < table.columns() => index,
+                Column::Index(index) => {
+                    return Err(pyo3::exceptions::PyIndexError::new_err(format!(
+                        "column {} out of range for table with {} columns",
+                        index,
+                        table.columns()
+                    )));
+                }
+                Column::Name(name) => table.column_index(&name).ok_or_else(|| {
+                    pyo3::exceptions::PyKeyError::new_err(format!("no column named {:?}", name))
+                })?,
+            };
+
+            table.sort_by(index, reverse);
+            Ok(())
+        })
+    }
+
+    #[getter]
+    pub fn section_path(&self) -> Vec<String> {
+        self.annotations()
+            .map(|annotations| annotations.section_path.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether a list item belongs to an ordered list, or `None` for other values.
+    #[getter]
+    pub fn ordered(&self) -> Option<bool> {
+        match self.as_node()? {
+            mq_markdown::Node::List(list) => Some(list.ordered),
+            _ => None,
+        }
+    }
+
+    /// The zero-based index of a list item within its list, or `None` for other values.
+    #[getter]
+    pub fn index(&self) -> Option<usize> {
+        match self.as_node()? {
+            mq_markdown::Node::List(list) => Some(list.index),
+            _ => None,
+        }
+    }
+
+    /// The number an ordered list starts at in the input document, or `None` for other values.
+    #[getter]
+    pub fn start(&self) -> Option<usize> {
+        self.annotations()?.start
+    }
+
+    /// The slug a heading is linked to with `#slug` in the input document, in `Options.slug_style`,
+    /// or `None` for other values and headings built by the query.
+    #[getter]
+    pub fn anchor(&self) -> Option<String> {
+        self.annotations()?.anchor.clone()
+    }
+
+    /// The depth of a heading or the nesting level of a list item, as set by `with_level`, or `None`
+    /// for other values.
+    #[getter]
+    pub fn level(&self) -> Option<usize> {
+        match self.as_node()? {
+            mq_markdown::Node::Heading(heading) => Some(heading.depth.into()),
+            mq_markdown::Node::List(list) => Some(list.level.into()),
+            _ => None,
+        }
+    }
+
+    /// Whether a task list item is checked, or `None` for other values and plain list items.
+    #[getter]
+    pub fn checked(&self) -> Option<bool> {
+        match self.as_node()? {
+            mq_markdown::Node::List(list) => list.checked,
+            _ => None,
+        }
+    }
+
+    /// The URL of a link, image or link definition, or `None` for other values.
+    #[getter]
+    pub fn url(&self) -> Option<String> {
+        match self.as_node()? {
+            mq_markdown::Node::Link(link) => Some(link.url.as_str().to_string()),
+            mq_markdown::Node::Image(image) => Some(image.url),
+            mq_markdown::Node::Definition(definition) => Some(definition.url.as_str().to_string()),
+            _ => None,
+        }
+    }
+
+    /// The title of a link, image or link definition, or `None` for other values and those without one.
+    #[getter]
+    pub fn title(&self) -> Option<String> {
+        match self.as_node()? {
+            mq_markdown::Node::Link(link) => link.title.as_ref().map(mq_markdown::Title::to_value),
+            mq_markdown::Node::Image(image) => image.title,
+            mq_markdown::Node::Definition(definition) => definition.title.as_ref().map(mq_markdown::Title::to_value),
+            _ => None,
+        }
+    }
+
+    /// The language of a code block, or `None` for other values and code blocks without one.
+    #[getter]
+    pub fn lang(&self) -> Option<String> {
+        match self.as_node()? {
+            mq_markdown::Node::Code(code) => code.lang,
+            _ => None,
+        }
+    }
+
+    /// The info string of a code block after the language, or `None` for other values.
+    #[getter]
+    pub fn meta(&self) -> Option<String> {
+        match self.as_node()? {
+            mq_markdown::Node::Code(code) => code.meta,
+            _ => None,
+        }
+    }
+
+    /// The attributes of a code block's meta string as a dict, e.g. `{"title": "x", "linenums": True}`
+    /// for `title="x" linenums`, or `None` for other values.
+    #[getter]
+    pub fn meta_dict<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let Some(mq_markdown::Node::Code(code)) = self.as_node() else {
+            return Ok(None);
+        };
+        let dict = PyDict::new(py);
+
+        for (key, value) in extract::parse_meta(code.meta.as_deref().unwrap_or_default()) {
+            match value {
+                Some(value) => dict.set_item(key, value)?,
+                None => dict.set_item(key, true)?,
+            }
+        }
+
+        Ok(Some(dict))
+    }
+
+    /// The content of a math value without its `$` or `$$` delimiters, or `None` for other values.
+    ///
+    /// Fenced code blocks with the `math` language count as math blocks.
+    #[getter]
+    pub fn math(&self) -> Option<String> {
+        match self.as_node()? {
+            mq_markdown::Node::Math(math) => Some(math.value),
+            mq_markdown::Node::MathInline(math) => Some(math.value.to_string()),
+            mq_markdown::Node::Code(code) if code.lang.as_deref() == Some("math") => Some(code.value),
+            _ => None,
+        }
+    }
+
+    /// The component name of an MDX JSX element, or `None` for other values and fragments (`<>`).
+    #[getter]
+    pub fn component(&self) -> Option<String> {
+        match self.as_node()? {
+            mq_markdown::Node::MdxJsxFlowElement(element) => element.name,
+            mq_markdown::Node::MdxJsxTextElement(element) => element.name.map(|name| name.to_string()),
+            _ => None,
+        }
+    }
+
+    /// The attributes of an MDX JSX element as a dict, or `None` for other values.
+    ///
+    /// Literal values are strings, expression values their source in braces, e.g. `"{1 + 1}"`, and
+    /// attributes without a value are `True`. Spread attributes such as `{...props}` are left out.
+    #[getter]
+    pub fn attributes<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let attributes = match self.as_node() {
+            Some(mq_markdown::Node::MdxJsxFlowElement(element)) => element.attributes,
+            Some(mq_markdown::Node::MdxJsxTextElement(element)) => element.attributes,
+            _ => return Ok(None),
+        };
+        let dict = PyDict::new(py);
+
+        for attribute in attributes {
+            // mq-markdown does not export the attribute types, so each one is read back from how it renders.
+            let rendered = mq_markdown::Node::MdxJsxFlowElement(mq_markdown::MdxJsxFlowElement {
+                children: Vec::new(),
+                position: None,
+                name: None,
+                attributes: vec![attribute],
+            })
+            .to_string_with(&mq_markdown::RenderOptions::default());
+            let attribute = rendered.trim_start_matches("< ").trim_end_matches(" />");
+
+            match attribute.split_once('=') {
+                _ if attribute.starts_with('{') => continue,
+                Some((name, value)) if value.starts_with('"') => dict.set_item(name, &value[1..value.len() - 1])?,
+                Some((name, value)) => dict.set_item(name, value)?,
+                None => dict.set_item(attribute, true)?,
+            }
+        }
+
+        Ok(Some(dict))
+    }
+
+    /// The source of an MDX expression or ESM block without the braces, or `None` for other values.
+    #[getter]
+    pub fn expression(&self) -> Option<String> {
+        match self.as_node()? {
+            mq_markdown::Node::MdxFlowExpression(expression) => Some(expression.value.to_string()),
+            mq_markdown::Node::MdxTextExpression(expression) => Some(expression.value.to_string()),
+            mq_markdown::Node::MdxJsEsm(esm) => Some(esm.value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// The row of a table cell or row, where row 0 is the header, or `None` for other values.
+    #[getter]
+    pub fn row_index(&self) -> Option<usize> {
+        match self.as_node()? {
+            mq_markdown::Node::TableCell(cell) => Some(cell.row),
+            mq_markdown::Node::TableRow(row) => row.values.iter().find_map(|cell| match cell {
+                mq_markdown::Node::TableCell(cell) => Some(cell.row),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The zero-based column of a table cell, or `None` for other values.
+    #[getter]
+    pub fn column_index(&self) -> Option<usize> {
+        match self.as_node()? {
+            mq_markdown::Node::TableCell(cell) => Some(cell.column),
+            _ => None,
+        }
+    }
+
+    /// The alignment of a table cell's column: `"left"`, `"right"`, `"center"`, or `None` when the
+    /// column is not aligned or the value is not a cell from the input document.
+    #[getter]
+    pub fn column_alignment(&self) -> Option<String> {
+        self.annotations()?.column_alignment.clone()
+    }
+
+    /// The zero-based index of the table a cell or row belongs to in the input document.
+    #[getter]
+    pub fn table_index(&self) -> Option<usize> {
+        self.annotations()?.table_index
+    }
+
+    #[getter]
+    pub fn source(&self) -> Option<String> {
+        match self {
+            MQValue::Markdown { annotations, .. } => annotations.as_ref()?.source.clone(),
+            MQValue::String { source, .. }
+            | MQValue::Number { source, .. }
+            | MQValue::Bool { source, .. }
+            | MQValue::None { source, .. } => source.clone(),
+            _ => None,
+        }
+    }
+
+    /// The zero-based index of the pipeline stage that produced the value, when
+    /// `Options.record_provenance` is set.
+    ///
+    /// For an empty value this is the stage that dropped it.
+    #[getter]
+    pub fn stage(&self) -> Option<usize> {
+        match self {
+            MQValue::Markdown { annotations, .. } => annotations.as_ref()?.stage,
+            MQValue::String { stage, .. }
+            | MQValue::Number { stage, .. }
+            | MQValue::Bool { stage, .. }
+            | MQValue::None { stage, .. } => *stage,
+            _ => None,
+        }
+    }
+
+    /// The name of the pipeline stage that produced the value, e.g. `select` or `.h`.
+    #[getter]
+    pub fn stage_name(&self) -> Option<String> {
+        match self {
+            MQValue::Markdown { annotations, .. } => annotations.as_ref()?.stage_name.clone(),
+            MQValue::String { stage_name, .. }
+            | MQValue::Number { stage_name, .. }
+            | MQValue::Bool { stage_name, .. }
+            | MQValue::None { stage_name, .. } => stage_name.clone(),
+            _ => None,
+        }
+    }
+
+    /// Where the value's node is in the input document, or `None` for values without a position,
+    /// such as arrays and nodes built by the query.
+    #[getter]
+    pub fn position(&self) -> Option<Position> {
+        let MQValue::Markdown { node, annotations, .. } = self else {
+            return None;
+        };
+
+        Some(Position::new(
+            node.0.as_ref()?.position()?,
+            annotations.as_ref().and_then(|annotations| annotations.offsets),
+        ))
+    }
+
     pub fn is_array(&self) -> bool {
         matches!(self, MQValue::Array { .. })
     }
@@ -221,17 +1311,120 @@ impl MQValue {
         matches!(self, MQValue::Markdown { .. })
     }
 
-    pub fn __getitem__(&self, idx: usize) -> PyResult<MQValue> {
-        let array = self.values();
+    /// Returns `True` for strings built by the query, as opposed to text nodes.
+    pub fn is_string(&self) -> bool {
+        matches!(self, MQValue::String { .. })
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, MQValue::Number { .. })
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, MQValue::Bool { .. })
+    }
+
+    /// The engine's `None`, e.g. what a selector returns for a node it does not match.
+    #[classattr]
+    #[pyo3(name = "NONE")]
+    fn none_value() -> MQValue {
+        MQValue::none()
+    }
+
+    /// Returns `True` for `None` from the engine, as opposed to an empty string.
+    pub fn is_none(&self) -> bool {
+        matches!(self, MQValue::None { .. })
+    }
+
+    /// Serializes the value to JSON, in the form of the values of `MQResult.dumps`: its type, its
+    /// text, its node with attributes and positions, and its annotations.
+    pub fn to_json(&self) -> PyResult<String> {
+        crate::serialize::value_dumps(self)
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, (String,))> {
+        crate::serialize::reduce(py, self.to_json()?)
+    }
+
+    fn __copy__(&self) -> Self {
+        self.clone()
+    }
+
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone()
+    }
+
+    /// Converts the value into plain Python objects: arrays into lists, dicts into dicts, numbers
+    /// and booleans into `int`, `float` and `bool`, `None` into `None` and anything else into its
+    /// text, e.g. the markdown of a node.
+    pub fn to_python<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        match self {
+            MQValue::Array { value } => value
+                .iter()
+                .map(|value| value.to_python(py))
+                .collect::<PyResult<Vec<_>>>()?
+                .into_bound_py_any(py),
+            MQValue::Dict { value } => {
+                let dict = PyDict::new(py);
+
+                for (key, value) in value {
+                    dict.set_item(key, value.to_python(py)?)?;
+                }
+
+                Ok(dict.into_any())
+            }
+            MQValue::Markdown { value, .. } | MQValue::String { value, .. } => value.into_bound_py_any(py),
+            MQValue::Number { value, .. } => value.into_bound_py_any(py),
+            MQValue::Bool { value, .. } => value.into_bound_py_any(py),
+            MQValue::None { .. } => py.None().into_bound_py_any(py),
+        }
+    }
+
+    /// Returns the value of a dict at a string `key`, the element of an array at `index`, counting
+    /// from the end when negative, or an array of the elements of a slice. Other values act as an
+    /// array holding just the value.
+    pub(crate) fn __getitem__<'py>(&self, py: Python<'py>, index: ValueIndex<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let index = match index {
+            ValueIndex::Key(key) => {
+                return match self {
+                    MQValue::Dict { value } => match value.get(&key) {
+                        Some(value) => value.clone().into_bound_py_any(py),
+                        None => Err(pyo3::exceptions::PyKeyError::new_err(key)),
+                    },
+                    _ => Err(pyo3::exceptions::PyTypeError::new_err(
+                        "MQValue indices must be integers or slices, or strings for dict values",
+                    )),
+                };
+            }
+            ValueIndex::Index(index) => index,
+        };
+        let values = self.values();
+
+        match index {
+            Index::Int(i) => values[position(i, values.len(), "MQValue")?].clone().into_bound_py_any(py),
+            Index::Slice(slice) => MQValue::Array {
+                value: slice_values(&values, &slice)?,
+            }
+            .into_bound_py_any(py),
+        }
+    }
 
-        if idx < array.len() {
-            Ok(array[idx].clone())
-        } else {
-            Err(pyo3::exceptions::PyIndexError::new_err(format!(
-                "Index {} out of range for MQResult with length {}",
-                idx,
-                array.len()
-            )))
+    /// Iterates over the keys of a dict, the elements of an array or just the value for others.
+    fn __iter__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyIterator>> {
+        match self {
+            MQValue::Dict { value } => PyList::new(py, value.keys())?.try_iter(),
+            _ => PyList::new(py, self.values())?.try_iter(),
+        }
+    }
+
+    /// Whether a dict has the key `item`, or an array holds a value equal to `item` or with `item`
+    /// as its text. Other values act as an array holding just the value.
+    fn __contains__(&self, item: ValueOrText<'_>) -> bool {
+        match (self, item) {
+            (MQValue::Dict { value }, ValueOrText::Text(key)) => value.contains_key(&key),
+            (MQValue::Dict { .. }, ValueOrText::Value(_)) => false,
+            (_, ValueOrText::Value(item)) => self.values().contains(&*item),
+            (_, ValueOrText::Text(text)) => self.values().iter().any(|value| value.text() == text),
         }
     }
 
@@ -239,6 +1432,16 @@ impl MQValue {
         self.text()
     }
 
+    /// Renders the value as markdown for Jupyter, or its text when it is not markdown.
+    fn _repr_markdown_(&self) -> String {
+        self.to_markdown(None).unwrap_or_else(|_| self.text())
+    }
+
+    /// Renders `_repr_markdown_` as HTML for Jupyter, with raw HTML shown as text.
+    fn _repr_html_(&self) -> String {
+        crate::render::to_display_html(&self._repr_markdown_())
+    }
+
     pub fn __repr__(&self) -> String {
         match self {
             MQValue::Array { value: arr } => format!(
@@ -260,25 +1463,36 @@ impl MQValue {
             MQValue::Markdown {
                 value,
                 markdown_type,
+                ..
             } => {
                 format!("MQValue::Markdown(\"{}\", {:?})", value, markdown_type)
             }
+            MQValue::String { value, .. } => format!("MQValue::String(\"{}\")", value),
+            MQValue::Number { value, .. } => format!("MQValue::Number({})", value),
+            MQValue::Bool { value, .. } => format!("MQValue::Bool({})", value),
+            MQValue::None { .. } => "MQValue::NONE".to_string(),
         }
     }
 
+    /// Whether the value is truthy as its Python equivalent would be, e.g. `False` for `0`.
     pub fn __bool__(&self) -> bool {
         match self {
             MQValue::Array { value } => !value.is_empty(),
             MQValue::Dict { value } => !value.is_empty(),
-            MQValue::Markdown { value, .. } => !value.is_empty(),
+            MQValue::Markdown { value, .. } | MQValue::String { value, .. } => !value.is_empty(),
+            MQValue::Number { value, .. } => value.0 != 0.0,
+            MQValue::Bool { value, .. } => *value,
+            MQValue::None { .. } => false,
         }
     }
 
+    /// The number of elements of an array or dict, or the length of the text of other values, so
+    /// only `None` and empty strings have no length.
     pub fn __len__(&self) -> usize {
         match self {
             MQValue::Array { value } => value.len(),
             MQValue::Dict { value } => value.len(),
-            MQValue::Markdown { value, .. } => value.len(),
+            _ => self.text().len(),
         }
     }
 
@@ -286,6 +1500,17 @@ impl MQValue {
         self == other
     }
 
+    /// Compares the markdown of two values ignoring whitespace, marker style and Unicode
+    /// normalization form, e.g. `*` and `-` list items or `_` and `*` emphasis.
+    pub fn equals_normalized(&self, other: ValueOrText<'_>) -> PyResult<bool> {
+        let other = match other {
+            ValueOrText::Value(value) => value.text(),
+            ValueOrText::Text(text) => text,
+        };
+
+        Ok(normalized(&self.text())? == normalized(&other)?)
+    }
+
     pub fn __ne__(&self, other: &Self) -> bool {
         !self.__eq__(other)
     }
@@ -304,7 +1529,12 @@ impl MQValue {
                     false
                 }
             }
-            (MQValue::Markdown { value: a, .. }, MQValue::Markdown { value: b, .. }) => a < b,
+            (
+                MQValue::Markdown { value: a, .. } | MQValue::String { value: a, .. },
+                MQValue::Markdown { value: b, .. } | MQValue::String { value: b, .. },
+            ) => a < b,
+            (MQValue::Number { value: a, .. }, MQValue::Number { value: b, .. }) => a < b,
+            (MQValue::Bool { value: a, .. }, MQValue::Bool { value: b, .. }) => a < b,
             _ => false,
         }
     }
@@ -323,7 +1553,12 @@ impl MQValue {
                     false
                 }
             }
-            (MQValue::Markdown { value: a, .. }, MQValue::Markdown { value: b, .. }) => a > b,
+            (
+                MQValue::Markdown { value: a, .. } | MQValue::String { value: a, .. },
+                MQValue::Markdown { value: b, .. } | MQValue::String { value: b, .. },
+            ) => a > b,
+            (MQValue::Number { value: a, .. }, MQValue::Number { value: b, .. }) => a > b,
+            (MQValue::Bool { value: a, .. }, MQValue::Bool { value: b, .. }) => a > b,
             _ => false,
         }
     }
@@ -1,3 +1,12 @@
+import ast
+import asyncio
+import copy
+import io
+import pathlib
+import pickle
+import unicodedata
+from concurrent.futures import ThreadPoolExecutor
+
 import pytest
 import mq
 
@@ -55,6 +64,18 @@ def test_mq_queries(code, content, expected):
             "<h1>Hello</h1><p>World</p>",
             ["# Hello"],
         ),
+        (
+            mq.InputFormat.CSV,
+            ".[][0]",
+            'name,note\napple,"red, ""sweet"""\npear,',
+            ["name", "apple", "pear"],
+        ),
+        (
+            mq.InputFormat.TSV,
+            ".[1][]",
+            "name\tnote\r\napple\tred, sweet\r\n",
+            ["apple", "red, sweet"],
+        ),
     ],
 )
 def test_input_formats(input_format, code, content, expected):
@@ -65,6 +86,33 @@ def test_input_formats(input_format, code, content, expected):
     assert result.values == expected
 
 
+def test_csv_input():
+    options = mq.Options()
+    options.input_format = mq.InputFormat.CSV
+    result = mq.parse_input('name,note\napple,"two\nlines"\n\npear,""\n', options)
+
+    assert [value.markdown_type for value in result][:3] == [
+        mq.MarkdownType.TableCell,
+        mq.MarkdownType.TableCell,
+        mq.MarkdownType.TableHeader,
+    ]
+    assert [value.text for value in result if value.markdown_type == mq.MarkdownType.TableCell] == [
+        "name",
+        "note",
+        "apple",
+        "two\nlines",
+        "pear",
+        "",
+    ]
+    note = result[4]
+    assert (note.position.start_line, note.position.start_column, note.position.end_line) == (2, 7, 3)
+    assert note.position.start_offset == 16
+    assert result[3].table_index == 0
+
+    with pytest.raises(mq.InputError, match="unterminated quoted field starting at line 1, column 3"):
+        mq.run(".", 'a,"b', options)
+
+
 def test_invalid_query():
     with pytest.raises(Exception) as exc_info:
         mq.run(".invalid_selector!!!", "# Heading", None)
@@ -72,8 +120,2594 @@ def test_invalid_query():
     assert "Error evaluating query" in str(exc_info.value)
 
 
+def test_query_error_classes():
+    with pytest.raises(mq.ParseError) as exc_info:
+        mq.run(".h |\n def f(", "# Heading")
+
+    assert exc_info.value.line == 2
+    assert exc_info.value.snippet == " def f("
+
+    with pytest.raises(mq.ParseError, match="Unexpected end of input") as exc_info:
+        mq.run(".h |\n let x = \n", "# Heading")
+
+    error = exc_info.value
+    assert (error.line, error.column, error.token, error.snippet) == (2, 9, None, " let x = ")
+    [diagnostic] = mq.check_syntax(".h |\n let x = \n")
+    assert (diagnostic.span.start_line, diagnostic.span.start_column, diagnostic.span.start_offset) == (2, 9, 13)
+
+    with pytest.raises(mq.EvalError) as exc_info:
+        mq.run(".h | add(1, 2, 3)", "# Heading")
+
+    assert isinstance(exc_info.value, RuntimeError)
+    assert (exc_info.value.line, exc_info.value.column) == (1, 6)
+    assert exc_info.value.token == "add"
+    assert exc_info.value.snippet == ".h | add(1, 2, 3)"
+
+    with pytest.raises(mq.EvalError, match=r"^contents\[0\]: ") as exc_info:
+        mq.run_many(".h | foo()", ["# Heading"])
+
+    assert exc_info.value.token == "foo"
+    assert mq.InputError is mq.MQParseInputError
+
+
 def test_html_to_markdown():
     html_content = "<h1>Hello World</h1><p>This is a <strong>test</strong>.</p>"
     expected_markdown = "# Hello World\n\nThis is a **test**."
     markdown = mq.html_to_markdown(html_content)
     assert markdown.strip() == expected_markdown
+
+
+def test_parallel_parse_matches_sequential():
+    section = (
+        "# Section {i}\n\n"
+        "Paragraph {i} with *emphasis* and a [link](https://example.com/{i}).\n\n"
+        "```python\nx = {i}\n\n\ny = x\n```\n\n"
+        "- item a\n\n- item b\n\n"
+        "<!--\n\ncomment {i}\n\n-->\n\n"
+    )
+    content = "---\ntitle: big\n\n---\n\n" + "".join(section.format(i=i) for i in range(3000))
+
+    options = mq.Options()
+    options.parallel_parse = True
+    parallel = mq.run(".", content, options)
+    sequential = mq.run(".", content, None)
+
+    assert len(parallel) == len(sequential)
+    assert parallel.values == sequential.values
+
+
+@pytest.mark.parametrize(
+    "content",
+    [
+        b"# Hello\n\n## World",
+        bytearray(b"# Hello\n\n## World"),
+        memoryview(b"# Hello\n\n## World"),
+        memoryview(b"xx# Hello\n\n## World")[2:],
+    ],
+)
+def test_buffer_content(content):
+    result = mq.run(".h", content, None)
+    assert result.values == ["# Hello", "## World"]
+
+
+def test_buffer_content_invalid_utf8():
+    with pytest.raises(UnicodeDecodeError):
+        mq.run(".h", b"# \xff", None)
+
+
+def test_buffer_content_invalid_type():
+    with pytest.raises(TypeError):
+        mq.run(".h", 1, None)
+
+
+def test_html_to_markdown_bytes():
+    markdown = mq.html_to_markdown(b"<h1>Hello World</h1>")
+    assert markdown.strip() == "# Hello World"
+
+
+@pytest.mark.parametrize(
+    "node, expected",
+    [
+        (mq.nodes.Heading(2, "Title"), "## Title"),
+        (mq.nodes.Heading(1, ["Hello ", mq.nodes.Strong("World")]), "# Hello **World**"),
+        (mq.nodes.Code("python", "print('Hello')"), "```python\nprint('Hello')\n```"),
+        (mq.nodes.Link("https://example.com", "Example"), "[Example](https://example.com)"),
+        (mq.nodes.Image("image.png", "Alt", "Title"), '![Alt](image.png "Title")'),
+        (mq.nodes.List("Item", ordered=True, index=1), "2. Item"),
+        (mq.nodes.List("Task", checked=False), "- [ ] Task"),
+        (mq.nodes.CodeInline("x = 1"), "`x = 1`"),
+        (mq.nodes.Blockquote("Quote"), "> Quote"),
+        (mq.nodes.HorizontalRule(), "---"),
+    ],
+)
+def test_node_constructors(node, expected):
+    assert node.to_markdown() == expected
+    assert str(node.to_value()) == expected
+
+
+def test_node_to_value():
+    value = mq.nodes.Heading(3, "Title").to_value()
+    assert value.markdown_type == mq.MarkdownType.Heading
+    assert value.is_markdown()
+
+
+def test_node_properties():
+    link = mq.nodes.Link("https://example.com", "Example", title="Title")
+    assert link.url == "https://example.com"
+    assert link.title == "Title"
+    assert isinstance(link, mq.nodes.Node)
+    assert mq.nodes.Heading(4, "Title").depth == 4
+    assert mq.nodes.Code(None, "x").lang is None
+
+    body = "```\nnested\n```"
+    markdown = mq.nodes.Code("md", body).to_markdown()
+    assert markdown == "````md\n```\nnested\n```\n````"
+    assert mq.code_blocks(markdown)[0].value == body
+    assert mq.render(mq.nodes.Code("md", body)) == markdown + "\n"
+
+
+def test_node_invalid_arguments():
+    with pytest.raises(ValueError):
+        mq.nodes.Heading(7, "Title")
+
+    with pytest.raises(TypeError):
+        mq.nodes.Strong(1)
+
+
+def test_parse_and_render_tree():
+    tree = mq.parse("# Title\n\nSome *text*\n\n- a\n- b\n")
+    assert [node.markdown_type for node in tree] == [
+        mq.MarkdownType.Heading,
+        mq.MarkdownType.Text,
+        mq.MarkdownType.Emphasis,
+        mq.MarkdownType.List,
+        mq.MarkdownType.List,
+    ]
+    assert isinstance(tree[0], mq.nodes.Heading)
+    assert tree[0].children == [mq.nodes.Text("Title")]
+    assert (tree[1].position.start_line, tree[1].position.start_column) == (3, 1)
+
+    tree[0] = tree[0].with_children(["New ", mq.nodes.Strong("title")])
+    assert mq.render(tree) == "# New **title**\n\nSome *text*\n\n- a\n- b\n"
+
+    options = mq.Options()
+    options.output_format = mq.OutputFormat.HTML
+    assert mq.render(tree[0], options).startswith("<h1>New <strong>title</strong></h1>")
+
+    assert mq.parse("<h2>x</h2>", input_format=mq.InputFormat.HTML) == [mq.nodes.Heading(2, "x")]
+    assert mq.parse("a\nb", input_format=mq.InputFormat.RAW) == [mq.nodes.Text("a\nb")]
+
+
+def test_document_builder():
+    doc = (
+        mq.DocumentBuilder()
+        .heading(1, "Title")
+        .paragraph(["Hello ", mq.nodes.Strong("world")])
+        .list(["a", ["a1"], "b"])
+        .list(["x", "y"], ordered=True)
+        .code("print(1)\n", "python")
+        .build()
+    )
+    assert doc == (
+        "# Title\n\nHello **world**\n\n- a\n  - a1\n- b\n\n1. x\n2. y\n\n```python\nprint(1)\n```\n"
+    )
+
+
+def test_document_builder_table():
+    doc = mq.DocumentBuilder().table([["Name", "Age"], ["A|B", 1], ["C"]], align=["left", "right"]).build()
+    assert doc == "|Name|Age|\n|:---|---:|\n|A\\|B|1|\n|C||\n"
+
+
+def test_document_builder_escapes_text():
+    doc = mq.DocumentBuilder().paragraph("# *not* a [heading]").paragraph("1. not a list").build()
+    assert doc == "\\# \\*not\\* a \\[heading\\]\n\n1\\. not a list\n"
+    assert mq.run(".h", doc).values == []
+
+    doc = mq.DocumentBuilder().table([["a\nb", "c"]]).paragraph("  - & $x$").build()
+    assert doc == "|a b|c|\n|---|---|\n\n  \\- \\& \\$x\\$\n"
+
+
+def test_document_builder_code_fence():
+    body = "Example:\n```python\nprint(1)\n```\n````"
+    doc = mq.DocumentBuilder().code(body, "md").paragraph("After").build()
+    assert doc == "`````md\n" + body + "\n`````\n\nAfter\n"
+    assert mq.code_blocks(doc)[0].value == body
+    assert len(mq.code_blocks(doc)) == 1 and len(mq.run(".", doc).values) == 2
+
+
+def test_document_builder_options():
+    options = mq.Options()
+    options.list_style = mq.ListStyle.STAR
+    assert mq.DocumentBuilder().list(["a", "b"]).build(options) == "* a\n* b\n"
+
+    builder = mq.DocumentBuilder().heading(1, "Intro").list(["a"]).table([["A", "Bee"], ["long", "x"]])
+    builder.heading(2, "Intro")
+    options = mq.Options(table_padding=True, list_marker_spacing=3, heading_anchors=True)
+    doc = builder.build(options)
+    assert doc == (
+        '# <a id="intro"></a>Intro\n\n-   a\n\n| A    | Bee |\n| ---- | --- |\n| long | x   |\n\n'
+        '## <a id="intro-1"></a>Intro\n'
+    )
+
+
+def test_document_builder_invalid_arguments():
+    with pytest.raises(ValueError):
+        mq.DocumentBuilder().heading(0, "Title")
+
+    with pytest.raises(ValueError):
+        mq.DocumentBuilder().table([["a"], ["b", "c"]])
+
+    with pytest.raises(ValueError):
+        mq.DocumentBuilder().table([["a"]], align=["middle"])
+
+
+@pytest.mark.parametrize(
+    "query,content,method,arg,expected",
+    [
+        (".h", "# Hello **world**", "with_text", "Bye", "# Bye"),
+        (".h", "# Hello", "with_level", 3, "### Hello"),
+        (".code", "```python\nold\n```", "with_text", "new", "```python\nnew\n```"),
+        (".link", "[a](http://a.com)", "with_url", "https://b.com", "[a](https://b.com)"),
+        (".link", "[a](http://a.com)", "with_text", "b", "[b](http://a.com)"),
+        (".image", "![alt](a.png)", "with_url", "b.png", "![alt](b.png)"),
+        (".list", "- item", "with_text", "changed", "- changed"),
+    ],
+)
+def test_value_updaters(query, content, method, arg, expected):
+    value = mq.run(query, content)[0]
+    updated = getattr(value, method)(arg)
+    assert updated.text == expected
+    assert updated.markdown_type == value.markdown_type
+    assert value.text != expected
+
+
+def test_value_updaters_invalid():
+    heading = mq.run(".h", "# Hello")[0]
+
+    with pytest.raises(TypeError):
+        heading.with_url("https://example.com")
+
+    with pytest.raises(ValueError):
+        heading.with_level(7)
+
+
+def test_value_node():
+    value = mq.run(".h2", "## Title")[0]
+    assert isinstance(value.node, mq.nodes.Node)
+    assert value.node.to_markdown() == "## Title"
+
+
+TABLE_CONTENT = "| Name | Age |\n| --- | ---: |\n| Bob | 30 |\n| Al | 5 |\n"
+
+
+def table_value():
+    return mq.run('nodes | import "table" | table::tables(self)', TABLE_CONTENT)[0]
+
+
+def test_table_helpers():
+    table = table_value()
+    assert table.is_table()
+    assert table.to_markdown() == "|Name|Age|\n|---|---:|\n|Bob|30|\n|Al|5|\n"
+
+    updated = table.add_row(["Cy", "100"]).sort_by("Age").set_cell(0, 1, "Years")
+    assert updated.is_table()
+    assert updated.to_markdown() == "|Name|Years|\n|---|---:|\n|Al|5|\n|Bob|30|\n|Cy|100|\n"
+    assert table.sort_by(0, reverse=True).to_markdown() == "|Name|Age|\n|---|---:|\n|Bob|30|\n|Al|5|\n"
+
+
+def test_dict_key_order():
+    options = mq.Options()
+    options.input_format = mq.InputFormat.NULL
+    first = mq.run('{"z": 1, "a": 2, "m": 3}', "", options)[0]
+    assert list(first.value.keys()) == ["a", "m", "z"]
+
+    value = mq.run('{"m": 1, "z": 2, "a": 3}', "", options)[0]
+    keys = list(value.value.keys())
+    assert keys == ["a", "m", "z"]
+    assert [v.text for v in value.value.values()] == ["3", "1", "2"]
+    assert value.keys() == keys
+    assert repr(value) == "MQValue::MAP({})".format(
+        ", ".join('"{}": MQValue::Number({})'.format(k, value.value[k].text) for k in keys)
+    )
+    assert list(table_value().value.keys()) == ["align", "header", "rows", "type"]
+    assert list(table_value().add_row(["x", "1"]).value.keys()) == ["align", "header", "rows", "type"]
+
+
+def test_to_test_string():
+    result = mq.run("select(is_h(self) || is_code(self))", "# Title\n\ntext\n\n```py\nx = 1\n```\n")
+    assert result.to_test_string() == "[0] Heading: # Title\n[1] Code:\n    ```py\n    x = 1\n    ```\n"
+
+    tables = mq.run('nodes | import "table" | table::tables(self)', TABLE_CONTENT)
+    assert tables.to_test_string() == (
+        "[0] Dict:\n"
+        "    align: TableHeader: |---|---:|\n"
+        "    header: Array:\n"
+        "        - TableCell: Name\n"
+        "        - TableCell: Age\n"
+        "    rows: Array:\n"
+        "        - Array:\n"
+        "            - TableCell: Bob\n"
+        "            - TableCell: 30\n"
+        "        - Array:\n"
+        "            - TableCell: Al\n"
+        "            - TableCell: 5\n"
+        "    type: String: table\n"
+    )
+
+
+def test_result_truthiness():
+    result = mq.run(".code", "# No code\n\ntext")
+    assert len(result) > 0
+    assert result.is_empty()
+    assert not result
+
+    result = mq.run(".h", "# Heading\n\ntext")
+    assert not result.is_empty()
+    assert result
+
+
+def test_result_warnings():
+    options = mq.Options()
+    options.input_format = mq.InputFormat.NULL
+    result = mq.run('[1, {"f": fn(): 1;}]', "", options)
+    assert result.warnings == ['values[0][1]["f"]: function has no Python equivalent and was converted to an empty value']
+
+    options = mq.Options()
+    options.parallel_parse = True
+    result = mq.run(".h", "# Title\n\n[ref]: https://example.com", options)
+    assert result.warnings == ["parallel_parse was ignored because the document contains reference definitions"]
+
+    assert mq.run(".h", "# Title").warnings == []
+
+
+def test_result_to_markdown_options():
+    result = mq.run(".list", "# Title\n\n- a\n- b\n")
+    options = mq.Options()
+    options.list_style = mq.ListStyle.STAR
+
+    assert result.to_markdown() == "- a\n- b\n"
+    assert result.to_markdown(options) == "* a\n* b\n"
+    assert result[1].to_markdown(options) == "* a\n"
+
+    options.link_title_style = mq.TitleSurroundStyle.SINGLE
+    assert mq.run(".link", '[x](https://example.com "T")').to_markdown(options) == "[x](https://example.com 'T')\n"
+
+
+LINT_CONTENT = """# Title
+
+## Intro
+
+text\t
+
+#### Deep
+
+more
+
+## intro
+
+# Second
+
+line with break  
+next
+"""
+
+
+def test_lint_structure():
+    findings = [(f.rule, f.line, f.column) for f in mq.lint_structure(LINT_CONTENT)]
+    assert findings == [
+        ("trailing-whitespace", 5, 5),
+        ("skipped-heading-level", 7, 1),
+        ("duplicate-heading", 11, 1),
+        ("empty-section", 11, 1),
+        ("multiple-h1", 13, 1),
+    ]
+
+    findings = mq.lint_structure(LINT_CONTENT, rules=["multiple-h1"])
+    assert len(findings) == 1
+    assert findings[0].message == "Multiple level 1 headings (first at line 1)"
+
+    assert mq.lint_structure("# Title\n\nBody\n") == []
+
+    content = "# A\n\n### B  \n\nline  \nbreak\n\n- item  \n  next\n\n```\ncode  \n```\n\nlast  \n"
+    findings = [(f.line, f.column) for f in mq.lint_structure(content, rules=["trailing-whitespace"])]
+    assert findings == [(3, 6), (15, 5)]
+
+    with pytest.raises(ValueError):
+        mq.lint_structure(LINT_CONTENT, rules=["no-such-rule"])
+
+
+def test_lint():
+    content = (
+        "# Title\n\n## Intro\n\nSee https://example.com or <https://a.org>.\n\n#### Deep\n\n## Intro\n\n"
+        "| a | b |\n|---|---|\n| 1   | 2 |\n\n| name  | v |\n|-------|---|\n| alpha | 1 |\n\n"
+        "[used]: /u\n[unused]: /v\n\nRead [this][used].\n"
+    )
+    findings = [(f.rule, f.line, f.column, f.severity) for f in mq.lint(content)]
+    assert findings == [
+        ("bare-url", 5, 5, "warning"),
+        ("skipped-heading-level", 7, 1, "warning"),
+        ("duplicate-heading", 9, 1, "warning"),
+        ("table-cell-whitespace", 13, 4, "info"),
+        ("unused-definition", 20, 1, "warning"),
+    ]
+
+    rule = mq.QueryRule("no-h4", ".h4", "Avoid level 4 headings", severity="error")
+    findings = mq.lint(content, rules=["unused-definition", rule])
+    assert [(f.rule, f.line, f.severity, f.message) for f in findings] == [
+        ("no-h4", 7, "error", "Avoid level 4 headings"),
+        ("unused-definition", 20, "warning", 'Link definition "unused" is never used'),
+    ]
+    assert mq.lint_structure("# A\n\ntext\n\n# B\n\ntext\n")[0].severity == "warning"
+
+    with pytest.raises(ValueError, match="Unknown lint rule"):
+        mq.lint(content, rules=["empty-section"])
+    with pytest.raises(ValueError, match="Unknown severity"):
+        mq.QueryRule("x", ".h", "m", severity="fatal")
+
+
+def test_result_size_limits():
+    content = "# a\n\ntext\n\n## b\n\n## c\n"
+
+    options = mq.Options()
+    options.max_results = 2
+    result = mq.run(".h", content, options)
+    assert result.values == ["# a", "## b"]
+    assert result.warnings == ["Results were truncated at max_results=2"]
+
+    options = mq.Options()
+    options.max_output_bytes = 8
+    result = mq.run(".h", content, options)
+    assert result.values == ["# a", "## b"]
+    assert result.warnings == ["Results were truncated at max_output_bytes=8"]
+
+    options.max_output_bytes = 100
+    assert mq.run(".h", content, options).warnings == []
+
+
+
+def test_execution_limits():
+    content = "# a\n\n## b\n\n## c\n"
+
+    options = mq.Options()
+    options.max_output_nodes = 2
+    with pytest.raises(mq.LimitExceededError, match="max_output_nodes=2"):
+        mq.run(".h", content, options)
+    assert mq.run(".h2", content, options).values == ["## b", "## c"]
+
+    options = mq.Options()
+    options.max_recursion_depth = 5
+    with pytest.raises(mq.LimitExceededError) as e:
+        mq.run("def f(x): f(x); | f(1)", content, options)
+    assert isinstance(e.value, mq.EvalError)
+    assert mq.run(".h | def g(n): if (n == 0): 0 else: g(n - 1); | g(3)", content, options).values == ["0"] * 3
+
+    # Depths beyond what fits on the stack would crash the interpreter.
+    with pytest.raises(ValueError, match="max_recursion_depth=5000"):
+        mq.Options(max_recursion_depth=5000)
+    with pytest.raises(ValueError):
+        options.max_recursion_depth = 5000
+    assert options.max_recursion_depth == 5
+    options.max_recursion_depth = None
+    assert options.max_recursion_depth is None
+    with pytest.raises(TypeError):
+        mq.Options(timeout_ms=500)
+
+
+def test_security_policy():
+    content = "# a\n\n## b\n"
+    options = mq.Options(security=mq.SecurityPolicy())
+
+    for query in ["$HOME", '.h | s"${$HOME}"', 'include "csv"', 'import "csv"', ".h | print(1)", "map([1], print)"]:
+        with pytest.raises(mq.SecurityError):
+            mq.run(query, content, options)
+    with pytest.raises(mq.SecurityError, match="print"):
+        mq.compile('def f(print): print; | print("x")', options)
+    for query in [
+        'let f = get_variable(intern("halt_error")) | f()',
+        "let f = get_variable(:inspect) | f(1)",
+        "let f = get_variable(:debug) | f(1)",
+        "all_symbols()",
+        'set_variable("x", 1)',
+    ]:
+        with pytest.raises(mq.SecurityError):
+            mq.run(query, content, options)
+    engine = mq.Engine(".h", options)
+    with pytest.raises(mq.SecurityError, match="print"):
+        engine.load_module('def shout(x): print(x);')
+    assert mq.run('.h | s"$$HOME ${upcase(to_text(self))}"', content, options).values == ["$HOME A", "$HOME B"]
+
+    options.security = mq.SecurityPolicy(allowed_functions=["to_text"])
+    assert mq.run(".h | to_text()", content, options).values == ["a", "b"]
+    with pytest.raises(mq.SecurityError, match="upcase"):
+        mq.run(".h | to_text() | upcase()", content, options)
+    options.security = mq.SecurityPolicy(allowed_functions=["intern", "all_symbols"])
+    assert mq.run('intern("x") | all_symbols()', content, options).values
+
+    options.security = mq.SecurityPolicy(allow_env=True, allow_include=True)
+    assert mq.run("$PATH", content, options).values
+    assert pickle.loads(pickle.dumps(options)) == options
+
+def test_query_placeholders():
+    assert mq.Q("[?, ?, ?, ?, \"?\"]", 1, 2.5, True, None) == '[1, 2.5, true, None, "?"]'
+    assert mq.Q("?", ["a", (1, 2)]) == '["a", [1, 2]]'
+
+    query = mq.Q(".h | select(contains(to_text(self), ?))", 'x") | halt("')
+    assert query == '.h | select(contains(to_text(self), "x\\") | halt(\\""))'
+    assert mq.run(query, "# Hello").values == []
+    assert mq.run(mq.Q(".h | select(contains(to_text(self), ?))", "Wor"), "# Hello\n\n## World").values == ["## World"]
+
+    with pytest.raises(ValueError):
+        mq.Q("select(?, ?)", "a")
+
+    with pytest.raises(ValueError):
+        mq.Q("select(?)", "a", "b")
+
+    with pytest.raises(TypeError):
+        mq.Q("?", object())
+
+
+
+def test_run_async():
+    async def main():
+        results = await asyncio.gather(*(mq.run_async(".h", f"# {i}") for i in range(3)))
+        assert [result.values for result in results] == [["# 0"], ["# 1"], ["# 2"]]
+        assert (await mq.run_async("x", "# A", args={"x": 1})).values == ["1"]
+
+        with pytest.raises(mq.ParseError):
+            await mq.run_async(".h | upcase(", "# A")
+
+        result = await mq.run_async(".h", "# A", source="a.md")
+        assert (result.source, result[0].source) == ("a.md", "a.md")
+        with pytest.raises(mq.ParseError, match="^a.md: "):
+            await mq.run_async(".h | upcase(", "# A", source="a.md")
+
+        assert (await mq.run_async(".h", b"# A\xff", errors="replace")).values == ["# A\ufffd"]
+        with pytest.raises(UnicodeDecodeError):
+            await mq.run_async(".h", b"# A\xff")
+
+    asyncio.run(main())
+
+    with pytest.raises(RuntimeError):
+        mq.run_async(".h", "# A")
+
+def test_run_args():
+    content = "# Draft\n\n## Draft notes\n\n## Final"
+    query = ".h | select(and(gte(.depth, min_level), contains(to_text(self), tag)))"
+    assert mq.run(query, content, args={"min_level": 2, "tag": 'Draft") | halt("'}).values == []
+    assert mq.run(query, content, args={"min_level": 2, "tag": "Draft"}).values == ["## Draft notes"]
+
+    result = mq.run('nodes | cfg["k"]', content, args={"cfg": {"k": [1, 2.5, True, None]}})
+    assert result.to_python() == [1, 2.5, True]
+    heading = mq.run(".h2", "## Draft notes")[0]
+    assert mq.run("nodes | heading", content, args={"heading": heading}).values == ["## Draft notes"]
+
+    # Arguments never leak into queries run without them.
+    with pytest.raises(mq.EvalError):
+        mq.run(".h | add(tag)", content)
+
+    with pytest.raises(ValueError):
+        mq.run(".h", content, args={"min-level": 2})
+
+    with pytest.raises(TypeError):
+        mq.run(".h", content, args={"tag": object()})
+
+
+def test_html_selector():
+    html = (
+        '<html><body><nav><a href="/home">Home</a></nav>'
+        '<main><h1>Title</h1><p>Read <a href="/docs">docs</a></p></main>'
+        "<main><h2>Second</h2></main></body></html>"
+    )
+    options = mq.Options()
+    options.input_format = mq.InputFormat.HTML
+
+    options.html_selector = "main"
+    assert mq.run(".h", html, options).values == ["# Title", "## Second"]
+
+    options.html_selector = "nav"
+    assert mq.run(".link", html, options).values == ["[Home](/home)"]
+
+    options.html_selector = "p a"
+    assert mq.run(".", html, options).values == ["[docs](/docs)"]
+
+    options.html_selector = "main >"
+    with pytest.raises(ValueError):
+        mq.run(".", html, options)
+
+
+HTML_IN_MARKDOWN = "# Title\n\n<div>\n<p>Hello <em>x</em></p>\n</div>\n\nText <b>bold</b> end\n"
+
+
+def test_html_policy():
+    options = mq.Options()
+    assert "<b>" in mq.run(".", HTML_IN_MARKDOWN, options).values
+
+    options.html_policy = mq.HtmlPolicy.CONVERT
+    result = mq.run(".", HTML_IN_MARKDOWN, options)
+    assert result.values == ["# Title", "Hello ", "*x*", "Text ", "bold", " end"]
+    assert result[1].section_path == ["Title"]
+
+    options.html_policy = mq.HtmlPolicy.STRIP
+    result = mq.run(".", HTML_IN_MARKDOWN, options)
+    assert result.values == ["# Title", "Hello x", "Text ", "bold", " end"]
+
+
+def test_html_policy_inline_and_block():
+    inline = "a <em>x</em> <a href='u'>t **s**</a> <kbd>k</kbd>\n"
+    block = "<div>\n\nz\n\n</div>\n\n<div>\nw<script>s()</script>\n</div>\n"
+
+    options = mq.Options(html_policy=mq.HtmlPolicy.CONVERT)
+    assert mq.run(".", inline, options).values == ["a ", "*x*", " ", "[t **s**](u)", " ", "<kbd>", "k", "</kbd>"]
+    assert mq.run(".", block, options).values == ["z", "w"]
+
+    options.html_policy = mq.HtmlPolicy.STRIP
+    assert mq.run(".", inline, options).values == ["a ", "x", " ", "t ", "**s**", " ", "k"]
+    assert mq.run(".", block, options).values == ["z", "w"]
+
+
+def test_equals_normalized():
+    item = mq.run(".list", "* a _b_\n")[0]
+    assert item.equals_normalized("-   a   *b*")
+    assert item.equals_normalized(mq.run(".list", "+ a *b*")[0])
+    assert not item.equals_normalized("- a b")
+
+    heading = mq.run(".h", "Title\n=====\n")[0]
+    assert heading.equals_normalized("#  Title")
+    assert not heading.equals_normalized("## Title")
+
+
+def test_unicode_normalization():
+    nfd = unicodedata.normalize("NFD", "# Café")
+    assert mq.run(".h", nfd).values != ["# Café"]
+
+    options = mq.Options()
+    options.unicode_normalization = mq.UnicodeNormalization.NFC
+    assert mq.run(".h", nfd, options).values == ["# Café"]
+
+    options.unicode_normalization = mq.UnicodeNormalization.NFKC
+    assert mq.run(".", "\ufb01le \u2460", options).values == ["file 1"]
+
+    assert mq.run(".h", nfd)[0].equals_normalized("# Café")
+
+
+UPDATE_CONTENT = "# Title\n\n* a\n\n## Section\n\ntext\n"
+
+
+def test_update():
+    result = mq.update(".h2 | upcase()", UPDATE_CONTENT)
+    assert result.changed
+    assert result
+    assert result.changed_positions == [(5, 1)]
+    assert result.content == "# Title\n\n- a\n\n## SECTION\n\ntext\n"
+
+    result = mq.update(".h3 | upcase()", UPDATE_CONTENT)
+    assert not result.changed
+    assert result.changed_positions == []
+    assert result.content == UPDATE_CONTENT
+
+
+
+def test_replace():
+    content = "# Title\n\nSee [x](http://a).\n\n## Section\n"
+    result = mq.replace(".link", content, lambda link: mq.nodes.Link(link.url.replace("http:", "https:"), "docs"))
+    assert result.changed
+    assert result.content == "# Title\n\nSee [docs](https://a).\n\n## Section\n"
+
+    assert mq.replace(".h2", content, lambda _: "Renamed").content == "# Title\n\nSee [x](http://a).\n\n## Renamed\n"
+    assert mq.replace(".h2", content, lambda _: mq.nodes.Heading(3, "New")).content.endswith("### New\n")
+
+    result = mq.replace(".h", content, lambda _: None)
+    assert not result.changed
+    assert result.content == content
+
+    with pytest.raises(TypeError):
+        mq.replace(".h", content, lambda _: object())
+
+def test_update_file(tmp_path):
+    path = tmp_path / "doc.md"
+    path.write_text(UPDATE_CONTENT)
+
+    assert not mq.update_file(".h3 | upcase()", path).changed
+    assert path.read_text() == UPDATE_CONTENT
+
+    assert mq.update_file(".h2 | upcase()", str(path)).changed
+    assert path.read_text() == "# Title\n\n- a\n\n## SECTION\n\ntext\n"
+
+
+def test_update_glob(tmp_path):
+    (tmp_path / "sub").mkdir()
+    (tmp_path / "doc.md").write_text(UPDATE_CONTENT)
+    (tmp_path / "sub" / "other.md").write_text("# Other\n")
+
+    results = mq.update_glob(".h2 | upcase()", str(tmp_path / "**" / "*.md"), dry_run=True)
+    assert [(path.endswith("doc.md"), result.changed) for path, result in results.items()] == [
+        (True, True),
+        (False, False),
+    ]
+    assert "-## Section\n+## SECTION\n" in results[str(tmp_path / "doc.md")].diff()
+    assert results[str(tmp_path / "sub" / "other.md")].diff() == ""
+    assert (tmp_path / "doc.md").read_text() == UPDATE_CONTENT
+
+    mq.update_glob(".h2 | upcase()", str(tmp_path / "**" / "*.md"))
+    assert (tmp_path / "doc.md").read_text() == "# Title\n\n- a\n\n## SECTION\n\ntext\n"
+
+
+def test_update_glob_all_or_nothing(tmp_path):
+    (tmp_path / "a.md").write_text(UPDATE_CONTENT)
+    (tmp_path / "b.md").write_bytes(b"\xff\xfe")
+    pattern = str(tmp_path / "*.md")
+
+    with pytest.raises(OSError, match="b.md"):
+        mq.update_glob(".h2 | upcase()", pattern, all_or_nothing=True)
+    assert (tmp_path / "a.md").read_text() == UPDATE_CONTENT
+
+    with pytest.raises(OSError, match="b.md"):
+        mq.update_glob(".h2 | upcase()", pattern)
+    assert (tmp_path / "a.md").read_text() == "# Title\n\n- a\n\n## SECTION\n\ntext\n"
+    assert sorted(path.name for path in tmp_path.iterdir()) == ["a.md", "b.md"]
+
+
+def test_diff():
+    old = "# Setup\n\nSome *text*.\nMore.\n\nGone.\n\n- a\n- b\n\n| x |\n|---|\n| 1 |\n"
+    new = "# Installation\n\nSome _text_.\nMore.\n\n- a\n- b\n- c\n\n| x |\n|---|\n| 2 |\n"
+    changes = mq.diff(old, new)
+
+    assert [(change.kind, change.type) for change in changes] == [
+        ("modified", "heading"),
+        ("removed", "paragraph"),
+        ("added", "list_item"),
+        ("modified", "table"),
+    ]
+    assert changes[0].old_text == "# Setup"
+    assert changes[0].text == "# Installation"
+    assert str(changes[0]) == 'heading modified: "# Setup" -> "# Installation"'
+    assert changes[1].text == "Gone."
+    assert changes[1].position.start_line == 6
+    assert changes[1].old_text is None
+    assert changes[2].text == "- c"
+    assert mq.diff(old, old) == []
+
+
+def test_result_windowing():
+    result = mq.run(".h", "# H1\n\ntext\n\n## H2\n\n### H3\n")
+
+    assert result.take(2).values == ["# H1", "## H2"]
+    assert result.skip(1).values == ["## H2", "### H3"]
+    assert result.window(1, 1).values == ["## H2"]
+    assert len(result.window(1, 10)) == 2
+    assert len(result.skip(5)) == 0
+    assert isinstance(result.take(1), mq.MQResult)
+
+
+def test_result_unique():
+    result = mq.run(".link", "[a](https://x.com) [b](https://x.com) [a](https://y.com) [a](https://x.com)")
+
+    assert result.unique().values == ["[a](https://x.com)", "[b](https://x.com)", "[a](https://y.com)"]
+    assert result.unique("url").values == ["[a](https://x.com)", "[a](https://y.com)"]
+    assert result.unique("text").values == ["[a](https://x.com)", "[b](https://x.com)"]
+    assert result.unique(lambda value: len(value.text)).values == ["[a](https://x.com)"]
+
+    with pytest.raises(ValueError):
+        result.unique("missing")
+
+
+def test_result_sorted():
+    result = mq.run(".link", "[b](https://2.com) [a](https://3.com) [c](https://1.com)")
+
+    assert result.sorted().values == ["[a](https://3.com)", "[b](https://2.com)", "[c](https://1.com)"]
+    assert result.sorted("url").values == ["[c](https://1.com)", "[b](https://2.com)", "[a](https://3.com)"]
+    assert result.sorted("text", reverse=True).values == ["[c](https://1.com)", "[b](https://2.com)", "[a](https://3.com)"]
+    assert result.sorted("position", reverse=True).values == ["[c](https://1.com)", "[a](https://3.com)", "[b](https://2.com)"]
+    assert result.sorted(lambda value: value.text[-6]).values == ["[c](https://1.com)", "[b](https://2.com)", "[a](https://3.com)"]
+    assert isinstance(result.sorted(), mq.MQResult)
+
+    with pytest.raises(ValueError):
+        result.sorted("missing")
+
+
+def test_result_count_by():
+    content = "# A\n\n## B\n\n## C\n\n[a](https://Docs.example.com:8080/x) [b](http://docs.example.com) [c](/rel)\n"
+
+    assert mq.run(".h", content).count_by("level") == {1: 1, 2: 2}
+    assert mq.run(".link", content).count_by("domain") == {"docs.example.com": 2}
+    assert mq.run(".", content).count_by("type") == {"Heading": 3, "Link": 3, "Text": 2}
+    assert mq.run(".h", content).count_by(lambda value: value.text[-1]) == {"A": 1, "B": 1, "C": 1}
+
+
+def test_run_source():
+    result = mq.run(".h", "# Title\n\n## Sub", source="guide.md")
+    assert result.source == "guide.md"
+    assert [value.source for value in result] == ["guide.md", "guide.md"]
+    assert result.take(1).source == "guide.md"
+    assert mq.run(".h", "# Title").source is None
+    assert mq.run(".h", "# Title")[0].source is None
+
+    with pytest.raises(RuntimeError, match="^guide.md: "):
+        mq.run("undefined_function()", "# Title", source="guide.md")
+
+
+def test_table_helpers_invalid():
+    table = table_value()
+
+    with pytest.raises(ValueError):
+        table.add_row(["only one"])
+
+    with pytest.raises(IndexError):
+        table.set_cell(5, 0, "x")
+
+    with pytest.raises(KeyError):
+        table.sort_by("Missing")
+
+    with pytest.raises(TypeError, match=r"add_row\(\) requires a table value; use mq.extract_tables\(\)"):
+        mq.run(".h", "# Heading")[0].add_row(["x"])
+
+
+@pytest.fixture
+def renderers():
+    yield
+    mq.clear_renderers()
+
+
+def test_register_renderer(renderers):
+    content = "# Title [a](https://a.com)\n\n```mermaid\ngraph TD\n```\n\n```python\nx\n```\n"
+
+    mq.register_renderer(
+        mq.MarkdownType.Code, lambda code: "![diagram](diagram.svg)" if code.lang == "mermaid" else None
+    )
+    mq.register_renderer(mq.MarkdownType.Link, lambda link: f"<{link.url}>")
+
+    assert mq.run(".code", content).values == ["![diagram](diagram.svg)", "```python\nx\n```"]
+    assert mq.run(".h", content).values == ["# Title <https://a.com>"]
+    assert mq.nodes.Link("https://b.com", "b").to_markdown() == "<https://b.com>"
+    assert mq.DocumentBuilder().code("graph TD", "mermaid").build() == "![diagram](diagram.svg)\n"
+
+    assert mq.unregister_renderer(mq.MarkdownType.Link)
+    assert not mq.unregister_renderer(mq.MarkdownType.Link)
+    assert mq.run(".h", content).values == ["# Title [a](https://a.com)"]
+
+
+def test_register_renderer_errors(renderers):
+    with pytest.raises(TypeError):
+        mq.register_renderer(mq.MarkdownType.Code, "not callable")
+
+    def fail(node):
+        raise ValueError("boom")
+
+    mq.register_renderer(mq.MarkdownType.Heading, fail)
+
+    with pytest.raises(ValueError):
+        mq.run(".h", "# Title")
+
+
+CODE_BLOCKS_CONTENT = """# Title
+
+```python title=example.py
+print(1)
+```
+
+- item
+
+  ```python
+  print(2)
+  ```
+
+```bash
+ls
+```
+"""
+
+
+def test_code_blocks():
+    assert mq.code_blocks(CODE_BLOCKS_CONTENT) == [
+        ("python", "print(1)", "title=example.py", (3, 1)),
+        ("python", "print(2)", None, (9, 3)),
+        ("bash", "ls", None, (13, 1)),
+    ]
+    assert [code for _, code, _, _ in mq.code_blocks(CODE_BLOCKS_CONTENT, lang="bash")] == ["ls"]
+    assert mq.code_blocks("# No code") == []
+
+
+def test_code_blocks_concat():
+    assert mq.code_blocks(CODE_BLOCKS_CONTENT, lang="python", concat=True) == "print(1)\nprint(2)\n"
+
+
+def test_code_block_objects():
+    block = mq.code_blocks(CODE_BLOCKS_CONTENT)[0]
+
+    assert (block.lang, block.value, block.meta) == ("python", "print(1)", "title=example.py")
+    assert (block.position.start_line, block.position.start_column) == (3, 1)
+    assert block[1] == "print(1)" and len(block) == 4
+    assert block == mq.code_blocks(CODE_BLOCKS_CONTENT)[0]
+
+
+def test_update_code_blocks():
+    content = "# T\n\n```sql\nselect 1\n```\n\n```python\nx\n```\n"
+
+    assert mq.update_code_blocks(content, lambda block: block.value.upper() + "\n", lang="sql") == (
+        "# T\n\n```sql\nSELECT 1\n```\n\n```python\nx\n```\n"
+    )
+    assert mq.update_code_blocks(content, lambda block: None) == content
+    assert mq.update_code_blocks(content, lambda block: mq.nodes.Html("<hr>") if block.lang == "python" else None) == (
+        "# T\n\n```sql\nselect 1\n```\n\n<hr>\n"
+    )
+
+    with pytest.raises(TypeError, match="str, a node or None"):
+        mq.update_code_blocks(content, lambda block: 1)
+
+
+def test_mdx_components():
+    content = (
+        '# Hi\n\n<Card title="Intro" count={1 + 1} hidden {...props}>\n  See <Badge tone="info">new</Badge>\n</Card>\n'
+        "\n{year}\n"
+    )
+
+    card, badge = mq.mdx_components(content)
+    assert card.markdown_type == mq.MarkdownType.MdxJsxFlowElement
+    assert (card.component, card.attributes) == ("Card", {"title": "Intro", "count": "{1 + 1}", "hidden": True})
+    assert (badge.component, badge.attributes, badge.position.start_line) == ("Badge", {"tone": "info"}, 4)
+    assert [value.component for value in mq.mdx_components(content, name="Badge")] == ["Badge"]
+    assert mq.mdx_components("# Plain") == []
+
+    result = mq.run("self", content, mq.Options(input_format=mq.InputFormat.MDX))
+    [expression] = [value for value in result if value.markdown_type == mq.MarkdownType.MdxFlowExpression]
+    assert (expression.expression, expression.component, expression.attributes) == ("year", None, None)
+
+
+def test_extract_tables():
+    content = """| Package | Version |
+| :--- | ---: |
+| **mq-lang** | `0.5.0` |
+| [mq-markdown](https://example.com) |
+
+text
+
+| Name |
+| --- |
+| mq |
+"""
+    assert mq.extract_tables(content) == [
+        [{"Package": "mq-lang", "Version": "0.5.0"}, {"Package": "mq-markdown", "Version": ""}],
+        [{"Name": "mq"}],
+    ]
+    assert mq.extract_tables("# No tables") == []
+
+    first = content.split("text")[0]
+    assert mq.run(".table", first).to_table() == mq.extract_tables(first)[0]
+    assert mq.run('nodes | import "table" | table::tables(self)', first).to_table() == mq.extract_tables(first)[0]
+    with pytest.raises(ValueError, match="cells of 2 tables"):
+        mq.run(".table", content).to_table()
+    with pytest.raises(ValueError, match="requires table cells"):
+        mq.run(".h", "# H1").to_table()
+
+    repeated = "| a | b | a | a_2 | a |\n| - | - | - | - | - |\n| 1 | 2 | 3 | 4 | 5 |\n"
+    expected = [{"a": "1", "b": "2", "a_2": "3", "a_2_2": "4", "a_3": "5"}]
+    assert mq.extract_tables(repeated) == [expected]
+    assert list(mq.extract_tables(repeated)[0][0]) == ["a", "b", "a_2", "a_2_2", "a_3"]
+    assert mq.run(".table", repeated).to_table() == expected
+
+def test_front_matter():
+    content = "---\ntitle: Hello\ntags: [a, b]\n---\n# Body\n"
+    assert mq.get_front_matter(content) == {"title": "Hello", "tags": ["a", "b"]}
+    assert mq.get_front_matter("# Body") == {}
+    assert mq.get_front_matter('+++\ntitle = "Hi"\n[extra]\nn = 1\n+++\n') == {"title": "Hi", "extra": {"n": 1}}
+
+    updated = mq.set_front_matter(content, {"title": "New", "draft": False})
+    assert updated == "---\ntitle: New\ndraft: false\n---\n# Body\n"
+    assert mq.get_front_matter(updated) == {"title": "New", "draft": False}
+    assert mq.set_front_matter(content, {"title": "T"}, format="toml") == '+++\ntitle = "T"\n+++\n# Body\n'
+    assert mq.set_front_matter(content, {}) == "# Body\n"
+    assert mq.set_front_matter("# Body\n", {"a": 1}) == "---\na: 1\n---\n# Body\n"
+
+    with pytest.raises(TypeError):
+        mq.set_front_matter(content, {"a": None}, format="toml")
+    with pytest.raises(mq.MQParseInputError):
+        mq.get_front_matter("---\n- a\n---\n")
+
+FOOTNOTES_CONTENT = """Text[^a] and more[^note].
+
+Again[^a].
+
+[^note]: Second **note**.
+
+[^a]: [example](https://example.com)
+
+[^unused]: Never referenced.
+"""
+
+
+@pytest.mark.parametrize(
+    "style,expected",
+    [
+        (
+            "parens",
+            "Text ([example](https://example.com)) and more (Second **note**.).\n\n"
+            "Again ([example](https://example.com)).\n",
+        ),
+        (
+            "link",
+            "Text [example](https://example.com) and more (Second **note**.).\n\n"
+            "Again [example](https://example.com).\n",
+        ),
+    ],
+)
+def test_inline_footnotes(style, expected):
+    assert mq.inline_footnotes(FOOTNOTES_CONTENT, style=style) == expected
+
+
+def test_inline_footnotes_invalid_style():
+    with pytest.raises(ValueError):
+        mq.inline_footnotes(FOOTNOTES_CONTENT, style="superscript")
+
+
+def test_renumber_footnotes():
+    assert mq.renumber_footnotes(FOOTNOTES_CONTENT) == (
+        "Text[^1] and more[^2].\n\nAgain[^1].\n\n[^1]: [example](https://example.com)\n\n[^2]: Second **note**.\n"
+    )
+
+
+CALLOUTS_CONTENT = """> [!WARNING]
+> Careful here
+
+> **Tip:** Use the cache.
+
+!!! note "Heads up"
+    Body text
+
+```
+> [!NOTE]
+> in code
+```
+"""
+
+
+@pytest.mark.parametrize(
+    "to,expected",
+    [
+        (
+            "gfm",
+            "> [!WARNING]\n> Careful here\n\n> [!TIP]\n> Use the cache.\n\n> [!NOTE]\n> **Heads up**\n> Body text\n\n"
+            "```\n> [!NOTE]\n> in code\n```\n",
+        ),
+        (
+            "mkdocs",
+            '!!! warning\n    Careful here\n\n!!! tip\n    Use the cache.\n\n!!! note "Heads up"\n    Body text\n\n'
+            "```\n> [!NOTE]\n> in code\n```\n",
+        ),
+        (
+            "blockquote",
+            "> **Warning**\n>\n> Careful here\n\n> **Tip:** Use the cache.\n\n> **Heads up**\n>\n> Body text\n\n"
+            "```\n> [!NOTE]\n> in code\n```\n",
+        ),
+    ],
+)
+def test_convert_callouts(to, expected):
+    assert mq.convert_callouts(CALLOUTS_CONTENT, to=to) == expected
+
+
+def test_convert_callouts_plain_blockquote():
+    content = "> Just a quote\n> **Note:** not a label\n"
+    assert mq.convert_callouts(content, to="mkdocs") == content
+
+    with pytest.raises(ValueError):
+        mq.convert_callouts(content, to="rst")
+
+
+FLUENT_CONTENT = """# Title
+
+## Install [guide](https://example.com/install)
+
+## Usage "quoted"
+
+```python
+print(1)
+```
+
+![logo](logo.png)
+"""
+
+
+def test_fluent_selectors():
+    doc = mq.doc(FLUENT_CONTENT)
+
+    assert doc.headings(level=2).query == ".h2"
+    assert doc.headings(level=1).text() == ["Title"]
+    assert doc.links().urls() == ["https://example.com/install"]
+    assert doc.images().urls() == ["logo.png"]
+    assert doc.code("python").markdown() == ["```python\nprint(1)\n```"]
+    assert len(doc.headings()) == 3
+    assert [value.text for value in doc.headings(2)] == [
+        "## Install [guide](https://example.com/install)",
+        '## Usage "quoted"',
+    ]
+
+
+def test_fluent_selectors_contains():
+    selection = mq.doc(FLUENT_CONTENT).headings().contains('"quoted"')
+    assert selection.query == '.h | select(contains(to_text(self), "\\"quoted\\""))'
+    assert selection.markdown() == ['## Usage "quoted"']
+    assert selection.first().markdown_type == mq.MarkdownType.Heading
+
+    with pytest.raises(ValueError):
+        mq.doc(FLUENT_CONTENT).headings(level=7)
+
+
+SECTIONS_CONTENT = """# Guide
+
+Intro [home](https://example.com)
+
+## Install
+
+```bash
+pip install markdown-query
+```
+
+### From source
+
+Build it.
+
+## Usage `mq`
+
+[docs](https://example.com/docs)
+"""
+
+
+@pytest.mark.parametrize(
+    "query,expected",
+    [
+        (".h", [[], ["Guide"], ["Guide", "Install"], ["Guide"]]),
+        (".link", [["Guide"], ["Guide", "Usage mq"]]),
+        (".code", [["Guide", "Install"]]),
+    ],
+)
+def test_section_path(query, expected):
+    assert [value.section_path for value in mq.run(query, SECTIONS_CONTENT) if value] == expected
+
+
+def test_section_path_without_position():
+    assert mq.run('"text"', SECTIONS_CONTENT)[0].section_path == []
+
+
+def test_by_section():
+    sections = mq.run(".link", "[pre](a)\n\n" + SECTIONS_CONTENT).by_section()
+    assert list(sections) == [(), ("Guide",), ("Guide", "Usage mq")]
+    assert [value.text for value in sections[("Guide",)]] == ["[home](https://example.com)"]
+
+    headings = mq.run(".h", SECTIONS_CONTENT).by_section()
+    assert list(headings) == [("Guide",), ("Guide", "Install"), ("Guide", "Install", "From source"), ("Guide", "Usage mq")]
+
+
+def test_list_item_kinds():
+    content = "3. a\n4. b\n\n- x\n  1. y\n"
+    result = mq.run(".[]", content, None)
+
+    assert [v.markdown_type for v in result] == [
+        mq.MarkdownType.OrderedList,
+        mq.MarkdownType.OrderedList,
+        mq.MarkdownType.List,
+        mq.MarkdownType.OrderedList,
+    ]
+    assert [v.ordered for v in result] == [True, True, False, True]
+    assert [v.index for v in result] == [0, 1, 0, 0]
+    assert [v.start for v in result] == [3, 3, None, 1]
+    assert result[3].text == "  1. y"
+
+    heading = mq.run(".h", "# Title", None)[0]
+    assert heading.ordered is None
+    assert heading.index is None
+    assert heading.start is None
+
+
+def test_table_cell_structure():
+    content = "| a | b |\n|:--|--:|\n| 1 | 2 |\n\n| x |\n|:-:|\n| y |\n"
+    cells = [v for v in mq.run("select(is_table_cell(self))", content, None) if v]
+
+    assert [(v.text, v.row_index, v.column_index) for v in cells] == [
+        ("a", 0, 0),
+        ("b", 0, 1),
+        ("1", 1, 0),
+        ("2", 1, 1),
+        ("x", 0, 0),
+        ("y", 1, 0),
+    ]
+    assert [v.column_alignment for v in cells] == ["left", "right", "left", "right", "center", "center"]
+    assert [v.table_index for v in cells] == [0, 0, 0, 0, 1, 1]
+
+    heading = mq.run(".h", "# Title", None)[0]
+    assert heading.row_index is None
+    assert heading.column_alignment is None
+
+
+def test_math_style():
+    content = "$$\nx^2\n$$\n\nInline $a+b$.\n\n```math\ny\n```\n"
+    result = mq.run("self", content, None)
+
+    assert [v.math for v in result if v.math is not None] == ["x^2", "a+b", "y"]
+
+    options = mq.Options()
+    options.math_style = mq.MathStyle.FENCED
+    assert result.to_markdown(options) == "```math\nx^2\n```\n\nInline $a+b$.\n\n```math\ny\n```\n"
+
+    options.math_style = mq.MathStyle.DOLLAR
+    assert result.to_markdown(options) == "$$\nx^2\n$$\n\nInline $a+b$.\n\n$$\ny\n$$\n"
+
+    assert result.to_markdown() == content
+
+
+def test_render_styles():
+    content = "# Title\n\n## Sub *x*\n\n### Third\n\n- a **b**\n  - nested\n\nx  \ny\n\n```py\n~~~\n```\n"
+    result = mq.run("self", content, None)
+    options = mq.Options(
+        heading_style=mq.HeadingStyle.SETEXT,
+        emphasis_style=mq.EmphasisStyle.UNDERSCORE,
+        strong_style=mq.EmphasisStyle.UNDERSCORE,
+        code_fence_style=mq.CodeFenceStyle.TILDE,
+        hard_break_style=mq.HardBreakStyle.SPACES,
+        list_marker_spacing=3,
+    )
+
+    assert result.to_markdown(options) == (
+        "Title\n=====\n\nSub _x_\n-------\n\n### Third\n\n-   a __b__\n    -   nested\n\nx  \ny\n\n~~~~py\n~~~\n~~~~\n"
+    )
+
+    options = mq.Options(hard_break_style=mq.HardBreakStyle.BACKSLASH, code_fence_style=mq.CodeFenceStyle.BACKTICK)
+    assert result.to_markdown(options).endswith("x\\\ny\n\n```py\n~~~\n```\n")
+
+
+def test_table_padding():
+    content = "| A | Bee |\n|:--|:-:|\n| long cell | x |\n| c |\n\n```\n|a|b|\n|-|-|\n```\n"
+    options = mq.Options(table_padding=True)
+
+    assert mq.run("self", content, None).to_markdown(options) == (
+        "| A         | Bee |\n| :-------- | :-: |\n| long cell |  x  |\n| c         |     |\n"
+        "\n\n```\n|a|b|\n|-|-|\n```\n"
+    )
+
+
+def test_sanitize():
+    content = (
+        '<script>alert(1)</script>\n\nHi <b onclick="x()">there</b> <script>evil()</script>!\n\n'
+        '<div>\n<iframe src="x"></iframe>\n<a href=" javascript:alert(1)" title="t">l</a>\n</div>\n'
+    )
+
+    assert mq.sanitize(content) == 'Hi <b>there</b> !\n\n<div>\n<a title="t">l</a>\n</div>\n'
+
+    escaped = mq.sanitize(content, policy="escape")
+    assert "<script>" not in escaped
+    assert "&lt;script&gt;alert(1)&lt;/script&gt;" in escaped
+    assert '&lt;b onclick="x()"&gt;there</b>' in escaped
+
+    safe = "# Title\n\n-  item <b>bold</b>\n"
+    assert mq.sanitize(safe) == safe
+
+    with pytest.raises(ValueError):
+        mq.sanitize(content, policy="remove")
+
+
+def test_url_and_title_options():
+    content = '[a](<https://example.com/a b/\u00fc?q=%20> "  Say\n  \\"hi\\" ")\n\n[d]: https://example.com/(p) "  "\n'
+    result = mq.run("self", content, None)
+
+    options = mq.Options()
+    options.percent_encode_urls = True
+    options.link_titles = mq.LinkTitlePolicy.NORMALIZE
+    assert result.to_markdown(options) == (
+        '[a](https://example.com/a%20b/%C3%BC?q=%20 "Say \\"hi\\"")\n\n[d]: https://example.com/%28p%29\n'
+    )
+
+    options.link_titles = mq.LinkTitlePolicy.STRIP
+    assert result.to_markdown(options) == "[a](https://example.com/a%20b/%C3%BC?q=%20)\n\n[d]: https://example.com/%28p%29\n"
+
+
+def test_apply_link_policies():
+    content = "A [docs](http://docs.example.com/a \"T\") B [ads](https://ads.net/x) C [gh](http://github.com) D [rel](/local)\n"
+    policies = {"*.example.com": ["https", "nofollow"], "ads.net": "block", "GitHub.com": "https"}
+
+    assert mq.apply_link_policies(content, policies) == (
+        'A <a href="https://docs.example.com/a" title="T" rel="nofollow">docs</a> B ads '
+        "C [gh](https://github.com) D [rel](/local)\n"
+    )
+
+    with pytest.raises(ValueError):
+        mq.apply_link_policies(content, {"example.com": "remove"})
+
+
+def test_code_meta_dict():
+    content = "```python title=\"app.py\" linenums hl_lines='2 3' {1,3-5} start=10\nprint(1)\n```\n\n```\nplain\n```\n"
+    code, plain = [v for v in mq.run(".code", content, None) if v]
+
+    assert code.lang == "python"
+    assert code.meta_dict == {
+        "title": "app.py",
+        "linenums": True,
+        "hl_lines": "2 3",
+        "{1,3-5}": True,
+        "start": "10",
+    }
+    assert plain.lang is None
+    assert plain.meta_dict == {}
+    assert mq.run(".h", "# Title", None)[0].meta_dict is None
+
+
+def test_normalize_headings():
+    content = "# getting started with the GitHub API.\n\nbody text.\n\n## Using `mq_run` In The Docs:\n\n### what is it for\n"
+
+    assert mq.normalize_headings(content) == (
+        "# Getting started with the GitHub API\n\nbody text.\n\n## Using `mq_run` in the docs\n\n### What is it for\n"
+    )
+    assert mq.normalize_headings(content, case="title", strip_trailing_punct=False) == (
+        "# Getting Started with the GitHub API.\n\nbody text.\n\n## Using `mq_run` in the Docs:\n\n### What Is It For\n"
+    )
+
+    with pytest.raises(ValueError):
+        mq.normalize_headings(content, case="upper")
+
+
+def test_whitespace_options():
+    builder = (
+        mq.DocumentBuilder()
+        .heading(1, "Title")
+        .paragraph(["text   "])
+        .node(mq.nodes.Html("<div>\n\n\n\n</div>\t"))
+        .code("x   \n\n\n\ny", "text")
+    )
+
+    options = mq.Options()
+    options.trim_trailing_whitespace = True
+    options.max_blank_lines = 1
+    options.final_newline = True
+
+    assert builder.build(options) == "# Title\n\ntext\n\n<div>\n\n</div>\n\n```text\nx   \n\n\n\ny\n```\n"
+    assert mq.run("self", "para\n\n<div>\n</div>   \n", None).to_markdown(options) == "para\n\n<div>\n</div>\n"
+
+
+def test_max_heading_depth():
+    content = "# A\n\n#### B\n\n##### C\n\n###### D\n"
+
+    assert mq.limit_heading_depth(content, 4) == "# A\n\n#### B\n\n#### C\n\n#### D\n"
+
+    findings = mq.lint_structure(content, rules=["max-heading-depth"], max_heading_depth=4)
+    assert [(f.rule, f.line) for f in findings] == [("max-heading-depth", 5), ("max-heading-depth", 7)]
+    assert mq.lint_structure(content, rules=["max-heading-depth"]) == []
+
+    with pytest.raises(ValueError):
+        mq.limit_heading_depth(content, 7)
+
+
+def test_anchors():
+    content = "# Hello, World!\n\n## \u00dcber `code`\n\n## Setup\n\n## Setup\n\n## Setup-1\n"
+    anchors = mq.anchors(content)
+
+    assert list(anchors) == ["hello-world", "\u00fcber-code", "setup", "setup-1", "setup-1-1"]
+    assert anchors["hello-world"].text == "Hello, World!"
+    assert (anchors["setup-1"].depth, anchors["setup-1"].line, anchors["setup-1"].column) == (2, 7, 1)
+
+
+def test_notebook_display():
+    content = "# Title\n\n- one\n- two\n\n## Next\n"
+    result = mq.run(".h", content, mq.Options(display_limit=1))
+
+    assert result._repr_markdown_() == "# Title\n\n*\u2026 1 more value*\n"
+    assert result._repr_html_() == "<h1>Title</h1>\n<p><em>\u2026 1 more value</em></p>\n"
+    assert result.take(2).display_limit == 1
+
+    result.display_limit = None
+    assert result._repr_markdown_() == result.to_markdown()
+    assert mq.run(".list", "- one\n")[0]._repr_html_() == "<ul>\n<li>one</li>\n</ul>\n"
+
+    for html in [
+        mq.run(".", "- <script>x</script>\n")._repr_html_(),
+        mq.run(".h", "# a <b>\n")[0]._repr_html_(),
+        mq.run(".", "<div onclick=\"x()\">\n\ntext\n")._repr_html_(),
+    ]:
+        assert "<script" not in html and "<b>" not in html and "<div" not in html
+    assert "&lt;script&gt;x&lt;/script&gt;" in mq.run(".", "- <script>x</script>\n")._repr_html_()
+    assert mq.run(".h", "# a <b>\n")._repr_html_() == "<h1>a &lt;b&gt;</h1>\n"
+
+
+def test_load_and_apply_edit():
+    content = "# Title\n\nFirst paragraph.\n\n## Section\n\n- a\n- b\n\n```\ncode\n```\n\nLast.\n"
+    doc = mq.load(content)
+    assert doc.run(".h | to_text()").values == ["Title", "Section"]
+
+    start = content.index("First")
+    assert doc.apply_edit((start, start + len("First")), "Second") == (9, 28)
+    assert doc.content == content.replace("First", "Second")
+
+    # Inserting lines moves the positions of the blocks after the edit.
+    doc.apply_edit((0, 0), "Intro\n\n")
+    section = [value for value in doc.run(".h2") if value][0]
+    assert (section.position.start_line, section.position.start_offset) == (7, 35)
+
+    # An unclosed fence turns everything after it into code.
+    doc.apply_edit((start, start), "```\n")
+    assert doc.run(".").values == mq.run(".", doc.content).values
+
+    with pytest.raises(ValueError, match="out of bounds"):
+        doc.apply_edit((5, 1000), "")
+
+
+def test_slug_styles():
+    assert mq.slugify("1. Setup -- Linux") == "1-setup----linux"
+    assert mq.slugify("1. Setup -- Linux", mq.SlugStyle.GITLAB) == "1-setup-linux"
+    assert mq.slugify("2024", mq.SlugStyle.GITLAB) == "anchor-2024"
+    assert mq.slugify("Intro", mq.SlugStyle.CUSTOM, str.upper) == "INTRO"
+
+    with pytest.raises(ValueError, match="requires a slug function"):
+        mq.slugify("Intro", mq.SlugStyle.CUSTOM)
+
+    options = mq.Options(slug_style=mq.SlugStyle.CUSTOM, slug_function=lambda text: "s-" + text.lower())
+    assert list(mq.anchors("# Intro\n\n# Intro\n", options)) == ["s-intro", "s-intro-1"]
+    assert [value.anchor for value in mq.run(".h", "# Intro\n\n# Intro\n", options)] == ["s-intro", "s-intro-1"]
+
+    options = mq.Options(heading_anchors=True)
+    rendered = mq.run(".", "# Hello World\n\ntext\n", options).to_markdown(options)
+    assert rendered == '# <a id="hello-world"></a>Hello World\n\ntext\n'
+    assert mq.run(".", rendered, options).to_markdown(options) == rendered
+
+
+def test_rename_headings():
+    content = (
+        "See [setup](#setup), [again](#setup-1), [ext](https://example.com#setup) and [faq][ref].\n\n"
+        "## Setup\n\n## Setup\n\n## FAQ\n\n[ref]: #faq\n"
+    )
+
+    assert mq.rename_headings(content, {"Setup": "Installation", "FAQ": "Questions"}) == (
+        "See [setup](#installation), [again](#installation-1), [ext](https://example.com#setup) and [faq][ref].\n\n"
+        "## Installation\n\n## Installation\n\n## Questions\n\n[ref]: #questions\n"
+    )
+
+    renamed = "See [setup](#setup).\n\n## Installation\n"
+    assert mq.rename_headings(renamed, {"Setup": "Installation"}) == "See [setup](#installation).\n\n## Installation\n"
+
+
+def test_text_utilities():
+    from mq import text
+
+    assert text.slugify("Hello, World! Über") == "hello-world-ber"
+    assert text.slugify("a b", "_") == "a_b"
+    assert text.trim("  x  ") == "x"
+    assert text.ltrim("  x  ") == "x  "
+    assert text.rtrim("  x  ") == "  x"
+    assert text.ltrimstr("v1.2", "v") == "1.2"
+    assert text.rtrimstr("file.md", ".md") == "file"
+    assert text.ltrimstr("1.2", "v") == "1.2"
+    assert text.upcase("mq") == "MQ"
+    assert text.downcase("MQ") == "mq"
+    assert text.url_encode("a b/ü?&") == "a%20b%2F%C3%BC%3F%26"
+
+    assert [text.slugify(v) for v in mq.run(".h", "# Hello World").values] == mq.run(
+        ".h | to_text() | slugify()", "# Hello World"
+    ).values
+
+
+def test_record_provenance():
+    markdown = "# A\n\n## B\n\ntext\n"
+    options = mq.Options()
+    options.record_provenance = True
+
+    result = mq.run(".h | select(.h.depth == 2) | to_text()", markdown, options)
+    assert [(v.text, v.stage, v.stage_name) for v in result] == [
+        ("", 1, "select"),
+        ("B", 2, "to_text"),
+        ("", 0, ".h"),
+    ]
+
+    # Pipes inside a definition belong to it, and values passing every stage unchanged count as the last one's.
+    result = mq.run('def f(x): x | upcase(); | .h | select(contains("B"))', markdown, options)
+    assert [(v.text, v.stage, v.stage_name) for v in result] == [
+        ("", 2, "select"),
+        ("## B", 2, "select"),
+        ("", 1, ".h"),
+    ]
+
+    assert mq.run(".h", markdown)[0].stage is None
+
+    # Prefixes are run with the query's arguments, so the stage dropping a value is the one blamed.
+    result = mq.run(".h | select(contains(to_text(self), n)) | to_text()", markdown, options, args={"n": "B"})
+    assert [(v.text, v.stage, v.stage_name) for v in result] == [
+        ("", 1, "select"),
+        ("B", 2, "to_text"),
+        ("", 0, ".h"),
+    ]
+    assert result.warnings == []
+
+
+def test_run_from_threads():
+    from concurrent.futures import ThreadPoolExecutor
+
+    documents = [f"# Doc {i}\n\n## Section {i}\n" for i in range(32)]
+
+    with ThreadPoolExecutor(max_workers=8) as pool:
+        results = list(pool.map(lambda doc: mq.run(".h | to_text()", doc).values, documents))
+
+    assert results == [[f"Doc {i}", f"Section {i}"] for i in range(32)]
+
+
+def test_run_many():
+    documents = [f"# Doc {i}\n\n## Section {i}\n" for i in range(40)]
+    results = mq.run_many(".h2 | to_text()", documents + [b"# Bytes"])
+
+    assert [r.values for r in results] == [[f"Section {i}"] for i in range(40)] + [[]]
+    assert mq.run_many(".h", []) == []
+    with pytest.raises(RuntimeError, match="Error compiling query"):
+        mq.run_many("def f(", ["# a"])
+    with pytest.raises(RuntimeError, match=r"contents\[1\]: "):
+        mq.run_many("error(\"boom\")", ["", "# a"])
+
+
+def test_run_many_front_matter():
+    documents = ["---\ndraft: true\n---\n# A\n", "+++\ndraft = false\n+++\n# B\n", "# C\n"]
+
+    results = mq.run_many(".h | to_text()", documents, front_matter={"draft": True})
+    assert [r and r.values for r in results] == [["A"], None, None]
+    results = mq.run_many(".h | to_text()", documents, front_matter=lambda meta: not meta.get("draft"))
+    assert [r and r.values for r in results] == [None, ["B"], ["C"]]
+    with pytest.raises(TypeError):
+        mq.run_many(".h", documents, front_matter="draft")
+    with pytest.raises(mq.MQParseInputError, match=r"contents\[0\]: "):
+        mq.run_many(".h", ["---\n[a\n---\n"], front_matter={})
+
+def test_run_releases_gil_from_threads():
+    from concurrent.futures import ThreadPoolExecutor
+
+    documents = [bytearray(f"# Doc {i}\n\n- item {i}\n", "utf-8") for i in range(16)]
+
+    def work(doc):
+        engine = mq.Engine(".h | to_text()")
+        return mq.run_text(".[] | to_text()", doc), engine.eval(doc).values
+
+    with ThreadPoolExecutor(max_workers=4) as pool:
+        results = list(pool.map(work, documents))
+
+    assert results == [(f"item {i}", [f"Doc {i}"]) for i in range(16)]
+
+
+def test_import_in_subinterpreter():
+    interpreters = pytest.importorskip("_xxsubinterpreters")
+    import sys
+
+    interpreter = interpreters.create()
+    try:
+        interpreters.run_string(
+            interpreter,
+            f"import sys\nsys.path[:] = {sys.path!r}\nimport mq\nassert mq.run('.h', '# a').values == ['# a']\n",
+        )
+    finally:
+        interpreters.destroy(interpreter)
+
+
+def test_definitions_do_not_leak_between_runs():
+    assert mq.run('def greet(): "hi"; | greet()', "a").values == ["hi"]
+    assert mq.run("let x = 1 | x", "a").values == ["1"]
+
+    with pytest.raises(RuntimeError):
+        mq.run("greet()", "a")
+
+    with pytest.raises(RuntimeError):
+        mq.run("x", "a")
+
+    assert mq.run("upcase()", "a").values == ["A"]
+
+
+def test_run_text():
+    markdown = "# A\n\n## B\n\ntext\n"
+
+    assert mq.run_text(".h", markdown) == mq.run(".h", markdown).text
+    assert mq.run_text(".h | to_text()", markdown, join=False) == ["A", "B"]
+    assert mq.run_text(".code", markdown) == ""
+
+    options = mq.Options()
+    options.max_results = 1
+    assert mq.run_text(".h", markdown, options, join=False) == ["# A"]
+
+    with pytest.raises(RuntimeError):
+        mq.run_text(".invalid!!!", markdown)
+
+    assert mq.run_text(".h | select(contains(level))", markdown, args={"level": "B"}) == "## B"
+    assert mq.run_text(".h | to_text()", b"# \xff\n", errors="replace") == "�"
+    with pytest.raises(UnicodeDecodeError):
+        mq.run_text(".h", b"# \xff\n")
+    with pytest.raises(RuntimeError, match="^doc.md: "):
+        mq.run_text(".invalid!!!", markdown, source="doc.md")
+
+
+def test_collect_timings():
+    assert mq.run(".h", "# A").timings is None
+
+    options = mq.Options()
+    options.collect_timings = True
+    result = mq.run(".h", "# A", options)
+
+    assert list(result.timings) == ["parse", "compile", "eval", "convert"]
+    assert all(isinstance(t, float) and t >= 0 for t in result.timings.values())
+    assert result.take(1).timings == result.timings
+
+
+def test_deeply_nested_html():
+    html = "<div>" * 100_000 + "x" + "</div>" * 100_000
+
+    with pytest.raises(mq.MQParseInputError, match="maximum depth of 512"):
+        mq.html_to_markdown(html)
+
+    options = mq.Options()
+    options.input_format = mq.InputFormat.HTML
+    with pytest.raises(RuntimeError):
+        mq.run(".", html, options)
+
+    nested = "<div>" * 60 + "x" + "</div>" * 60
+    assert mq.html_to_markdown(nested) == "x"
+
+    conversion = mq.ConversionOptions()
+    conversion.max_depth = 50
+    with pytest.raises(mq.MQParseInputError, match="maximum depth of 50"):
+        mq.html_to_markdown(nested, conversion)
+
+    # Unclosed list items and void elements do not add levels.
+    assert mq.html_to_markdown("<ul>" + "<li>x<br>" * 1000 + "</ul>").count("x") == 1000
+
+
+def test_decode_errors():
+    html = b"<p>caf\xe9</p>"
+
+    with pytest.raises(UnicodeDecodeError):
+        mq.html_to_markdown(html)
+
+    assert mq.html_to_markdown(html, errors="replace") == "caf�"
+    assert mq.run(".", bytearray(b"# caf\xe9"), errors="replace").values == ["# caf�"]
+    assert mq.run(".", "# café", errors="replace").values == ["# café"]
+
+    with pytest.raises(ValueError, match="invalid errors"):
+        mq.run(".", b"# a", errors="ignore")
+
+
+@pytest.mark.parametrize(
+    "encoding, codec",
+    [
+        (mq.Encoding.UTF8, "utf-8"),
+        (mq.Encoding.UTF16, "utf-16"),
+        (mq.Encoding.UTF16_LE, "utf-16-le"),
+        (mq.Encoding.UTF16_BE, "utf-16-be"),
+        (mq.Encoding.LATIN1, "latin-1"),
+    ],
+)
+def test_content_encoding(encoding, codec, tmp_path):
+    content = "# Café\n\n## Thé\n"
+    data = content.encode(codec)
+    options = mq.Options(encoding=encoding)
+
+    assert mq.run(".h", data, options).values == ["# Café", "## Thé"]
+    assert mq.run(".h", bytearray(data), options).values == ["# Café", "## Thé"]
+    assert mq.run_text(".h", memoryview(data), options) == "# Café\n## Thé"
+    assert mq.run(".h", content, options).values == ["# Café", "## Thé"]
+    (tmp_path / "doc.md").write_bytes(data)
+    assert mq.run_file(".h", tmp_path / "doc.md", options).values == ["# Café", "## Thé"]
+
+
+def test_content_encoding_errors():
+    options = mq.Options(encoding=mq.Encoding.UTF16_LE)
+    with pytest.raises(UnicodeDecodeError, match="utf-16"):
+        mq.run(".", b"#\x00 \x00\x00\xd8", options)
+    assert mq.run(".", b"#\x00 \x00\x00\xd8", options, errors="replace").values == ["# \ufffd"]
+    with pytest.raises(ValueError, match="invalid errors"):
+        mq.run(".", b"# a", options, errors="ignore")
+
+
+def test_parse_input():
+    markdown = "# A\n\n## B\n\n1. x\n"
+    result = mq.parse_input(markdown)
+
+    assert result.values == mq.run("self", markdown).values
+    assert [v.markdown_type for v in result] == [
+        mq.MarkdownType.Heading,
+        mq.MarkdownType.Heading,
+        mq.MarkdownType.OrderedList,
+    ]
+    assert result[2].section_path == ["A", "B"]
+    assert result[2].start == 1
+
+    options = mq.Options()
+    options.input_format = mq.InputFormat.TEXT
+    assert mq.parse_input("a\nb", options).values == ["a", "b"]
+
+
+def test_dumps_and_loads():
+    markdown = "# A *b* **c**\n\n- [x] d\n\n| e |\n|---|\n| f |\n"
+    result = mq.parse_input(markdown)
+    loaded = mq.MQResult.loads(result.dumps())
+
+    assert loaded.values == result.values
+    assert [v.markdown_type for v in loaded] == [v.markdown_type for v in result]
+    assert loaded.to_markdown() == result.to_markdown()
+    assert [v.section_path for v in loaded] == [v.section_path for v in result]
+
+    result = mq.run('.h | {"text": to_text(), "nodes": [self]}', markdown)
+    assert mq.MQResult.loads(result.dumps().encode()).values == result.values
+
+    with pytest.raises(ValueError, match="Invalid serialized result"):
+        mq.MQResult.loads('{"version": 3}')
+
+
+def test_to_json_and_from_json():
+    import json
+
+    result = mq.run(".h", "# T [a](u)\n\n## b")
+    data = json.loads(result[0].to_json())
+    assert data["type"] == "Heading"
+    assert data["markdown"] == "# T [a](u)"
+    assert data["node"]["values"][1]["url"] == "u"
+    assert data["node"]["position"]["start"] == {"line": 1, "column": 1}
+
+    assert mq.from_json(result[0].to_json()) == result[0]
+    loaded = mq.from_json(result.to_json())
+    assert isinstance(loaded, mq.MQResult)
+    assert loaded.values == result.values
+    assert loaded[1].section_path == ["T a"]
+
+    with pytest.raises(ValueError, match="Invalid serialized value"):
+        mq.from_json('{"type": "Nope", "markdown": ""}')
+
+    with pytest.raises(ValueError, match="Invalid JSON"):
+        mq.from_json("[")
+
+
+def test_conversion_options_from_config():
+    options = mq.ConversionOptions.from_dict({"use_title_as_h1": True, "max_depth": 20})
+    assert options.use_title_as_h1
+    assert options.max_depth == 20
+    assert not options.generate_front_matter
+
+    options = mq.ConversionOptions.from_json('{"generate_front_matter": true, "max_depth": null}')
+    assert options.generate_front_matter
+    assert options.max_depth is None
+
+    with pytest.raises(ValueError, match="Unknown conversion option 'max_dept'"):
+        mq.ConversionOptions.from_dict({"max_dept": 3})
+    with pytest.raises(TypeError, match="must be a bool"):
+        mq.ConversionOptions.from_dict({"use_title_as_h1": "yes"})
+    with pytest.raises(ValueError, match="at least 1"):
+        mq.ConversionOptions.from_dict({"max_depth": 0})
+    with pytest.raises(ValueError, match="must be an object"):
+        mq.ConversionOptions.from_json("[]")
+
+
+def test_html_to_markdown_stream():
+    html = "<html><head><title>Export</title></head><body><div>" + "".join(
+        f"<h2>Section {i}</h2><p>Caf\u00e9 {i}<p>More <b>text</b></p><ul><li>a<li>b</ul>" for i in range(50)
+    ) + "</div></body></html>"
+    expected = mq.html_to_markdown(html)
+
+    chunks = list(mq.html_to_markdown_stream(io.BytesIO(html.encode()), chunk_size=200))
+    assert len(chunks) > 1
+    assert "".join(chunks) == expected
+
+    data = html.encode()
+    pieces = (data[i : i + 7] for i in range(0, len(data), 7))
+    assert "".join(mq.html_to_markdown_stream(pieces, chunk_size=300)) == expected
+
+    options = mq.ConversionOptions(use_title_as_h1=True)
+    assert list(mq.html_to_markdown_stream(["<head><title>T</title></head>", "<p>b</p>"], options)) == ["# T\n\nb"]
+    with pytest.raises(ValueError, match="root_selector"):
+        mq.html_to_markdown_stream([html], mq.ConversionOptions(root_selector="main"))
+    with pytest.raises(TypeError, match="must be str or bytes"):
+        list(mq.html_to_markdown_stream([1]))
+
+
+def test_conversion_selectors():
+    html = (
+        "<html><body><nav><a href='/'>Home</a></nav><article class='main'><h1>Hi</h1>"
+        "<p>Text<span class='ad'> buy</span></p><table><tr><td>a</td></tr></table></article>"
+        "<footer>Footer</footer></body></html>"
+    )
+    options = mq.ConversionOptions(root_selector="article.main", exclude_selectors=[".ad"])
+    assert mq.html_to_markdown(html, options) == "# Hi\n\nText\n\n| a |\n|---|"
+
+    options = mq.ConversionOptions(exclude_selectors=["nav", "footer"], keep_html_for=["table"])
+    assert mq.html_to_markdown(html, options) == (
+        "# Hi\n\nText buy\n\n<table><tbody><tr><td>a</td></tr></tbody></table>"
+    )
+
+    options = mq.ConversionOptions.from_dict({"root_selector": "main", "keep_html_for": ["details"]})
+    assert options.root_selector == "main"
+    assert options.keep_html_for == ["details"]
+    with pytest.raises(TypeError, match="must be a list of str"):
+        mq.ConversionOptions.from_dict({"exclude_selectors": "nav"})
+    with pytest.raises(ValueError, match="Invalid CSS selector"):
+        mq.html_to_markdown(html, mq.ConversionOptions(exclude_selectors=["<nav>"]))
+
+
+
+def test_options_keyword_arguments():
+    options = mq.Options(input_format=mq.InputFormat.HTML, max_results=3, trim_trailing_whitespace=True)
+    assert options.input_format == mq.InputFormat.HTML
+    assert options.max_results == 3
+    assert options.trim_trailing_whitespace
+    assert mq.Options() != options
+    assert mq.run(".h", "<h1>A</h1>", options).values == ["# A"]
+
+    options = mq.ConversionOptions(use_title_as_h1=True, max_depth=10)
+    assert options.use_title_as_h1
+    assert options.max_depth == 10
+
+    with pytest.raises(TypeError, match="unexpected keyword argument 'input_fmt'"):
+        mq.Options(input_fmt=mq.InputFormat.HTML)
+    with pytest.raises(TypeError):
+        mq.Options(max_results="3")
+    with pytest.raises(TypeError, match="unexpected keyword argument 'from_dict'"):
+        mq.ConversionOptions(from_dict={})
+
+
+
+def test_pickle_and_copy():
+    options = mq.Options(input_format=mq.InputFormat.CSV, list_style=mq.ListStyle.STAR, max_results=3)
+    assert pickle.loads(pickle.dumps(options)) == options
+    assert copy.copy(options) == options
+    assert copy.deepcopy(options) is not options
+    options = mq.ConversionOptions(use_title_as_h1=True)
+    assert pickle.loads(pickle.dumps(options)) == options
+
+    result = mq.run(".h", "# A\n\n## B", source="doc.md")
+    restored = pickle.loads(pickle.dumps(result))
+    assert restored.values == ["# A", "## B"]
+    assert restored.source == "doc.md"
+    assert restored[1].node == result[1].node
+    assert copy.deepcopy(result).values == result.values
+
+    value = result[0]
+    assert pickle.loads(pickle.dumps(value)) == value
+    assert copy.copy(value) == value
+
+def test_stubs_cover_module():
+    def defined(body):
+        names = set()
+        for node in body:
+            if isinstance(node, (ast.ClassDef, ast.FunctionDef)):
+                names.add(node.name)
+            elif isinstance(node, ast.AnnAssign) and isinstance(node.target, ast.Name):
+                names.add(node.target.id)
+            elif isinstance(node, ast.Assign):
+                names.update(target.id for target in node.targets if isinstance(target, ast.Name))
+        return names
+
+    stub = ast.parse((pathlib.Path(__file__).parent.parent / "src" / "mq" / "mq.pyi").read_text())
+    assert set(mq.__all__) - {"nodes", "text"} <= defined(stub.body)
+
+    for node in stub.body:
+        if not isinstance(node, ast.ClassDef):
+            continue
+        cls = getattr(mq, node.name)
+        # The variants of MQValue are classes of their own, typed through MQValue itself.
+        public = {
+            name
+            for name in dir(cls)
+            if not name.startswith("_")
+            and not hasattr(BaseException, name)
+            and not isinstance(getattr(cls, name), type)
+        }
+        assert public <= defined(node.body), f"mq.pyi is missing {public - defined(node.body)} on {node.name}"
+
+def test_links_by_section():
+    markdown = "[pre](p)\n\n# A\n\n- [x](u1)\n\n## B\n\n> [self](#a)\n\n# C\n\n[r][D] [n][none]\n\n[d]: u2\n"
+
+    assert mq.links_by_section(markdown) == {
+        (): [("pre", "p", (1, 1))],
+        ("A",): [("x", "u1", (5, 3))],
+        ("A", "B"): [],
+        ("C",): [("r", "u2", (13, 1))],
+    }
+
+
+def test_extract_links_and_images():
+    markdown = (
+        '[a](u1 "T") <https://b.io> [r][d] [x][none] <me@x.io>\n\n- ![logo](l.png) ![i][d]\n\n[d]: u2 "D"\n'
+    )
+    links = mq.extract_links(markdown)
+
+    assert [(link.text, link.url, link.title, link.kind) for link in links] == [
+        ("a", "u1", "T", "inline"),
+        ("https://b.io", "https://b.io", None, "autolink"),
+        ("r", "u2", "D", "reference"),
+        ("me@x.io", "mailto:me@x.io", None, "autolink"),
+    ]
+    assert (links[0].position.start_line, links[0].position.start_column) == (1, 1)
+
+    images = mq.extract_images(markdown)
+    assert [(image.text, image.url, image.kind) for image in images] == [
+        ("logo", "l.png", "inline"),
+        ("i", "u2", "reference"),
+    ]
+    assert images[0].position.start_line == 3
+
+
+def test_rewrite_links():
+    markdown = "[a](/v1/a) <https://old.io> ![i](/v1/i.png) [r][d]\n\n[d]: /v1/r\n"
+    seen = []
+
+    def mapper(url):
+        seen.append(url)
+        return url.replace("/v1/", "/v2/").replace("old.io", "new.io") if url != "/v1/a" else None
+
+    assert mq.rewrite_links(markdown, mapper) == (
+        "[a](/v1/a) [https://new.io](https://new.io) ![i](/v2/i.png) [r][d]\n\n[d]: /v2/r\n"
+    )
+    assert seen == ["/v1/a", "https://old.io", "/v1/i.png", "/v1/r"]
+
+    with pytest.raises(TypeError, match="str or None"):
+        mq.rewrite_links(markdown, lambda url: 1)
+    with pytest.raises(ZeroDivisionError):
+        mq.rewrite_links(markdown, lambda url: 1 / 0)
+
+
+def test_audit_images():
+    markdown = "![](a.png)\n\n![Logo](b.png) ![logo](c.png) ![Logo](b.png)\n\n- ![" + "x" * 20 + "](d.png)\n"
+    findings = mq.audit_images(markdown, max_alt_length=10)
+
+    assert [(f.rule, f.line, f.column) for f in findings] == [
+        ("missing-alt", 1, 1),
+        ("duplicate-alt", 3, 16),
+        ("long-alt", 5, 3),
+    ]
+    assert "b.png" in findings[1].message
+    assert mq.audit_images("![A cat](cat.png)") == []
+
+
+def test_to_commonmark_strict():
+    result = mq.parse_input("# A \\*b\\*\n\n2\\. x \\| `` a`b ``\n\n````md\n```\nnested\n```\n````\n\n    indented\n")
+    strict = result.to_commonmark_strict()
+
+    assert strict == (
+        "# A \\*b\\*\n\n2\\. x \\| ``a`b``\n\n````md\n```\nnested\n```\n````\n\n```\nindented\n```\n"
+    )
+    assert result.to_markdown().startswith("# A *b*\n")
+
+    quoted = mq.parse_input("> " + strict.replace("\n", "\n> "))
+    assert [v.markdown_type for v in quoted] == [mq.MarkdownType.Blockquote]
+    assert mq.run(".code", "> " + strict.replace("\n", "\n> ")).text == (
+        "```md\n```\nnested\n```\n```\n```\nindented\n```"
+    )
+
+
+def test_verify_toc():
+    content = (
+        "# Doc\n\n<!-- toc -->\n\n- [Intro](#intro)\n- [Sub](#sub)\n- [Usage](#setup)\n- [Old](#old)\n\n"
+        "<!-- tocstop -->\n\n## Intro\n\n### Sub\n\n## Setup\n\n## New\n"
+    )
+
+    assert [(f.rule, f.line) for f in mq.verify_toc(content)] == [
+        ("level-mismatch", 6),
+        ("text-mismatch", 7),
+        ("stale-entry", 8),
+        ("missing-entry", 18),
+    ]
+    assert mq.verify_toc("<!-- toc -->\n- [B](#b)\n- [A](#a)\n\n## A\n\n## B\n")[0].rule == "wrong-order"
+    assert mq.verify_toc("<!-- toc -->\n- [A](#a)\n  - [B c](#b-c)\n\n## A\n\n### B c\n") == []
+    assert [f.rule for f in mq.verify_toc("# Doc\n", marker="[toc]")] == ["missing-toc"]
+
+
+def test_toc():
+    content = "# Guide\n\n## Install [beta]\n\n### Linux\n\n#### Deep\n\n## Usage\n\n## Usage\n"
+
+    toc = mq.toc(content)
+    assert toc == (
+        "- [Guide](#guide)\n"
+        "  - [Install \\[beta\\]](#install-beta)\n"
+        "    - [Linux](#linux)\n"
+        "  - [Usage](#usage)\n"
+        "  - [Usage](#usage-1)\n"
+    )
+    assert mq.verify_toc(f"<!-- toc -->\n{toc}\n{content}".replace("#### Deep\n\n", "")) == []
+
+    [guide] = mq.toc(content, max_level=2, format="list")
+    assert (guide["title"], guide["level"], guide["slug"], guide["line"], guide["column"]) == ("Guide", 1, "guide", 1, 1)
+    assert [(c["title"], c["slug"], c["children"]) for c in guide["children"]] == [
+        ("Install [beta]", "install-beta", []),
+        ("Usage", "usage", []),
+        ("Usage", "usage-1", []),
+    ]
+    assert mq.toc("text") == ""
+    with pytest.raises(ValueError, match="Invalid toc format"):
+        mq.toc(content, format="html")
+
+
+def test_normalize_code_languages():
+    content = "```JS\n1\n```\n\n```sh {a}\n2\n```\n\n```\n3\n```\n\n```foo\n4\n```\n"
+
+    assert mq.normalize_code_languages(content, default="text") == (
+        "```javascript\n1\n```\n\n```bash {a}\n2\n```\n\n```text\n3\n```\n\n```foo\n4\n```\n",
+        ["foo"],
+    )
+    assert mq.normalize_code_languages(content, aliases={"foo": "fortran"})[1] == []
+    assert mq.normalize_code_languages(content, known=["foo"])[1] == []
+
+
+def test_extract_with_context():
+    content = "# Title\n\nIntro\n\n## Install\n\nRun [pip](https://pip.pypa.io) now.\n\n- a\n- b\n"
+
+    [snippet] = mq.extract_with_context(content, ".link")
+    assert snippet.value.markdown_type == mq.MarkdownType.Link
+    assert snippet.block == "Run [pip](https://pip.pypa.io) now."
+    assert snippet.before == ["## Install"]
+    assert snippet.after == ["- a\n- b"]
+    assert snippet.to_markdown() == "## Install\n\nRun [pip](https://pip.pypa.io) now.\n\n- a\n- b\n"
+
+    [snippet] = mq.extract_with_context(content, ".h2", before=5, after=0)
+    assert snippet.before == ["# Title", "Intro"]
+    assert snippet.after == []
+
+
+def test_result_compact():
+    result = mq.run('if (is_h(.)): "" else: .', "# A\n\ntext\n\nmore\n")
+    assert len(result) == 3
+    compacted = result.compact()
+    assert [value.text for value in compacted] == ["text", "more"]
+
+    result = mq.run('[.h, ""]', "# A\n\n# B\n")
+    assert len(result) == 2
+    assert result.compact().values == ["# A\n# B\n"]
+
+
+def test_html_fragment_to_value():
+    heading = mq.html_fragment_to_value("<h2>Hi <em>there</em></h2>")
+    assert heading.markdown_type == mq.MarkdownType.Heading
+    assert heading.text == "## Hi *there*"
+
+    paragraph = mq.html_fragment_to_value("<p>Hello <strong>x</strong> y</p>")
+    assert paragraph.is_array()
+    items = mq.html_fragment_to_value("<ul><li>a</li><li>b</li></ul>")
+
+    doc = mq.DocumentBuilder().node(heading).paragraph(paragraph).node(items).build()
+    assert doc == "## Hi *there*\n\nHello **x** y\n\n- a\n- b\n"
+
+    with pytest.raises(TypeError):
+        mq.DocumentBuilder().node(3)
+
+
+def test_analyze():
+    analysis = mq.analyze('.h | select(contains("API")) | to_text()')
+    assert analysis.read_only
+    assert analysis.selectors == [".h"]
+    assert analysis.functions == ["select", "contains", "to_text"]
+    assert analysis.mutations == []
+
+    analysis = mq.analyze("def bold(x): to_strong(x); | .link | bold()")
+    assert not analysis.read_only
+    assert analysis.mutations == ["to_strong"]
+
+    assert mq.analyze(".h | map(to_h)").mutations == ["to_h"]
+    assert mq.analyze('import "csv" | .h').mutations == ["import"]
+
+    with pytest.raises(RuntimeError):
+        mq.analyze("def f(")
+
+
+def test_check_syntax_and_compile():
+    assert mq.check_syntax(".h | upcase()") == []
+
+    [diagnostic] = mq.check_syntax(".h |\n let x = ;")
+    assert diagnostic.severity == "error"
+    assert "`;`" in diagnostic.message
+    assert (diagnostic.span.start_line, diagnostic.span.start_column) == (2, 10)
+    assert diagnostic.span.start_offset == 14
+
+    engine = mq.compile(".h | upcase()")
+    assert engine.eval("# a").values == ["# A"]
+
+    with pytest.raises(mq.ParseError):
+        mq.compile("def f(")
+
+    diagnostics = mq.check_syntax('"\u00e9" | shout() | upcase()\n| shout() | whisper(1)')
+    assert [d.message for d in diagnostics] == ["Unknown function `shout`", "Unknown function `whisper`"]
+    spans = [(d.span.start_line, d.span.start_column, d.span.start_offset) for d in diagnostics]
+    assert spans == [(1, 7, 7), (2, 13, 38)]
+    assert mq.check_syntax("def shout(x): upcase(x); | let f = fn(g): g(1); | shout(f(to_text))") == []
+    assert mq.check_syntax('include "extra" | shout()') == []
+
+    with pytest.raises(mq.ParseError, match="Unknown function `shout`") as e:
+        mq.compile(".h | shout()")
+    assert (e.value.line, e.value.column, e.value.token, e.value.snippet) == (1, 6, "shout", ".h | shout()")
+    assert mq.Engine(".h | shout()").code == ".h | shout()"
+
+def test_builtins():
+    builtins = {builtin.name: builtin for builtin in mq.builtins()}
+    assert {builtin.category for builtin in builtins.values()} == {"function", "module", "selector"}
+    assert builtins["halt"].signature == "halt(exit_code)"
+    assert builtins["halt"].category == "function"
+    assert builtins["contains"].signature == "contains(haystack, needle)"
+    assert builtins["contains"].doc == "Checks if string contains a substring"
+    assert builtins["contains"].category == "module"
+    assert builtins[".h"].signature == ".h"
+    assert builtins[".h"].category == "selector"
+    assert all(builtin.doc for builtin in builtins.values())
+    assert not any(name.startswith("_") for name in builtins)
+    assert repr(builtins["halt"]) == 'Builtin("halt(exit_code)", "function")'
+
+    for name, builtin in builtins.items():
+        if builtin.category != "selector":
+            assert mq.check_syntax(f"{name}()") == [], name
+
+
+def test_value_structured_fields():
+    content = '## See [docs](https://mqlang.org/ "mq")\n\n- [x] done\n  - nested\n\n![logo](logo.png)\n'
+    heading = mq.run(".h", content)[0]
+    assert heading.level == 2
+    assert heading.url is None
+
+    link = heading.node.children[1]
+    assert isinstance(link, mq.nodes.Link)
+    assert link.url == "https://mqlang.org/"
+    assert isinstance(link.children[0], mq.nodes.Text)
+
+    assert mq.run(".link", '[docs](https://mqlang.org/ "mq")')[0].title == "mq"
+    _, done, nested, image = mq.run(".", content)
+    assert image.url == "logo.png"
+    assert [(item.level, item.checked) for item in (done, nested)] == [(0, True), (1, None)]
+
+def test_result_render_output_format():
+    result = mq.run(".", "# Title **x**\n\n- a\n")
+    options = mq.Options()
+    assert options.output_format is None
+    assert result.render() == result.to_markdown()
+
+    options.output_format = mq.OutputFormat.HTML
+    assert result.render(options) == "<h1>Title <strong>x</strong></h1>\n<ul>\n<li>a</li>\n</ul>\n"
+
+    options.output_format = mq.OutputFormat.JSON
+    import json
+
+    assert [node["type"] for node in json.loads(result.render(options))] == ["Heading", "List"]
+
+    options.output_format = mq.OutputFormat.TEXT
+    assert result.render(options) == "Title x\na\n"
+
+    doc = "# Title\n\n## Section **x**\n"
+    result = mq.run(".h2", doc, mq.Options(output_format=mq.OutputFormat.HTML))
+    assert result.render() == "<h2>Section <strong>x</strong></h2>\n"
+    assert result.take(1).render() == result.render()
+    assert result.render(mq.Options()) == "## Section **x**\n"
+    assert mq.parse_input(doc, mq.Options(output_format=mq.OutputFormat.TEXT)).render() == "Title\nSection x\n"
+
+
+def test_render_html_attributes():
+    attributes = {
+        mq.MarkdownType.Heading: {"class": "docs-h{level}"},
+        mq.MarkdownType.Code: {"class": "block"},
+        mq.MarkdownType.CodeInline: {"class": "inline", "title": 'a "b"'},
+    }
+    options = mq.Options(output_format=mq.OutputFormat.HTML, html_attributes=attributes)
+    result = mq.run(".", "## A\n\n```rs\nx\n```\n\nSee `y`.\n", options)
+
+    assert result.render() == (
+        '<h2 class="docs-h2">A</h2>\n'
+        '<pre class="block"><code class="language-rs">x\n</code></pre>\n'
+        '<p>See <code class="inline" title="a &quot;b&quot;">y</code>.</p>\n'
+    )
+    assert mq.Options(html_attributes={mq.MarkdownType.Image: {"class": "a"}}).html_attributes == {
+        mq.MarkdownType.Image: {"class": "a"}
+    }
+    assert pickle.loads(pickle.dumps(options)) == options
+    with pytest.raises(ValueError, match="Text"):
+        mq.Options(html_attributes={mq.MarkdownType.Text: {"class": "a"}})
+    with pytest.raises(ValueError, match="attribute name"):
+        mq.Options(html_attributes={mq.MarkdownType.Heading: {"on click": "a"}})
+
+def test_run_iter():
+    content = "# A\n\n## B\n\nText\n\n## C\n"
+    values = mq.run_iter(".h2", content)
+    assert iter(values) is values
+
+    first = next(values)
+    assert first.text == "## B"
+    assert first.section_path == ["A"]
+    assert [v.text for v in values] == ["## C"]
+    assert list(mq.run_iter(".code", content)) == []
+    with pytest.raises(RuntimeError, match="Error evaluating query"):
+        mq.run_iter('error("boom")', content)
+
+def test_debug_trace():
+    options = mq.Options(debug=True)
+    result = mq.run(".h | select(.h.depth == 3) | to_text()", "# Title\n\n## Usage\n\ntext\n", options)
+    assert [(s.index, s.name, s.code) for s in result.trace] == [
+        (0, ".h", ".h"),
+        (1, "select", "select(.h.depth == 3)"),
+        (2, "to_text", "to_text()"),
+    ]
+    assert [(s.input_count, s.output_count) for s in result.trace] == [(3, 2), (2, 0), (0, 0)]
+    assert all(s.duration >= 0 for s in result.trace)
+    assert "output_count=0" in repr(result.trace[1])
+
+    trace = mq.run("let x = 1 | .h | to_text()", "# A", options).trace
+    assert [(s.code, s.output_count) for s in trace] == [("let x = 1", 1), (".h", 1), ("to_text()", 1)]
+    assert mq.run(".h", "# A").trace is None
+
+    query = ".h | select(contains(to_text(self), n)) | to_text()"
+    trace = mq.run(query, "# A\n\n## B\n", options, args={"n": "B"}).trace
+    assert [(s.output_count, s.error) for s in trace] == [(2, None), (1, None), (1, None)]
+
+    engine = mq.Engine(".h | shout() | to_text()", options)
+    engine.load_module("def shout(): upcase(to_text(self));")
+    assert [s.output_count for s in engine.eval("# a").trace] == [1, 1, 1]
+
+    # Queries with side effects are not run again for each stage.
+    trace = mq.run(".h | inspect() | to_text()", "# A", options).trace
+    assert [s.output_count for s in trace] == [None, None, 1]
+    assert "inspect" in trace[0].error
+
+def test_run_file_and_glob(tmp_path):
+    (tmp_path / "docs").mkdir()
+    (tmp_path / "a.md").write_text("# A\n")
+    (tmp_path / "docs" / "b.md").write_bytes("# B é\n".encode("utf-16"))
+    (tmp_path / "docs" / "c.md").write_bytes(b"\xef\xbb\xbf# C\n")
+
+    result = mq.run_file(".h | to_text()", tmp_path / "a.md")
+    assert result.values == ["A"]
+    assert result.source == str(tmp_path / "a.md")
+
+    results = mq.run_glob(".h | to_text()", str(tmp_path / "**" / "*.md"))
+    assert {path: r.values for path, r in results.items()} == {
+        str(tmp_path / "a.md"): ["A"],
+        str(tmp_path / "docs" / "b.md"): ["B é"],
+        str(tmp_path / "docs" / "c.md"): ["C"],
+    }
+
+    (tmp_path / "docs" / "d.md").write_text("---\ntags: [api]\n---\n# D\n")
+    results = mq.run_glob(".h | to_text()", str(tmp_path / "**" / "*.md"), front_matter={"tags": ["api"]})
+    assert {path: r.values for path, r in results.items()} == {str(tmp_path / "docs" / "d.md"): ["D"]}
+    results = mq.run_glob(".h | to_text()", str(tmp_path / "*.md"), front_matter=lambda meta: not meta)
+    assert list(results) == [str(tmp_path / "a.md")]
+
+    lazy = mq.run_glob(".h | to_text()", str(tmp_path / "**" / "*.md"), lazy=True)
+    assert iter(lazy) is lazy
+    assert sorted((path, r.values) for path, r in lazy) == [
+        (str(tmp_path / "a.md"), ["A"]),
+        (str(tmp_path / "docs" / "b.md"), ["B é"]),
+        (str(tmp_path / "docs" / "c.md"), ["C"]),
+        (str(tmp_path / "docs" / "d.md"), ["D"]),
+    ]
+    lazy = mq.run_glob(".h", str(tmp_path / "**" / "*.md"), front_matter={"tags": ["api"]}, lazy=True)
+    assert [path for path, _ in lazy] == [str(tmp_path / "docs" / "d.md")]
+    with pytest.raises(TypeError):
+        mq.run_glob(".h", str(tmp_path / "*.md"), front_matter=1, lazy=True)
+
+    (tmp_path / "bad.md").write_bytes(b"# \xff\n")
+    with pytest.raises(UnicodeDecodeError):
+        mq.run_file(".h", tmp_path / "bad.md")
+    assert mq.run_file(".h | to_text()", tmp_path / "bad.md", errors="replace").values == ["\ufffd"]
+    with pytest.raises(OSError):
+        mq.run_file(".h", tmp_path / "missing.md")
+
+def test_run_multi():
+    documents = {"intro.md": "# Intro\n\n## Why\n", "api.md": b"# API\n"}
+
+    results = mq.run_multi(".h | to_text()", documents)
+    assert list(results) == ["intro.md", "api.md"]
+    assert results["intro.md"].values == ["Intro", "Why"]
+    assert results["api.md"].source == "api.md"
+    assert results["api.md"][0].source == "api.md"
+
+    results = mq.run_multi('.h | s"${file}#${depth}: ${to_text(self)}"', documents, name_var="file", args={"depth": 1})
+    assert [v for r in results.values() for v in r.values] == ["intro.md#1: Intro", "intro.md#1: Why", "api.md#1: API"]
+    assert mq.run_multi(".h", {}) == {}
+    with pytest.raises(mq.EvalError, match="^api.md: "):
+        mq.run_multi('.h | error("boom")', {"api.md": "# API"})
+    with pytest.raises(ValueError, match="Invalid argument name"):
+        mq.run_multi(".h", documents, name_var="file-name")
+
+def test_value_position():
+    content = "# Tïtle **x**\n\nSee `code`\n"
+    heading, text, code = mq.run(".", content)
+    position = heading.position
+    assert (position.start_line, position.start_column, position.end_line, position.end_column) == (1, 1, 1, 15)
+    assert content.encode()[code.position.start_offset : code.position.end_offset] == b"`code`"
+    assert code.position.start_line == 3
+
+    strong = heading.node.children[1]
+    assert strong.position.start_column == 10
+    assert strong.position.start_offset is None
+    assert mq.nodes.Text("a").position is None
+    assert mq.run('"built"', content)[0].position is None
+    assert mq.MQResult.loads(mq.run(".", content).dumps())[2].position == code.position
+
+def test_to_python():
+    options = mq.Options()
+    options.input_format = mq.InputFormat.NULL
+    result = mq.run('[1, 2.5, true, None, "s", {"a": [false]}]', "", options)
+    assert result.to_python() == [[1, 2.5, True, None, "s", {"a": [False]}]]
+    assert isinstance(result[0].to_python()[0], int)
+
+    assert mq.run('nodes | {"count": len()}', "# A\n\n# B").to_python() == [{"count": 2}]
+    assert mq.run(".h", "# A\n\ntext").to_python() == ["# A"]
+    assert mq.MQResult.loads(result.dumps()).to_python() == result.to_python()
+
+
+def test_to_records():
+    result = mq.run("select(or(.h, .link, .code))", "# T\n\n[a](https://x.org)\n\n```py\nx\n```\n", source="a.md")
+    records = result.to_records()
+    assert records[0] == {
+        "text": "# T",
+        "type": "Heading",
+        "level": 1,
+        "url": None,
+        "title": None,
+        "lang": None,
+        "line": 1,
+        "column": 1,
+        "end_line": 1,
+        "end_column": 4,
+        "section_path": [],
+        "source": "a.md",
+    }
+    assert [(r["type"], r["url"], r["lang"], r["section_path"]) for r in records[1:]] == [
+        ("Link", "https://x.org", None, ["T"]),
+        ("Code", None, "py", ["T"]),
+    ]
+    assert all(record.keys() == records[0].keys() for record in records)
+
+
+def test_result_and_value_sequence_protocol():
+    result = mq.run(".h", "# H1\n\n## H2\n\n### H3")
+    assert [value.text for value in result] == ["# H1", "## H2", "### H3"]
+    assert result[-1].text == "### H3"
+    assert isinstance(result[1:], mq.MQResult)
+    assert result[1:].values == ["## H2", "### H3"]
+    assert result[::-2].values == ["### H3", "# H1"]
+    with pytest.raises(IndexError, match="Index -4 out of range for MQResult with length 3"):
+        result[-4]
+
+    options = mq.Options()
+    options.input_format = mq.InputFormat.NULL
+    array = mq.run("[1, 2, 3]", "", options)[0]
+    assert [value.text for value in array] == ["1", "2", "3"]
+    assert array[-1].text == "3"
+    assert array[:2].is_array()
+    assert [value.text for value in array[:2]] == ["1", "2"]
+    with pytest.raises(IndexError, match="for MQValue"):
+        array[3]
+    assert [value.text for value in result[0]] == ["# H1"]
+
+def test_dict_value_mapping_protocol():
+    options = mq.Options(input_format=mq.InputFormat.NULL)
+    value = mq.run('{"name": "mq", "tags": ["md", "cli"]}', "", options)[0]
+    assert value["name"].text == "mq"
+    assert [tag.text for tag in value["tags"]] == ["md", "cli"]
+    assert value.keys() == ["name", "tags"] == list(value)
+    assert [v.text for v in value.values] == ["mq", "md\ncli"]
+    assert [(k, v.text) for k, v in value.items()] == [("name", "mq"), ("tags", "md\ncli")]
+    assert "name" in value and "missing" not in value
+    assert "md" in value["tags"] and "rust" not in value["tags"]
+    assert dict(value)["name"].text == "mq"
+    with pytest.raises(KeyError, match="missing"):
+        value["missing"]
+
+    heading = mq.run(".h", "# H1")[0]
+    with pytest.raises(TypeError, match="strings for dict values"):
+        heading["name"]
+    with pytest.raises(TypeError, match=r"keys\(\) requires a dict value"):
+        heading.keys()
+
+def test_value_variants():
+    count, text, heading = mq.run('nodes | [len(), to_text(first()), is_h(first())] | .[]', "# A\n\n3\n")
+
+    assert count.is_number() and count.value == 2 and isinstance(count, mq.MQValue.Number)
+    assert text.is_string() and text.value == "A" and not text.is_markdown()
+    assert heading.is_bool() and heading.value is True
+    assert count.markdown_type is None
+    assert repr(count) == "MQValue::Number(2)"
+    assert count == mq.MQValue.Number(2) and count != mq.MQValue.String("2")
+    assert mq.MQValue.Number(2) < mq.MQValue.Number(10)
+    assert not mq.MQValue.Number(0) and isinstance(mq.MQValue.NONE, mq.MQValue.Null)
+
+    loaded = mq.MQResult.loads(mq.run('nodes | [len(), "x", false, None]', "a").dumps())
+    assert [repr(v) for v in loaded] == [
+        "MQValue::Number(1)",
+        'MQValue::String("x")',
+        "MQValue::Bool(false)",
+        "MQValue::NONE",
+    ]
+
+
+def test_value_annotations():
+    *_, text = mq.run("select(.text)", "# A\n\nb\n", mq.Options(), source="a.md")
+    assert text.annotations["source"] == "a.md" and text.annotations["section_path"] == ["A"]
+    assert text.annotations["offsets"] == (5, 6)
+
+    built = mq.MQValue.Markdown("b", mq.MarkdownType.Text, annotations={"source": "b.md", "stage": 1})
+    assert built.source == "b.md" and built.stage == 1 and built.section_path == []
+    assert mq.MQValue.Markdown("c", mq.MarkdownType.Text).annotations is None
+
+    with pytest.raises(TypeError):
+        mq.MQValue.Markdown("b", mq.MarkdownType.Text, annotations={"sources": "b.md"})
+
+
+def test_value_none():
+    heading, text = mq.run(".h", "# Title\n\ntext\n")
+    assert not heading.is_none()
+    assert text.is_none()
+    assert text == mq.MQValue.NONE
+    assert repr(text) == "MQValue::NONE"
+
+    [empty] = mq.run('""', "text")
+    assert not empty.is_none()
+    assert empty != mq.MQValue.NONE
+
+
+def test_engine():
+    engine = mq.Engine(".h | to_text()")
+    assert engine.code == ".h | to_text()"
+    assert engine.eval("# A\n\n## B\n").values == ["A", "B"]
+    assert engine.eval(b"# C", source="c.md").source == "c.md"
+
+    failing = mq.Engine('def f(x): error("boom"); | .h | f()')
+    for _ in range(2):
+        with pytest.raises(RuntimeError, match="boom"):
+            failing.eval("# A")
+
+    with pytest.raises(RuntimeError):
+        mq.Engine(".h | (")
+
+
+def test_modules(tmp_path):
+    (tmp_path / "loud.mq").write_text('def shout(x): upcase(x) | add("!");\n')
+    options = mq.Options()
+    options.module_paths = [tmp_path]
+    assert mq.run('import "loud" | .h | loud::shout()', "# a", options).values == ["# A!"]
+    assert mq.run('include "loud" | .h | shout()', "# a", options).values == ["# A!"]
+
+    engine = mq.Engine(".h | shout() | twice()")
+    engine.load_module(tmp_path / "loud.mq")
+    engine.load_module("def twice(x): add(x, x);")
+    assert engine.eval("# a").values == ["# A!A!"]
+
+    # Modules are loaded again after a failed query replaces the engine.
+    failing = mq.Engine(".h | boom()")
+    failing.load_module('def boom(x): error("boom");')
+    for _ in range(2):
+        with pytest.raises(mq.EvalError, match="boom"):
+            failing.eval("# a")
+
+    with pytest.raises(mq.ParseError, match="Error loading module"):
+        engine.load_module("def f(")
+
+    with pytest.raises(FileNotFoundError):
+        engine.load_module(tmp_path / "missing.mq")
+
+
+def test_engine_persistent_definitions():
+    engine = mq.Engine()
+    assert engine.eval("# a").values == ["# a"]
+    assert engine.eval('def shout(x): upcase(x); let suffix = "!"', None).values == []
+    assert engine.defined_names() == ["shout", "suffix"]
+    assert engine.eval(".h | shout() | add(suffix)", "# a\n\n## b").values == ["# A!", "## B!"]
+
+    # Definitions survive a failed query, which replaces the engine.
+    with pytest.raises(mq.EvalError):
+        engine.eval('error("boom")', None)
+    assert engine.eval(".h | shout()", "# c", source="c.md").values == ["# C"]
+
+    engine.reset()
+    assert engine.defined_names() == []
+    with pytest.raises(mq.EvalError, match="not defined"):
+        engine.eval(".h | shout()", "# c")
+    with pytest.raises(TypeError, match="positional arguments"):
+        engine.eval()
+
+
+def test_stats():
+    doc = "---\ntitle: x\n---\n\n# Title\n\nSome **bold** text with `code` and a [link](u).\n\n## Sub\n\n"
+    doc += "- item ![img](i.png)\n\n```py\na = 1\nb = 2\n```\n\n<div>html</div>\n"
+    stats = mq.stats(doc, words_per_minute=5)
+    assert (stats.words, stats.headings, stats.code_blocks, stats.code_lines) == (11, {1: 1, 2: 1}, 1, 2)
+    assert (stats.links, stats.images, stats.reading_minutes) == (1, 1, 2.2)
+    assert stats.characters == len("Title" + "Some bold text with code and a link." + "Sub" + "item ")
+    with pytest.raises(ValueError, match="words_per_minute"):
+        mq.stats(doc, words_per_minute=0)
+
+
+def test_html_converter():
+    html = '<p>Press <span class="kbd">Ctrl</span></p><div class="note" id="n"><p>Be <em>careful</em></p></div>'
+    html += "<p>end</p>"
+    converter = mq.HtmlConverter()
+    converter.on_element("span.kbd", lambda attrs, markdown: f"<kbd>{markdown}</kbd>")
+    converter.on_element(".note", lambda attrs, markdown: f"> [!NOTE] {attrs['id']}\n> {markdown}")
+    assert converter.convert(html) == "Press <kbd>Ctrl</kbd>\n\n> [!NOTE] n\n> Be *careful*\n\nend"
+
+    converter.on_element(".note", lambda attrs, markdown: None)
+    assert converter.convert(html) == "Press <kbd>Ctrl</kbd>\n\nBe *careful*\n\nend"
+    converter.on_element("span", lambda attrs, markdown: 1)
+    with pytest.raises(TypeError, match="str or None"):
+        converter.convert(html)
+
+
+def test_reference_style():
+    doc = "See [docs][d], ![logo][img] and [x][missing].[^1]\n\n"
+    doc += '[d]: https://d.example "Docs"\n[img]: logo.png\n\n[^1]: Note.\n'
+    assert mq.resolve_references(doc) == (
+        'See [docs](https://d.example "Docs"), ![logo](logo.png) and [x][missing]. (Note.)\n'
+    )
+    assert mq.resolve_references(doc, footnotes=None).endswith("[x][missing].[^1]\n\n[^1]: Note.\n")
+
+    inline = "[a](https://a.example), [b](https://b.example) and ![a](https://a.example)\n"
+    doc = inline + "\n[b]: https://b.example\n"
+    assert mq.to_reference_style(doc) == "[a][1], [b] and ![a][1]\n\n[b]: https://b.example\n\n[1]: https://a.example\n"
+    assert mq.resolve_references(mq.to_reference_style(doc)) == inline
+
+
+def test_sections():
+    doc = "Intro\n\n# Title\n\n## Install\n\npip\n\n### Extra\n\nmore\n\n## Usage\n\nuse\n"
+    sections = mq.sections(doc)
+    assert [(s.heading, s.level) for s in sections] == [("Title", 1), ("Install", 2), ("Extra", 3), ("Usage", 2)]
+    install = mq.get_section(doc, "install")
+    assert install.text == doc[install.start : install.end] == "## Install\n\npip\n\n### Extra\n\nmore\n\n"
+    assert [str(node) for node in install.nodes] == ["pip", "### Extra", "more"]
+    assert mq.get_section(doc, "missing") is None
+
+    assert mq.replace_section(doc, "Install", "new") == "Intro\n\n# Title\n\n## Install\n\nnew\n\n## Usage\n\nuse\n"
+    assert mq.replace_section(doc, "usage", "## Use", keep_heading=False).endswith("more\n\n## Use\n")
+    assert mq.remove_section(doc, "Install") == "Intro\n\n# Title\n\n## Usage\n\nuse\n"
+    with pytest.raises(ValueError, match="No section"):
+        mq.remove_section(doc, "missing")
+
+
+def test_engine_threads():
+    engine = mq.Engine(".h | shout()")
+    engine.load_module("def shout(x): upcase(x);")
+    with ThreadPoolExecutor(4) as pool:
+        results = pool.map(engine.eval, [f"# a{i}" for i in range(50)])
+        assert [result.values for result in results] == [[f"# A{i}"] for i in range(50)]
+        engine.eval('let suffix = "!"', None)
+        assert list(pool.map(lambda i: engine.eval(".h | add(suffix)", f"# {i}").values, range(8))) == [
+            [f"# {i}!"] for i in range(8)
+        ]
+
+
+def test_engine_threads_release_environments():
+    statm = pathlib.Path("/proc/self/statm")
+    if not statm.exists():
+        pytest.skip("needs /proc to measure memory")
+
+    import gc
+    import threading
+
+    def rss():
+        return int(statm.read_text().split()[1]) * 4096
+
+    module = "let big = range(1, 100000);"
+    barrier = threading.Barrier(4)
+
+    def use(engine):
+        barrier.wait()
+        return engine.eval(".h", "# a").values
+
+    with ThreadPoolExecutor(4) as pool:
+        for round in range(12):
+            engine = mq.Engine(".h")
+            engine.load_module(module)
+            assert list(pool.map(use, [engine] * 4)) == [["# a"]] * 4
+            del engine
+            gc.collect()
+
+            if round == 3:
+                start = rss()
+
+        # Eight engines on four threads would hold about 130 MB of environments if they were kept.
+        assert rss() - start < 40_000_000
+
+
+def test_engine_cache():
+    engine = mq.Engine(".h", cache_size=2)
+    assert engine.eval("# a").values == ["# a"]
+    assert engine.eval("# a", source="a.md").source == "a.md"
+    engine.eval("# b")
+    engine.eval("# c")
+    assert str(engine.cache_info()) == "CacheInfo(hits=1, misses=3, maxsize=2, currsize=2)"
+
+    engine.eval("def shout(x): upcase(x);", None)
+    assert engine.cache_info().currsize == 0
+    engine.cache_clear()
+    assert engine.cache_info().hits == 0
+    assert mq.Engine(".h").cache_info().maxsize == 0
+
+    # Entries are found by comparing the content, so no two documents share a result.
+    engine = mq.Engine(".h", cache_size=1000)
+    documents = [f"# {i}" for i in range(500)]
+    assert [engine.eval(doc).values for doc in documents + documents] == [[doc] for doc in documents + documents]
+    assert engine.eval(b"# 7").values == ["# 7"]
+    assert (engine.cache_info().hits, engine.cache_info().misses) == (501, 500)